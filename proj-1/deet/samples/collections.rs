@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn total(v: &Vec<i32>) -> i32 {
+    v.iter().sum()
+}
+
+fn main() {
+    let v = vec![1, 2, 3];
+    let s = String::from("hello");
+    let some: Option<i32> = Some(5);
+    let none: Option<i32> = None;
+    let b = Box::new(Point { x: 1, y: 2 });
+    let mut m = HashMap::new();
+    m.insert(1, 10);
+    m.insert(2, 20);
+    let t = total(&v);
+    println!("{} {} {:?} {:?} {}", s, t, some, none, b.x + b.y + m[&1]);
+}
@@ -0,0 +1,550 @@
+//! What deet needs to know about the processor the program runs on: where the program counter,
+//! stack pointer and frame pointer are kept, what a breakpoint instruction looks like and where
+//! the program counter is left once one traps, which registers a system call's number and
+//! arguments are in, and how hardware watchpoints are set. Everything that reads registers goes
+//! through `Native`, the architecture deet was built for, rather than assuming x86-64's rip and
+//! int3. (Decoding instructions, calling functions in the inferior and injecting system calls are
+//! only done on x86-64.)
+
+#[cfg(target_arch = "x86_64")]
+use nix::errno::Errno;
+use nix::unistd::Pid;
+
+pub trait Arch {
+    /// The general-purpose registers, as ptrace reads and writes them
+    type Registers: Copy;
+
+    /// The instruction a software breakpoint is planted as
+    const BREAKPOINT: &'static [u8];
+
+    /// How far past a breakpoint instruction the program counter is once its trap is reported.
+    /// It has to be moved back by this much for the original instruction to run when we go on.
+    const TRAP_PC_OFFSET: usize;
+
+    /// The e_machine of ELF files (and core dumps) for this architecture
+    const ELF_MACHINE: u16;
+
+    /// What named_registers calls the program counter, stack pointer and frame pointer, which
+    /// expressions can also refer to as $pc, $sp and $fp
+    const PC_REGISTER: &'static str;
+    const SP_REGISTER: &'static str;
+    const FP_REGISTER: &'static str;
+
+    fn get_registers(pid: Pid) -> Result<Self::Registers, nix::Error>;
+
+    fn set_registers(pid: Pid, regs: &Self::Registers) -> Result<(), nix::Error>;
+
+    fn pc(regs: &Self::Registers) -> usize;
+
+    fn set_pc(regs: &mut Self::Registers, pc: usize);
+
+    fn stack_pointer(regs: &Self::Registers) -> usize;
+
+    fn frame_pointer(regs: &Self::Registers) -> usize;
+
+    /// Returns the registers in the order `info registers` prints them.
+    fn named_registers(regs: &Self::Registers) -> Vec<(&'static str, u64)>;
+
+    /// Returns a mutable reference to the register with the given name (as named_registers names
+    /// it).
+    fn register_mut<'a>(regs: &'a mut Self::Registers, name: &str) -> Option<&'a mut u64>;
+
+    /// The number of the system call the inferior is stopped at the entry or exit of
+    fn syscall_number(regs: &Self::Registers) -> u64;
+
+    /// The system call's six arguments, in order
+    fn syscall_args(regs: &Self::Registers) -> [u64; 6];
+
+    /// What the system call returned, at its exit
+    fn syscall_return(regs: &Self::Registers) -> u64;
+
+    /// Whether a system call stop is at the call's entry rather than its exit.
+    fn is_syscall_entry(regs: &Self::Registers) -> bool;
+
+    /// Whether a SIGTRAP with this si_code was raised by a breakpoint instruction, rather than
+    /// by single-stepping or a debug register.
+    fn is_breakpoint_trap(si_code: i32) -> bool;
+
+    /// How many hardware watchpoints can be set at once, or 0 if deet can't set them here
+    const WATCHPOINT_SLOTS: usize;
+
+    /// Programs watchpoint `slot` to trap after any write to the len bytes at addr. len must be
+    /// 1, 2, 4 or 8, and addr must be aligned to it.
+    fn set_watchpoint(pid: Pid, slot: usize, addr: usize, len: usize) -> Result<(), nix::Error>;
+
+    /// Turns watchpoint `slot` off again.
+    fn clear_watchpoint(pid: Pid, slot: usize) -> Result<(), nix::Error>;
+
+    /// Returns which slots' watchpoints were hit since the last call, one bit per slot.
+    fn take_watchpoint_hits(pid: Pid) -> Result<usize, nix::Error>;
+}
+
+#[cfg(target_arch = "x86_64")]
+pub type Native = X86_64;
+
+/// The general-purpose registers of the architecture deet was built for
+pub type Registers = <Native as Arch>::Registers;
+
+#[cfg(target_arch = "aarch64")]
+pub type Native = aarch64::Aarch64;
+
+#[cfg(target_arch = "x86_64")]
+pub struct X86_64;
+
+/// Offset of u_debugreg in struct user (see sys/user.h), for PTRACE_PEEKUSER/POKEUSER
+#[cfg(target_arch = "x86_64")]
+const DEBUGREG_OFFSET: usize = 848;
+
+#[cfg(target_arch = "x86_64")]
+impl X86_64 {
+    fn peek_debugreg(pid: Pid, index: usize) -> Result<u64, nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * std::mem::size_of::<u64>();
+        let val = unsafe {
+            Errno::clear();
+            libc::ptrace(
+                libc::PTRACE_PEEKUSER,
+                pid.as_raw(),
+                offset as *mut libc::c_void,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        // PEEKUSER returns the value itself, so -1 is only an error if errno was set
+        if val == -1 && Errno::last() != Errno::UnknownErrno {
+            return Err(nix::Error::Sys(Errno::last()));
+        }
+        Ok(val as u64)
+    }
+
+    fn poke_debugreg(pid: Pid, index: usize, val: u64) -> Result<(), nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * std::mem::size_of::<u64>();
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_POKEUSER,
+                pid.as_raw(),
+                offset as *mut libc::c_void,
+                val as *mut libc::c_void,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Arch for X86_64 {
+    type Registers = libc::user_regs_struct;
+
+    /// int3
+    const BREAKPOINT: &'static [u8] = &[0xcc];
+
+    /// int3 traps after it has executed, leaving rip just past it
+    const TRAP_PC_OFFSET: usize = 1;
+
+    /// EM_X86_64
+    const ELF_MACHINE: u16 = 62;
+
+    const PC_REGISTER: &'static str = "rip";
+    const SP_REGISTER: &'static str = "rsp";
+    const FP_REGISTER: &'static str = "rbp";
+
+    fn get_registers(pid: Pid) -> Result<Self::Registers, nix::Error> {
+        nix::sys::ptrace::getregs(pid)
+    }
+
+    fn set_registers(pid: Pid, regs: &Self::Registers) -> Result<(), nix::Error> {
+        nix::sys::ptrace::setregs(pid, *regs)
+    }
+
+    fn pc(regs: &Self::Registers) -> usize {
+        regs.rip as usize
+    }
+
+    fn set_pc(regs: &mut Self::Registers, pc: usize) {
+        regs.rip = pc as u64;
+    }
+
+    fn stack_pointer(regs: &Self::Registers) -> usize {
+        regs.rsp as usize
+    }
+
+    fn frame_pointer(regs: &Self::Registers) -> usize {
+        regs.rbp as usize
+    }
+
+    fn named_registers(regs: &Self::Registers) -> Vec<(&'static str, u64)> {
+        vec![
+            ("rax", regs.rax),
+            ("rbx", regs.rbx),
+            ("rcx", regs.rcx),
+            ("rdx", regs.rdx),
+            ("rsi", regs.rsi),
+            ("rdi", regs.rdi),
+            ("rbp", regs.rbp),
+            ("rsp", regs.rsp),
+            ("r8", regs.r8),
+            ("r9", regs.r9),
+            ("r10", regs.r10),
+            ("r11", regs.r11),
+            ("r12", regs.r12),
+            ("r13", regs.r13),
+            ("r14", regs.r14),
+            ("r15", regs.r15),
+            ("rip", regs.rip),
+            ("eflags", regs.eflags),
+            ("cs", regs.cs),
+            ("ss", regs.ss),
+            ("ds", regs.ds),
+            ("es", regs.es),
+            ("fs", regs.fs),
+            ("gs", regs.gs),
+            ("fs_base", regs.fs_base),
+            ("gs_base", regs.gs_base),
+        ]
+    }
+
+    fn register_mut<'a>(regs: &'a mut Self::Registers, name: &str) -> Option<&'a mut u64> {
+        Some(match name {
+            "rax" => &mut regs.rax,
+            "rbx" => &mut regs.rbx,
+            "rcx" => &mut regs.rcx,
+            "rdx" => &mut regs.rdx,
+            "rsi" => &mut regs.rsi,
+            "rdi" => &mut regs.rdi,
+            "rbp" => &mut regs.rbp,
+            "rsp" => &mut regs.rsp,
+            "r8" => &mut regs.r8,
+            "r9" => &mut regs.r9,
+            "r10" => &mut regs.r10,
+            "r11" => &mut regs.r11,
+            "r12" => &mut regs.r12,
+            "r13" => &mut regs.r13,
+            "r14" => &mut regs.r14,
+            "r15" => &mut regs.r15,
+            "rip" => &mut regs.rip,
+            "eflags" => &mut regs.eflags,
+            "cs" => &mut regs.cs,
+            "ss" => &mut regs.ss,
+            "ds" => &mut regs.ds,
+            "es" => &mut regs.es,
+            "fs" => &mut regs.fs,
+            "gs" => &mut regs.gs,
+            "fs_base" => &mut regs.fs_base,
+            "gs_base" => &mut regs.gs_base,
+            _ => return None,
+        })
+    }
+
+    /// The kernel keeps the number in orig_rax, as rax is overwritten with the return value
+    fn syscall_number(regs: &Self::Registers) -> u64 {
+        regs.orig_rax
+    }
+
+    fn syscall_args(regs: &Self::Registers) -> [u64; 6] {
+        [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9]
+    }
+
+    fn syscall_return(regs: &Self::Registers) -> u64 {
+        regs.rax
+    }
+
+    /// On entry the kernel hasn't run the call yet, and rax holds -ENOSYS
+    fn is_syscall_entry(regs: &Self::Registers) -> bool {
+        regs.rax as i64 == -(libc::ENOSYS as i64)
+    }
+
+    /// The kernel reports int3 as SI_KERNEL; single steps come as TRAP_TRACE and debug registers
+    /// as TRAP_HWBKPT.
+    fn is_breakpoint_trap(si_code: i32) -> bool {
+        si_code == libc::SI_KERNEL
+    }
+
+    /// The debug address registers DR0-DR3, which DR7 turns on and DR6 reports hits in
+    const WATCHPOINT_SLOTS: usize = 4;
+
+    fn set_watchpoint(pid: Pid, slot: usize, addr: usize, len: usize) -> Result<(), nix::Error> {
+        let len_bits = match len {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            8 => 0b10,
+            _ => return Err(nix::Error::Sys(Errno::EINVAL)),
+        };
+        X86_64::poke_debugreg(pid, slot, addr as u64)?;
+        let mut dr7 = X86_64::peek_debugreg(pid, 7)?;
+        // clear this slot's condition and length bits, then set write-only (0b01) and the length
+        dr7 &= !(0b1111 << (16 + 4 * slot));
+        dr7 |= (0b01 | len_bits << 2) << (16 + 4 * slot);
+        // local enable
+        dr7 |= 1 << (2 * slot);
+        X86_64::poke_debugreg(pid, 7, dr7)
+    }
+
+    fn clear_watchpoint(pid: Pid, slot: usize) -> Result<(), nix::Error> {
+        let mut dr7 = X86_64::peek_debugreg(pid, 7)?;
+        dr7 &= !(0b1111 << (16 + 4 * slot));
+        dr7 &= !(0b11 << (2 * slot));
+        X86_64::poke_debugreg(pid, 7, dr7)
+    }
+
+    /// The hits are bits 0-3 of DR6, which is cleared: the processor only ever sets its bits, so
+    /// otherwise every later trap would look like it came from the same watchpoints.
+    fn take_watchpoint_hits(pid: Pid) -> Result<usize, nix::Error> {
+        let dr6 = X86_64::peek_debugreg(pid, 6)?;
+        X86_64::poke_debugreg(pid, 6, 0)?;
+        Ok((dr6 & 0b1111) as usize)
+    }
+}
+
+// Built on every host so that its encodings are tested everywhere, but only used on aarch64
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+mod aarch64 {
+    use super::Arch;
+    use nix::errno::Errno;
+    use nix::unistd::Pid;
+    use std::mem::size_of;
+
+    pub struct Aarch64;
+
+    /// struct user_pt_regs, what PTRACE_GETREGSET returns for NT_PRSTATUS on aarch64
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Aarch64Registers {
+        /// x0 to x30; x29 is the frame pointer and x30 the link register
+        pub regs: [u64; 31],
+        pub sp: u64,
+        pub pc: u64,
+        pub pstate: u64,
+    }
+
+    /// The register set PTRACE_GETREGSET and PTRACE_SETREGSET read and write the general-purpose
+    /// registers as (from <elf.h>)
+    const NT_PRSTATUS: usize = 1;
+
+    /// The names of x0 to x30, for named_registers
+    const X_NAMES: [&str; 31] = [
+        "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13",
+        "x14", "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26",
+        "x27", "x28", "x29", "x30",
+    ];
+
+    /// The si_code of a SIGTRAP raised by a brk instruction
+    pub(super) const TRAP_BRKPT: i32 = 1;
+
+    /// Encodes `brk #imm`, which raises a breakpoint exception with imm recorded in ESR_EL1.
+    pub(super) const fn brk(imm: u16) -> u32 {
+        0xd420_0000 | (imm as u32) << 5
+    }
+
+    /// `brk #0`, little-endian
+    const BRK_0: [u8; 4] = brk(0).to_le_bytes();
+
+    impl Aarch64 {
+        /// Reads or writes the registers with PTRACE_GETREGSET or PTRACE_SETREGSET: aarch64 has no
+        /// PTRACE_GETREGS.
+        fn regset(
+            pid: Pid,
+            request: libc::c_uint,
+            regs: *mut Aarch64Registers,
+        ) -> Result<(), nix::Error> {
+            let mut iov = libc::iovec {
+                iov_base: regs as *mut libc::c_void,
+                iov_len: size_of::<Aarch64Registers>(),
+            };
+            let res = unsafe {
+                libc::ptrace(
+                    request,
+                    pid.as_raw(),
+                    NT_PRSTATUS as *mut libc::c_void,
+                    &mut iov as *mut libc::iovec,
+                )
+            };
+            Errno::result(res).map(drop)
+        }
+    }
+
+    impl Arch for Aarch64 {
+        type Registers = Aarch64Registers;
+
+        const BREAKPOINT: &'static [u8] = &BRK_0;
+
+        /// brk traps before it executes, leaving pc at the brk itself
+        const TRAP_PC_OFFSET: usize = 0;
+
+        /// EM_AARCH64
+        const ELF_MACHINE: u16 = 183;
+
+        const PC_REGISTER: &'static str = "pc";
+        const SP_REGISTER: &'static str = "sp";
+        const FP_REGISTER: &'static str = "x29";
+
+        fn get_registers(pid: Pid) -> Result<Self::Registers, nix::Error> {
+            let mut regs = Aarch64Registers::default();
+            Aarch64::regset(pid, libc::PTRACE_GETREGSET, &mut regs)?;
+            Ok(regs)
+        }
+
+        fn set_registers(pid: Pid, regs: &Self::Registers) -> Result<(), nix::Error> {
+            let mut regs = *regs;
+            Aarch64::regset(pid, libc::PTRACE_SETREGSET, &mut regs)
+        }
+
+        fn pc(regs: &Self::Registers) -> usize {
+            regs.pc as usize
+        }
+
+        fn set_pc(regs: &mut Self::Registers, pc: usize) {
+            regs.pc = pc as u64;
+        }
+
+        fn stack_pointer(regs: &Self::Registers) -> usize {
+            regs.sp as usize
+        }
+
+        fn frame_pointer(regs: &Self::Registers) -> usize {
+            regs.regs[29] as usize
+        }
+
+        fn named_registers(regs: &Self::Registers) -> Vec<(&'static str, u64)> {
+            let mut named: Vec<(&'static str, u64)> = X_NAMES
+                .iter()
+                .copied()
+                .zip(regs.regs.iter().copied())
+                .collect();
+            named.push(("sp", regs.sp));
+            named.push(("pc", regs.pc));
+            named.push(("pstate", regs.pstate));
+            named
+        }
+
+        fn register_mut<'a>(regs: &'a mut Self::Registers, name: &str) -> Option<&'a mut u64> {
+            match name {
+                "sp" => Some(&mut regs.sp),
+                "pc" => Some(&mut regs.pc),
+                "pstate" => Some(&mut regs.pstate),
+                _ => {
+                    let index = X_NAMES.iter().position(|x| *x == name)?;
+                    Some(&mut regs.regs[index])
+                }
+            }
+        }
+
+        fn syscall_number(regs: &Self::Registers) -> u64 {
+            regs.regs[8]
+        }
+
+        fn syscall_args(regs: &Self::Registers) -> [u64; 6] {
+            let mut args = [0; 6];
+            args.copy_from_slice(&regs.regs[..6]);
+            args
+        }
+
+        fn syscall_return(regs: &Self::Registers) -> u64 {
+            regs.regs[0]
+        }
+
+        /// While reporting a syscall stop, the kernel sets x7 to 0 at the entry and 1 at the exit
+        /// (and puts it back before the program carries on)
+        fn is_syscall_entry(regs: &Self::Registers) -> bool {
+            regs.regs[7] == 0
+        }
+
+        fn is_breakpoint_trap(si_code: i32) -> bool {
+            si_code == TRAP_BRKPT
+        }
+
+        /// Watchpoints would be set through NT_ARM_HW_WATCH, but they trap before the write
+        /// happens, and would have to be stepped over; deet doesn't do that yet.
+        const WATCHPOINT_SLOTS: usize = 0;
+
+        fn set_watchpoint(_: Pid, _: usize, _: usize, _: usize) -> Result<(), nix::Error> {
+            Err(nix::Error::Sys(Errno::EOPNOTSUPP))
+        }
+
+        fn clear_watchpoint(_: Pid, _: usize) -> Result<(), nix::Error> {
+            Err(nix::Error::Sys(Errno::EOPNOTSUPP))
+        }
+
+        fn take_watchpoint_hits(_: Pid) -> Result<usize, nix::Error> {
+            Ok(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::aarch64::*;
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn aarch64_breakpoint_is_brk_0() {
+        assert_eq!(Aarch64::BREAKPOINT, &[0x00, 0x00, 0x20, 0xd4]);
+        assert_eq!(Aarch64::TRAP_PC_OFFSET, 0);
+    }
+
+    #[test]
+    fn aarch64_brk_encodes_its_immediate() {
+        // As assembled by `as`: brk #0x1 is d4200020, brk #0x3e8 is d4207d00
+        assert_eq!(brk(1), 0xd420_0020);
+        assert_eq!(brk(1000), 0xd420_7d00);
+        assert_eq!(brk(0xffff), 0xd43f_ffe0);
+    }
+
+    #[test]
+    fn aarch64_registers_match_user_pt_regs() {
+        // 31 general-purpose registers, then sp, pc and pstate
+        assert_eq!(size_of::<Aarch64Registers>(), 34 * 8);
+        let mut regs = Aarch64Registers::default();
+        regs.regs[29] = 0x7fff_f000;
+        Aarch64::set_pc(&mut regs, 0x40_0580);
+        assert_eq!(Aarch64::pc(&regs), 0x40_0580);
+        assert_eq!(regs.pc, 0x40_0580);
+        assert_eq!(Aarch64::frame_pointer(&regs), 0x7fff_f000);
+    }
+
+    #[test]
+    fn aarch64_registers_are_named_x0_to_x30_then_sp_pc_pstate() {
+        let mut regs = Aarch64Registers::default();
+        regs.regs[30] = 0x40_0600;
+        regs.sp = 0x7fff_e000;
+        let named = Aarch64::named_registers(&regs);
+        assert_eq!(named.len(), 34);
+        assert_eq!(named[30], ("x30", 0x40_0600));
+        assert_eq!(named[31], ("sp", 0x7fff_e000));
+        *Aarch64::register_mut(&mut regs, "x19").unwrap() = 7;
+        assert_eq!(regs.regs[19], 7);
+        assert!(Aarch64::register_mut(&mut regs, "x31").is_none());
+    }
+
+    #[test]
+    fn aarch64_breakpoint_traps_are_trap_brkpt() {
+        assert!(Aarch64::is_breakpoint_trap(TRAP_BRKPT));
+        assert!(!Aarch64::is_breakpoint_trap(libc::SI_KERNEL));
+    }
+
+    #[test]
+    fn aarch64_says_watchpoints_are_unsupported() {
+        assert_eq!(Aarch64::WATCHPOINT_SLOTS, 0);
+        assert!(Aarch64::set_watchpoint(Pid::from_raw(1), 0, 0x1000, 8).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn x86_64_breakpoint_is_int3() {
+        let mut decoder = iced_x86::Decoder::new(64, X86_64::BREAKPOINT, 0);
+        assert_eq!(decoder.decode().code(), iced_x86::Code::Int3);
+        // The trap leaves rip past the whole instruction
+        assert_eq!(X86_64::TRAP_PC_OFFSET, X86_64::BREAKPOINT.len());
+        assert!(X86_64::is_breakpoint_trap(libc::SI_KERNEL));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn x86_64_pc_and_frame_pointer_are_rip_and_rbp() {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rbp = 0x7ffd_e000;
+        X86_64::set_pc(&mut regs, 0x40_1136);
+        assert_eq!(regs.rip, 0x40_1136);
+        assert_eq!(X86_64::pc(&regs), 0x40_1136);
+        assert_eq!(X86_64::frame_pointer(&regs), 0x7ffd_e000);
+    }
+}
@@ -0,0 +1,191 @@
+//! Tab completion at the (deet) prompt. The first word completes to a command name; what comes
+//! after depends on the command: locations complete to function and source file names,
+//! expressions to the names of the variables in scope where the program is stopped, and `help`
+//! and `info` to what they take.
+
+use crate::debugger_command::{find_command, COMMANDS, INFO_COMMANDS};
+use crate::dwarf_data::DwarfData;
+use rustyline::completion::{Completer, FilenameCompleter};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::rc::Rc;
+
+pub struct DeetHelper {
+    debug_data: Rc<DwarfData>,
+    /// Where the program is stopped, if it is
+    rip: Option<usize>,
+    filenames: FilenameCompleter,
+}
+
+/// What the word being completed can be
+enum WordKind {
+    Command,
+    /// A function, or a source file to be followed by a line number
+    Location,
+    /// A variable in an expression
+    Variable,
+    /// A path to a file, as in gcore's argument
+    Path,
+    /// One of a fixed set of words, as after info
+    OneOf(Vec<&'static str>),
+}
+
+impl DeetHelper {
+    pub fn new(debug_data: Rc<DwarfData>) -> DeetHelper {
+        DeetHelper {
+            debug_data,
+            rip: None,
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// Sets where the program is stopped, which decides what variables are in scope.
+    pub fn set_rip(&mut self, rip: Option<usize>) {
+        self.rip = rip;
+    }
+
+    /// Works out what the word after the words already typed can be.
+    fn word_kind(&self, words: &[&str]) -> Option<WordKind> {
+        let command = match words.first() {
+            Some(word) => find_command(word).ok()?,
+            None => return Some(WordKind::Command),
+        };
+        Some(match (command.names[0], words.len()) {
+            ("help", 1) => {
+                WordKind::OneOf(COMMANDS.iter().map(|command| command.names[0]).collect())
+            }
+            ("info", 1) => WordKind::OneOf(INFO_COMMANDS.iter().map(|(name, _)| *name).collect()),
+            ("info", _) if words[1] == "line" => WordKind::Location,
+            ("info", 2) if words[1] == "proc" => WordKind::OneOf(vec!["mappings"]),
+            ("break", 1) | ("tbreak", 1) | ("until", 1) | ("advance", 1) => WordKind::Location,
+            ("break", _) | ("tbreak", _) if words[2..].first() == Some(&"if") => WordKind::Variable,
+            ("set", 1) => WordKind::OneOf(vec!["var", "environment", "cwd"]),
+            ("set", 2) if words[1] == "cwd" => WordKind::Path,
+            ("set", _) if words[1] != "var" && words[1] != "variable" => return None,
+            ("unset", 1) => WordKind::OneOf(vec!["environment"]),
+            ("show", 1) => WordKind::OneOf(vec!["environment", "cwd", "tty"]),
+            ("tty", 1) | ("source", 1) => WordKind::Path,
+            ("save", 1) => WordKind::OneOf(vec!["breakpoints", "session"]),
+            ("save", 2) => WordKind::Path,
+            ("print", _) | ("call", _) | ("set", _) | ("watch", _) | ("x", _) | ("find", _) => {
+                WordKind::Variable
+            }
+            ("gcore", 1) | ("run", _) => WordKind::Path,
+            _ => return None,
+        })
+    }
+
+    /// Returns the names of the functions we know of.
+    fn function_names(&self) -> BTreeSet<String> {
+        self.debug_data
+            .symbols()
+            .iter()
+            // C++ names come with their parameter types
+            .map(|symbol| symbol.name.split('(').next().unwrap().to_string())
+            .collect()
+    }
+
+    /// Returns the names of the source files we have debug info for, without their directories.
+    fn file_names(&self) -> BTreeSet<String> {
+        self.debug_data
+            .unit_names()
+            .filter_map(|name| Some(Path::new(name).file_name()?.to_str()?.to_string()))
+            .collect()
+    }
+
+    /// Returns the names of the variables in scope where the program is stopped: the locals and
+    /// parameters of the function it's in, and the globals of that function's file.
+    fn variable_names(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        let rip = match self.rip {
+            Some(rip) => rip,
+            None => return names,
+        };
+        if let Some(func) = self.debug_data.get_function_containing(rip) {
+            names.extend(func.variables.iter().map(|var| var.name.clone()));
+        }
+        if let Some(file) = self.debug_data.get_file_containing(rip) {
+            names.extend(file.global_variables.iter().map(|var| var.name.clone()));
+        }
+        names
+    }
+}
+
+impl Completer for DeetHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        // The word being completed starts after the last character that can't be in a name
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || "_:.-/".contains(c)))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let words: Vec<&str> = before[..start].split_whitespace().collect();
+        let kind = match self.word_kind(&words) {
+            Some(kind) => kind,
+            None => return Ok((pos, Vec::new())),
+        };
+        let word = &before[start..];
+        let candidates: Vec<String> = match kind {
+            WordKind::Command => COMMANDS
+                .iter()
+                .flat_map(|command| command.names.iter())
+                .map(|name| name.to_string())
+                .collect(),
+            WordKind::Location => {
+                let mut names = self.function_names();
+                names.extend(
+                    self.file_names()
+                        .into_iter()
+                        .map(|name| format!("{}:", name)),
+                );
+                names.into_iter().collect()
+            }
+            WordKind::Variable => {
+                // Complete the name at the end of the expression, unless it's a member's
+                let start = before
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map_or(0, |i| i + 1);
+                if before[..start].ends_with('.') || before[..start].ends_with("->") {
+                    return Ok((pos, Vec::new()));
+                }
+                let word = &before[start..];
+                let names = self.variable_names().into_iter();
+                return Ok((start, names.filter(|name| name.starts_with(word)).collect()));
+            }
+            WordKind::Path => {
+                let (start, pairs) = self.filenames.complete(line, pos, ctx)?;
+                return Ok((
+                    start,
+                    pairs.into_iter().map(|pair| pair.replacement).collect(),
+                ));
+            }
+            WordKind::OneOf(words) => words.iter().map(|word| word.to_string()).collect(),
+        };
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        matches.sort();
+        matches.dedup();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for DeetHelper {}
+
+impl Highlighter for DeetHelper {}
+
+impl Validator for DeetHelper {}
+
+impl Helper for DeetHelper {}
@@ -0,0 +1,628 @@
+//! Support for debugging ELF core dumps. A core file is an ELF file of type ET_CORE: its PT_NOTE
+//! segment holds the register state of each thread (NT_PRSTATUS) along with some information about
+//! the process, and each PT_LOAD segment holds the contents of one memory mapping. Read-only
+//! mappings of the executable are usually left out of the dump, so we fall back to the executable's
+//! own PT_LOAD segments for those addresses.
+//!
+//! We can also write core files in the same format (gcore), so a stopped inferior can be saved and
+//! loaded back later with --core.
+
+use crate::arch::{Arch, Native, Registers};
+use crate::proc_maps::{self, Mapping};
+use crate::process_image::ProcessImage;
+use memmap::Mmap;
+use nix::errno::Errno;
+use nix::sys::uio::{process_vm_readv, IoVec, RemoteIoVec};
+use nix::unistd::{getgid, getuid, Pid};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::{fmt, fs, ptr, slice};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const AT_ENTRY: u64 = 9;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PAGE_SIZE: usize = 4096;
+/// How much of a mapping gcore reads at once
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+// Offsets into struct elf_prstatus and struct elf_prpsinfo on 64-bit Linux (see linux/elfcore.h).
+// elf_prstatus ends with the registers, then an int (pr_fpvalid) padded to 8 bytes.
+const PRSTATUS_SIZE: usize = PRSTATUS_REGS_OFFSET + size_of::<Registers>() + 8;
+const PRSTATUS_CURSIG_OFFSET: usize = 12;
+const PRSTATUS_PID_OFFSET: usize = 32;
+const PRSTATUS_PPID_OFFSET: usize = 36;
+const PRSTATUS_PGRP_OFFSET: usize = 40;
+const PRSTATUS_SID_OFFSET: usize = 44;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_SNAME_OFFSET: usize = 1;
+const PRPSINFO_UID_OFFSET: usize = 16;
+const PRPSINFO_GID_OFFSET: usize = 20;
+const PRPSINFO_PID_OFFSET: usize = 24;
+const PRPSINFO_PPID_OFFSET: usize = 28;
+const PRPSINFO_PGRP_OFFSET: usize = 32;
+const PRPSINFO_SID_OFFSET: usize = 36;
+const PRPSINFO_FNAME_OFFSET: usize = 40;
+const PRPSINFO_FNAME_LEN: usize = 16;
+const PRPSINFO_PSARGS_OFFSET: usize = 56;
+const PRPSINFO_PSARGS_LEN: usize = 80;
+
+#[derive(Debug)]
+pub enum Error {
+    ErrorOpeningFile,
+    NotAnElfFile,
+    NotACoreFile,
+    UnsupportedArchitecture,
+    MissingPrstatus,
+    Truncated,
+    ErrorReadingProcess,
+    ErrorWritingFile,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ErrorOpeningFile => write!(f, "could not open file"),
+            Error::NotAnElfFile => write!(f, "not a 64-bit little-endian ELF file"),
+            Error::NotACoreFile => write!(f, "not a core file"),
+            Error::UnsupportedArchitecture => write!(f, "core file is for another architecture"),
+            Error::MissingPrstatus => write!(f, "core file has no NT_PRSTATUS note"),
+            Error::Truncated => write!(f, "file is truncated"),
+            Error::ErrorReadingProcess => write!(f, "could not read process information"),
+            Error::ErrorWritingFile => write!(f, "could not write file"),
+        }
+    }
+}
+
+/// A contiguous range of the process's memory, and where its contents are in the file it came
+/// from. Memory past the end of what's in the file (like .bss) reads as zeros.
+struct Segment {
+    vaddr: usize,
+    len: usize,
+    offset: usize,
+    file_len: usize,
+}
+
+impl Segment {
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.len
+    }
+}
+
+pub struct CoreFile {
+    pid: i32,
+    signal: i32,
+    command_line: String,
+    regs: Registers,
+    /// The core file, which the segments' contents are read from as they're needed. Dumps can be
+    /// far bigger than the memory we have to spare.
+    core: Mmap,
+    segments: Vec<Segment>,
+    /// The executable and its segments, if we could read it
+    exe: Option<(Mmap, Vec<Segment>)>,
+}
+
+impl CoreFile {
+    /// Parses the core file at core_path. target is the executable that produced the core; it is
+    /// used to fill in memory (mostly code) that the kernel did not write to the dump.
+    pub fn open(core_path: &str, target: &str) -> Result<CoreFile, Error> {
+        let core = map_file(core_path)?;
+        if read_u16(&core, 16)? != ET_CORE {
+            return Err(Error::NotACoreFile);
+        }
+        check_header(&core)?;
+
+        let mut prstatus: Option<&[u8]> = None;
+        let mut prpsinfo: Option<&[u8]> = None;
+        let mut entry: Option<u64> = None;
+        let mut segments = Vec::new();
+        for phdr in program_headers(&core)? {
+            match phdr.p_type {
+                PT_NOTE => {
+                    for (n_type, desc) in notes(phdr.file_data(&core)?)? {
+                        match n_type {
+                            // The first NT_PRSTATUS belongs to the thread that caused the dump
+                            NT_PRSTATUS if prstatus.is_none() => prstatus = Some(desc),
+                            NT_PRPSINFO => prpsinfo = Some(desc),
+                            NT_AUXV => entry = auxv_entry(desc),
+                            _ => {}
+                        }
+                    }
+                }
+                // Mappings that were not dumped have a file size of zero; skip them so that reads
+                // fall through to the executable instead.
+                PT_LOAD if phdr.p_filesz > 0 => {
+                    let vaddr = phdr.p_vaddr as usize;
+                    segments.push(phdr.segment(vaddr, phdr.p_filesz, &core)?);
+                }
+                _ => {}
+            }
+        }
+
+        let prstatus = prstatus.ok_or(Error::MissingPrstatus)?;
+        if prstatus.len() < PRSTATUS_REGS_OFFSET + size_of::<Registers>() {
+            return Err(Error::Truncated);
+        }
+        let regs = unsafe {
+            let regs = prstatus[PRSTATUS_REGS_OFFSET..].as_ptr();
+            ptr::read_unaligned(regs as *const Registers)
+        };
+        let command_line = match prpsinfo {
+            Some(info) => {
+                let psargs = c_string(info, PRPSINFO_PSARGS_OFFSET, PRPSINFO_PSARGS_LEN);
+                if psargs.is_empty() {
+                    c_string(info, PRPSINFO_FNAME_OFFSET, PRPSINFO_FNAME_LEN)
+                } else {
+                    psargs
+                }
+            }
+            None => String::new(),
+        };
+
+        Ok(CoreFile {
+            pid: read_u32(prstatus, PRSTATUS_PID_OFFSET)? as i32,
+            signal: read_u16(prstatus, PRSTATUS_CURSIG_OFFSET)? as i32,
+            command_line,
+            regs,
+            core,
+            segments,
+            exe: load_executable_segments(target, entry).ok(),
+        })
+    }
+
+    /// Returns the pid of the process that dumped core.
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Returns the number of the signal that terminated the process, or 0 if there was none (e.g.
+    /// the dump was taken from a live process).
+    pub fn signal(&self) -> i32 {
+        self.signal
+    }
+
+    /// Returns the command line of the process, as recorded in NT_PRPSINFO.
+    pub fn command_line(&self) -> &str {
+        &self.command_line
+    }
+
+    /// Finds the segment containing addr, along with the file its contents are in.
+    fn find_segment(&self, addr: usize) -> Option<(&Segment, &[u8])> {
+        let core = self
+            .segments
+            .iter()
+            .map(|segment| (segment, &self.core[..]));
+        let exe = self
+            .exe
+            .iter()
+            .flat_map(|(exe, segments)| segments.iter().map(move |segment| (segment, &exe[..])));
+        core.chain(exe).find(|(segment, _)| segment.contains(addr))
+    }
+}
+
+impl ProcessImage for CoreFile {
+    fn get_registers(&self) -> Result<Registers, nix::Error> {
+        Ok(self.regs)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        let bytes = self.read_bytes(addr, size_of::<u64>())?;
+        Ok(u64::from_le_bytes(bytes[..].try_into().unwrap()))
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let curr = addr + bytes.len();
+            let (segment, file) = self.find_segment(curr).ok_or(nix::Error::Sys(Errno::EIO))?;
+            let start = curr - segment.vaddr;
+            let end = std::cmp::min(segment.len, start + len - bytes.len());
+            // Whatever of that is in the file, then zeros
+            let in_file = segment.file_len.min(end).max(start);
+            bytes.extend_from_slice(&file[segment.offset + start..segment.offset + in_file]);
+            bytes.resize(bytes.len() + end - in_file, 0);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Writes a core file for the stopped process pid to path. regs are its current registers and
+/// signal is the signal it is stopped with. Every readable mapping listed in /proc/<pid>/maps is
+/// copied into the dump; mappings we are unable to read are recorded with no contents, the same
+/// way the kernel records mappings it leaves out. patches lists (address, byte) pairs to write
+/// over the process's memory in the dump, so that breakpoints don't end up in the saved code.
+pub fn write_core_file(
+    path: &str,
+    pid: Pid,
+    regs: &Registers,
+    signal: i32,
+    patches: &[(usize, u8)],
+) -> Result<(), Error> {
+    let maps = proc_maps::read_maps(pid).or(Err(Error::ErrorReadingProcess))?;
+    let auxv = fs::read(format!("/proc/{}/auxv", pid)).or(Err(Error::ErrorReadingProcess))?;
+    let stat =
+        fs::read_to_string(format!("/proc/{}/stat", pid)).or(Err(Error::ErrorReadingProcess))?;
+    let (comm, ppid, pgrp, sid) = parse_stat(&stat).ok_or(Error::ErrorReadingProcess)?;
+    let mut cmdline =
+        fs::read(format!("/proc/{}/cmdline", pid)).or(Err(Error::ErrorReadingProcess))?;
+    // Arguments are NUL-separated; ps-style output joins them with spaces
+    for byte in cmdline.iter_mut() {
+        if *byte == 0 {
+            *byte = b' ';
+        }
+    }
+
+    let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+    put_u16(&mut prstatus, PRSTATUS_CURSIG_OFFSET, signal as u16);
+    put_u32(&mut prstatus, PRSTATUS_PID_OFFSET, pid.as_raw() as u32);
+    put_u32(&mut prstatus, PRSTATUS_PPID_OFFSET, ppid);
+    put_u32(&mut prstatus, PRSTATUS_PGRP_OFFSET, pgrp);
+    put_u32(&mut prstatus, PRSTATUS_SID_OFFSET, sid);
+    let regs_bytes = unsafe {
+        slice::from_raw_parts(
+            regs as *const Registers as *const u8,
+            size_of::<Registers>(),
+        )
+    };
+    prstatus[PRSTATUS_REGS_OFFSET..PRSTATUS_REGS_OFFSET + regs_bytes.len()]
+        .copy_from_slice(regs_bytes);
+
+    let mut prpsinfo = vec![0u8; PRPSINFO_SIZE];
+    prpsinfo[PRPSINFO_SNAME_OFFSET] = b't';
+    put_u32(&mut prpsinfo, PRPSINFO_UID_OFFSET, getuid().as_raw());
+    put_u32(&mut prpsinfo, PRPSINFO_GID_OFFSET, getgid().as_raw());
+    put_u32(&mut prpsinfo, PRPSINFO_PID_OFFSET, pid.as_raw() as u32);
+    put_u32(&mut prpsinfo, PRPSINFO_PPID_OFFSET, ppid);
+    put_u32(&mut prpsinfo, PRPSINFO_PGRP_OFFSET, pgrp);
+    put_u32(&mut prpsinfo, PRPSINFO_SID_OFFSET, sid);
+    put_c_string(
+        &mut prpsinfo,
+        PRPSINFO_FNAME_OFFSET,
+        PRPSINFO_FNAME_LEN,
+        comm.as_bytes(),
+    );
+    put_c_string(
+        &mut prpsinfo,
+        PRPSINFO_PSARGS_OFFSET,
+        PRPSINFO_PSARGS_LEN,
+        &cmdline,
+    );
+
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRSTATUS, &prstatus);
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+    push_note(&mut notes, NT_AUXV, &auxv);
+
+    // Layout: ELF header, program headers (PT_NOTE first), the notes, then page-aligned segment
+    // contents. Every readable mapping is dumped whole, so the layout is known before reading any.
+    let phnum = 1 + maps.len();
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let mut data_offset = align_to_page(notes_offset + notes.len());
+    let dumped_len = |mapping: &Mapping| {
+        if mapping.is_readable() {
+            mapping.len()
+        } else {
+            0
+        }
+    };
+
+    let mut out = Vec::new();
+    push_elf_header(&mut out, phnum as u16);
+    push_program_header(
+        &mut out,
+        PT_NOTE,
+        0,
+        notes_offset as u64,
+        0,
+        notes.len() as u64,
+        0,
+        4,
+    );
+    for mapping in maps.iter() {
+        let mut flags = 0;
+        if mapping.is_readable() {
+            flags |= PF_R;
+        }
+        if mapping.is_writable() {
+            flags |= PF_W;
+        }
+        if mapping.is_executable() {
+            flags |= PF_X;
+        }
+        push_program_header(
+            &mut out,
+            PT_LOAD,
+            flags,
+            data_offset as u64,
+            mapping.start as u64,
+            dumped_len(mapping) as u64,
+            mapping.len() as u64,
+            PAGE_SIZE as u64,
+        );
+        data_offset += dumped_len(mapping);
+    }
+    out.extend_from_slice(&notes);
+    out.resize(align_to_page(out.len()), 0);
+
+    let file = File::create(path).or(Err(Error::ErrorWritingFile))?;
+    let mut file = BufWriter::new(file);
+    file.write_all(&out).or(Err(Error::ErrorWritingFile))?;
+    // Mappings can be far bigger than the memory we have to spare, so copy them a piece at a time
+    let mut chunk = vec![0u8; COPY_CHUNK_SIZE];
+    for mapping in maps.iter().filter(|mapping| mapping.is_readable()) {
+        let mut start = mapping.start;
+        while start < mapping.end {
+            let len = COPY_CHUNK_SIZE.min(mapping.end - start);
+            let data = &mut chunk[..len];
+            read_chunk(pid, start, data);
+            for (addr, byte) in patches {
+                if *addr >= start && *addr < start + len {
+                    data[*addr - start] = *byte;
+                }
+            }
+            file.write_all(data).or(Err(Error::ErrorWritingFile))?;
+            start += len;
+        }
+    }
+    file.flush().or(Err(Error::ErrorWritingFile))
+}
+
+/// Reads the inferior's memory at addr into data, leaving zeros wherever it can't be read (such
+/// as pages of a file mapping past the end of the file).
+fn read_chunk(pid: Pid, addr: usize, data: &mut [u8]) {
+    let len = data.len();
+    let remote = RemoteIoVec { base: addr, len };
+    let read = process_vm_readv(pid, &[IoVec::from_mut_slice(data)], &[remote]).unwrap_or(0);
+    for byte in data[read..].iter_mut() {
+        *byte = 0;
+    }
+}
+
+/// Extracts (comm, ppid, pgrp, session) from the contents of /proc/<pid>/stat.
+fn parse_stat(stat: &str) -> Option<(String, u32, u32, u32)> {
+    // comm is wrapped in parentheses and may contain spaces, so split around the last ')'
+    let comm_start = stat.find('(')? + 1;
+    let comm_end = stat.rfind(')')?;
+    let comm = stat.get(comm_start..comm_end)?.to_string();
+    let mut fields = stat.get(comm_end + 1..)?.split_whitespace();
+    let _state = fields.next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let pgrp = fields.next()?.parse().ok()?;
+    let sid = fields.next()?.parse().ok()?;
+    Some((comm, ppid, pgrp, sid))
+}
+
+fn push_elf_header(out: &mut Vec<u8>, phnum: u16) {
+    out.extend_from_slice(ELF_MAGIC);
+    out.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, 1 /* EV_CURRENT */]);
+    out.resize(16, 0);
+    out.extend_from_slice(&ET_CORE.to_le_bytes());
+    out.extend_from_slice(&Native::ELF_MACHINE.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&phnum.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shentsize
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_program_header(
+    out: &mut Vec<u8>,
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+) {
+    out.extend_from_slice(&p_type.to_le_bytes());
+    out.extend_from_slice(&p_flags.to_le_bytes());
+    out.extend_from_slice(&p_offset.to_le_bytes());
+    out.extend_from_slice(&p_vaddr.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes()); // p_paddr
+    out.extend_from_slice(&p_filesz.to_le_bytes());
+    out.extend_from_slice(&p_memsz.to_le_bytes());
+    out.extend_from_slice(&p_align.to_le_bytes());
+}
+
+fn push_note(out: &mut Vec<u8>, n_type: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&n_type.to_le_bytes());
+    out.extend_from_slice(name);
+    out.resize(align4(out.len()), 0);
+    out.extend_from_slice(desc);
+    out.resize(align4(out.len()), 0);
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, val: u16) {
+    buf[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+// copy a string into a fixed-size field, truncating it so that it stays NUL-terminated
+fn put_c_string(buf: &mut [u8], offset: usize, max_len: usize, val: &[u8]) {
+    let len = std::cmp::min(val.len(), max_len - 1);
+    buf[offset..offset + len].copy_from_slice(&val[..len]);
+}
+
+fn align_to_page(n: usize) -> usize {
+    (n + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Maps the executable at path and finds its PT_LOAD segments. If the executable is
+/// position-independent, entry (the AT_ENTRY value from the core's auxiliary vector) is used to
+/// find where it was loaded.
+fn load_executable_segments(path: &str, entry: Option<u64>) -> Result<(Mmap, Vec<Segment>), Error> {
+    let exe = map_file(path)?;
+    check_header(&exe)?;
+    let bias = match entry {
+        Some(entry) => entry.wrapping_sub(read_u64(&exe, 24)?),
+        None => 0,
+    };
+    let mut segments = Vec::new();
+    for phdr in program_headers(&exe)? {
+        if phdr.p_type == PT_LOAD {
+            let vaddr = phdr.p_vaddr.wrapping_add(bias) as usize;
+            // .bss and friends occupy memory but not space in the file
+            segments.push(phdr.segment(vaddr, phdr.p_memsz, &exe)?);
+        }
+    }
+    Ok((exe, segments))
+}
+
+fn map_file(path: &str) -> Result<Mmap, Error> {
+    let file = File::open(path).or(Err(Error::ErrorOpeningFile))?;
+    unsafe { Mmap::map(&file).or(Err(Error::ErrorOpeningFile)) }
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+}
+
+impl ProgramHeader {
+    fn file_data<'a>(&self, file: &'a [u8]) -> Result<&'a [u8], Error> {
+        let start = self.p_offset as usize;
+        let end = start
+            .checked_add(self.p_filesz as usize)
+            .ok_or(Error::Truncated)?;
+        file.get(start..end).ok_or(Error::Truncated)
+    }
+
+    /// Describes the first len bytes of the memory the segment occupies once it's loaded at vaddr,
+    /// checking that they fit in the address space and that its contents are in file.
+    fn segment(&self, vaddr: usize, len: u64, file: &[u8]) -> Result<Segment, Error> {
+        let len = len as usize;
+        vaddr.checked_add(len).ok_or(Error::Truncated)?;
+        Ok(Segment {
+            vaddr,
+            len,
+            offset: self.p_offset as usize,
+            file_len: std::cmp::min(self.file_data(file)?.len(), len),
+        })
+    }
+}
+
+/// Makes sure file is an ELF file that we know how to read.
+fn check_header(file: &[u8]) -> Result<(), Error> {
+    if file.len() < 64 || &file[..4] != ELF_MAGIC {
+        return Err(Error::NotAnElfFile);
+    }
+    if file[4] != ELFCLASS64 || file[5] != ELFDATA2LSB {
+        return Err(Error::NotAnElfFile);
+    }
+    if read_u16(file, 18)? != Native::ELF_MACHINE {
+        return Err(Error::UnsupportedArchitecture);
+    }
+    Ok(())
+}
+
+fn program_headers(file: &[u8]) -> Result<Vec<ProgramHeader>, Error> {
+    let phoff = read_u64(file, 32)? as usize;
+    let phentsize = read_u16(file, 54)? as usize;
+    let phnum = read_u16(file, 56)? as usize;
+    let mut headers = Vec::with_capacity(phnum);
+    for i in 0..phnum {
+        let base = i
+            .checked_mul(phentsize)
+            .and_then(|offset| offset.checked_add(phoff))
+            .ok_or(Error::Truncated)?;
+        headers.push(ProgramHeader {
+            p_type: read_u32(file, base)?,
+            p_offset: read_u64(file, base + 8)?,
+            p_vaddr: read_u64(file, base + 16)?,
+            p_filesz: read_u64(file, base + 32)?,
+            p_memsz: read_u64(file, base + 40)?,
+        });
+    }
+    Ok(headers)
+}
+
+/// Splits the contents of a PT_NOTE segment into (type, descriptor) pairs.
+fn notes(mut data: &[u8]) -> Result<Vec<(u32, &[u8])>, Error> {
+    let mut notes = Vec::new();
+    while data.len() >= 12 {
+        let namesz = read_u32(data, 0)? as usize;
+        let descsz = read_u32(data, 4)? as usize;
+        let n_type = read_u32(data, 8)?;
+        let desc_start = 12 + align4(namesz);
+        let desc = data
+            .get(desc_start..desc_start + descsz)
+            .ok_or(Error::Truncated)?;
+        notes.push((n_type, desc));
+        data = data.get(desc_start + align4(descsz)..).unwrap_or(&[]);
+    }
+    Ok(notes)
+}
+
+/// Finds AT_ENTRY in the contents of an NT_AUXV note.
+fn auxv_entry(auxv: &[u8]) -> Option<u64> {
+    auxv.chunks_exact(16)
+        .map(|pair| {
+            (
+                u64::from_le_bytes(pair[..8].try_into().unwrap()),
+                u64::from_le_bytes(pair[8..].try_into().unwrap()),
+            )
+        })
+        .find(|(key, _)| *key == AT_ENTRY)
+        .map(|(_, value)| value)
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn c_string(data: &[u8], offset: usize, max_len: usize) -> String {
+    let bytes = data.get(offset..offset + max_len).unwrap_or(&[]);
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len])
+        .trim_end()
+        .to_string()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    let end = offset.checked_add(2).ok_or(Error::Truncated)?;
+    let bytes = data.get(offset..end).ok_or(Error::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    let end = offset.checked_add(4).ok_or(Error::Truncated)?;
+    let bytes = data.get(offset..end).ok_or(Error::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, Error> {
+    let end = offset.checked_add(8).ok_or(Error::Truncated)?;
+    let bytes = data.get(offset..end).ok_or(Error::Truncated)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
@@ -1,25 +1,225 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::str::FromStr;
 
-use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::{Inferior, Status};
+use crate::arch::{Arch, Native, Registers};
+use crate::completion::DeetHelper;
+use crate::core_file::{self, CoreFile};
+use crate::debugger_command::{
+    find_command, DebuggerCommand, Redirections, COMMANDS, INFO_COMMANDS,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::disasm;
+use crate::dwarf_data::Line;
+use crate::dwarf_data::{
+    DwarfData, Error as DwarfError, Function, Location, Symbol, Type, TypeKind, Variable,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::expr::Evaluator;
+use crate::expr::{self, Expr, Place};
+use crate::heap::Heap;
+use crate::inferior::{self, Inferior, LaunchSettings, Status, Terminal};
+#[cfg(target_arch = "x86_64")]
+use crate::inferior::{Argument, CallOutcome};
+use crate::proc_maps::{self, Mapping};
+use crate::process_image::ProcessImage;
+#[cfg(target_arch = "x86_64")]
+use crate::record::{self, Recording};
+use crate::syscalls;
+use crate::tui::{self, Tui};
+#[cfg(target_arch = "x86_64")]
+use iced_x86::{
+    FlowControl, Formatter, Instruction, InstructionInfoFactory, IntelFormatter, OpAccess, OpKind,
+    Register,
+};
 use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use regex::Regex;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 #[derive(Clone)]
 pub struct Breakpoint {
+    pub addr: usize,
+    /// The bytes the breakpoint instruction replaced
+    pub orig_bytes: Vec<u8>,
+    /// Where it was asked for, e.g. `main` or `structs.c:20`, which is what `save breakpoints`
+    /// writes out so that it finds the same place again after the program is rebuilt
+    pub location: String,
+    /// Only stop here when this expression is true
+    pub condition: Option<String>,
+    /// Delete the breakpoint the first time it's hit (tbreak)
+    pub temporary: bool,
+}
+
+/// The file of commands deet runs when it starts, if there's one beside the program
+const INIT_FILE: &str = ".deetinit";
+
+pub struct Watchpoint {
+    expr: String,
     addr: usize,
-    pub orig_byte: u8,
+    entity_type: Type,
+    /// The value the watched memory had when we last stopped
+    old_value: Vec<u8>,
+    /// For a local variable, the frame it lives in: the watchpoint goes away when it returns
+    scope: Option<WatchScope>,
+}
+
+struct WatchScope {
+    /// The frame's canonical frame address, which the stack pointer is back at once it returns
+    frame: usize,
+    /// Where the frame returns to
+    return_addr: usize,
+}
+
+/// How many instructions past rip the TUI's disassembly pane shows
+const TUI_DISASSEMBLY_LEN: usize = 40;
+
+/// Functions bigger than this are disassembled for the TUI from rip instead of their start
+#[cfg(target_arch = "x86_64")]
+const MAX_DISASSEMBLY_BYTES: usize = 0x4000;
+
+/// How far up the stack to look for a return address into the program when the inferior is
+/// stopped in code we have no debugging information for (see Debugger::calling_line).
+const STACK_SCAN_WORDS: usize = 256;
+
+/// find reads memory a page at a time
+const FIND_BLOCK_SIZE: usize = 0x1000;
+
+/// si_code values saying why a SIGSEGV or SIGBUS was sent, from <asm-generic/siginfo.h>
+const SEGV_MAPERR: i32 = 1;
+const SEGV_ACCERR: i32 = 2;
+const SEGV_BNDERR: i32 = 3;
+const SEGV_PKUERR: i32 = 4;
+const BUS_ADRALN: i32 = 1;
+const BUS_ADRERR: i32 = 2;
+const BUS_OBJERR: i32 = 3;
+
+/// Nothing can be mapped below this address (the default vm.mmap_min_addr), so a fault there is
+/// almost always a null pointer, plus an offset, being dereferenced
+const NULL_PAGE_LIMIT: usize = 0x10000;
+
+/// The gap Linux keeps below the stack for it to grow into; a fault there means it overflowed
+const STACK_GUARD_GAP: usize = 0x100000;
+
+/// How many frames of the backtrace the crash summary shows
+const CRASH_BACKTRACE_LEN: usize = 16;
+
+/// An event that stops the inferior, set with `catch`.
+pub enum Catchpoint {
+    /// Entry to or return from one of these system calls, or any system call if it's empty
+    Syscall(Vec<u64>),
+    /// Receiving one of these signals, or any signal but SIGTRAP and SIGINT if it's empty
+    Signal(Vec<Signal>),
+    /// A call to fork or vfork
+    Fork,
+    /// A successful call to execve
+    Exec,
+    /// The process exiting, just before it goes away
+    Exit,
+}
+
+impl Catchpoint {
+    /// Returns the ptrace events we need to ask for to notice this catchpoint's event.
+    fn ptrace_events(&self) -> ptrace::Options {
+        match *self {
+            Catchpoint::Fork => {
+                ptrace::Options::PTRACE_O_TRACEFORK | ptrace::Options::PTRACE_O_TRACEVFORK
+            }
+            Catchpoint::Exec => ptrace::Options::PTRACE_O_TRACEEXEC,
+            Catchpoint::Exit => ptrace::Options::PTRACE_O_TRACEEXIT,
+            Catchpoint::Syscall(_) | Catchpoint::Signal(_) => ptrace::Options::empty(),
+        }
+    }
+
+    /// Returns the command that sets this catchpoint, for save.
+    fn command(&self) -> String {
+        match *self {
+            Catchpoint::Syscall(ref numbers) => {
+                let mut command = "catch syscall".to_string();
+                for number in numbers {
+                    match syscalls::name(*number) {
+                        Some(name) => command.push_str(&format!(" {}", name)),
+                        None => command.push_str(&format!(" {}", number)),
+                    }
+                }
+                command
+            }
+            Catchpoint::Signal(ref signals) => {
+                let mut command = "catch signal".to_string();
+                for signal in signals {
+                    command.push_str(&format!(" {}", signal));
+                }
+                command
+            }
+            Catchpoint::Fork => "catch fork".to_string(),
+            Catchpoint::Exec => "catch exec".to_string(),
+            Catchpoint::Exit => "catch exit".to_string(),
+        }
+    }
+
+    fn catches_signal(&self, signal: Signal) -> bool {
+        match *self {
+            Catchpoint::Signal(ref signals) if signals.is_empty() => {
+                signal != Signal::SIGTRAP && signal != Signal::SIGINT
+            }
+            Catchpoint::Signal(ref signals) => signals.contains(&signal),
+            _ => false,
+        }
+    }
+}
+
+/// The ways of stepping through the program a source line at a time
+#[derive(Clone, Copy, PartialEq)]
+enum LineStep {
+    /// Stop at the next line, including in functions that are called or inlined (`step`)
+    Step,
+    /// Stop at the next line, stepping over calls, inlined or not (`next`)
+    Next,
+    /// Like Next, but only stop at a line after the current one, so that jumping back to the top
+    /// of a loop keeps going until the loop is done (`until`)
+    Until,
+}
+
+/// An expression to print whenever the program stops, set with `display`
+struct Display {
+    id: usize,
+    expr: String,
+}
+
+/// A snapshot of the inferior: a forked copy of it that we keep stopped so we can come back to it.
+struct Checkpoint {
+    id: usize,
+    process: Inferior,
 }
 
 pub struct Debugger {
     target: String,
     history_path: String,
-    readline: Editor<()>,
+    readline: Editor<DeetHelper>,
     inferior: Option<Inferior>,
-    debug_data: DwarfData,
+    core: Option<CoreFile>,
+    /// Shared with the readline helper, which completes names from it
+    debug_data: Rc<DwarfData>,
     breakpoints: HashMap<usize, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    #[cfg(target_arch = "x86_64")]
+    recording: Option<Recording>,
+    checkpoints: Vec<Checkpoint>,
+    #[cfg(target_arch = "x86_64")]
+    next_checkpoint_id: usize,
+    displays: Vec<Display>,
+    next_display_id: usize,
+    catchpoints: Vec<Catchpoint>,
+    /// The system call the inferior was last seen entering, and how we described it then (its
+    /// arguments may not be readable any more by the time it returns)
+    syscall_in_progress: Option<(u64, String)>,
+    /// The full-screen interface, with --tui
+    tui: Option<Tui>,
+    /// The environment, directory and terminal to run the program with
+    launch: LaunchSettings,
 }
 
 impl Debugger {
@@ -33,14 +233,24 @@ impl Debugger {
                 std::process::exit(1);
             }
             Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
+                println!(
+                    "Could not load debugging symbols from {}: {:?}",
+                    target, err
+                );
+                match DwarfData::from_symbol_table(target) {
+                    Ok(val) => val,
+                    Err(_) => std::process::exit(1),
+                }
             }
         };
-        debug_data.print();
+        if !debug_data.has_debug_info() {
+            println!("(No debugging symbols found in {})", target);
+        }
 
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
+        let debug_data = Rc::new(debug_data);
+        let mut readline = Editor::<DeetHelper>::new();
+        readline.set_helper(Some(DeetHelper::new(debug_data.clone())));
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
 
@@ -49,61 +259,733 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data,
             breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            recording: None,
+            checkpoints: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            next_checkpoint_id: 1,
+            displays: Vec::new(),
+            next_display_id: 1,
+            catchpoints: Vec::new(),
+            syscall_in_progress: None,
+            tui: None,
+            launch: LaunchSettings::new(),
+        }
+    }
+
+    /// Gives the program a pseudo-terminal of its own each time it's run (--pty).
+    pub fn use_pty(&mut self) {
+        self.launch.terminal = Terminal::Pty;
+    }
+
+    /// Switches to the full-screen interface, if stdout is a terminal.
+    pub fn enable_tui(&mut self) {
+        self.tui = Tui::new();
+        if self.tui.is_none() {
+            println!("--tui needs a terminal");
+        }
+    }
+
+    /// Loads a core dump of the target so that its state can be inspected without running it.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match CoreFile::open(path, &self.target) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("Could not load core file {}: {}", path, err);
+                std::process::exit(1);
+            }
+        };
+        println!(
+            "Core was generated by `{}' (pid {}).",
+            core.command_line(),
+            core.pid()
+        );
+        if let Ok(signal) = Signal::try_from(core.signal()) {
+            println!("Program terminated with signal {}.", signal);
+        }
+        let rip = Native::pc(&core.get_registers().unwrap());
+        self.core = Some(core);
+        self.print_location(rip);
+    }
+
+    /// Returns the process whose state commands like backtrace and print should look at: the
+    /// running inferior if there is one, otherwise the loaded core file.
+    fn process_image(&self) -> Option<&dyn ProcessImage> {
+        match self.inferior {
+            Some(ref inferior) => Some(inferior),
+            None => self.core.as_ref().map(|core| core as &dyn ProcessImage),
+        }
+    }
+
+    /// Returns the running program's registers. If there isn't one, or ptrace can't read them
+    /// (because the process was killed behind our back, say), prints why and returns None.
+    fn current_registers(&self) -> Option<Registers> {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return None;
+            }
+        };
+        match inferior.get_registers() {
+            Ok(regs) => Some(regs),
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                None
+            }
+        }
+    }
+
+    fn print_location(&self, addr: usize) {
+        let func = match self.debug_data.get_function_from_addr(addr) {
+            None => return,
+            Some(val) => val,
+        };
+        match self.debug_data.get_line_from_addr(addr) {
+            Some(line) => println!("Stopped at {} ({})", func, line),
+            // Code we only have a symbol for
+            None => println!("Stopped at {} ({:#x})", func, addr),
         }
     }
 
+    /// Reports that the inferior has stopped at addr: where that is, and the displays.
+    fn print_stop(&self, addr: usize) {
+        self.print_location(addr);
+        self.show_displays();
+    }
+
+    /// Returns the (file, line number) that addr belongs to, for comparing where we are while
+    /// stepping.
+    fn source_line(&self, addr: usize) -> Option<(String, usize)> {
+        self.debug_data
+            .get_line_from_addr(addr)
+            .map(|line| (line.file, line.number))
+    }
+
     pub fn cont(&mut self) {
         if self.inferior.is_none() {
             println!("The program is not being run.");
             return;
         }
-        let result = self.inferior.as_mut().unwrap().cont(&self.breakpoints);
+        if self.is_recording() {
+            // Go one instruction at a time so that everything gets logged (or replayed)
+            while self.step_checked(true).is_some() {}
+            return;
+        }
+        let trace_syscalls = self
+            .catchpoints
+            .iter()
+            .any(|catchpoint| matches!(catchpoint, Catchpoint::Syscall(_)));
+        // Without catch syscall, stop when a watched local's frame returns. (With it, the
+        // watchpoint is deleted at the next stop after that instead.)
+        if !trace_syscalls && self.watchpoints.iter().any(|wp| wp.scope.is_some()) {
+            self.cont_in_watch_scope();
+            return;
+        }
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let result = if trace_syscalls {
+                inferior.cont_syscall(&self.breakpoints, None)
+            } else {
+                inferior.cont(&self.breakpoints)
+            };
+            match result {
+                Ok(Status::Stopped(Signal::SIGTRAP, rip))
+                    if self.breakpoints.contains_key(&rip) && !self.should_stop_at(rip) =>
+                {
+                    continue
+                }
+                Ok(Status::Stopped(Signal::SIGTRAP, _)) if self.unchanged_watchpoint_trap() => {
+                    continue
+                }
+                Ok(Status::Syscall(_)) => match self.check_syscall_catchpoints() {
+                    Ok(true) => return,
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                },
+                _ => {}
+            }
+            self.report_status(result, true);
+            return;
+        }
+    }
+
+    /// Describes the system call the inferior is stopped at the entry or exit of. Returns its
+    /// number, whether this is the entry, and the call as strace would print it (with the return
+    /// value, on exit).
+    fn decode_syscall_stop(&mut self) -> Result<(u64, bool, String), nix::Error> {
+        let inferior = self.inferior.as_ref().unwrap();
+        let regs = inferior.get_registers()?;
+        let number = Native::syscall_number(&regs);
+        if Native::is_syscall_entry(&regs) {
+            let call = syscalls::format_call(&regs, inferior);
+            self.syscall_in_progress = Some((number, call.clone()));
+            return Ok((number, true, call));
+        }
+        let call = match self.syscall_in_progress.take() {
+            Some((entered, call)) if entered == number => call,
+            _ => syscalls::format_call(&regs, inferior),
+        };
+        let ret = syscalls::format_return(number, Native::syscall_return(&regs));
+        Ok((number, false, format!("{} = {}", call, ret)))
+    }
+
+    /// Called when the inferior stops at a system call. If a catchpoint wants this one, says so
+    /// and returns true; otherwise returns false so that we carry on.
+    fn check_syscall_catchpoints(&mut self) -> Result<bool, nix::Error> {
+        let (number, entering, description) = self.decode_syscall_stop()?;
+        let caught = self.find_catchpoint(|catchpoint| match catchpoint {
+            Catchpoint::Syscall(numbers) => numbers.is_empty() || numbers.contains(&number),
+            _ => false,
+        });
+        let index = match caught {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        println!(
+            "Catchpoint {} ({} syscall {}), {}",
+            index,
+            if entering { "call to" } else { "returned from" },
+            syscalls::display_name(number),
+            description
+        );
+        self.check_watchpoints();
+        self.print_calling_location();
+        Ok(true)
+    }
+
+    /// Returns the number of the first catchpoint for which is_match returns true.
+    fn find_catchpoint<F: Fn(&Catchpoint) -> bool>(&self, is_match: F) -> Option<usize> {
+        self.catchpoints.iter().position(is_match)
+    }
+
+    /// Like print_stop, but if the inferior is stopped somewhere we have no line information for
+    /// (such as inside libc), shows the line in the program that called into it instead.
+    fn print_calling_location(&self) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let regs = match inferior.get_registers() {
+            Ok(regs) => regs,
+            Err(_) => return,
+        };
+        let pc = Native::pc(&regs);
+        if self.debug_data.get_line_from_addr(pc).is_some() {
+            self.print_location(pc);
+        } else if let Some((func, line)) = self.calling_line(inferior, &regs) {
+            println!("Called from {} ({})", func, line);
+        }
+        self.show_displays();
+    }
+
+    /// Explains a crash the program has just stopped with: the address it tried to access and
+    /// why it couldn't, what's mapped there, the instruction that tried, the line it's on and the
+    /// variables that line uses, and how the program got there.
+    fn report_crash(&self, signal: Signal) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let (info, regs) = match (ptrace::getsiginfo(inferior.pid()), inferior.get_registers()) {
+            (Ok(info), Ok(regs)) => (info, regs),
+            _ => return,
+        };
+        let rip = Native::pc(&regs);
+        println!("Crash summary:");
+        // A signal from kill or raise has nothing to do with what the program was doing
+        if info.si_code <= 0 {
+            println!(
+                "  Cause:       {} was sent to the program, not caused by a bad access",
+                signal
+            );
+        } else {
+            // For a general protection fault (e.g. a non-canonical address), the kernel doesn't
+            // say which address it was
+            let addr = if info.si_code == libc::SI_KERNEL {
+                None
+            } else {
+                Some(unsafe { info.si_addr() } as usize)
+            };
+            let access = self.crash_access(inferior, &regs, addr);
+            let what = match (access, addr) {
+                (_, Some(addr)) if addr == rip => format!("executing code at {:#x}", addr),
+                (Some((verb, 1, addr)), _) => format!("{} 1 byte at {:#x}", verb, addr),
+                (Some((verb, len, addr)), _) => format!("{} {} bytes at {:#x}", verb, len, addr),
+                (None, Some(addr)) => format!("accessing {:#x}", addr),
+                (None, None) => "accessing memory".to_string(),
+            };
+            println!(
+                "  Cause:       {}: {}",
+                what,
+                describe_fault_code(signal, info.si_code)
+            );
+            let mapping = match addr.or_else(|| access.map(|(_, _, addr)| addr)) {
+                Some(addr) => self.describe_fault_address(inferior, addr),
+                None => None,
+            };
+            if let Some(mapping) = mapping {
+                println!("  Address:     {}", mapping);
+            }
+        }
+        match self.crash_instruction(inferior, &regs) {
+            Some((text, registers)) => {
+                let description = self.describe_address(rip, "+");
+                if description.starts_with("0x") {
+                    println!("  Instruction: {:#x}: {}", rip, text);
+                } else {
+                    println!("  Instruction: {:#x} <{}>: {}", rip, description, text);
+                }
+                if !registers.is_empty() {
+                    println!("  Registers:   {}", registers.join(", "));
+                }
+            }
+            None => println!("  Instruction: can't be read at {:#x}", rip),
+        }
+        self.print_crash_source(inferior, &regs);
+        println!("  Backtrace:");
+        // A stack overflow leaves a great many frames; the innermost are the ones that matter
+        let mut frames = 0;
+        let result = inferior.walk_stack(&self.debug_data, &mut |line| {
+            if frames < CRASH_BACKTRACE_LEN {
+                println!("    {}", line);
+            }
+            frames += 1;
+        });
+        if frames > CRASH_BACKTRACE_LEN {
+            println!("    ({} more frames)", frames - CRASH_BACKTRACE_LEN);
+        }
+        if let Err(err) = result {
+            println!("    Error reading stack: {}", err);
+        }
+    }
+
+    /// Decodes the instruction the program crashed on to work out which of its memory accesses
+    /// faulted (see faulting_access).
+    #[cfg(target_arch = "x86_64")]
+    fn crash_access(
+        &self,
+        inferior: &Inferior,
+        regs: &Registers,
+        addr: Option<usize>,
+    ) -> Option<(&'static str, usize, usize)> {
+        let instruction =
+            disasm::decode_instruction(inferior, Native::pc(regs), &self.breakpoints)?;
+        faulting_access(&instruction, regs, addr)
+    }
+
+    /// Disassembles the instruction the program crashed on, and lists the registers its memory
+    /// operand is computed from (see address_registers).
+    #[cfg(target_arch = "x86_64")]
+    fn crash_instruction(
+        &self,
+        inferior: &Inferior,
+        regs: &Registers,
+    ) -> Option<(String, Vec<String>)> {
+        let instruction =
+            disasm::decode_instruction(inferior, Native::pc(regs), &self.breakpoints)?;
+        let mut text = String::new();
+        IntelFormatter::new().format(&instruction, &mut text);
+        Some((text, address_registers(&instruction, regs)))
+    }
+
+    /// Says which mapping a faulting address is in, or what its not being in one suggests.
+    fn describe_fault_address(&self, inferior: &Inferior, addr: usize) -> Option<String> {
+        let maps = proc_maps::read_maps(inferior.pid()).ok()?;
+        if let Some(mapping) = maps
+            .iter()
+            .find(|mapping| addr >= mapping.start && addr < mapping.end)
+        {
+            return Some(format!(
+                "{:#x} is in {} ({:#x}-{:#x}, {})",
+                addr,
+                mapping.path.as_deref().unwrap_or("anonymous memory"),
+                mapping.start,
+                mapping.end,
+                mapping.perms
+            ));
+        }
+        let below_stack = maps.iter().any(|mapping| {
+            mapping.path.as_deref() == Some("[stack]")
+                && addr < mapping.start
+                && mapping.start - addr <= STACK_GUARD_GAP
+        });
+        Some(if addr < NULL_PAGE_LIMIT {
+            format!(
+                "{:#x} isn't mapped; it's so close to 0 that a null pointer was probably \
+                 dereferenced",
+                addr
+            )
+        } else if below_stack {
+            format!(
+                "{:#x} is just below the stack, which has probably overflowed (runaway \
+                 recursion?)",
+                addr
+            )
+        } else {
+            format!("{:#x} isn't in any mapping", addr)
+        })
+    }
+
+    /// Prints the source line the crash happened on, and the values of the variables it uses.
+    /// If the program crashed in code we have no line information for, such as in libc, prints
+    /// the line it was called from instead.
+    fn print_crash_source(&self, inferior: &Inferior, regs: &Registers) {
+        let rip = Native::pc(regs);
+        let (func, line) = match self.calling_line(inferior, regs) {
+            Some(found) => found,
+            None => return,
+        };
+        // Compilers put code that comes from no line of source, like code they add themselves, on
+        // line 0
+        let text = std::fs::read_to_string(&line.file).ok().and_then(|source| {
+            let index = line.number.checked_sub(1)?;
+            Some(source.lines().nth(index)?.trim().to_string())
+        });
+        let location = match text {
+            Some(ref text) => format!("{}:{}: {}", line.file, line.number, text),
+            None => format!("{}:{}", line.file, line.number),
+        };
+        let in_frame = self.debug_data.get_line_from_addr(rip).is_some();
+        let text = text.unwrap_or_default();
+        if in_frame {
+            println!("  Source:      {}", location);
+        } else {
+            println!("  Source:      called from {} at {}", func, location);
+            // The variables can only be found in the frame the program stopped in
+            return;
+        }
+        let function = self.debug_data.get_function_containing(rip);
+        let file = self.debug_data.get_file_containing(rip);
+        let in_scope = |name: &str| {
+            let is_named = |var: &Variable| var.name == name;
+            matches!(function, Some(func) if func.variables.iter().any(is_named))
+                || matches!(file, Some(file) if file.global_variables.iter().any(is_named))
+        };
+        let mut names: Vec<&str> = Vec::new();
+        let identifier = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+        for name in identifier.find_iter(&text) {
+            if !names.contains(&name.as_str()) && in_scope(name.as_str()) {
+                names.push(name.as_str());
+            }
+        }
+        let values: Vec<String> = names
+            .iter()
+            .filter_map(|name| {
+                let (value, _) = expr::evaluate(name, &self.debug_data, inferior).ok()?;
+                let formatted = expr::format_value(&value, &self.debug_data, Some(inferior));
+                Some(format!("{} = {}", name, formatted))
+            })
+            .collect();
+        if !values.is_empty() {
+            println!("  Variables:   {}", values.join(", "));
+        }
+    }
+
+    /// An exec replaces the program in memory, breakpoints and all. If the new program is the
+    /// target again, put the breakpoints back; otherwise we have no symbols for it anyway.
+    fn reinsert_breakpoints_after_exec(&mut self, path: &str) {
+        let new_program = std::fs::canonicalize(path);
+        let same_program = match (new_program, std::fs::canonicalize(&self.target)) {
+            (Ok(new_program), Ok(target)) => new_program == target,
+            _ => false,
+        };
+        if same_program {
+            self.inferior
+                .as_mut()
+                .unwrap()
+                .insert_breakpoints(&self.breakpoints);
+        } else {
+            println!(
+                "The process is no longer running {}; its breakpoints are gone.",
+                self.target
+            );
+        }
+    }
+
+    /// Finds the function and source line that the code at the pc was reached from: the pc itself
+    /// if it is in code we have line information for, and otherwise (say, in a libc syscall
+    /// wrapper, which has no frame pointer to follow) the innermost return address on the stack
+    /// that points into such code.
+    fn calling_line(&self, image: &dyn ProcessImage, regs: &Registers) -> Option<(String, Line)> {
+        let rip = Native::pc(regs);
+        if let Some(line) = self.debug_data.get_line_from_addr(rip) {
+            return Some((self.debug_data.get_function_from_addr(rip)?, line));
+        }
+        for i in 0..STACK_SCAN_WORDS {
+            let word = match image.read_word(Native::stack_pointer(regs) + 8 * i) {
+                Ok(word) => word as usize,
+                Err(_) => return None,
+            };
+            // A function's own address is more likely a function pointer than a return address
+            let func = match self.debug_data.get_function_containing(word) {
+                Some(func) if func.address != word => func,
+                _ => continue,
+            };
+            // The call instruction is the one just before the return address
+            if let Some(line) = self.debug_data.get_line_from_addr(word - 1) {
+                return Some((func.name.clone(), line));
+            }
+        }
+        None
+    }
+
+    /// Sets a catchpoint: `catch syscall [name|number]...`, `catch signal [signal]...`, or
+    /// `catch fork|exec|exit`.
+    fn catch(&mut self, event: &str, args: &[String]) {
+        match event {
+            "syscall" => self.catch_syscall(args),
+            "signal" => self.catch_signal(args),
+            "fork" | "exec" | "exit" if !args.is_empty() => {
+                println!("The \"catch {}\" command takes no arguments.", event)
+            }
+            "fork" => self.add_catchpoint(Catchpoint::Fork, "fork".to_string()),
+            "exec" => self.add_catchpoint(Catchpoint::Exec, "exec".to_string()),
+            "exit" => self.add_catchpoint(Catchpoint::Exit, "exit".to_string()),
+            _ => println!(
+                "Unknown event \"{}\"; try syscall, signal, fork, exec or exit.",
+                event
+            ),
+        }
+    }
+
+    fn add_catchpoint(&mut self, catchpoint: Catchpoint, description: String) {
+        println!("Catchpoint {} ({})", self.catchpoints.len(), description);
+        self.catchpoints.push(catchpoint);
+        self.set_event_options();
+    }
+
+    /// Asks the inferior's kernel to stop it for the events our catchpoints are waiting for.
+    fn set_event_options(&mut self) {
+        let events = self
+            .catchpoints
+            .iter()
+            .fold(ptrace::Options::empty(), |events, c| {
+                events | c.ptrace_events()
+            });
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(e) = inferior.set_event_options(events) {
+                println!("Unable to set ptrace options: {}", e);
+            }
+        }
+    }
+
+    /// Sets a catchpoint on the given signals (by name, with or without SIG, or number), or all of
+    /// them.
+    fn catch_signal(&mut self, names: &[String]) {
+        let mut signals = Vec::new();
+        for name in names {
+            let signal = match name.parse::<i32>() {
+                Ok(number) => Signal::try_from(number).ok(),
+                Err(_) if name.starts_with("SIG") => Signal::from_str(name).ok(),
+                Err(_) => Signal::from_str(&format!("SIG{}", name)).ok(),
+            };
+            match signal {
+                Some(signal) => signals.push(signal),
+                None => {
+                    println!("Only signals 1-31 are valid as numeric signals.");
+                    return;
+                }
+            }
+        }
+        let description = match signals.len() {
+            0 => "standard signals".to_string(),
+            _ => {
+                let names: Vec<String> = signals.iter().map(|s| s.to_string()).collect();
+                format!("signal {}", names.join(" "))
+            }
+        };
+        self.add_catchpoint(Catchpoint::Signal(signals), description);
+    }
+
+    /// Sets a catchpoint on the named (or numbered) system calls, or on all of them.
+    fn catch_syscall(&mut self, names: &[String]) {
+        let mut numbers = Vec::new();
+        for name in names {
+            match name.parse().ok().or_else(|| syscalls::number(name)) {
+                Some(number) => numbers.push(number),
+                None => {
+                    println!("Unknown syscall name '{}'.", name);
+                    return;
+                }
+            }
+        }
+        let described: Vec<String> = numbers
+            .iter()
+            .map(|number| format!("'{}' [{}]", syscalls::display_name(*number), number))
+            .collect();
+        let description = match described.len() {
+            0 => "any syscall".to_string(),
+            1 => format!("syscall {}", described[0]),
+            _ => format!("syscalls {}", described.join(" ")),
+        };
+        self.add_catchpoint(Catchpoint::Syscall(numbers), description);
+    }
+
+    /// Runs the target to completion without stopping, printing every system call it makes to
+    /// stderr the way strace does, along with the source line that made it. Returns the exit code
+    /// deet should exit with.
+    pub fn strace(&mut self, args: &Vec<String>) -> i32 {
+        self.inferior = match Inferior::new(
+            &self.target,
+            args,
+            &self.launch,
+            &Redirections::default(),
+            &mut self.breakpoints,
+        ) {
+            Some(inferior) => Some(inferior),
+            None => {
+                eprintln!("Error starting subprocess");
+                return 1;
+            }
+        };
+        let mut signal = None;
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            match inferior.cont_syscall(&self.breakpoints, signal.take()) {
+                Ok(Status::Syscall(_)) => {
+                    let (number, entering, description) = match self.decode_syscall_stop() {
+                        Ok(decoded) => decoded,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            return 1;
+                        }
+                    };
+                    // Calls are printed when they return, except for exit and exit_group
+                    let description = match (entering, syscalls::never_returns(number)) {
+                        (true, true) => format!("{} = ?", description),
+                        (true, false) => continue,
+                        (false, _) => description,
+                    };
+                    let inferior = self.inferior.as_ref().unwrap();
+                    match inferior
+                        .get_registers()
+                        .ok()
+                        .and_then(|regs| self.calling_line(inferior, &regs))
+                    {
+                        Some((func, line)) => eprintln!("{}  <{} ({})>", description, func, line),
+                        None => eprintln!("{}", description),
+                    }
+                }
+                // Sent after a successful execve; the program didn't ask for it
+                Ok(Status::Stopped(Signal::SIGTRAP, _)) => {}
+                // We don't ask for ptrace events here
+                Ok(Status::Forked(_)) | Ok(Status::Execed) | Ok(Status::Exiting(_)) => {}
+                Ok(Status::Stopped(sig, _)) => {
+                    eprintln!("--- {} ---", sig);
+                    signal = Some(sig);
+                }
+                Ok(Status::Exited(exit_code)) => {
+                    eprintln!("+++ exited with {} +++", exit_code);
+                    self.inferior = None;
+                    return exit_code;
+                }
+                Ok(Status::Signaled(sig)) => {
+                    eprintln!("+++ killed by {} +++", sig);
+                    self.inferior = None;
+                    return 128 + sig as i32;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+            }
+        }
+    }
+
+    /// Returns whether we should stop at the breakpoint at addr, i.e. whether its condition (if
+    /// it has one) is true. A condition that can't be evaluated stops, so the user can fix it.
+    fn should_stop_at(&self, addr: usize) -> bool {
+        let condition = match self
+            .breakpoints
+            .get(&addr)
+            .and_then(|bp| bp.condition.as_ref())
+        {
+            Some(condition) => condition,
+            None => return true,
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        match expr::evaluate(condition, &self.debug_data, inferior) {
+            Ok((value, _)) => value.is_true(),
+            Err(err) => {
+                println!("Error in testing condition for breakpoint at {:#x}:", addr);
+                println!("{}", err);
+                true
+            }
+        }
+    }
+
+    /// Prints what happened to the inferior after it was resumed, and forgets about it if it
+    /// exited. announce_traps controls whether a SIGTRAP stop is announced as a signal; it isn't
+    /// when we were single-stepping, since every step ends with one.
+    fn report_status(&mut self, result: Result<Status, nix::Error>, announce_traps: bool) {
         match result {
             Ok(status) => match status {
                 Status::Exited(exit_code) => {
                     println!("Child exited (status {})", exit_code);
                     self.inferior = None;
+                    self.discard_recording();
                 }
                 Status::Signaled(signal) => {
                     println!("Child exited (signal {})", signal);
                     self.inferior = None;
+                    self.discard_recording();
                 }
                 Status::Stopped(signal, rip) => {
-                    println!("Child stopped (signal {})", signal);
-                    let line = match self.debug_data.get_line_from_addr(rip as usize) {
-                        None => return,
-                        Some(val) => val,
-                    };
-                    let func = match self.debug_data.get_function_from_addr(rip as usize) {
-                        None => return,
-                        Some(val) => val,
-                    };
-                    println!("Stopped at {} ({})", func, line);
-                    let pid = self.inferior.as_ref().unwrap().pid();
-                    let mut regs = ptrace::getregs(pid).unwrap();
-                    regs.rip -= 1;
-                    // if inferior is stopped at a breakpoint
-                    if self.breakpoints.contains_key(&(regs.rip as usize)) {
-                        // rewind the instruction pointer (subtract 1 byte)
-                        if ptrace::setregs(pid, regs).is_err() {
-                            println!("Unable to set rip");
-                            return;
-                        }
-
-                        let bp = self.breakpoints.get(&(regs.rip as usize)).unwrap();
-                        // restore instruction
-                        if self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .write_byte(bp.addr, bp.orig_byte)
-                            .is_err()
-                        {
-                            println!("Unable to restore instruction at {:#x}", bp.addr);
-                        }
+                    if let Some(index) = self.find_catchpoint(|c| c.catches_signal(signal)) {
+                        println!("Catchpoint {} (signal {})", index, signal);
+                        self.check_watchpoints();
+                        self.print_calling_location();
+                        return;
+                    }
+                    if signal != Signal::SIGTRAP || announce_traps {
+                        println!("Child stopped (signal {})", signal);
+                    }
+                    if signal == Signal::SIGTRAP {
+                        self.delete_if_temporary(rip);
                     }
+                    self.check_watchpoints();
+                    self.print_location(rip);
+                    if (signal == Signal::SIGSEGV || signal == Signal::SIGBUS)
+                        && !self.is_replaying()
+                    {
+                        self.report_crash(signal);
+                    }
+                    self.show_displays();
+                }
+                Status::Syscall(rip) => self.print_stop(rip),
+                // We only ask for these events when there's a catchpoint for them
+                Status::Forked(child) => {
+                    let index = self.find_catchpoint(|c| matches!(c, Catchpoint::Fork));
+                    println!(
+                        "Catchpoint {} (forked process {})",
+                        index.unwrap_or(0),
+                        child
+                    );
+                    self.check_watchpoints();
+                    self.print_calling_location();
+                }
+                Status::Execed => {
+                    let index = self.find_catchpoint(|c| matches!(c, Catchpoint::Exec));
+                    let pid = self.inferior.as_ref().unwrap().pid();
+                    let path = std::fs::read_link(format!("/proc/{}/exe", pid))
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default();
+                    println!("Catchpoint {} (exec'd {})", index.unwrap_or(0), path);
+                    self.reinsert_breakpoints_after_exec(&path);
+                    self.print_calling_location();
+                }
+                Status::Exiting(exit_status) => {
+                    let index = self.find_catchpoint(|c| matches!(c, Catchpoint::Exit));
+                    println!(
+                        "Catchpoint {} (exit), process {} about to exit ({})",
+                        index.unwrap_or(0),
+                        self.inferior.as_ref().unwrap().pid(),
+                        inferior::describe_exit(exit_status)
+                    );
+                    self.check_watchpoints();
+                    self.print_calling_location();
                 }
             },
             Err(e) => {
@@ -112,128 +994,2284 @@ impl Debugger {
         }
     }
 
-    pub fn run(&mut self) {
-        loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
-                    }
-                    if let Some(inferior) =
-                        Inferior::new(&self.target, &args, &mut self.breakpoints)
-                    {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // (milestone 1): make the inferior run
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        self.cont();
-                    } else {
-                        println!("Error starting subprocess");
-                    }
+    #[cfg(target_arch = "x86_64")]
+    fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn is_replaying(&self) -> bool {
+        match self.recording {
+            Some(ref recording) => recording.is_replaying(),
+            None => false,
+        }
+    }
+
+    /// Forgets the recorded history, e.g. because the process it belongs to is gone.
+    #[cfg(target_arch = "x86_64")]
+    fn discard_recording(&mut self) {
+        self.recording = None;
+    }
+
+    /// Executes one instruction forward. While replaying, the instruction's effects are re-applied
+    /// from the log; while recording, it is executed and logged; otherwise it is single-stepped.
+    #[cfg(target_arch = "x86_64")]
+    fn forward_step(&mut self) -> Result<Status, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        match self.recording {
+            Some(ref mut recording) if recording.is_replaying() => {
+                let record = recording.replay_forward(inferior)?.unwrap();
+                Ok(Status::Stopped(
+                    Signal::SIGTRAP,
+                    Native::pc(&record.regs_after),
+                ))
+            }
+            Some(ref mut recording) => recording.record_step(inferior, &self.breakpoints),
+            None => inferior.step_instruction(&self.breakpoints),
+        }
+    }
+
+    /// Undoes one instruction from the execution log. Returns None if there is no more history.
+    #[cfg(target_arch = "x86_64")]
+    fn backward_step(&mut self) -> Result<Option<Status>, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        let recording = self.recording.as_mut().unwrap();
+        Ok(recording
+            .step_back(inferior)?
+            .map(|record| Status::Stopped(Signal::SIGTRAP, Native::pc(&record.regs_before))))
+    }
+
+    /// Executes one instruction forwards or backwards, and reports anything that should end a
+    /// multi-instruction command: the program exiting or getting a signal, reaching a breakpoint,
+    /// a watched value changing, or running out of recorded history. Returns the new rip if
+    /// execution can carry on, or None if we stopped (in which case the reason has been printed).
+    fn step_checked(&mut self, forward: bool) -> Option<usize> {
+        let was_replaying = self.is_replaying();
+        let result = if forward {
+            self.forward_step().map(Some)
+        } else {
+            self.backward_step()
+        };
+        match result {
+            Ok(Some(Status::Stopped(Signal::SIGTRAP, rip))) => {
+                if self.breakpoints.contains_key(&rip) && self.should_stop_at(rip) {
+                    println!("Hit breakpoint at {:#x}", rip);
+                    self.delete_if_temporary(rip);
+                    self.print_stop(rip);
+                    return None;
                 }
-                DebuggerCommand::Continue => {
-                    self.cont();
+                if self.check_watchpoints() {
+                    self.print_stop(rip);
+                    return None;
                 }
-                DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!("The program is not being run.");
-                        continue;
-                    }
-                    self.inferior
-                        .as_mut()
-                        .unwrap()
-                        .print_backtrace(&self.debug_data)
-                        .unwrap();
-                }
-                DebuggerCommand::Break(addr) => {
-                    let mut bp_addr: Option<usize> = None;
-                    if addr.starts_with('*') {
-                        let res = parse_address(&addr[1..]);
-                        match res {
-                            Some(addr) => {
-                                bp_addr = Some(addr);
-                            }
-                            None => {
-                                println!("Please provide a valid address!");
-                            }
-                        }
-                    } else if let Ok(line) = addr.parse::<usize>() {
-                        // line number
-                        if let Some(addr) =
-                            self.debug_data.get_addr_for_line(None, line)
-                        {
-                            bp_addr = Some(addr);
-                        } else {
-                            println!("Please provide a valid address!");
-                        }
-                    } else if let Some(addr) = self
-                        .debug_data
-                        .get_addr_for_function(None, &addr)
-                    {
-                        // function name
-                        bp_addr = Some(addr);
-                    } else {
-                        println!("Please provide a valid address!");
-                    }
-                    if bp_addr.is_some() {
-                        println!(
-                            "Set breakpoint {} at {:#x}",
-                            self.breakpoints.len(),
-                            bp_addr.unwrap()
-                        );
-                        if self.inferior.is_some() {
-                            let res = self
-                                .inferior
-                                .as_mut()
-                                .unwrap()
-                                .write_byte(bp_addr.unwrap(), 0xcc);
-                            match res {
-                                Ok(orig_byte) => {
-                                    self.breakpoints.insert(
-                                        bp_addr.unwrap(),
-                                        Breakpoint {
-                                            addr: bp_addr.unwrap(),
-                                            orig_byte,
-                                        },
-                                    );
-                                }
-                                Err(_) => {
-                                    println!("Unable to set breakpoint at {:#x}", bp_addr.unwrap());
-                                }
-                            }
-                        } else {
-                            self.breakpoints.insert(
-                                bp_addr.unwrap(),
-                                Breakpoint {
-                                    addr: bp_addr.unwrap(),
-                                    orig_byte: 0,
-                                },
-                            );
-                        }
-                    }
+                if forward && was_replaying && !self.is_replaying() {
+                    println!("No more reverse-execution history.");
+                    self.print_stop(rip);
+                    return None;
                 }
-                DebuggerCommand::Quit => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
+                Some(rip)
+            }
+            Ok(Some(status)) => {
+                self.report_status(Ok(status), false);
+                None
+            }
+            Ok(None) => {
+                println!("No more reverse-execution history.");
+                if let Some(image) = self.process_image() {
+                    if let Ok(regs) = image.get_registers() {
+                        self.print_stop(Native::pc(&regs));
                     }
-                    return;
                 }
+                None
+            }
+            Err(e) => {
+                println!("{}", e);
+                None
             }
         }
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
+    fn step_instruction(&mut self, forward: bool) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        if !forward && !self.is_recording() {
+            println!("Target does not support reverse execution; use \"record\" first.");
+            return;
+        }
+        if let Some(rip) = self.step_checked(forward) {
+            self.print_stop(rip);
+        }
+    }
+
+    /// Steps forward a source line at a time (see LineStep for the variations).
+    fn step_line(&mut self, mode: LineStep) {
+        let rip = match self.current_registers() {
+            Some(regs) => Native::pc(&regs),
+            None => return,
+        };
+        let start_line = self.source_line(rip);
+        let start_function = self.function_start(rip);
+        let start_depth = self.debug_data.get_frames(rip).len();
+        let mut rip = rip;
         loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
-                Err(ReadlineError::Interrupted) => {
-                    // User pressed ctrl+c. We're going to ignore it
-                    println!("Type \"quit\" to exit");
+            let call_return_addr = self.call_return_address(rip);
+            rip = match self.step_checked(true) {
+                Some(rip) => rip,
+                None => return,
+            };
+            if let Some(return_addr) = call_return_addr {
+                if rip != return_addr {
+                    if mode == LineStep::Step && self.debug_data.get_line_from_addr(rip).is_some() {
+                        // Step into the callee, stopping once its prologue has set up the frame
+                        if let Some(body) = self.debug_data.get_prologue_end(rip) {
+                            rip = match self.run_until(&[body], &|_, _| true) {
+                                Some(rip) => rip,
+                                None => return,
+                            };
+                        }
+                        self.print_stop(rip);
+                        return;
+                    }
+                    // We're at the first instruction of the callee, and the return address is on
+                    // top of the stack. Run until it returns to this frame.
+                    let callee_regs = match self.current_registers() {
+                        Some(regs) => regs,
+                        None => return,
+                    };
+                    let callee_sp = Native::stack_pointer(&callee_regs);
+                    rip = match self.finish_call(return_addr, callee_sp) {
+                        Some(rip) => rip,
+                        None => return,
+                    };
+                }
+            }
+            let line = self.source_line(rip);
+            if line.is_none() || line == start_line {
+                continue;
+            }
+            // We returned to the caller
+            if self.function_start(rip) != start_function {
+                self.print_stop(rip);
+                return;
+            }
+            // Code inlined into this function belongs to a call, which next and until step over
+            if mode != LineStep::Step && self.debug_data.get_frames(rip).len() > start_depth {
+                continue;
+            }
+            let moved_forward = match (&line, &start_line) {
+                (Some((_, number)), Some((_, start_number))) => number > start_number,
+                _ => true,
+            };
+            if mode != LineStep::Until || moved_forward {
+                self.print_stop(rip);
+                return;
+            }
+        }
+    }
+
+    /// Returns the address of the start of the function containing addr.
+    fn function_start(&self, addr: usize) -> Option<usize> {
+        self.debug_data
+            .get_function_containing(addr)
+            .map(|func| func.address)
+    }
+
+    /// Runs until the function call whose stack frame starts at callee_sp returns to
+    /// return_addr. Returns the new rip, or None if something else stopped us first.
+    fn finish_call(&mut self, return_addr: usize, callee_sp: usize) -> Option<usize> {
+        // A recursive call could get to the return address early, so check the stack pointer
+        self.run_until(&[return_addr], &|_, regs| {
+            Native::stack_pointer(regs) > callee_sp
+        })
+    }
+
+    /// Runs until rip reaches one of the addresses in stops and accept (given the registers)
+    /// agrees that this is the time to stop there. The addresses get temporary breakpoints, which
+    /// are gone again afterwards. Returns the new rip, or None if something else stopped us first
+    /// (in which case the reason has been printed).
+    fn run_until(
+        &mut self,
+        stops: &[usize],
+        accept: &dyn Fn(&Debugger, &Registers) -> bool,
+    ) -> Option<usize> {
+        let is_target = |debugger: &Debugger, rip: usize| {
+            stops.contains(&rip)
+                && match debugger.inferior.as_ref().unwrap().get_registers() {
+                    Ok(regs) => accept(debugger, &regs),
+                    Err(_) => false,
+                }
+        };
+        if self.is_recording() {
+            loop {
+                let rip = self.step_checked(true)?;
+                if is_target(self, rip) {
+                    return Some(rip);
+                }
+            }
+        }
+        // Plant breakpoints at the addresses that don't have one already, and let the program run
+        // at full speed
+        let mut breakpoints = self.breakpoints.clone();
+        let mut planted = Vec::new();
+        for &addr in stops {
+            if breakpoints.contains_key(&addr) {
+                continue;
+            }
+            let orig_bytes = match self.inferior.as_mut().unwrap().plant_breakpoint(addr) {
+                Ok(orig_bytes) => orig_bytes,
+                Err(e) => {
+                    println!("Unable to set breakpoint at {:#x}: {}", addr, e);
+                    continue;
+                }
+            };
+            breakpoints.insert(
+                addr,
+                Breakpoint {
+                    addr,
+                    orig_bytes: orig_bytes.clone(),
+                    location: format!("*{:#x}", addr),
+                    condition: None,
+                    temporary: true,
+                },
+            );
+            planted.push((addr, orig_bytes));
+        }
+        let result = loop {
+            match self.inferior.as_mut().unwrap().cont(&breakpoints) {
+                Ok(Status::Stopped(Signal::SIGTRAP, rip)) if is_target(self, rip) => {
+                    break Ok(Status::Stopped(Signal::SIGTRAP, rip))
+                }
+                // One of ours, but not the time to stop (e.g. a deeper recursive call)
+                Ok(Status::Stopped(Signal::SIGTRAP, rip))
+                    if stops.contains(&rip) && !self.breakpoints.contains_key(&rip) =>
+                {
+                    continue
+                }
+                Ok(Status::Stopped(Signal::SIGTRAP, rip))
+                    if self.breakpoints.contains_key(&rip) && !self.should_stop_at(rip) =>
+                {
+                    continue
+                }
+                Ok(Status::Stopped(Signal::SIGTRAP, _)) if self.unchanged_watchpoint_trap() => {
+                    continue
+                }
+                other => break other,
+            }
+        };
+        if let Ok(ref status) = result {
+            if !matches!(
+                status,
+                Status::Exited(_) | Status::Signaled(_) | Status::Execed
+            ) {
+                let inferior = self.inferior.as_mut().unwrap();
+                for (addr, orig_bytes) in planted {
+                    if inferior.write_bytes(addr, &orig_bytes).is_err() {
+                        println!("Unable to restore instruction at {:#x}", addr);
+                    }
+                }
+            }
+        }
+        match result {
+            Ok(Status::Stopped(Signal::SIGTRAP, rip)) if is_target(self, rip) => Some(rip),
+            other => {
+                self.report_status(other, true);
+                None
+            }
+        }
+    }
+
+    /// Runs to location (the `advance` command). With current_frame_only (the `until` command),
+    /// only stops there in the current function call or one it returns to, not a deeper recursive
+    /// call. Either way, stops if the current function returns first.
+    fn run_to_location(&mut self, location: &str, current_frame_only: bool) {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let targets = self.resolve_location(location);
+        if targets.is_empty() {
+            println!("Please provide a valid address!");
+            return;
+        }
+        let regs = match self.current_registers() {
+            Some(regs) => regs,
+            None => return,
+        };
+        let frame = self.frame_address(&regs);
+        let mut stops = targets.clone();
+        if let Some(frame) = frame {
+            if let Ok(return_addr) = inferior.read_word(frame - 8) {
+                stops.push(return_addr as usize);
+            }
+        }
+        let rip = self.run_until(&stops, &|debugger, regs| {
+            let frame = match frame {
+                Some(frame) => frame,
+                None => return true,
+            };
+            if targets.contains(&Native::pc(regs)) {
+                // A deeper call's frame is further down the stack than ours
+                match debugger.frame_address(regs) {
+                    Some(target_frame) => !current_frame_only || target_frame >= frame,
+                    None => true,
+                }
+            } else {
+                Native::stack_pointer(regs) >= frame
+            }
+        });
+        if let Some(rip) = rip {
+            self.print_stop(rip);
+        }
+    }
+
+    /// Runs until the function the inferior is stopped in returns (the `finish` command), and
+    /// prints the value it returned.
+    fn finish(&mut self) {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let regs = match self.current_registers() {
+            Some(regs) => regs,
+            None => return,
+        };
+        let return_addr = self
+            .frame_address(&regs)
+            .and_then(|frame| Some((frame, inferior.read_word(frame - 8).ok()? as usize)));
+        let (frame, return_addr) = match return_addr {
+            Some(found) => found,
+            None => {
+                println!("\"finish\" not meaningful in the outermost frame.");
+                return;
+            }
+        };
+        let pc = Native::pc(&regs);
+        let return_type = self
+            .debug_data
+            .get_function_containing(pc)
+            .map(|func| func.return_type.clone());
+        if let Some(func) = self.debug_data.get_function_from_addr(pc) {
+            println!("Run till exit from {}", func);
+        }
+        let at_return = |_: &Debugger, regs: &Registers| Native::stack_pointer(regs) >= frame;
+        let rip = match self.run_until(&[return_addr], &at_return) {
+            Some(rip) => rip,
+            None => return,
+        };
+        self.print_location(rip);
+        // Without debugging information we don't know what (if anything) it returned
+        if let Some(return_type) = return_type.filter(Type::is_scalar) {
+            self.print_return_value(return_type);
+        }
+        self.show_displays();
+    }
+
+    /// Prints the value of type return_type that a function which has just returned left behind.
+    #[cfg(target_arch = "x86_64")]
+    fn print_return_value(&self, return_type: Type) {
+        let inferior = self.inferior.as_ref().unwrap();
+        if let Ok((rax, xmm0)) = inferior.return_value() {
+            let bits = if matches!(return_type.kind, TypeKind::Float) {
+                xmm0
+            } else {
+                rax
+            };
+            let value = expr::Value::from_u64(return_type, bits);
+            let formatted = expr::format_value(&value, &self.debug_data, Some(inferior));
+            println!("Value returned is {}", formatted);
+        }
+    }
+
+    /// If the instruction at rip is a call, returns the address it will return to.
+    #[cfg(target_arch = "x86_64")]
+    fn call_return_address(&self, rip: usize) -> Option<usize> {
+        let inferior = self.inferior.as_ref().unwrap();
+        let instruction = disasm::decode_instruction(inferior, rip, &self.breakpoints)?;
+        if is_call(&instruction) {
+            Some(instruction.next_ip() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the canonical frame address of the function the inferior is stopped in (see
+    /// disasm::frame_address).
+    #[cfg(target_arch = "x86_64")]
+    fn frame_address(&self, regs: &Registers) -> Option<usize> {
+        let func_start = self.function_start(Native::pc(regs))?;
+        disasm::frame_address(
+            self.inferior.as_ref().unwrap(),
+            func_start,
+            regs,
+            &self.breakpoints,
+        )
+    }
+
+    /// Steps backwards over one instruction. If that instruction was the return from a function
+    /// call, keep going backwards until we get to the call instruction. Returns the new rip, or
+    /// None if we stopped for some other reason.
+    #[cfg(target_arch = "x86_64")]
+    fn reverse_step_over(&mut self) -> Option<usize> {
+        // If we're about to undo a return, remember where it returned to. The matching call is the
+        // one that would return to the same address with the same stack pointer; counting calls
+        // and returns instead would be thrown off by tail calls and longjmp.
+        let returned_to = match self.recording.as_ref().unwrap().previous() {
+            Some(record) => match disasm::decode_instruction(
+                self.inferior.as_ref().unwrap(),
+                Native::pc(&record.regs_before),
+                &self.breakpoints,
+            ) {
+                Some(instruction) if instruction.flow_control() == FlowControl::Return => {
+                    let regs = &record.regs_after;
+                    Some((Native::pc(regs), Native::stack_pointer(regs)))
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        loop {
+            // If there's no history left, this reports it and returns None
+            let rip = self.step_checked(false)?;
+            let (return_addr, return_sp) = match returned_to {
+                Some(target) => target,
+                None => return Some(rip),
+            };
+            let regs = self.current_registers()?;
+            if Native::stack_pointer(&regs) == return_sp {
+                if let Some(instruction) = disasm::decode_instruction(
+                    self.inferior.as_ref().unwrap(),
+                    rip,
+                    &self.breakpoints,
+                ) {
+                    if is_call(&instruction) && instruction.next_ip() as usize == return_addr {
+                        return Some(rip);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Steps backwards to the beginning of the previous source line, stepping over function
+    /// calls.
+    #[cfg(target_arch = "x86_64")]
+    fn reverse_next(&mut self) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        if self.recording.is_none() {
+            println!("Target does not support reverse execution; use \"record\" first.");
+            return;
+        }
+        let rip = match self.current_registers() {
+            Some(regs) => Native::pc(&regs),
+            None => return,
+        };
+        let start_line = self.source_line(rip);
+        // First go back until we leave the current line...
+        let mut line = start_line.clone();
+        while line.is_none() || line == start_line {
+            let rip = match self.reverse_step_over() {
+                Some(rip) => rip,
+                None => return,
+            };
+            line = self.source_line(rip);
+        }
+        // ...then keep going until we reach the beginning of the line we're now in
+        while let Some(record) = self.recording.as_ref().unwrap().previous() {
+            let prev_rip = Native::pc(&record.regs_before);
+            // If the previous instruction was a return, undoing it takes us back to the call, which
+            // ends just before the address it returned to
+            let prev_addr = match disasm::decode_instruction(
+                self.inferior.as_ref().unwrap(),
+                prev_rip,
+                &self.breakpoints,
+            ) {
+                Some(instruction) if instruction.flow_control() == FlowControl::Return => {
+                    Native::pc(&record.regs_after) - 1
+                }
+                _ => prev_rip,
+            };
+            if self.source_line(prev_addr) != line {
+                break;
+            }
+            if self.reverse_step_over().is_none() {
+                return;
+            }
+        }
+        let rip = match self.current_registers() {
+            Some(regs) => Native::pc(&regs),
+            None => return,
+        };
+        self.print_stop(rip);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn reverse_continue(&mut self) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        if self.recording.is_none() {
+            println!("Target does not support reverse execution; use \"record\" first.");
+            return;
+        }
+        while self.step_checked(false).is_some() {}
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn start_recording(&mut self, limit: Option<usize>) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        if self.recording.is_some() {
+            println!("The process is already being recorded. Use \"record stop\" first.");
+            return;
+        }
+        let limit = limit.unwrap_or(record::DEFAULT_INSTRUCTION_LIMIT);
+        if limit == 0 {
+            println!("The instruction limit must be at least 1.");
+            return;
+        }
+        println!("Recording execution (up to {} instructions).", limit);
+        self.recording = Some(Recording::new(limit));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn stop_recording(&mut self) {
+        if self.recording.take().is_none() {
+            println!("No recording is currently active.");
+        } else {
+            println!("Process record is stopped and all execution logs are deleted.");
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn print_record_info(&self) {
+        match self.recording {
+            Some(ref recording) => {
+                println!(
+                    "Recorded {} instructions (limit {}).",
+                    recording.len(),
+                    recording.limit()
+                );
+                if recording.is_replaying() {
+                    println!(
+                        "Replaying: {} instructions behind the end of the log.",
+                        recording.replay_offset()
+                    );
+                }
+            }
+            None => println!("No recording is currently active."),
+        }
+    }
+
+    fn watch(&mut self, expr: &str) {
+        if Native::WATCHPOINT_SLOTS == 0 {
+            println!("Hardware watchpoints aren't supported on this architecture.");
+            return;
+        }
+        if self.watchpoints.len() == Native::WATCHPOINT_SLOTS {
+            println!(
+                "All {} hardware watchpoints are in use.",
+                Native::WATCHPOINT_SLOTS
+            );
+            return;
+        }
+        let mut scope = None;
+        let (addr, entity_type) = if let Some(addr) = expr.strip_prefix('*') {
+            match parse_address(addr) {
+                Some(addr) => (
+                    addr,
+                    Type::new(
+                        "unsigned long".to_string(),
+                        8,
+                        TypeKind::Int { signed: false },
+                    ),
+                ),
+                None => {
+                    println!("Please provide a valid address!");
+                    return;
+                }
+            }
+        } else {
+            // Locals are resolved relative to the current frame; globals work without a process
+            let regs = match self.process_image().map(|image| image.get_registers()) {
+                Some(Ok(regs)) => Some(regs),
+                _ => None,
+            };
+            let (rip, rbp) = match regs {
+                Some(regs) => (Native::pc(&regs), Native::frame_pointer(&regs)),
+                None => (0, 0),
+            };
+            match self.debug_data.get_variable(rip, expr) {
+                Some(var) => match var.location {
+                    Location::FramePointerOffset(_) if self.inferior.is_none() => {
+                        println!(
+                            "Cannot watch local variable {} without a running process.",
+                            expr
+                        );
+                        return;
+                    }
+                    Location::FramePointerOffset(_) => {
+                        scope = regs.and_then(|regs| self.watch_scope(&regs));
+                        (var.location.address(rbp), var.entity_type.clone())
+                    }
+                    _ => (var.location.address(rbp), var.entity_type.clone()),
+                },
+                None => {
+                    println!("No symbol \"{}\" in current context.", expr);
+                    return;
+                }
+            }
+        };
+        let len = entity_type.size;
+        if ![1, 2, 4, 8].contains(&len) || addr % len != 0 {
+            println!(
+                "Cannot watch {}: hardware watchpoints need an aligned 1, 2, 4 or 8 byte value.",
+                expr
+            );
+            return;
+        }
+        let slot = self.watchpoints.len();
+        let mut old_value = Vec::new();
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(e) = inferior.set_hw_watchpoint(slot, addr, len) {
+                println!("Unable to set watchpoint at {:#x}: {}", addr, e);
+                return;
+            }
+            old_value = inferior.read_bytes(addr, len).unwrap_or_default();
+        }
+        println!("Watchpoint {}: {} ({:#x})", slot, expr, addr);
+        self.watchpoints.push(Watchpoint {
+            expr: expr.to_string(),
+            addr,
+            entity_type,
+            old_value,
+            scope,
+        });
+    }
+
+    /// The frame the inferior is stopped in, for watching one of its local variables.
+    fn watch_scope(&self, regs: &Registers) -> Option<WatchScope> {
+        let frame = self.frame_address(regs)?;
+        let return_addr = self.inferior.as_ref()?.read_word(frame - 8).ok()? as usize;
+        Some(WatchScope { frame, return_addr })
+    }
+
+    /// Whether the frame a watchpoint's variable lives in has returned.
+    fn left_scope(wp: &Watchpoint, regs: &Registers) -> bool {
+        matches!(wp.scope, Some(ref scope) if Native::stack_pointer(regs) >= scope.frame)
+    }
+
+    /// Deletes the watchpoints on local variables, saying so, e.g. once the program is started
+    /// again and their frames are gone.
+    fn delete_scoped_watchpoints(&mut self) {
+        while let Some(slot) = self.watchpoints.iter().position(|wp| wp.scope.is_some()) {
+            self.delete_watchpoint(slot);
+        }
+    }
+
+    /// Deletes a watchpoint whose variable went out of scope. The ones after it move down a slot,
+    /// as their numbers are the debug registers they're in.
+    fn delete_watchpoint(&mut self, slot: usize) {
+        println!(
+            "\nWatchpoint {} deleted because the program has left the block in\n\
+             which its expression is valid.\n",
+            slot
+        );
+        self.watchpoints.remove(slot);
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        for (slot, wp) in self.watchpoints.iter().enumerate().skip(slot) {
+            let _ = inferior.set_hw_watchpoint(slot, wp.addr, wp.entity_type.size);
+        }
+        let _ = inferior.clear_hw_watchpoint(self.watchpoints.len());
+    }
+
+    /// Called when the inferior stops with a SIGTRAP that isn't one of our breakpoints. Returns
+    /// true if it was only for writes that left the watched values as they were, which (like
+    /// gdb) we don't stop for.
+    fn unchanged_watchpoint_trap(&mut self) -> bool {
+        let inferior = self.inferior.as_mut().unwrap();
+        let hits = match inferior.take_watchpoint_hits() {
+            Ok(hits) if hits != 0 => hits,
+            _ => return false,
+        };
+        let regs = match inferior.get_registers() {
+            Ok(regs) => regs,
+            Err(_) => return false,
+        };
+        self.watchpoints.iter().enumerate().all(|(slot, wp)| {
+            let unchanged = matches!(
+                inferior.read_bytes(wp.addr, wp.entity_type.size),
+                Ok(ref value) if *value == wp.old_value
+            );
+            hits & (1 << slot) == 0 || (unchanged && !Debugger::left_scope(wp, &regs))
+        })
+    }
+
+    /// Runs until something stops the program or the frame of a watched local variable returns,
+    /// which `continue` does instead of just letting the program go when there is one.
+    fn cont_in_watch_scope(&mut self) {
+        let returns: Vec<usize> = self
+            .watchpoints
+            .iter()
+            .filter_map(|wp| wp.scope.as_ref().map(|scope| scope.return_addr))
+            .collect();
+        let rip = self.run_until(&returns, &|debugger, regs| {
+            debugger
+                .watchpoints
+                .iter()
+                .any(|wp| Debugger::left_scope(wp, regs))
+        });
+        if let Some(rip) = rip {
+            self.check_watchpoints();
+            self.print_stop(rip);
+        }
+    }
+
+    /// Programs the watchpoints into a freshly started inferior.
+    fn install_watchpoints(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        for (slot, wp) in self.watchpoints.iter_mut().enumerate() {
+            if let Err(e) = inferior.set_hw_watchpoint(slot, wp.addr, wp.entity_type.size) {
+                println!("Unable to set watchpoint at {:#x}: {}", wp.addr, e);
+            }
+            wp.old_value = inferior
+                .read_bytes(wp.addr, wp.entity_type.size)
+                .unwrap_or_default();
+        }
+    }
+
+    /// Compares each watched value to what it was at the last stop, printing the ones that
+    /// changed. Returns true if any did.
+    fn check_watchpoints(&mut self) -> bool {
+        let inferior = match self.inferior {
+            Some(ref mut inferior) => inferior,
+            None => return false,
+        };
+        // Whatever DR6 says has been dealt with now
+        let _ = inferior.take_watchpoint_hits();
+        let mut triggered = false;
+        if let Ok(regs) = inferior.get_registers() {
+            while let Some(slot) = self
+                .watchpoints
+                .iter()
+                .position(|wp| Debugger::left_scope(wp, &regs))
+            {
+                self.delete_watchpoint(slot);
+                triggered = true;
+            }
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        let debug_data = &self.debug_data;
+        for (slot, wp) in self.watchpoints.iter_mut().enumerate() {
+            let new_value = match inferior.read_bytes(wp.addr, wp.entity_type.size) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            if new_value != wp.old_value {
+                println!("\nWatchpoint {}: {}\n", slot, wp.expr);
+                let format = |bytes: &Vec<u8>| {
+                    let value = expr::Value {
+                        ty: wp.entity_type.clone(),
+                        bytes: bytes.clone(),
+                        place: Some(Place::Memory(wp.addr)),
+                    };
+                    expr::format_value(&value, debug_data, Some(inferior))
+                };
+                println!("Old value = {}", format(&wp.old_value));
+                println!("New value = {}", format(&new_value));
+                wp.old_value = new_value;
+                triggered = true;
+            }
+        }
+        triggered
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            self.refresh_tui();
+            let cmd = self.get_next_command();
+            if !self.execute(cmd) {
+                return;
+            }
+        }
+    }
+
+    /// Runs the commands in the .deetinit beside the program, if there is one. Returns false if
+    /// one of them was quit.
+    pub fn source_init_file(&mut self) -> bool {
+        let path = self.init_file_path();
+        if !std::path::Path::new(&path).exists() {
+            return true;
+        }
+        self.source(&path)
+    }
+
+    /// Runs the commands in a script and then quits, killing the program if it is still running,
+    /// for `deet --batch`.
+    pub fn run_batch(&mut self, path: &str) {
+        if self.source(path) {
+            self.execute(DebuggerCommand::Quit);
+        }
+    }
+
+    fn init_file_path(&self) -> String {
+        let dir = std::path::Path::new(&self.target)
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new(""));
+        dir.join(INIT_FILE).to_string_lossy().to_string()
+    }
+
+    /// Runs the commands in a file, as if they were typed. Returns false if one of them was quit.
+    fn source(&mut self, path: &str) -> bool {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                println!("{}: {}.", path, err);
+                return true;
+            }
+        };
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match DebuggerCommand::from_line(line) {
+                Ok(cmd) => {
+                    if !self.execute(cmd) {
+                        return false;
+                    }
+                }
+                Err(err) => println!("{}:{}: {}", path, i + 1, err),
+            }
+        }
+        true
+    }
+
+    /// Writes commands that set the breakpoints, watchpoints and catchpoints again to a file,
+    /// along with the launch settings and displays if what is "session". A session goes to the
+    /// .deetinit beside the program if no file is given.
+    fn save(&self, what: &str, path: Option<String>) {
+        let path = path.unwrap_or_else(|| self.init_file_path());
+        let mut commands = Vec::new();
+        if what == "session" {
+            commands.extend(self.settings_commands());
+        }
+        let mut breakpoints: Vec<&Breakpoint> = self.breakpoints.values().collect();
+        breakpoints.sort_by_key(|bp| bp.addr);
+        for bp in breakpoints {
+            let mut command = format!(
+                "{} {}",
+                if bp.temporary { "tbreak" } else { "break" },
+                quote(&self.symbolic_location(bp))
+            );
+            if let Some(ref condition) = bp.condition {
+                command.push_str(&format!(" if {}", condition));
+            }
+            // A function that was inlined has a breakpoint wherever it was
+            if !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+        for wp in &self.watchpoints {
+            commands.push(format!("watch {}", wp.expr));
+        }
+        if what == "session" {
+            for display in &self.displays {
+                commands.push(format!("display {}", display.expr));
+            }
+        }
+        commands.extend(self.catchpoints.iter().map(Catchpoint::command));
+        let contents: String = commands.iter().map(|c| format!("{}\n", c)).collect();
+        match std::fs::write(&path, contents) {
+            Ok(()) => println!("Saved to file '{}'.", path),
+            Err(err) => println!("{}: {}.", path, err),
+        }
+    }
+
+    /// Describes where a breakpoint is without depending on addresses, where we can, so that the
+    /// description still works after the program is rebuilt: a bare line number (which is in
+    /// whatever file was current) becomes file:line, and the address a function starts at becomes
+    /// the function's name.
+    fn symbolic_location(&self, bp: &Breakpoint) -> String {
+        if bp.location.parse::<usize>().is_ok() {
+            if let Some(line) = self.debug_data.get_line_from_addr(bp.addr) {
+                let file = std::path::Path::new(&line.file)
+                    .file_name()
+                    .map_or(line.file.clone(), |name| name.to_string_lossy().to_string());
+                return format!("{}:{}", file, line.number);
+            }
+        } else if bp.location.starts_with('*') {
+            if let Some(symbol) = self.debug_data.get_symbol_containing(bp.addr) {
+                if symbol.address == bp.addr {
+                    return symbol.name.clone();
+                }
+            }
+        }
+        bp.location.clone()
+    }
+
+    /// Returns the commands that make the settings made with set environment, set cwd and tty.
+    fn settings_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        let inherited: HashMap<String, String> = std::env::vars().collect();
+        let mut unset: Vec<&String> = inherited
+            .keys()
+            .filter(|name| !self.launch.environment.contains_key(*name))
+            .collect();
+        unset.sort();
+        for name in unset {
+            commands.push(format!("unset environment {}", quote(name)));
+        }
+        for (name, value) in &self.launch.environment {
+            if inherited.get(name) != Some(value) {
+                commands.push(format!(
+                    "set environment {}",
+                    quote(&format!("{}={}", name, value))
+                ));
+            }
+        }
+        if let Some(ref cwd) = self.launch.cwd {
+            commands.push(format!("set cwd {}", quote(cwd)));
+        }
+        match self.launch.terminal {
+            Terminal::Shared => {}
+            Terminal::Path(ref path) => commands.push(format!("tty {}", quote(path))),
+            Terminal::Pty => commands.push("tty pty".to_string()),
+        }
+        commands
+    }
+
+    /// Carries out a command, whether typed at the prompt or read from a file. Returns false if
+    /// it was quit.
+    fn execute(&mut self, cmd: DebuggerCommand) -> bool {
+        // After any of these, the program is somewhere new (or gone)
+        match cmd {
+            DebuggerCommand::Run(args, redirections) => {
+                if self.inferior.is_some() {
+                    self.inferior.as_mut().unwrap().kill().unwrap();
+                }
+                // A live process replaces whatever core file we were looking at
+                self.core = None;
+                self.discard_recording();
+                self.delete_checkpoints();
+                if let Some(inferior) = Inferior::new(
+                    &self.target,
+                    &args,
+                    &self.launch,
+                    &redirections,
+                    &mut self.breakpoints,
+                ) {
+                    // Create the inferior
+                    self.inferior = Some(inferior);
+                    self.set_event_options();
+                    // The frames the watched locals were in are gone with the old process
+                    self.delete_scoped_watchpoints();
+                    self.install_watchpoints();
+                    // (milestone 1): make the inferior run
+                    // You may use self.inferior.as_mut().unwrap() to get a mutable reference
+                    // to the Inferior object
+                    self.cont();
+                } else {
+                    println!("Error starting subprocess");
+                }
+            }
+            DebuggerCommand::Continue => {
+                self.cont();
+            }
+            DebuggerCommand::Backtrace => match self.process_image() {
+                Some(image) => {
+                    if let Err(err) = image.print_backtrace(&self.debug_data) {
+                        println!("Error reading stack: {}", err);
+                    }
+                }
+                None => println!("The program is not being run."),
+            },
+            DebuggerCommand::Print(expression) => self.print_expression(&expression),
+            DebuggerCommand::Call(expression) => self.call_function(&expression),
+            DebuggerCommand::SetVar(expression) => {
+                self.evaluate(&expression);
+            }
+            DebuggerCommand::Examine(count, addr) => self.examine_memory(count, &addr),
+            DebuggerCommand::Find(size, args) => self.find_in_memory(size, &args),
+            DebuggerCommand::Info(what, arg) => match what.as_str() {
+                "r" | "registers" => self.print_registers(),
+                "record" => self.print_record_info(),
+                "checkpoints" => self.print_checkpoints(),
+                "display" => self.print_display_info(),
+                "functions" => self.print_functions(arg.as_deref()),
+                "variables" => self.print_variables(arg.as_deref()),
+                "types" => self.print_types(arg.as_deref()),
+                "line" => self.print_line_info(arg.as_deref()),
+                "source" => self.print_source_info(),
+                "symbol" => match arg.as_deref().and_then(parse_address) {
+                    Some(addr) => self.print_symbol_info(addr),
+                    None => println!("Argument required (address)."),
+                },
+                "proc" => match arg.as_deref() {
+                    Some("mappings") => self.print_mappings(),
+                    _ => println!("Usage: info proc mappings"),
+                },
+                "sharedlibrary" => self.print_shared_libraries(),
+                "heap" => self.print_heap(),
+                "bins" => self.print_bins(),
+                _ => println!("Unrecognized info command."),
+            },
+            DebuggerCommand::GenerateCore(path) => self.generate_core(path),
+            DebuggerCommand::StepInstruction => self.step_instruction(true),
+            DebuggerCommand::Step => self.step_line(LineStep::Step),
+            DebuggerCommand::Next => self.step_line(LineStep::Next),
+            DebuggerCommand::Watch(expr) => self.watch(&expr),
+            DebuggerCommand::Record(limit) => self.start_recording(limit),
+            DebuggerCommand::RecordStop => self.stop_recording(),
+            DebuggerCommand::ReverseStepInstruction => self.step_instruction(false),
+            DebuggerCommand::ReverseNext => self.reverse_next(),
+            DebuggerCommand::ReverseContinue => self.reverse_continue(),
+            DebuggerCommand::Catch(event, args) => self.catch(&event, &args),
+            DebuggerCommand::Checkpoint => self.checkpoint(),
+            DebuggerCommand::Restart(id) => self.restart(id),
+            DebuggerCommand::Break(location, condition) => {
+                self.set_breakpoint(&location, condition, false)
+            }
+            DebuggerCommand::TemporaryBreak(location, condition) => {
+                self.set_breakpoint(&location, condition, true)
+            }
+            DebuggerCommand::Until(None) => self.step_line(LineStep::Until),
+            DebuggerCommand::Until(Some(location)) => self.run_to_location(&location, true),
+            DebuggerCommand::Advance(location) => self.run_to_location(&location, false),
+            DebuggerCommand::Finish => self.finish(),
+            DebuggerCommand::Help(topic) => print_help(topic.as_deref()),
+            DebuggerCommand::SetEnvironment(name, value) => {
+                self.launch.environment.insert(name, value);
+            }
+            DebuggerCommand::UnsetEnvironment(Some(name)) => {
+                self.launch.environment.remove(&name);
+            }
+            DebuggerCommand::UnsetEnvironment(None) => self.launch.environment.clear(),
+            DebuggerCommand::SetCwd(Some(dir)) => {
+                if std::path::Path::new(&dir).is_dir() {
+                    self.launch.cwd = Some(dir);
+                } else {
+                    println!("{}: No such directory.", dir);
+                }
+            }
+            DebuggerCommand::SetCwd(None) => self.launch.cwd = None,
+            DebuggerCommand::Tty(terminal) => self.launch.terminal = terminal,
+            DebuggerCommand::Show(what, arg) => self.show_setting(&what, arg.as_deref()),
+            DebuggerCommand::Save(what, path) => self.save(&what, path),
+            DebuggerCommand::Display(Some(expr)) => self.add_display(expr),
+            DebuggerCommand::Display(None) => self.show_displays(),
+            DebuggerCommand::Undisplay(ids) => self.undisplay(&ids),
+            DebuggerCommand::Source(path) => {
+                if !self.source(&path) {
+                    return false;
+                }
+            }
+            DebuggerCommand::Quit => {
+                if self.inferior.is_some() {
+                    self.inferior.as_mut().unwrap().kill().unwrap();
+                }
+                self.delete_checkpoints();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn add_display(&mut self, expr: String) {
+        if let Err(err) = expr::parse(&expr, &self.debug_data) {
+            println!("{}", err);
+            return;
+        }
+        let display = Display {
+            id: self.next_display_id,
+            expr,
+        };
+        self.next_display_id += 1;
+        // Show it straight away if there's something to show it for
+        if self.process_image().is_some() {
+            self.show_display(&display);
+        }
+        self.displays.push(display);
+    }
+
+    /// Prints the displayed expressions, if the program is stopped somewhere.
+    fn show_displays(&self) {
+        if self.process_image().is_none() {
+            return;
+        }
+        for display in &self.displays {
+            self.show_display(display);
+        }
+    }
+
+    /// Prints a displayed expression. One that can't be evaluated here, e.g. because it uses a
+    /// variable of a function the program isn't in, says why instead; it'll show again when the
+    /// program is somewhere it can be.
+    fn show_display(&self, display: &Display) {
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => return,
+        };
+        // Assignments in the expression don't take effect: displaying something shouldn't change
+        // the program
+        match expr::evaluate(&display.expr, &self.debug_data, image) {
+            Ok((value, _)) => println!(
+                "{}: {} = {}",
+                display.id,
+                display.expr,
+                expr::format_value(&value, &self.debug_data, Some(image))
+            ),
+            Err(err) => println!("{}: {} = <{}>", display.id, display.expr, err),
+        }
+    }
+
+    /// Deletes the displays with the given numbers, or all of them if there are none.
+    fn undisplay(&mut self, ids: &[usize]) {
+        if ids.is_empty() {
+            self.displays.clear();
+            return;
+        }
+        for id in ids {
+            match self.displays.iter().position(|display| display.id == *id) {
+                Some(index) => {
+                    self.displays.remove(index);
+                }
+                None => println!("No display number {}.", id),
+            }
+        }
+    }
+
+    fn print_display_info(&self) {
+        if self.displays.is_empty() {
+            println!("There are no auto-display expressions now.");
+            return;
+        }
+        println!("Auto-display expressions now in effect:");
+        for display in &self.displays {
+            println!("{:<4} {}", display.id, display.expr);
+        }
+    }
+
+    /// Resolves a location as given to break, tbreak, until and advance: `*address`, a line
+    /// number, or a function name. A function can have several addresses if it was inlined.
+    /// Returns an empty list if the location is invalid.
+    fn resolve_location(&self, location: &str) -> Vec<usize> {
+        if let Some(addr) = location.strip_prefix('*') {
+            parse_address(addr).into_iter().collect()
+        } else if let Ok(line) = location.parse::<usize>() {
+            self.debug_data
+                .get_addr_for_line(None, line)
+                .into_iter()
+                .collect()
+        } else if let Some((file, line)) = parse_file_line(location) {
+            self.debug_data
+                .get_addr_for_line(Some(file), line)
+                .into_iter()
+                .collect()
+        } else {
+            self.debug_data.get_addrs_for_function(location)
+        }
+    }
+
+    fn set_breakpoint(&mut self, location: &str, condition: Option<String>, temporary: bool) {
+        let addrs = self.resolve_location(location);
+        if addrs.is_empty() {
+            println!("Please provide a valid address!");
+            return;
+        }
+        if let Some(ref condition) = condition {
+            if let Err(err) = expr::parse(condition, &self.debug_data) {
+                println!("{}", err);
+                return;
+            }
+        }
+        for addr in addrs {
+            self.set_breakpoint_at(addr, location, condition.clone(), temporary);
+        }
+    }
+
+    fn set_breakpoint_at(
+        &mut self,
+        addr: usize,
+        location: &str,
+        condition: Option<String>,
+        temporary: bool,
+    ) {
+        println!(
+            "Set {}breakpoint {} at {:#x}",
+            if temporary { "temporary " } else { "" },
+            self.breakpoints.len(),
+            addr
+        );
+        let orig_bytes = match (self.breakpoints.get(&addr), self.inferior.as_mut()) {
+            // There's already a breakpoint there, so don't mistake it for the original instruction
+            (Some(bp), _) => bp.orig_bytes.clone(),
+            (None, Some(inferior)) => match inferior.plant_breakpoint(addr) {
+                Ok(orig_bytes) => orig_bytes,
+                Err(_) => {
+                    println!("Unable to set breakpoint at {:#x}", addr);
+                    return;
+                }
+            },
+            (None, None) => Vec::new(),
+        };
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                addr,
+                orig_bytes,
+                location: location.to_string(),
+                condition,
+                temporary,
+            },
+        );
+    }
+
+    /// Called when we stop at the breakpoint at addr: deletes it if it was a temporary one.
+    fn delete_if_temporary(&mut self, addr: usize) {
+        match self.breakpoints.get(&addr) {
+            Some(bp) if bp.temporary => {}
+            _ => return,
+        }
+        let bp = self.breakpoints.remove(&addr).unwrap();
+        println!("Deleted temporary breakpoint at {:#x}", addr);
+        if let Some(inferior) = self.inferior.as_mut() {
+            if inferior.write_bytes(addr, &bp.orig_bytes).is_err() {
+                println!("Unable to restore instruction at {:#x}", addr);
+            }
+        }
+        // Checkpoints have the breakpoint in their memory too; restarting one would otherwise
+        // trap on a breakpoint we no longer know about
+        for checkpoint in &mut self.checkpoints {
+            let _ = checkpoint.process.write_bytes(addr, &bp.orig_bytes);
+        }
+    }
+
+    /// Saves a snapshot of the running inferior that we can restart from later.
+    #[cfg(target_arch = "x86_64")]
+    fn checkpoint(&mut self) {
+        if self.is_replaying() {
+            println!("Cannot create a checkpoint while replaying the execution log.");
+            return;
+        }
+        let inferior = match self.inferior {
+            Some(ref mut inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        match inferior.fork() {
+            Ok(process) => {
+                let id = self.next_checkpoint_id;
+                self.next_checkpoint_id += 1;
+                println!("Checkpoint {}: fork returned pid {}.", id, process.pid());
+                self.checkpoints.push(Checkpoint { id, process });
+            }
+            Err(err) => println!("Unable to create checkpoint: {}", err),
+        }
+    }
+
+    /// Lists the functions we have debug info for whose names match the regex pattern, grouped by
+    /// file, then the ones we only know from the symbol table.
+    fn print_functions(&self, pattern: Option<&str>) {
+        let pattern = match parse_regex(pattern) {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        let mut functions: Vec<(&str, &Function)> = self
+            .debug_data
+            .functions()
+            .filter(|(_, func)| func.text_length > 0 && pattern.is_match(&func.name))
+            .collect();
+        functions.sort_by(|(file_a, a), (file_b, b)| (file_a, &a.name).cmp(&(file_b, &b.name)));
+        println!("All defined functions:");
+        let mut current_file = None;
+        for (file, func) in functions {
+            if current_file != Some(file) {
+                println!("\nFile {}:", file);
+                current_file = Some(file);
+            }
+            let parameters: Vec<&str> = func.parameters.iter().map(|t| t.name.as_str()).collect();
+            println!(
+                "{}:\t{} {}({});",
+                func.line_number,
+                func.return_type.name,
+                func.name,
+                parameters.join(", ")
+            );
+        }
+        let symbols: Vec<&Symbol> = self
+            .debug_data
+            .symbols()
+            .iter()
+            .filter(|symbol| {
+                pattern.is_match(&symbol.name)
+                    && self.debug_data.get_function_at(symbol.address).is_none()
+            })
+            .collect();
+        if !symbols.is_empty() {
+            println!("\nNon-debugging symbols:");
+            for symbol in symbols {
+                println!("{:#018x}  {}", symbol.address, symbol.name);
+            }
+        }
+    }
+
+    /// Lists the global variables whose names match the regex pattern, grouped by file.
+    fn print_variables(&self, pattern: Option<&str>) {
+        let pattern = match parse_regex(pattern) {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        println!("All defined variables:");
+        for file in self.debug_data.files() {
+            let mut variables: Vec<&Variable> = file
+                .global_variables
+                .iter()
+                .filter(|var| pattern.is_match(&var.name))
+                .collect();
+            if variables.is_empty() {
+                continue;
+            }
+            variables.sort_by(|a, b| a.name.cmp(&b.name));
+            println!("\nFile {}:", file.name);
+            for var in variables {
+                println!(
+                    "{}:\t{};",
+                    var.line_number,
+                    declaration(&var.entity_type.name, &var.name)
+                );
+            }
+        }
+    }
+
+    /// Lists the named types whose names match the regex pattern.
+    fn print_types(&self, pattern: Option<&str>) {
+        let pattern = match parse_regex(pattern) {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        // Pointers, arrays and qualified types are named after the type they're derived from
+        let mut names: Vec<&str> = self
+            .debug_data
+            .types()
+            .filter(|ty| match ty.kind {
+                TypeKind::Pointer(_) | TypeKind::Array(_, _) | TypeKind::Function => false,
+                _ => !ty.name.is_empty() && !ty.name.contains("{...}") && !is_qualified(&ty.name),
+            })
+            .map(|ty| ty.name.as_str())
+            .filter(|name| pattern.is_match(name))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        println!("All defined types:");
+        for name in names {
+            println!("{}", name);
+        }
+    }
+
+    /// Shows which addresses the code for a line occupies: the given location's line, or the
+    /// current one.
+    fn print_line_info(&self, location: Option<&str>) {
+        let addrs = match location {
+            Some(location) => self.resolve_location(location),
+            None => match self.current_registers() {
+                Some(regs) => vec![Native::pc(&regs)],
+                None => return,
+            },
+        };
+        if addrs.is_empty() {
+            println!("Please provide a valid location!");
+            return;
+        }
+        for addr in addrs {
+            match (
+                self.debug_data.get_line_from_addr(addr),
+                self.debug_data.get_line_extent(addr),
+            ) {
+                (Some(line), Some((start, end))) => println!(
+                    "Line {} of \"{}\" starts at address {:#x} <{}> and ends at {:#x} <{}>.",
+                    line.number,
+                    line.file,
+                    start,
+                    self.describe_address(start, "+"),
+                    end,
+                    self.describe_address(end, "+"),
+                ),
+                _ => println!(
+                    "No line number information available for address {:#x}",
+                    addr
+                ),
+            }
+        }
+    }
+
+    /// Describes the source file the program is stopped in.
+    fn print_source_info(&self) {
+        let line = match self.inferior {
+            Some(_) => match self.current_registers() {
+                Some(regs) => self.debug_data.get_line_from_addr(Native::pc(&regs)),
+                None => return,
+            },
+            None => None,
+        };
+        // Code in a header belongs to the compilation unit of the file that included it
+        let file = line
+            .as_ref()
+            .and_then(|line| self.debug_data.get_file_containing(line.address));
+        let line = match line {
+            Some(line) => line,
+            None => {
+                println!("No current source file.");
+                return;
+            }
+        };
+        println!("Current source file is {}", line.file);
+        if let Some(file) = file {
+            if !file.compilation_directory.is_empty() {
+                println!("Compilation directory is {}", file.compilation_directory);
+            }
+        }
+        match std::fs::read_to_string(&line.file) {
+            Ok(source) => println!("Contains {} lines.", source.lines().count()),
+            Err(_) => println!("The source file can't be read."),
+        }
+        if let Some(file) = file {
+            if !file.language.is_empty() {
+                println!("Source language is {}.", file.language);
+            }
+            if !file.producer.is_empty() {
+                println!("Producer is {}.", file.producer);
+            }
+        }
+    }
+
+    /// Says which function or global variable addr is in.
+    fn print_symbol_info(&self, addr: usize) {
+        if let Some(var) = self.debug_data.get_global_containing(addr) {
+            match var.location {
+                Location::Address(start) if start != addr => {
+                    println!("{} + {}", var.name, addr - start)
+                }
+                _ => println!("{}", var.name),
+            }
+            return;
+        }
+        let description = self.describe_address(addr, " + ");
+        if description.starts_with("0x") {
+            println!("No symbol matches {:#x}.", addr);
+        } else {
+            println!("{}", description);
+        }
+    }
+
+    /// Describes addr relative to the start of the function it's in, e.g. func2+15, using
+    /// separator between the two. Addresses outside any function are shown as they are.
+    fn describe_address(&self, addr: usize, separator: &str) -> String {
+        let (name, start) = match self.debug_data.get_function_containing(addr) {
+            Some(func) => (&func.name, func.address),
+            None => match self.debug_data.get_symbol_containing(addr) {
+                Some(symbol) => (&symbol.name, symbol.address),
+                None => return format!("{:#x}", addr),
+            },
+        };
+        if addr == start {
+            name.to_string()
+        } else {
+            format!("{}{}{}", name, separator, addr - start)
+        }
+    }
+
+    /// Prints what the program will be run with: its environment (or one variable of it), its
+    /// working directory, or its terminal.
+    fn show_setting(&self, what: &str, arg: Option<&str>) {
+        match (what, arg) {
+            ("environment", Some(name)) => match self.launch.environment.get(name) {
+                Some(value) => println!("{} = {}", name, value),
+                None => println!("Environment variable \"{}\" not defined.", name),
+            },
+            ("environment", None) => {
+                for (name, value) in &self.launch.environment {
+                    println!("{}={}", name, value);
+                }
+            }
+            ("cwd", _) => match self.launch.cwd {
+                Some(ref cwd) => println!("The program runs in {}.", cwd),
+                None => println!("The program runs in deet's working directory."),
+            },
+            _ => match self.launch.terminal {
+                Terminal::Shared => println!("The program runs on deet's terminal."),
+                Terminal::Path(ref path) => println!("The program runs on {}.", path),
+                Terminal::Pty => println!("The program runs on a pseudo-terminal of its own."),
+            },
+        }
+    }
+
+    fn print_checkpoints(&self) {
+        if self.checkpoints.is_empty() {
+            println!("No checkpoints.");
+            return;
+        }
+        for checkpoint in self.checkpoints.iter() {
+            print!("{:<4} process {}", checkpoint.id, checkpoint.process.pid());
+            match checkpoint.process.get_registers() {
+                Ok(regs) => {
+                    let rip = Native::pc(&regs);
+                    match (
+                        self.debug_data.get_function_from_addr(rip),
+                        self.debug_data.get_line_from_addr(rip),
+                    ) {
+                        (Some(func), Some(line)) => {
+                            println!(" at {:#x}, {} ({})", rip, func, line)
+                        }
+                        _ => println!(" at {:#x}", rip),
+                    }
+                }
+                Err(err) => println!(" (unable to read registers: {})", err),
+            }
+        }
+    }
+
+    /// Replaces the running inferior with a fresh copy of checkpoint `id`. The checkpoint itself
+    /// is left untouched, so it can be restarted again.
+    #[cfg(target_arch = "x86_64")]
+    fn restart(&mut self, id: usize) {
+        let checkpoint = match self.checkpoints.iter_mut().find(|c| c.id == id) {
+            Some(checkpoint) => checkpoint,
+            None => {
+                println!("Invalid checkpoint number {}.", id);
+                return;
+            }
+        };
+        let mut process = match checkpoint.process.fork() {
+            Ok(process) => process,
+            Err(err) => {
+                println!("Unable to restart checkpoint {}: {}", id, err);
+                return;
+            }
+        };
+        // Breakpoints set since the checkpoint was taken aren't in its memory yet
+        process.insert_breakpoints(&self.breakpoints);
+        if let Some(mut inferior) = self.inferior.take() {
+            let _ = inferior.kill_silently();
+        }
+        println!("Switching to checkpoint {} (pid {})", id, process.pid());
+        let rip = process.get_registers().map(|regs| Native::pc(&regs));
+        self.inferior = Some(process);
+        // The recorded history belongs to the process we just threw away
+        self.discard_recording();
+        self.set_event_options();
+        self.install_watchpoints();
+        if let Ok(rip) = rip {
+            self.print_stop(rip);
+        }
+    }
+
+    /// Kills all the checkpoint processes.
+    fn delete_checkpoints(&mut self) {
+        for mut checkpoint in self.checkpoints.drain(..) {
+            let _ = checkpoint.process.kill_silently();
+        }
+    }
+
+    /// Evaluates an expression in the current frame, carrying out any assignments it makes.
+    /// Returns None (having printed why) if it can't be evaluated.
+    fn evaluate(&mut self, expression: &str) -> Option<expr::Value> {
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => {
+                println!("The program is not being run.");
+                return None;
+            }
+        };
+        let (value, writes) = match expr::evaluate(expression, &self.debug_data, image) {
+            Ok(result) => result,
+            Err(err) => {
+                println!("{}", err);
+                return None;
+            }
+        };
+        if writes.is_empty() {
+            return Some(value);
+        }
+        let inferior = match self.inferior {
+            Some(ref mut inferior) => inferior,
+            None => {
+                println!("Cannot modify a core file.");
+                return None;
+            }
+        };
+        for (place, bytes) in writes {
+            let result = match place {
+                Place::Memory(addr) => inferior.write_bytes(addr, &bytes),
+                Place::Register(name) => {
+                    let mut buf = [0u8; 8];
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    inferior.set_register(name, u64::from_le_bytes(buf))
+                }
+            };
+            if let Err(err) = result {
+                println!("Unable to write to {:?}: {}", place, err);
+                return None;
+            }
+        }
+        Some(value)
+    }
+
+    fn print_expression(&mut self, expression: &str) {
+        if let Ok(Expr::Call(_, _)) = expr::parse(expression, &self.debug_data) {
+            self.call_function(expression);
+            return;
+        }
+        if let Some(value) = self.evaluate(expression) {
+            let formatted = expr::format_value(&value, &self.debug_data, self.process_image());
+            println!("{} = {}", expression, formatted);
+        }
+    }
+
+    /// Evaluates an expression of the form func(args...) by calling the function in the
+    /// inferior, and prints what it returns.
+    #[cfg(target_arch = "x86_64")]
+    fn call_function(&mut self, expression: &str) {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None => {
+                println!("You can't do that without a process to debug.");
+                return;
+            }
+        };
+        if self.recording.is_some() {
+            println!("Cannot call functions while recording; use \"record stop\" first.");
+            return;
+        }
+        let (callee, args) = match expr::parse(expression, &self.debug_data) {
+            Ok(Expr::Call(callee, args)) => (callee, args),
+            // Not a call after all, so there's nothing to run
+            Ok(_) => {
+                self.print_expression(expression);
+                return;
+            }
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+
+        let mut evaluator = match Evaluator::new(&self.debug_data, inferior) {
+            Ok(evaluator) => evaluator,
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                return;
+            }
+        };
+        let result = evaluator
+            .evaluate(&callee)
+            .and_then(|callee| evaluator.decay(callee))
+            .and_then(|callee| match callee.ty.kind {
+                TypeKind::Pointer(ref target) if matches!(target.kind, TypeKind::Function) => {
+                    Ok(callee.raw() as usize)
+                }
+                _ => Err(expr::Error::BadOperand(
+                    "a function call".to_string(),
+                    callee.ty.name,
+                )),
+            });
+        let addr = match result {
+            Ok(addr) => addr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        // Without debugging information for the function, assume it takes the arguments as
+        // given and returns an int, as C used to
+        let (parameters, return_type) = match self.debug_data.get_function_at(addr) {
+            Some(func) => (func.parameters.clone(), func.return_type.clone()),
+            None => (Vec::new(), expr::int_type()),
+        };
+        if !return_type.is_scalar() && !matches!(return_type.kind, TypeKind::Void) {
+            println!(
+                "Calling functions that return {} isn't supported.",
+                return_type.name
+            );
+            return;
+        }
+        if args.len() < parameters.len() {
+            println!("Too few arguments in function call.");
+            return;
+        }
+
+        let mut arguments = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let value = match evaluator.evaluate(arg) {
+                Ok(value) => value,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            };
+            // Arguments are converted to the parameter's type as in an assignment. Extra
+            // (variadic) arguments get C's default promotions instead.
+            let param_type = match parameters.get(i) {
+                Some(param_type) => param_type.clone(),
+                None if value.is_float() => expr::double_type(),
+                None if value.ty.is_scalar() && value.ty.size < 4 => expr::int_type(),
+                None => match evaluator.decay(value.clone()) {
+                    Ok(decayed) => decayed.ty,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                },
+            };
+            if !param_type.is_scalar() {
+                println!("Passing {} by value isn't supported.", param_type.name);
+                return;
+            }
+            match evaluator.convert(value, &param_type) {
+                Ok(value) if value.is_float() => arguments.push(Argument::Sse(value.raw())),
+                Ok(value) => arguments.push(Argument::Integer(value.as_i64() as u64)),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+        }
+
+        let inferior = self.inferior.as_mut().unwrap();
+        match inferior.call_function(addr, &arguments, &self.breakpoints) {
+            Ok(CallOutcome::Returned(rax, xmm0)) => {
+                let bits = if matches!(return_type.kind, TypeKind::Float) {
+                    xmm0
+                } else {
+                    rax
+                };
+                if let TypeKind::Void = return_type.kind {
+                    return;
+                }
+                let value = expr::Value::from_u64(return_type, bits);
+                let formatted = expr::format_value(&value, &self.debug_data, Some(inferior));
+                println!("{} = {}", expression, formatted);
+            }
+            Ok(CallOutcome::Interrupted(Status::Stopped(signal, rip))) => {
+                println!(
+                    "The program stopped in the called function (signal {} at {:#x}).",
+                    signal, rip
+                );
+                println!("The call was abandoned and the program's state restored.");
+            }
+            Ok(CallOutcome::Interrupted(status)) => self.report_status(Ok(status), true),
+            Err(err) => println!("Error calling function: {}", err),
+        }
+    }
+
+    fn examine_memory(&self, count: usize, addr: &str) {
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let start = match parse_address(addr) {
+            Some(addr) => addr,
+            None => {
+                println!("Please provide a valid address!");
+                return;
+            }
+        };
+        for i in 0..count {
+            let addr = start + i * 8;
+            if i % 2 == 0 {
+                print!("{:#x}:", addr);
+            }
+            match image.read_word(addr) {
+                Ok(word) => print!("\t{:#018x}", word),
+                Err(_) => {
+                    println!("\tCannot access memory at address {:#x}", addr);
+                    return;
+                }
+            }
+            if i % 2 == 1 || i == count - 1 {
+                println!();
+            }
+        }
+    }
+
+    /// Searches memory from args[0] to args[1] (inclusive, or +length) for the values in the
+    /// rest of args, one after another, and prints where they're found. With a live process,
+    /// only the mapped parts of the range are read.
+    fn find_in_memory(&self, size: Option<usize>, args: &[String]) {
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let start = match self.evaluate_quietly(image, &args[0]) {
+            Some(value) => value.raw() as usize,
+            None => return,
+        };
+        let end = match args[1].strip_prefix('+') {
+            Some(len) => match self.evaluate_quietly(image, len) {
+                Some(len) if len.raw() > 0 => start.saturating_add(len.raw() as usize - 1),
+                Some(_) => {
+                    println!("Empty search range.");
+                    return;
+                }
+                None => return,
+            },
+            None => match self.evaluate_quietly(image, &args[1]) {
+                Some(end) => end.raw() as usize,
+                None => return,
+            },
+        };
+        if end < start {
+            println!("Invalid search space, end precedes start.");
+            return;
+        }
+        let mut pattern = Vec::new();
+        for arg in &args[2..] {
+            if let Some(quote) = arg.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                match unquote(arg, quote) {
+                    Some(text) => pattern.extend(text.bytes()),
+                    None => {
+                        println!("Unterminated string in {}.", arg);
+                        return;
+                    }
+                }
+                continue;
+            }
+            let value = match self.evaluate_quietly(image, arg) {
+                Some(value) => value,
+                None => return,
+            };
+            match size {
+                Some(size) => pattern.extend_from_slice(&value.raw().to_le_bytes()[..size]),
+                None => pattern.extend_from_slice(&value.bytes),
+            }
+        }
+        if pattern.is_empty() {
+            println!("Nothing to search for.");
+            return;
+        }
+        // The end of the range is inclusive, so it may be the last address there is
+        let ranges: Vec<(usize, usize)> = match self.inferior {
+            Some(ref inferior) => match proc_maps::read_maps(inferior.pid()) {
+                Ok(maps) => maps
+                    .iter()
+                    .filter(|mapping| mapping.is_readable())
+                    .map(|mapping| (mapping.start.max(start), (mapping.end - 1).min(end)))
+                    .filter(|(from, to)| from <= to)
+                    .collect(),
+                Err(err) => {
+                    println!("Could not read the memory map: {}", err);
+                    return;
+                }
+            },
+            None => vec![(start, end)],
+        };
+        let mut found = 0;
+        for (from, to) in ranges {
+            // The bytes read so far that a match could still start in
+            let mut buf = Vec::new();
+            let mut buf_start = from;
+            let mut page = from - from % FIND_BLOCK_SIZE;
+            loop {
+                let first = from.max(page) - page;
+                let last = to.min(page + (FIND_BLOCK_SIZE - 1)) - page;
+                match image.read_bytes(page, FIND_BLOCK_SIZE) {
+                    Ok(bytes) => buf.extend_from_slice(&bytes[first..=last]),
+                    Err(_) => {
+                        // Unreadable (in a core file, not dumped): no match can span it
+                        buf.clear();
+                        buf_start = page + last + 1;
+                    }
+                }
+                for (i, window) in buf.windows(pattern.len()).enumerate() {
+                    if window == &pattern[..] {
+                        let addr = buf_start + i;
+                        let description = self.describe_address(addr, "+");
+                        if description.starts_with("0x") {
+                            println!("{:#x}", addr);
+                        } else {
+                            println!("{:#x} <{}>", addr, description);
+                        }
+                        found += 1;
+                    }
+                }
+                // Keep the bytes a match starting in them would need more of
+                let keep = buf.len().min(pattern.len() - 1);
+                buf_start += buf.len() - keep;
+                buf.drain(..buf.len() - keep);
+                if to - page < FIND_BLOCK_SIZE {
+                    break;
+                }
+                page += FIND_BLOCK_SIZE;
+            }
+        }
+        match found {
+            0 => println!("Pattern not found."),
+            1 => println!("1 pattern found."),
+            _ => println!("{} patterns found.", found),
+        }
+    }
+
+    /// Evaluates an expression without carrying out any assignments in it, printing why if it
+    /// can't be.
+    fn evaluate_quietly(&self, image: &dyn ProcessImage, expression: &str) -> Option<expr::Value> {
+        match expr::evaluate(expression, &self.debug_data, image) {
+            Ok((value, _)) => Some(value),
+            Err(err) => {
+                println!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Reads the running program's memory map, or says why it can't.
+    fn read_maps(&self) -> Option<(Pid, Vec<Mapping>)> {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None if self.core.is_some() => {
+                println!("A core file's memory map isn't available.");
+                return None;
+            }
+            None => {
+                println!("The program is not being run.");
+                return None;
+            }
+        };
+        match proc_maps::read_maps(inferior.pid()) {
+            Ok(maps) => Some((inferior.pid(), maps)),
+            Err(err) => {
+                println!("Could not read the memory map: {}", err);
+                None
+            }
+        }
+    }
+
+    fn print_mappings(&self) {
+        let (pid, maps) = match self.read_maps() {
+            Some(maps) => maps,
+            None => return,
+        };
+        println!("process {}", pid);
+        println!(
+            "{:>18} {:>18} {:>10} {:>10}  {:<5}  objfile",
+            "Start Addr", "End Addr", "Size", "Offset", "Perms"
+        );
+        for mapping in maps {
+            println!(
+                "{:>#18x} {:>#18x} {:>#10x} {:>#10x}  {:<5}  {}",
+                mapping.start,
+                mapping.end,
+                mapping.len(),
+                mapping.offset,
+                mapping.perms,
+                mapping.path.as_deref().unwrap_or("")
+            );
+        }
+    }
+
+    /// Lists the shared libraries mapped into the program, with where their code is.
+    fn print_shared_libraries(&self) {
+        let (pid, maps) = match self.read_maps() {
+            Some(maps) => maps,
+            None => return,
+        };
+        let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).ok();
+        let libraries: Vec<&Mapping> = maps
+            .iter()
+            .filter(|mapping| mapping.is_executable())
+            .filter(|mapping| match mapping.path {
+                Some(ref path) => {
+                    path.starts_with('/') && exe.as_deref() != Some(std::path::Path::new(path))
+                }
+                None => false,
+            })
+            .collect();
+        if libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("{:<20}{:<20}Shared Object Library", "From", "To");
+        for mapping in libraries {
+            println!(
+                "{:<#20x}{:<#20x}{}",
+                mapping.start,
+                mapping.end,
+                mapping.path.as_deref().unwrap_or("")
+            );
+        }
+    }
+
+    fn read_heap(&self) -> Option<Heap> {
+        let (_, maps) = self.read_maps()?;
+        match Heap::read(self.process_image()?, &maps) {
+            Ok(heap) => Some(heap),
+            Err(err) => {
+                println!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Lists the chunks in the heap, saying which are in use, and what looks corrupted.
+    fn print_heap(&self) {
+        let heap = match self.read_heap() {
+            Some(heap) => heap,
+            None => return,
+        };
+        println!(
+            "Heap {:#x}-{:#x} (chunks start 0x10 bytes before what malloc returns):",
+            heap.start, heap.end
+        );
+        println!("{:<18}  {:>10}  Status", "Chunk", "Size");
+        let (mut used, mut free) = (0, 0);
+        for (i, chunk) in heap.chunks.iter().enumerate() {
+            let next = heap.chunks.get(i + 1);
+            let status = if let Some(bin) = heap.bin_holding(chunk.addr) {
+                free += chunk.size;
+                format!("free ({})", bin.name)
+            } else if heap.top().map(|top| top.addr) == Some(chunk.addr) {
+                "top".to_string()
+            } else if Some(chunk.addr) == heap.tcache {
+                "malloc's tcache".to_string()
+            } else if matches!(next, Some(next) if !next.prev_inuse) {
+                free += chunk.size;
+                "free".to_string()
+            } else {
+                used += chunk.size;
+                "in use".to_string()
+            };
+            println!("{:<#18x}  {:>#10x}  {}", chunk.addr, chunk.size, status);
+        }
+        println!("{} bytes in use, {} free.", used, free);
+        for err in &heap.errors {
+            println!("{}", err);
+        }
+        if heap.arena.is_none() {
+            println!("Couldn't find malloc's main arena, so only the tcache's bins are known.");
+        }
+    }
+
+    /// Lists the chunks on each of malloc's free lists.
+    fn print_bins(&self) {
+        let heap = match self.read_heap() {
+            Some(heap) => heap,
+            None => return,
+        };
+        match heap.arena {
+            Some(arena) => println!("Main arena at {}:", self.describe_address(arena, "+")),
+            None => println!("Couldn't find malloc's main arena, so only the tcache is shown."),
+        }
+        if heap.bins.is_empty() {
+            println!("There are no free chunks.");
+        }
+        for bin in &heap.bins {
+            let chunks: Vec<String> = bin
+                .chunks
+                .iter()
+                .map(|chunk| format!("{:#x}", chunk))
+                .collect();
+            println!(
+                "{} [{}]: {}",
+                bin.name,
+                bin.chunks.len(),
+                chunks.join(" -> ")
+            );
+            if let Some(ref err) = bin.error {
+                println!("    corrupted: {}", err);
+            }
+        }
+    }
+
+    fn generate_core(&self, path: Option<String>) {
+        let inferior = match self.inferior {
+            Some(ref inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let pid = inferior.pid();
+        let path = path.unwrap_or_else(|| format!("core.{}", pid));
+        let regs = match inferior.get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Error reading registers: {}", err);
+                return;
+            }
+        };
+        let signal = ptrace::getsiginfo(pid)
+            .map(|info| info.si_signo)
+            .unwrap_or(0);
+        // Save the original instructions rather than our breakpoints
+        let patches: Vec<(usize, u8)> = self
+            .breakpoints
+            .values()
+            .flat_map(|bp| (bp.addr..).zip(bp.orig_bytes.iter().copied()))
+            .collect();
+        match core_file::write_core_file(&path, pid, &regs, signal, &patches) {
+            Ok(()) => println!("Saved corefile {}", path),
+            Err(err) => println!("Could not write core file {}: {}", path, err),
+        }
+    }
+
+    fn print_registers(&self) {
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        match image.get_registers() {
+            Ok(regs) => {
+                for (name, value) in Native::named_registers(&regs) {
+                    println!("{:<10} {:<#20x} {}", name, value, value as i64);
+                }
+            }
+            Err(err) => println!("Error reading registers: {}", err),
+        }
+    }
+
+    /// Redraws the TUI's panes, if we're using it, to show where the program is now.
+    fn refresh_tui(&mut self) {
+        if self.tui.is_some() {
+            let view = self.tui_view();
+            self.tui.as_mut().unwrap().draw(&view);
+        }
+    }
+
+    /// Gathers what the TUI's panes show from the state of the program.
+    fn tui_view(&self) -> tui::View {
+        let mut view = tui::View::default();
+        let image = match self.process_image() {
+            Some(image) => image,
+            None => {
+                view.message = Some("The program is not being run.".to_string());
+                return view;
+            }
+        };
+        let regs = match image.get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                view.message = Some(format!("Error reading registers: {}", err));
+                return view;
+            }
+        };
+        let rip = Native::pc(&regs);
+        view.rip = Some(rip);
+        view.registers = Native::named_registers(&regs);
+        let _ = image.walk_stack(&self.debug_data, &mut |line| view.backtrace.push(line));
+        match self.debug_data.get_line_from_addr(rip) {
+            Some(line) => {
+                view.breakpoint_lines = self
+                    .breakpoints
+                    .keys()
+                    .filter_map(|addr| self.debug_data.get_line_from_addr(*addr))
+                    .filter(|bp_line| bp_line.file == line.file)
+                    .map(|bp_line| bp_line.number)
+                    .collect();
+                view.source = Some((line.file, line.number));
+            }
+            None => view.message = Some(format!("No source for {:#x}", rip)),
+        }
+        view.disassembly = self.disassemble_around(image, rip, TUI_DISASSEMBLY_LEN);
+        view
+    }
+
+    /// Disassembles the function containing rip, from its start to count instructions past rip,
+    /// or just from rip if we don't know where the function starts.
+    #[cfg(target_arch = "x86_64")]
+    fn disassemble_around(
+        &self,
+        image: &dyn ProcessImage,
+        rip: usize,
+        count: usize,
+    ) -> Vec<(usize, String, bool)> {
+        let start = self
+            .function_start(rip)
+            .or_else(|| Some(self.debug_data.get_symbol_containing(rip)?.address))
+            .filter(|start| rip - start < MAX_DISASSEMBLY_BYTES)
+            .unwrap_or(rip);
+        let instructions = self.disassemble(image, start, rip, count);
+        // If the function doesn't decode in step with rip, start again from rip
+        if instructions.iter().any(|(addr, _, _)| *addr == rip) {
+            instructions
+        } else {
+            self.disassemble(image, rip, rip, count)
+        }
+    }
+
+    /// Disassembles from start until count instructions past rip, returning each instruction's
+    /// address and text, and whether there's a breakpoint on it.
+    #[cfg(target_arch = "x86_64")]
+    fn disassemble(
+        &self,
+        image: &dyn ProcessImage,
+        start: usize,
+        rip: usize,
+        count: usize,
+    ) -> Vec<(usize, String, bool)> {
+        let mut formatter = IntelFormatter::new();
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        let mut after_rip = 0;
+        while after_rip < count {
+            let instruction = match disasm::decode_instruction(image, addr, &self.breakpoints) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let mut text = String::new();
+            formatter.format(&instruction, &mut text);
+            instructions.push((addr, text, self.breakpoints.contains_key(&addr)));
+            if addr >= rip {
+                after_rip += 1;
+            }
+            addr = instruction.next_ip() as usize;
+        }
+        instructions
+    }
+
+    /// This function prompts the user to enter a command, and continues re-prompting until the user
+    /// enters a valid command. It uses DebuggerCommand::from_line to do the command parsing.
+    ///
+    /// You don't need to read, understand, or modify this function.
+    fn get_next_command(&mut self) -> DebuggerCommand {
+        // Tell the completer where we are, so it knows what variables are in scope
+        let rip = self
+            .process_image()
+            .and_then(|image| image.get_registers().ok())
+            .map(|regs| Native::pc(&regs));
+        if let Some(helper) = self.readline.helper_mut() {
+            helper.set_rip(rip);
+        }
+        loop {
+            // Print prompt and get next line of user input
+            match self.readline.readline("(deet) ") {
+                Err(ReadlineError::Interrupted) => {
+                    // User pressed ctrl+c. We're going to ignore it
+                    println!("Type \"quit\" to exit");
                 }
                 Err(ReadlineError::Eof) => {
                     // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
@@ -253,11 +3291,9 @@ impl Debugger {
                             self.history_path, err
                         );
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    match DebuggerCommand::from_line(&line) {
+                        Ok(cmd) => return cmd,
+                        Err(err) => println!("{}", err),
                     }
                 }
             }
@@ -265,6 +3301,271 @@ impl Debugger {
     }
 }
 
+/// Stand-ins for what needs an instruction decoder or calling convention we only have for x86-64:
+/// commands built on them say they aren't supported, and stops and crash reports leave out what
+/// we can't work out.
+#[cfg(not(target_arch = "x86_64"))]
+impl Debugger {
+    fn is_recording(&self) -> bool {
+        false
+    }
+
+    fn is_replaying(&self) -> bool {
+        false
+    }
+
+    fn discard_recording(&mut self) {}
+
+    fn forward_step(&mut self) -> Result<Status, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        inferior.step_instruction(&self.breakpoints)
+    }
+
+    /// There's never a recording to step back through.
+    fn backward_step(&mut self) -> Result<Option<Status>, nix::Error> {
+        Ok(None)
+    }
+
+    fn start_recording(&mut self, _limit: Option<usize>) {
+        println!("Process record isn't supported on this architecture.");
+    }
+
+    fn stop_recording(&mut self) {
+        println!("No recording is currently active.");
+    }
+
+    fn print_record_info(&self) {
+        println!("No recording is currently active.");
+    }
+
+    fn reverse_next(&mut self) {
+        println!("Target does not support reverse execution.");
+    }
+
+    fn reverse_continue(&mut self) {
+        println!("Target does not support reverse execution.");
+    }
+
+    fn checkpoint(&mut self) {
+        println!("Checkpoints aren't supported on this architecture.");
+    }
+
+    fn restart(&mut self, id: usize) {
+        println!("Invalid checkpoint number {}.", id);
+    }
+
+    fn call_function(&mut self, _expression: &str) {
+        println!("Calling functions isn't supported on this architecture.");
+    }
+
+    fn print_return_value(&self, _return_type: Type) {}
+
+    /// Without decoding it we can't tell whether an instruction is a call, so next steps into
+    /// functions.
+    fn call_return_address(&self, _rip: usize) -> Option<usize> {
+        None
+    }
+
+    /// Without decoding the prologue we can't tell where the frame is, so finish, until and
+    /// advance only work in the outermost frame, and watchpoints on locals stay until deleted.
+    fn frame_address(&self, _regs: &Registers) -> Option<usize> {
+        None
+    }
+
+    fn crash_access(
+        &self,
+        _inferior: &Inferior,
+        _regs: &Registers,
+        _addr: Option<usize>,
+    ) -> Option<(&'static str, usize, usize)> {
+        None
+    }
+
+    fn crash_instruction(
+        &self,
+        _inferior: &Inferior,
+        _regs: &Registers,
+    ) -> Option<(String, Vec<String>)> {
+        None
+    }
+
+    fn disassemble_around(
+        &self,
+        _image: &dyn ProcessImage,
+        _rip: usize,
+        _count: usize,
+    ) -> Vec<(usize, String, bool)> {
+        Vec::new()
+    }
+}
+
+/// Prints the list of commands, or what one command does.
+fn print_help(topic: Option<&str>) {
+    let topic = match topic {
+        Some(topic) => topic,
+        None => {
+            println!("List of commands:\n");
+            for command in COMMANDS {
+                // Just the first sentence
+                let summary = command.description.split(". ").next().unwrap();
+                let end = if summary.ends_with('.') { "" } else { "." };
+                println!("{:<18} -- {}{}", command.names[0], summary, end);
+            }
+            println!("\nType \"help\" followed by a command name for more about it.");
+            return;
+        }
+    };
+    let command = match find_command(topic) {
+        Ok(command) => command,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    println!("{} {}", command.names[0], command.usage);
+    println!("{}", command.description);
+    if command.names.len() > 1 {
+        println!("Abbreviations: {}", command.names[1..].join(", "));
+    }
+    if command.names[0] == "info" {
+        println!();
+        for (name, description) in INFO_COMMANDS {
+            println!("info {:<13} -- {}", name, description);
+        }
+    }
+}
+
+/// Quotes an argument to a command if it needs it to be read back as one word.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c)) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Splits a location like `structs.c:20` into the file and line number.
+fn parse_file_line(location: &str) -> Option<(&str, usize)> {
+    let colon = location.rfind(':')?;
+    let line = location[colon + 1..].parse().ok()?;
+    Some((&location[..colon], line))
+}
+
+/// Compiles the regex argument of the info commands, which matches everything if there isn't one.
+fn parse_regex(pattern: Option<&str>) -> Option<Regex> {
+    match Regex::new(pattern.unwrap_or("")) {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            println!("Invalid regexp: {}", err);
+            None
+        }
+    }
+}
+
+/// Writes a C declaration of a variable called name with the given type, e.g. `int grid[2][3]`.
+fn declaration(type_name: &str, name: &str) -> String {
+    if let Some(brackets) = type_name.find(" [") {
+        format!(
+            "{} {}{}",
+            &type_name[..brackets],
+            name,
+            &type_name[brackets + 1..]
+        )
+    } else if type_name.ends_with('*') {
+        format!("{}{}", type_name, name)
+    } else {
+        format!("{} {}", type_name, name)
+    }
+}
+
+/// Returns true for the name of a qualified type, like `const int`.
+fn is_qualified(name: &str) -> bool {
+    ["const ", "volatile ", "restrict ", "_Atomic "]
+        .iter()
+        .any(|qualifier| name.starts_with(qualifier))
+}
+
+/// Explains the si_code of a SIGSEGV or SIGBUS.
+fn describe_fault_code(signal: Signal, code: i32) -> &'static str {
+    match (signal, code) {
+        (Signal::SIGSEGV, SEGV_MAPERR) => "address not mapped to object (SEGV_MAPERR)",
+        (Signal::SIGSEGV, SEGV_ACCERR) => "invalid permissions for mapped object (SEGV_ACCERR)",
+        (Signal::SIGSEGV, SEGV_BNDERR) => "failed address bound checks (SEGV_BNDERR)",
+        (Signal::SIGSEGV, SEGV_PKUERR) => "access denied by protection keys (SEGV_PKUERR)",
+        (Signal::SIGBUS, BUS_ADRALN) => "invalid address alignment (BUS_ADRALN)",
+        (Signal::SIGBUS, BUS_ADRERR) => "nonexistent physical address (BUS_ADRERR)",
+        (Signal::SIGBUS, BUS_OBJERR) => "object-specific hardware error (BUS_OBJERR)",
+        (_, libc::SI_KERNEL) => "general protection fault, e.g. a non-canonical address",
+        _ => "unknown reason",
+    }
+}
+
+/// Works out which of an instruction's memory accesses faulted: the one covering addr, or if the
+/// kernel didn't say where the fault was, the first. Returns whether it was reading or writing,
+/// how many bytes, and where.
+#[cfg(target_arch = "x86_64")]
+fn faulting_access(
+    instruction: &Instruction,
+    regs: &Registers,
+    addr: Option<usize>,
+) -> Option<(&'static str, usize, usize)> {
+    let mut factory = InstructionInfoFactory::new();
+    let info = factory.info(instruction);
+    info.used_memory().iter().find_map(|used| {
+        let start =
+            used.virtual_address(0, |reg, _, _| disasm::register_value(regs, reg))? as usize;
+        let len = used.memory_size().size();
+        if let Some(addr) = addr {
+            if addr < start || addr - start >= len.max(1) {
+                return None;
+            }
+        }
+        let verb = match used.access() {
+            OpAccess::Read | OpAccess::CondRead => "reading",
+            OpAccess::Write | OpAccess::CondWrite => "writing",
+            _ => "updating",
+        };
+        Some((verb, len, start))
+    })
+}
+
+/// Lists the registers an instruction's memory operand is computed from, with their values.
+#[cfg(target_arch = "x86_64")]
+fn address_registers(instruction: &Instruction, regs: &Registers) -> Vec<String> {
+    let mut registers = Vec::new();
+    if !instruction.op_kinds().any(|kind| kind == OpKind::Memory) {
+        return registers;
+    }
+    for register in [instruction.memory_base(), instruction.memory_index()].iter() {
+        if *register == Register::None || *register == Register::RIP {
+            continue;
+        }
+        if let Some(value) = disasm::register_value(regs, *register) {
+            registers.push(format!("{:?} = {:#x}", register, value).to_lowercase());
+        }
+    }
+    registers
+}
+
+/// Takes the quotes off a string for find, and the backslashes off the characters they escape.
+/// Returns None if the closing quote is missing.
+fn unquote(arg: &str, quote: char) -> Option<String> {
+    let mut text = String::new();
+    let mut chars = arg[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                '0' => text.push('\0'),
+                c => text.push(c),
+            },
+            c if c == quote => return Some(text),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
 // parse a usize from a hexadecimal string
 fn parse_address(addr: &str) -> Option<usize> {
     let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
@@ -274,3 +3575,11 @@ fn parse_address(addr: &str) -> Option<usize> {
     };
     usize::from_str_radix(addr_without_0x, 16).ok()
 }
+
+#[cfg(target_arch = "x86_64")]
+fn is_call(instruction: &Instruction) -> bool {
+    matches!(
+        instruction.flow_control(),
+        FlowControl::Call | FlowControl::IndirectCall
+    )
+}
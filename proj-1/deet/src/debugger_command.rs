@@ -1,29 +1,794 @@
+use crate::inferior::Terminal;
+
 pub enum DebuggerCommand {
     Quit,
-    Run(Vec<String>),
+    /// Arguments, and where to send the program's input and output
+    Run(Vec<String>, Redirections),
     Continue,
     Backtrace,
-    Break(String),
+    /// Location, and an optional condition under which to stop
+    Break(String, Option<String>),
+    /// tbreak: like Break, but deleted when it's first hit
+    TemporaryBreak(String, Option<String>),
+    /// until [location]: run to the next line past this one, or to location in this frame
+    Until(Option<String>),
+    /// advance <location>: run to location
+    Advance(String),
+    /// finish: run until the current function returns
+    Finish,
+    Print(String),
+    /// set var <expr>: evaluate an assignment in the inferior
+    SetVar(String),
+    /// call <func>(<args>): run a function in the inferior
+    Call(String),
+    /// x/N <addr>: print N words of memory starting at addr
+    Examine(usize, String),
+    /// find[/size] <start>, <end|+length>, <values...>: search memory for a sequence of values,
+    /// each size bytes if given
+    Find(Option<usize>, Vec<String>),
+    /// info <what> [argument]
+    Info(String, Option<String>),
+    GenerateCore(Option<String>),
+    StepInstruction,
+    Step,
+    Next,
+    Watch(String),
+    /// Start recording, optionally with a limit on how many instructions to keep
+    Record(Option<usize>),
+    RecordStop,
+    ReverseStepInstruction,
+    ReverseNext,
+    ReverseContinue,
+    /// catch <event> [args...]: stop when a system call, signal, fork, exec or exit happens
+    Catch(String, Vec<String>),
+    Checkpoint,
+    /// Switch to a copy of the given checkpoint
+    Restart(usize),
+    /// help [command]
+    Help(Option<String>),
+    /// set environment <name>[=<value>]
+    SetEnvironment(String, String),
+    /// unset environment [name]: remove a variable from the program's environment, or all of them
+    UnsetEnvironment(Option<String>),
+    /// set cwd [directory]: where to run the program, or back to deet's own directory
+    SetCwd(Option<String>),
+    /// tty <terminal|pty|shared>: the terminal to run the program on
+    Tty(Terminal),
+    /// show <environment|cwd|tty> [argument]
+    Show(String, Option<String>),
+    /// save <breakpoints|session> [file]
+    Save(String, Option<String>),
+    /// source <file>: run the commands in a file
+    Source(String),
+    /// display [expression]: print an expression whenever the program stops, or print them all
+    Display(Option<String>),
+    /// undisplay [numbers...]: stop displaying some expressions, or all of them
+    Undisplay(Vec<usize>),
+}
+
+/// A command deet understands: the names it goes by, what `help` says about it, and how to parse
+/// it. The registry of them (COMMANDS) is what commands are looked up in, and what `help` and tab
+/// completion list.
+pub struct Command {
+    /// The command's name, then its abbreviations. Any unique prefix of the name works too.
+    pub names: &'static [&'static str],
+    /// The arguments it takes, as `help` shows them
+    pub usage: &'static str,
+    pub description: &'static str,
+    /// Parses the command's arguments, or explains what's wrong with them.
+    parse: fn(&Args) -> Result<DebuggerCommand, String>,
+}
+
+/// Where `run` sends the program's standard streams, when not to the terminal
+#[derive(Default, Clone)]
+pub struct Redirections {
+    /// `< file`
+    pub stdin: Option<String>,
+    /// `> file`
+    pub stdout: Option<String>,
+    /// `2> file`
+    pub stderr: Option<String>,
+}
+
+/// A word of a command line, with its quotes and backslashes taken out
+struct Token {
+    text: String,
+    /// Where the word starts in the line
+    start: usize,
+    /// Whether it starts with a quote or backslash, in which case it's taken literally even if it
+    /// looks like a `<` or `>` redirection or the `if` of a condition
+    quoted: bool,
+}
+
+/// The arguments a command was given
+struct Args<'a> {
+    /// The command's name, for messages
+    name: &'static str,
+    /// The word the command was invoked with, e.g. `tb` or `x/4`
+    word: &'a str,
+    line: &'a str,
+    /// The words after the command
+    tokens: Vec<Token>,
+}
+
+impl<'a> Args<'a> {
+    fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn get(&self, i: usize) -> Option<&str> {
+        self.tokens.get(i).map(|token| token.text.as_str())
+    }
+
+    /// Returns the ith argument, or an error naming what it should have been.
+    fn required(&self, i: usize, what: &str) -> Result<&str, String> {
+        self.get(i)
+            .ok_or_else(|| format!("Argument required ({}).", what))
+    }
+
+    /// Returns the line from the ith argument on, as it was typed. Expressions are taken this
+    /// way, so that their own quotes are kept.
+    fn rest(&self, i: usize) -> Option<&'a str> {
+        let token = self.tokens.get(i)?;
+        Some(self.line[token.start..].trim_end())
+    }
+
+    /// Checks that the command wasn't given more than max arguments.
+    fn at_most(&self, max: usize) -> Result<(), String> {
+        match (max, self.len()) {
+            (_, len) if len <= max => Ok(()),
+            (0, _) => Err(format!("\"{}\" takes no arguments.", self.name)),
+            _ => Err("Junk at end of arguments.".to_string()),
+        }
+    }
+
+    /// For commands that take no arguments: returns command if there aren't any.
+    fn none(&self, command: DebuggerCommand) -> Result<DebuggerCommand, String> {
+        self.at_most(0)?;
+        Ok(command)
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        names: &["run", "r"],
+        usage: "[args...] [< infile] [> outfile]",
+        description: "Start the program (killing it first if it's already running), with its \
+                      input or output redirected to files if asked.",
+        parse: parse_run,
+    },
+    Command {
+        names: &["tty"],
+        usage: "<terminal|pty|shared>",
+        description: "Run the program on another terminal (e.g. /dev/pts/3, as printed by `tty` \
+                      there), on a pseudo-terminal of its own whose input and output deet passes \
+                      on, or on deet's terminal again. Takes effect when it's next run.",
+        parse: |args| {
+            let terminal = args.required(0, "terminal device, pty or shared")?;
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Tty(match terminal {
+                "pty" => Terminal::Pty,
+                "shared" => Terminal::Shared,
+                path => Terminal::Path(path.to_string()),
+            }))
+        },
+    },
+    Command {
+        names: &["continue", "c", "cont"],
+        usage: "",
+        description: "Keep running the program until something stops it.",
+        parse: |args| args.none(DebuggerCommand::Continue),
+    },
+    Command {
+        names: &["backtrace", "bt", "back"],
+        usage: "",
+        description: "Show the chain of function calls that led to where the program is.",
+        parse: |args| args.none(DebuggerCommand::Backtrace),
+    },
+    Command {
+        names: &["break", "b"],
+        usage: "<location> [if <condition>]",
+        description: "Stop when the program gets to a location: a function, a line number, \
+                      file:line, or *address. With a condition, only stop if it's true.",
+        parse: parse_break,
+    },
+    Command {
+        names: &["tbreak", "tb"],
+        usage: "<location> [if <condition>]",
+        description: "Like break, but the breakpoint is deleted the first time it's hit.",
+        parse: parse_break,
+    },
+    Command {
+        names: &["until", "u"],
+        usage: "[location]",
+        description: "Run to the next line past this one, so that loops run to the end, or to \
+                      a location in the current function.",
+        parse: |args| {
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Until(args.get(0).map(|s| s.to_string())))
+        },
+    },
+    Command {
+        names: &["advance"],
+        usage: "<location>",
+        description: "Run to a location.",
+        parse: |args| {
+            let location = args.required(0, "location")?;
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Advance(location.to_string()))
+        },
+    },
+    Command {
+        names: &["finish", "fin"],
+        usage: "",
+        description: "Run until the current function returns, and print the value it returns.",
+        parse: |args| args.none(DebuggerCommand::Finish),
+    },
+    Command {
+        names: &["next", "n"],
+        usage: "",
+        description: "Run to the next line, stepping over function calls.",
+        parse: |args| args.none(DebuggerCommand::Next),
+    },
+    Command {
+        names: &["step", "s"],
+        usage: "",
+        description: "Run to the next line, stepping into function calls.",
+        parse: |args| args.none(DebuggerCommand::Step),
+    },
+    Command {
+        names: &["stepi", "si"],
+        usage: "",
+        description: "Run one machine instruction.",
+        parse: |args| args.none(DebuggerCommand::StepInstruction),
+    },
+    Command {
+        names: &["print", "p"],
+        usage: "<expression>",
+        description: "Evaluate a C expression and print its value.",
+        parse: |args| match args.rest(0) {
+            Some(expr) => Ok(DebuggerCommand::Print(expr.to_string())),
+            None => Err("Argument required (expression to print).".to_string()),
+        },
+    },
+    Command {
+        names: &["call"],
+        usage: "<function>(<args>)",
+        description: "Call a function in the program and print what it returns.",
+        parse: |args| match args.rest(0) {
+            Some(expr) => Ok(DebuggerCommand::Call(expr.to_string())),
+            None => Err("Argument required (function to call).".to_string()),
+        },
+    },
+    Command {
+        names: &["set"],
+        usage: "var <variable> = <expression> | environment <name>[=<value>] | cwd [directory]",
+        description: "Change the value of a variable, or set an environment variable or the \
+                      working directory (by default, deet's) for the program to run with.",
+        parse: parse_set,
+    },
+    Command {
+        names: &["unset"],
+        usage: "environment [name]",
+        description: "Remove a variable from the environment the program runs with, or all of \
+                      them.",
+        parse: |args| {
+            let what = args.required(0, "what to unset: environment")?;
+            lookup(what, &["environment"], "unset command")?;
+            args.at_most(2)?;
+            Ok(DebuggerCommand::UnsetEnvironment(
+                args.get(1).map(|name| name.to_string()),
+            ))
+        },
+    },
+    Command {
+        names: &["show"],
+        usage: "<environment [name]|cwd|tty>",
+        description: "Show the environment, working directory or terminal the program runs with.",
+        parse: |args| {
+            let what = args.required(0, "what to show: environment, cwd or tty")?;
+            let what = lookup(what, &["environment", "cwd", "tty"], "show command")?;
+            args.at_most(if what == "environment" { 2 } else { 1 })?;
+            Ok(DebuggerCommand::Show(
+                what.to_string(),
+                args.get(1).map(|name| name.to_string()),
+            ))
+        },
+    },
+    Command {
+        names: &["x"],
+        usage: "[/count] <address>",
+        description: "Show count words of memory (by default, one) starting at an address.",
+        parse: |args| {
+            let count = match args.word.find('/').map(|i| &args.word[i + 1..]) {
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("Invalid count \"{}\".", count)),
+                },
+                None => 1,
+            };
+            let addr = args.required(0, "starting display address")?;
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Examine(count, addr.to_string()))
+        },
+    },
+    Command {
+        names: &["find"],
+        usage: "[/b|/h|/w|/g] <start>, <end|+length>, <values...>",
+        description: "Search memory from start up to and including end for a sequence of \
+                      values: strings in quotes, or expressions, each taking as many bytes as \
+                      its type does, or one, two, four or eight with /b, /h, /w or /g.",
+        parse: |args| {
+            let size = match args.word.find('/').map(|i| &args.word[i + 1..]) {
+                Some("b") => Some(1),
+                Some("h") => Some(2),
+                Some("w") => Some(4),
+                Some("g") => Some(8),
+                Some(size) => return Err(format!("Invalid size \"{}\".", size)),
+                None => None,
+            };
+            let parts = split_commas(args.rest(0).unwrap_or(""))?;
+            if parts.len() < 3 {
+                return Err(
+                    "Usage: find [/b|/h|/w|/g] <start>, <end|+length>, <values...>".to_string(),
+                );
+            }
+            Ok(DebuggerCommand::Find(size, parts))
+        },
+    },
+    Command {
+        names: &["watch"],
+        usage: "<expression>",
+        description: "Stop when the value of an expression changes.",
+        parse: |args| match args.rest(0) {
+            Some(expr) => Ok(DebuggerCommand::Watch(expr.to_string())),
+            None => Err("Argument required (expression to watch).".to_string()),
+        },
+    },
+    Command {
+        names: &["display"],
+        usage: "[expression]",
+        description: "Print the value of an expression every time the program stops. Without \
+                      one, print all the expressions being displayed now.",
+        parse: |args| {
+            Ok(DebuggerCommand::Display(
+                args.rest(0).map(|expr| expr.to_string()),
+            ))
+        },
+    },
+    Command {
+        names: &["undisplay"],
+        usage: "[numbers...]",
+        description: "Stop displaying the expressions with the given numbers (as info display \
+                      lists them), or all of them.",
+        parse: |args| {
+            let mut ids = Vec::new();
+            for token in &args.tokens {
+                match token.text.parse() {
+                    Ok(id) => ids.push(id),
+                    Err(_) => return Err(format!("Invalid display number \"{}\".", token.text)),
+                }
+            }
+            Ok(DebuggerCommand::Undisplay(ids))
+        },
+    },
+    Command {
+        names: &["catch"],
+        usage: "<syscall|signal|fork|exec|exit> [names...]",
+        description: "Stop when the program makes a system call, gets a signal, forks, execs or \
+                      exits. System calls and signals can be limited to the ones named.",
+        parse: |args| {
+            let event = args.required(0, "event to catch: syscall, signal, fork, exec or exit")?;
+            Ok(DebuggerCommand::Catch(
+                event.to_string(),
+                args.tokens[1..]
+                    .iter()
+                    .map(|token| token.text.clone())
+                    .collect(),
+            ))
+        },
+    },
+    Command {
+        names: &["info", "i"],
+        usage: "<what> [argument]",
+        description: "Show information about the program. `help info` lists what there is.",
+        parse: |args| {
+            let what = args.required(0, "what to show; try \"help info\"")?;
+            let what = match what {
+                // Short for registers, even though it's a prefix of record too
+                "r" => "registers",
+                _ => {
+                    let names: Vec<&str> = INFO_COMMANDS.iter().map(|(name, _)| *name).collect();
+                    lookup(what, &names, "info command")
+                        .map_err(|err| format!("{} Try \"help info\".", err))?
+                }
+            };
+            Ok(DebuggerCommand::Info(
+                what.to_string(),
+                args.rest(1).map(|arg| arg.to_string()),
+            ))
+        },
+    },
+    Command {
+        names: &["record", "rec"],
+        usage: "[limit|stop]",
+        description: "Start recording what the program does, so that it can be run backwards, \
+                      keeping at most limit instructions. `record stop` stops recording.",
+        parse: |args| {
+            args.at_most(1)?;
+            match args.get(0) {
+                Some("stop") => Ok(DebuggerCommand::RecordStop),
+                Some(limit) => match limit.parse() {
+                    Ok(limit) => Ok(DebuggerCommand::Record(Some(limit))),
+                    Err(_) => Err(format!("Invalid instruction limit \"{}\".", limit)),
+                },
+                None => Ok(DebuggerCommand::Record(None)),
+            }
+        },
+    },
+    Command {
+        names: &["reverse-stepi", "rsi"],
+        usage: "",
+        description: "Go back one machine instruction (while recording).",
+        parse: |args| args.none(DebuggerCommand::ReverseStepInstruction),
+    },
+    Command {
+        names: &["reverse-next", "rn"],
+        usage: "",
+        description: "Go back to the previous line, stepping over function calls (while \
+                      recording).",
+        parse: |args| args.none(DebuggerCommand::ReverseNext),
+    },
+    Command {
+        names: &["reverse-continue", "rc"],
+        usage: "",
+        description: "Run backwards until something stops the program (while recording).",
+        parse: |args| args.none(DebuggerCommand::ReverseContinue),
+    },
+    Command {
+        names: &["checkpoint"],
+        usage: "",
+        description: "Save a snapshot of the program to come back to with restart.",
+        parse: |args| args.none(DebuggerCommand::Checkpoint),
+    },
+    Command {
+        names: &["restart"],
+        usage: "<checkpoint>",
+        description: "Go back to a checkpoint.",
+        parse: |args| {
+            let id = args.required(0, "checkpoint number")?;
+            args.at_most(1)?;
+            match id.parse() {
+                Ok(id) => Ok(DebuggerCommand::Restart(id)),
+                Err(_) => Err(format!("Invalid checkpoint number \"{}\".", id)),
+            }
+        },
+    },
+    Command {
+        names: &["gcore"],
+        usage: "[file]",
+        description: "Write a core dump of the program (to core.<pid> by default).",
+        parse: |args| {
+            args.at_most(1)?;
+            Ok(DebuggerCommand::GenerateCore(
+                args.get(0).map(|path| path.to_string()),
+            ))
+        },
+    },
+    Command {
+        names: &["save"],
+        usage: "breakpoints <file> | session [file]",
+        description: "Write commands that set the breakpoints, watchpoints and catchpoints again \
+                      to a file, for source to read. A session has the settings made with set \
+                      environment, set cwd and tty and the displays too, and goes to .deetinit \
+                      beside the program by default, which deet reads when it starts.",
+        parse: |args| {
+            let what = args.required(0, "what to save: breakpoints or session")?;
+            let what = lookup(what, &["breakpoints", "session"], "save command")?;
+            let path = match (what, args.get(1)) {
+                ("breakpoints", None) => return Err("Argument required (file name).".to_string()),
+                (_, path) => path.map(|path| path.to_string()),
+            };
+            args.at_most(2)?;
+            Ok(DebuggerCommand::Save(what.to_string(), path))
+        },
+    },
+    Command {
+        names: &["source"],
+        usage: "<file>",
+        description: "Run the commands in a file, as if they were typed. Lines starting with # \
+                      are comments.",
+        parse: |args| {
+            let path = args.required(0, "file name")?;
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Source(path.to_string()))
+        },
+    },
+    Command {
+        names: &["help", "h"],
+        usage: "[command]",
+        description: "List the commands, or describe one of them.",
+        parse: |args| {
+            args.at_most(1)?;
+            Ok(DebuggerCommand::Help(args.get(0).map(|s| s.to_string())))
+        },
+    },
+    Command {
+        names: &["quit", "q"],
+        usage: "",
+        description: "Kill the program, if it's running, and exit.",
+        parse: |args| args.none(DebuggerCommand::Quit),
+    },
+];
+
+/// What `info` can show, and what `help info` says about each
+pub const INFO_COMMANDS: &[(&str, &str)] = &[
+    ("registers", "The registers (also `info r`)."),
+    ("functions", "The functions whose names match a regex."),
+    (
+        "variables",
+        "The global variables whose names match a regex.",
+    ),
+    ("types", "The types whose names match a regex."),
+    ("line", "Where the code for a line or function is."),
+    ("source", "The source file the program is stopped in."),
+    ("symbol", "The symbol an address is in."),
+    ("record", "What's been recorded."),
+    ("checkpoints", "The checkpoints."),
+    ("display", "The expressions printed when the program stops."),
+    ("proc", "The program's memory map (`info proc mappings`)."),
+    ("sharedlibrary", "The shared libraries loaded and where."),
+    ("heap", "The chunks in malloc's heap, used and free."),
+    ("bins", "malloc's lists of free chunks."),
+];
+
+/// Parses run's arguments, taking out the redirections: `<`, `>` or `2>` followed by a file
+/// name, with or without a space in between. Quoting them passes them to the program instead.
+fn parse_run(args: &Args) -> Result<DebuggerCommand, String> {
+    let mut program_args = Vec::new();
+    let mut redirections = Redirections::default();
+    let mut tokens = args.tokens.iter();
+    while let Some(token) = tokens.next() {
+        let op = ["<", ">", "2>"]
+            .iter()
+            .find(|op| !token.quoted && token.text.starts_with(*op));
+        let (op, file) = match op {
+            Some(op) => (op, &token.text[op.len()..]),
+            None => {
+                program_args.push(token.text.clone());
+                continue;
+            }
+        };
+        let file = if !file.is_empty() {
+            file.to_string()
+        } else {
+            match tokens.next() {
+                Some(next) => next.text.clone(),
+                None => return Err(format!("Missing file name after \"{}\".", op)),
+            }
+        };
+        match *op {
+            "<" => redirections.stdin = Some(file),
+            ">" => redirections.stdout = Some(file),
+            _ => redirections.stderr = Some(file),
+        }
+    }
+    Ok(DebuggerCommand::Run(program_args, redirections))
+}
+
+fn parse_set(args: &Args) -> Result<DebuggerCommand, String> {
+    let usage = "Usage: set var <variable> = <expression>, set environment <name>[=<value>] or \
+                 set cwd [directory]";
+    let what = match args.get(0) {
+        Some(what) => lookup(what, &["variable", "environment", "cwd"], "set command")
+            .map_err(|_| usage.to_string())?,
+        None => return Err(usage.to_string()),
+    };
+    match what {
+        "variable" => match args.rest(1) {
+            Some(expr) => Ok(DebuggerCommand::SetVar(expr.to_string())),
+            None => Err(usage.to_string()),
+        },
+        "environment" => {
+            // name=value, name = value or name value, where the value can have spaces in it
+            let words: Vec<&str> = args.tokens[1..]
+                .iter()
+                .map(|token| token.text.as_str())
+                .collect();
+            let setting = words.join(" ");
+            let (name, value) = match setting.find(&['=', ' '][..]) {
+                Some(i) => {
+                    let value = setting[i..].trim_start();
+                    (
+                        &setting[..i],
+                        value.strip_prefix('=').unwrap_or(value).trim_start(),
+                    )
+                }
+                None => (setting.as_str(), ""),
+            };
+            if name.is_empty() {
+                return Err("Argument required (environment variable and value).".to_string());
+            }
+            Ok(DebuggerCommand::SetEnvironment(
+                name.to_string(),
+                value.to_string(),
+            ))
+        }
+        _ => {
+            args.at_most(2)?;
+            Ok(DebuggerCommand::SetCwd(
+                args.get(1).map(|dir| dir.to_string()),
+            ))
+        }
+    }
+}
+
+fn parse_break(args: &Args) -> Result<DebuggerCommand, String> {
+    let location = args.required(0, "location")?.to_string();
+    let condition = match args.get(1) {
+        Some("if") if !args.tokens[1].quoted => match args.rest(2) {
+            Some(condition) => Some(condition.to_string()),
+            None => return Err("Argument required (condition).".to_string()),
+        },
+        Some(_) => return Err("Junk at end of arguments.".to_string()),
+        None => None,
+    };
+    if args.name == "tbreak" {
+        Ok(DebuggerCommand::TemporaryBreak(location, condition))
+    } else {
+        Ok(DebuggerCommand::Break(location, condition))
+    }
+}
+
+/// Finds which of names word means: the one it's equal to, or else the only one it's a prefix of.
+/// kind is what names are, for the error message.
+fn lookup<'n>(word: &str, names: &[&'n str], kind: &str) -> Result<&'n str, String> {
+    if let Some(name) = names.iter().find(|name| **name == word) {
+        return Ok(name);
+    }
+    let matches: Vec<&str> = names
+        .iter()
+        .filter(|name| name.starts_with(word))
+        .copied()
+        .collect();
+    match matches.len() {
+        0 => Err(format!("Undefined {}: \"{}\".", kind, word)),
+        1 => Ok(matches[0]),
+        _ => Err(format!(
+            "Ambiguous {} \"{}\": {}.",
+            kind,
+            word,
+            matches.join(", ")
+        )),
+    }
+}
+
+/// Looks up a command in the registry by any of its names, or by a unique prefix of its full
+/// name. A command can have a suffix after a slash, as in x/4.
+pub fn find_command(word: &str) -> Result<&'static Command, String> {
+    let name = word.split('/').next().unwrap();
+    // Abbreviations win over prefixes: s is step, even though it's a prefix of set too
+    if let Some(command) = COMMANDS
+        .iter()
+        .find(|command| command.names.contains(&name))
+    {
+        return Ok(command);
+    }
+    let names: Vec<&str> = COMMANDS.iter().map(|command| command.names[0]).collect();
+    let name = lookup(name, &names, "command").map_err(|err| format!("{} Try \"help\".", err))?;
+    Ok(COMMANDS
+        .iter()
+        .find(|command| command.names[0] == name)
+        .unwrap())
+}
+
+/// Splits find's arguments at the commas that aren't inside quotes or brackets, as in
+/// `find &buf[0], +sizeof(buf), "a,b", f(1, 2)`.
+fn split_commas(line: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                part.push(c);
+                part.extend(chars.next());
+                continue;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') | (None, '[') => depth += 1,
+            (None, ')') | (None, ']') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(part.trim().to_string());
+                part.clear();
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    if quote.is_some() {
+        return Err("Unterminated quoted string.".to_string());
+    }
+    parts.push(part.trim().to_string());
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err("Empty argument to find.".to_string());
+    }
+    Ok(parts)
+}
+
+/// Splits a command line into words. Single or double quotes keep spaces in a word, and a
+/// backslash (outside single quotes) keeps the character after it as it is.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    loop {
+        // Skip to the start of the next word
+        while let Some((_, c)) = chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        let start = match chars.peek() {
+            Some((i, _)) => *i,
+            None => return Ok(tokens),
+        };
+        let mut token = Token {
+            text: String::new(),
+            start,
+            quoted: false,
+        };
+        let mut quote = None;
+        while let Some((_, c)) = chars.next() {
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None, '\'') | (None, '"') => {
+                    quote = Some(c);
+                    token.quoted |= token.text.is_empty();
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some('\''), c) => token.text.push(c),
+                (_, '\\') => match chars.next() {
+                    Some((_, escaped)) => {
+                        token.quoted |= token.text.is_empty();
+                        token.text.push(escaped);
+                    }
+                    None => token.text.push('\\'),
+                },
+                (_, c) => token.text.push(c),
+            }
+        }
+        if quote.is_some() {
+            return Err("Unterminated quoted string.".to_string());
+        }
+        tokens.push(token);
+    }
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
-            "q" | "quit" => Some(DebuggerCommand::Quit),
-            "r" | "run" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Run(
-                    args.iter().map(|s| s.to_string()).collect(),
-                ))
-            }
-            "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
-            "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => {
-                let addr = tokens[1];
-                Some(DebuggerCommand::Break(addr.to_string()))
-            }
-            // Default case:
-            _ => None,
+    /// Parses a command line, or explains what's wrong with it.
+    pub fn from_line(line: &str) -> Result<DebuggerCommand, String> {
+        let mut tokens = tokenize(line)?;
+        if tokens.is_empty() {
+            return Err("No command given.".to_string());
+        }
+        let first = tokens.remove(0);
+        let command = find_command(&first.text)?;
+        // Only x and find take a suffix
+        if first.text.contains('/') && command.names[0] != "x" && command.names[0] != "find" {
+            return Err(format!("\"{}\" doesn't take a /suffix.", command.names[0]));
         }
+        let args = Args {
+            name: command.names[0],
+            word: &first.text,
+            line,
+            tokens,
+        };
+        (command.parse)(&args)
     }
 }
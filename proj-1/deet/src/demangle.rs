@@ -0,0 +1,32 @@
+//! Turns the mangled symbol names that compilers emit for Rust and C++ functions back into the
+//! paths they were written as.
+
+/// Demangles a Rust (legacy or v0) or Itanium C++ symbol, returning the name as-is if it isn't
+/// mangled.
+pub fn demangle(name: &str) -> String {
+    // Legacy Rust symbols are valid Itanium symbols too, so they have to be told apart by the
+    // hash they end with
+    if name.starts_with("_R") || has_rust_hash(name) {
+        if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+            // The alternate format leaves off the hash
+            return format!("{:#}", demangled);
+        }
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&cpp_demangle::DemangleOptions::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Returns true if name looks like a legacy Rust symbol, whose last path component is `h`
+/// followed by a 16 digit hex hash, e.g. `_ZN2rs4main17h5bc8a1e2d5f7b0c3E`.
+fn has_rust_hash(name: &str) -> bool {
+    name.match_indices("17h").any(|(i, _)| {
+        let hash = &name.as_bytes()[i + 3..];
+        hash.len() > 16 && hash[16] == b'E' && hash[..16].iter().all(|b| b.is_ascii_hexdigit())
+    })
+}
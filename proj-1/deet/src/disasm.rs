@@ -0,0 +1,119 @@
+//! Decoding the inferior's machine code, built on iced-x86.
+
+use crate::arch::Registers;
+use crate::debugger::Breakpoint;
+use crate::process_image::ProcessImage;
+use iced_x86::{Code, Decoder, DecoderOptions, Instruction, Register};
+use std::collections::HashMap;
+
+/// The longest an x86-64 instruction can be.
+const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// How many instructions into a function we look for its `push rbp; mov rbp, rsp` prologue. An
+/// endbr64 can come first.
+const PROLOGUE_SCAN_LEN: usize = 4;
+
+/// Decodes the instruction at addr. Breakpoints are patched out of the bytes we read, so the
+/// result is the instruction the program will actually execute.
+pub fn decode_instruction(
+    image: &dyn ProcessImage,
+    addr: usize,
+    breakpoints: &HashMap<usize, Breakpoint>,
+) -> Option<Instruction> {
+    let mut bytes = Vec::with_capacity(MAX_INSTRUCTION_LEN + 8);
+    // Read a word at a time so that an instruction near the end of a mapping still decodes
+    while bytes.len() < MAX_INSTRUCTION_LEN {
+        match image.read_word(addr + bytes.len()) {
+            Ok(word) => bytes.extend_from_slice(&word.to_le_bytes()),
+            Err(_) => break,
+        }
+    }
+    for bp in breakpoints.values() {
+        for (i, orig_byte) in bp.orig_bytes.iter().enumerate() {
+            let offset = (bp.addr + i).wrapping_sub(addr);
+            if let Some(byte) = bytes.get_mut(offset) {
+                *byte = *orig_byte;
+            }
+        }
+    }
+    let mut decoder = Decoder::with_ip(64, &bytes, addr as u64, DecoderOptions::NONE);
+    let instruction = decoder.decode();
+    if instruction.is_invalid() {
+        None
+    } else {
+        Some(instruction)
+    }
+}
+
+/// Returns the canonical frame address of the function starting at func_start that rip is in:
+/// the value rsp will have once it returns, just above its return address. The function must use
+/// a frame pointer; how we find the frame depends on how much of its prologue has run. Returns
+/// None if the function doesn't start with the usual prologue.
+pub fn frame_address(
+    image: &dyn ProcessImage,
+    func_start: usize,
+    regs: &Registers,
+    breakpoints: &HashMap<usize, Breakpoint>,
+) -> Option<usize> {
+    let (rip, rsp, rbp) = (regs.rip as usize, regs.rsp as usize, regs.rbp as usize);
+    let mut addr = func_start;
+    let mut pushed_rbp = false;
+    for _ in 0..PROLOGUE_SCAN_LEN {
+        if addr == rip {
+            // The return address is on top of the stack, under the saved rbp if it was pushed
+            return Some(if pushed_rbp { rsp + 16 } else { rsp + 8 });
+        }
+        let instruction = decode_instruction(image, addr, breakpoints)?;
+        let is_mov_rbp_rsp = matches!(instruction.code(), Code::Mov_rm64_r64 | Code::Mov_r64_rm64)
+            && instruction.op0_register() == Register::RBP
+            && instruction.op1_register() == Register::RSP;
+        if instruction.code() == Code::Push_r64 && instruction.op0_register() == Register::RBP {
+            pushed_rbp = true;
+        } else if pushed_rbp && is_mov_rbp_rsp {
+            // rip is past the prologue, so rbp points at the saved rbp
+            return Some(rbp + 16);
+        }
+        addr = instruction.next_ip() as usize;
+    }
+    None
+}
+
+/// Returns the value of an iced register operand (including sub-registers like eax or al, and the
+/// fs/gs segment bases) given the inferior's registers. Returns None for registers that aren't in
+/// user_regs_struct, such as vector registers.
+pub fn register_value(regs: &Registers, register: Register) -> Option<u64> {
+    let full = match register.full_register() {
+        Register::RAX => regs.rax,
+        Register::RBX => regs.rbx,
+        Register::RCX => regs.rcx,
+        Register::RDX => regs.rdx,
+        Register::RSI => regs.rsi,
+        Register::RDI => regs.rdi,
+        Register::RBP => regs.rbp,
+        Register::RSP => regs.rsp,
+        Register::R8 => regs.r8,
+        Register::R9 => regs.r9,
+        Register::R10 => regs.r10,
+        Register::R11 => regs.r11,
+        Register::R12 => regs.r12,
+        Register::R13 => regs.r13,
+        Register::R14 => regs.r14,
+        Register::R15 => regs.r15,
+        Register::RIP => regs.rip,
+        Register::FS => regs.fs_base,
+        Register::GS => regs.gs_base,
+        // Other segments have a base of 0 in 64-bit mode
+        Register::CS | Register::DS | Register::ES | Register::SS => 0,
+        _ => return None,
+    };
+    Some(match register {
+        Register::AH | Register::BH | Register::CH | Register::DH => (full >> 8) & 0xff,
+        _ if register.is_segment_register() => full,
+        _ => match register.size() {
+            1 => full & 0xff,
+            2 => full & 0xffff,
+            4 => full & 0xffff_ffff,
+            _ => full,
+        },
+    })
+}
@@ -1,8 +1,17 @@
+use crate::demangle::demangle;
+use crate::dwarf_index;
 use crate::gimli_wrapper;
+use crate::gimli_wrapper::{DwarfReader, DwoRef, SplitDwarf};
 use addr2line::Context;
 use object::Object;
+use once_cell::unsync::OnceCell;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::{fmt, fs};
+use std::path::Path;
+use std::{fmt, fs, sync};
+
+/// Where separate debug info files are installed, e.g. by distributions' -dbg packages
+const DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
 
 #[derive(Debug)]
 pub enum Error {
@@ -10,14 +19,54 @@ pub enum Error {
     DwarfFormatError(gimli_wrapper::Error),
 }
 
+/// The debug info of an executable. Reading all of it up front takes far too long for a big
+/// binary, so at first we only read the list of compilation units and the indexes of what's in
+/// them, and each unit is parsed the first time we need something from it.
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// The path of the executable
+    path: String,
+    dwarf: Option<gimli::Dwarf<DwarfReader>>,
+    units: Vec<Unit>,
+    /// The address ranges of the units' code, sorted, with the index of the unit each is in
+    unit_ranges: Vec<(usize, usize, usize)>,
+    /// The indexes of the units that define each function, variable and type, from the file's
+    /// .gdb_index or .debug_names if it has one, or else built the first time we look up a name
+    name_index: OnceCell<HashMap<String, Vec<usize>>>,
+    /// Function symbols from the ELF symbol tables, for code we have no debug info for
+    symbols: Vec<Symbol>,
+    /// The executable's .dwp package of split DWARF, if it has one, loaded the first time a unit
+    /// built with -gsplit-dwarf is
+    dwp: OnceCell<Option<SplitDwarf>>,
+    /// The file the debug info is in, which is the executable unless it was stripped
+    debug_file: Option<sync::Arc<memmap::Mmap>>,
+    /// addr2line's context, built the first time we look up an address, since that means parsing
+    /// every unit's line table. addr2line can't read split DWARF, so we don't use it for
+    /// executables that have any.
+    addr2line: OnceCell<Option<Context<Addr2lineReader>>>,
+}
+
+type Addr2lineReader = addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>;
+
+/// A compilation unit, whose contents are loaded the first time they're needed
+struct Unit {
+    /// The offset of the unit in .debug_info
+    offset: usize,
+    name: String,
+    /// Where the unit's DIEs are, if it was built with -gsplit-dwarf
+    dwo: Option<DwoRef>,
+    contents: OnceCell<UnitContents>,
+}
+
+struct UnitContents {
+    file: File,
+    /// Every type in the unit, keyed by the offset of its DIE in .debug_info
+    types: HashMap<usize, Type>,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        let names: Vec<&str> = self.units.iter().map(|unit| unit.name.as_str()).collect();
+        write!(f, "DwarfData {{units: {:?}}}", names)
     }
 }
 
@@ -28,69 +77,341 @@ impl From<gimli_wrapper::Error> for Error {
 }
 
 impl DwarfData {
+    /// Loads the debug info for the executable at path, from the file itself or, if it has been
+    /// stripped, from a separate debug file found through its build ID or .gnu_debuglink.
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
-        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
-        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
+        let mmap = sync::Arc::new(map_file(path)?);
+        let object = object::File::parse(&**mmap)
             .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        let mut symbols = load_symbols(&object);
+
+        let debug_mmap = match object.section_data_by_name(".debug_info") {
+            Some(_) => None,
+            None => match find_debug_file(path, &object) {
+                Some(debug_path) => Some(sync::Arc::new(map_file(&debug_path)?)),
+                None => None,
+            },
+        };
+        let debug_object = match debug_mmap {
+            Some(ref debug_mmap) => Some(
+                object::File::parse(&***debug_mmap)
+                    .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?,
+            ),
+            None => None,
+        };
+        if let Some(ref debug_object) = debug_object {
+            symbols.extend(load_symbols(debug_object));
+        }
+        let object = debug_object.as_ref().unwrap_or(&object);
+        let file = debug_mmap.as_ref().unwrap_or(&mmap);
+
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let dwarf = gimli_wrapper::load_dwarf(file, object, endian)?;
+
+        // .gdb_index has both names and addresses. Otherwise, names may be in .debug_names and
+        // addresses in .debug_aranges, and if they aren't, we read each unit's address ranges
+        // from its first DIE.
+        let (name_index, address_index) = match object
+            .section_data_by_name(".gdb_index")
+            .and_then(|data| dwarf_index::read_gdb_index(&data))
+        {
+            Some((names, addresses)) => (Some(names), Some(addresses)),
+            None => (None, None),
+        };
+        let name_index = name_index.or_else(|| {
+            let debug_names = object.section_data_by_name(".debug_names")?;
+            let debug_str = object
+                .section_data_by_name(".debug_str")
+                .unwrap_or_default();
+            dwarf_index::read_debug_names(&debug_names, &debug_str)
+        });
+        let address_index = address_index
+            .or_else(|| dwarf_index::read_aranges(&object.section_data_by_name(".debug_aranges")?))
+            .filter(|addresses| !addresses.is_empty());
+        let summaries = gimli_wrapper::load_unit_summaries(&dwarf, address_index.is_none())?;
+
+        let mut unit_ranges: Vec<(usize, usize, usize)> = summaries
+            .iter()
+            .enumerate()
+            .flat_map(|(i, summary)| summary.ranges.iter().map(move |(s, e)| (*s, *e, i)))
+            .collect();
+        let units: Vec<Unit> = summaries
+            .into_iter()
+            .map(|summary| Unit {
+                offset: summary.offset,
+                name: summary.name,
+                dwo: summary.dwo,
+                contents: OnceCell::new(),
+            })
+            .collect();
+        if let Some(addresses) = address_index {
+            unit_ranges = addresses
+                .into_iter()
+                .filter_map(|(start, end, offset)| Some((start, end, unit_index(&units, offset)?)))
+                .collect();
+        }
+        unit_ranges.retain(|(start, end, _)| start < end);
+        unit_ranges.sort();
+        let name_cell = OnceCell::new();
+        if let Some(names) = name_index {
+            let _ = name_cell.set(unit_indexes(names, &units));
+        }
+
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            path: path.to_string(),
+            dwarf: Some(dwarf),
+            units,
+            unit_ranges,
+            name_index: name_cell,
+            symbols: sort_symbols(symbols),
+            dwp: OnceCell::new(),
+            debug_file: Some(file.clone()),
+            addr2line: OnceCell::new(),
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    /// Loads just the ELF symbol tables of the executable at path, for when its debug info can't
+    /// be read. That's enough to set breakpoints on functions and name them in backtraces.
+    pub fn from_symbol_table(path: &str) -> Result<DwarfData, Error> {
+        let mmap = map_file(path)?;
+        let object = object::File::parse(&*mmap)
+            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        Ok(DwarfData {
+            path: path.to_string(),
+            dwarf: None,
+            units: Vec::new(),
+            unit_ranges: Vec::new(),
+            name_index: OnceCell::new(),
+            symbols: sort_symbols(load_symbols(&object)),
+            dwp: OnceCell::new(),
+            debug_file: None,
+            addr2line: OnceCell::new(),
         })
     }
 
+    /// Returns true if there's debug info for at least one compilation unit.
+    pub fn has_debug_info(&self) -> bool {
+        !self.units.is_empty()
+    }
+
+    /// Returns the contents of the unit at index in units, loading them if this is the first time
+    /// we've looked.
+    fn unit(&self, index: usize) -> &UnitContents {
+        let unit = &self.units[index];
+        unit.contents.get_or_init(|| {
+            let loaded = match self.dwarf {
+                Some(ref dwarf) => self.with_split_dwarf(unit, |split| {
+                    gimli_wrapper::load_unit(dwarf, unit.offset, split)
+                }),
+                None => Err(gimli_wrapper::Error::IoError),
+            };
+            match loaded {
+                Ok((file, types)) => UnitContents { file, types },
+                Err(err) => {
+                    println!("Could not load debug info for {}: {:?}", unit.name, err);
+                    UnitContents {
+                        file: File {
+                            name: unit.name.clone(),
+                            ..Default::default()
+                        },
+                        types: HashMap::new(),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Calls f with the split DWARF of a unit built with -gsplit-dwarf: the executable's .dwp
+    /// package if the unit is in it, or else its .dwo file. f gets None for other units, and for
+    /// ones whose split DWARF we can't find, which leaves us just their lines.
+    fn with_split_dwarf<T>(&self, unit: &Unit, f: impl FnOnce(Option<&SplitDwarf>) -> T) -> T {
+        let (dwarf, dwo) = match (self.dwarf.as_ref(), unit.dwo.as_ref()) {
+            (Some(dwarf), Some(dwo)) => (dwarf, dwo),
+            _ => return f(None),
+        };
+        let dwp = self
+            .dwp
+            .get_or_init(|| load_split_dwarf(&format!("{}.dwp", self.path), dwarf));
+        if let Some(dwp) = dwp.as_ref().filter(|dwp| dwp.contains(dwo.id)) {
+            return f(Some(dwp));
+        }
+        // The .dwo file may have moved along with the executable since it was built
+        let beside = Path::new(&self.path)
+            .with_file_name(Path::new(&dwo.path).file_name().unwrap_or_default());
+        let split = load_split_dwarf(&dwo.path, dwarf)
+            .or_else(|| load_split_dwarf(beside.to_str()?, dwarf));
+        if split.is_none() {
+            println!("Could not load split debug info from {}", dwo.path);
+        }
+        f(split.as_ref())
+    }
+
+    /// Returns the contents of every unit, loading all of them. That's slow for a big binary, so
+    /// it's only for when we have to look at everything.
+    fn all_units(&self) -> impl Iterator<Item = &UnitContents> {
+        (0..self.units.len()).map(move |i| self.unit(i))
+    }
+
+    /// Returns the unit whose code contains addr.
+    fn unit_containing(&self, addr: usize) -> Option<&UnitContents> {
+        let after = self
+            .unit_ranges
+            .partition_point(|(start, _, _)| *start <= addr);
+        let (_, end, index) = self.unit_ranges.get(after.checked_sub(1)?)?;
+        if addr < *end {
+            Some(self.unit(*index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the unit the DIE at offset in .debug_info belongs to. DIEs in split units have
+    /// offsets that tell us their skeleton unit's.
+    fn unit_with_die(&self, offset: usize) -> Option<&UnitContents> {
+        let offset = gimli_wrapper::get_skeleton_offset(offset).unwrap_or(offset);
+        let after = self.units.partition_point(|unit| unit.offset <= offset);
+        Some(self.unit(after.checked_sub(1)?))
+    }
+
+    /// Returns the indexes of the units that define name, building the name index if the file
+    /// didn't have one.
+    fn units_defining(&self, name: &str) -> Vec<usize> {
+        let index = self.name_index.get_or_init(|| {
+            let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+            let dwarf = match self.dwarf {
+                Some(ref dwarf) => dwarf,
+                None => return index,
+            };
+            for (i, unit) in self.units.iter().enumerate() {
+                let names = self.with_split_dwarf(unit, |split| {
+                    gimli_wrapper::load_unit_names(dwarf, unit.offset, split)
+                });
+                for name in names.unwrap_or_default() {
+                    let indexes = index.entry(name).or_default();
+                    if indexes.last() != Some(&i) {
+                        indexes.push(i);
+                    }
+                }
+            }
+            index
+        });
+        index.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Looks up a source file by its full path, or by the end of it, e.g. its name alone. There can
+    /// be several: rustc splits a crate into codegen units, all named after its root file.
+    fn target_files<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a File> + 'a {
+        let suffix = format!("/{}", file);
+        self.units
+            .iter()
+            .enumerate()
+            .filter(move |(_, unit)| unit.name == file || unit.name.ends_with(&suffix))
+            .map(move |(index, _)| &self.unit(index).file)
+    }
+
+    /// Returns the paths of the source files of the compilation units, without loading them.
+    pub fn unit_names(&self) -> impl Iterator<Item = &str> {
+        self.units.iter().map(|unit| unit.name.as_str())
+    }
+
+    /// Returns the source file of the compilation unit whose code contains addr.
+    pub fn get_file_containing(&self, addr: usize) -> Option<&File> {
+        Some(&self.unit_containing(addr)?.file)
+    }
+
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
-        };
-        Some(
-            target_file
-                .lines
+        let first_line_in = |file: &File| {
+            file.lines
                 .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+                .find(|line| line.number >= line_number)
+                .map(|line| (line.number, line.address))
+        };
+        let (_, address) = match file {
+            Some(filename) => self
+                .target_files(filename)
+                .filter_map(first_line_in)
+                .min_by_key(|(number, _)| *number)?,
+            None if self.units.is_empty() => return None,
+            None => first_line_in(&self.unit(0).file)?,
+        };
+        Some(address)
     }
 
+    /// Returns the address of the out-of-line copy of a function. (A function that was only ever
+    /// inlined has none; see get_addrs_for_function.)
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        // C++ names come with their parameter types, e.g. geo::area(int, int)
+        let is_named = |name: &str| name.split('(').next() == Some(func_name);
+        let has_code = |func: &&Function| {
+            (func.name == func_name || is_named(&func.qualified_name)) && func.text_length > 0
+        };
+        let find_in =
+            |contents: &UnitContents| Some(contents.file.functions.iter().find(has_code)?.address);
         match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
+            Some(filename) => self
+                .target_files(filename)
+                .find_map(|file| Some(file.functions.iter().find(has_code)?.address)),
             None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
+                if let Some(addr) = self
+                    .units_defining(func_name)
+                    .into_iter()
+                    .find_map(|i| find_in(self.unit(i)))
+                {
+                    return Some(addr);
+                }
+                // Otherwise the symbol table can tell us which unit to look in. It mustn't come
+                // first: in a Rust program, the symbol called main is the C entry point that calls
+                // the program's main.
+                let symbol = self.symbols.iter().find(|symbol| is_named(&symbol.name))?;
+                let unit = self.unit_containing(symbol.address);
+                Some(unit.and_then(find_in).unwrap_or(symbol.address))
+            }
+        }
+    }
+
+    /// Returns every address where a function's code starts: its out-of-line copy, if it has one,
+    /// and each place it was inlined.
+    pub fn get_addrs_for_function(&self, func_name: &str) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .get_addr_for_function(None, func_name)
+            .into_iter()
+            .collect();
+        for i in self.units_defining(func_name) {
+            for call in &self.unit(i).file.inlined_calls {
+                if call.name == func_name && !addrs.contains(&call.address) {
+                    addrs.push(call.address);
                 }
-                None
             }
         }
+        addrs
+    }
+
+    /// Returns addr2line's context, building it if this is the first lookup.
+    fn addr2line(&self) -> Option<&Context<Addr2lineReader>> {
+        self.addr2line
+            .get_or_init(|| {
+                if self.units.iter().any(|unit| unit.dwo.is_some()) {
+                    return None;
+                }
+                let object = object::File::parse(&***self.debug_file.as_ref()?).ok()?;
+                Context::new(&object).ok()
+            })
+            .as_ref()
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
-            .addr2line
+        let addr2line = match self.addr2line() {
+            Some(addr2line) => addr2line,
+            None => return self.get_line_in_unit(curr_addr),
+        };
+        let location = addr2line
             .find_location(curr_addr.try_into().unwrap())
             .ok()??;
         Some(Line {
@@ -102,65 +423,482 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+        let frame = self.get_frames(curr_addr).into_iter().next()?;
+        Some(frame.function)
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
+    /// Returns the symbol table entry for the function containing curr_addr.
+    pub fn get_symbol_containing(&self, curr_addr: usize) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            curr_addr == symbol.address
+                || (curr_addr > symbol.address && curr_addr < symbol.address + symbol.size)
+        })
+    }
+
+    /// Returns the chain of calls that the code at curr_addr is part of, innermost first: any
+    /// functions inlined there, then the function they were inlined into. Each frame's line is
+    /// where it was executing, which for the outer ones is the line of the inlined call.
+    /// Without debug info, the only frame is the function from the symbol table.
+    pub fn get_frames(&self, curr_addr: usize) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let found = match self.addr2line() {
+            Some(addr2line) => addr2line.find_frames(curr_addr.try_into().unwrap()).ok(),
+            None => None,
+        };
+        let mut iter = match found {
+            Some(iter) => iter,
+            None if self.addr2line().is_none() && self.unit_containing(curr_addr).is_some() => {
+                return self.get_unit_frames(curr_addr)
             }
+            None => return self.get_symbol_frame(curr_addr).into_iter().collect(),
+        };
+        while let Ok(Some(frame)) = iter.next() {
+            let function = match frame.function.as_ref().and_then(|f| f.raw_name().ok()) {
+                Some(name) => demangle(&name),
+                None => continue,
+            };
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line?.try_into().unwrap(),
+                    address: curr_addr,
+                })
+            });
+            frames.push(Frame {
+                function,
+                line,
+                inlined: true,
+            });
+        }
+        if let Some(outermost) = frames.last_mut() {
+            outermost.inlined = false;
+        }
+        if frames.is_empty() {
+            frames.extend(self.get_symbol_frame(curr_addr));
+        }
+        frames
+    }
 
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
+    /// Returns the line containing curr_addr from our own line table, for when we can't use
+    /// addr2line.
+    fn get_line_in_unit(&self, curr_addr: usize) -> Option<Line> {
+        let line = self
+            .unit_containing(curr_addr)?
+            .file
+            .lines
+            .iter()
+            .filter(|line| line.address <= curr_addr)
+            .max_by_key(|line| line.address)?;
+        Some(Line {
+            address: curr_addr,
+            ..line.clone()
+        })
+    }
+
+    /// Works out the frames at curr_addr (see get_frames) from the unit's functions and inlined
+    /// calls, for when we can't use addr2line. Inlined calls nest, so the innermost of those
+    /// containing curr_addr is the one with the least code.
+    fn get_unit_frames(&self, curr_addr: usize) -> Vec<Frame> {
+        let contents = match self.unit_containing(curr_addr) {
+            Some(contents) => contents,
+            None => return Vec::new(),
+        };
+        let contains = |ranges: &[(usize, usize)]| {
+            ranges
+                .iter()
+                .any(|(start, end)| curr_addr >= *start && curr_addr < *end)
+        };
+        let size = |call: &&InlinedCall| -> usize {
+            call.ranges.iter().map(|(start, end)| end - start).sum()
+        };
+        let mut calls: Vec<&InlinedCall> = contents
+            .file
+            .inlined_calls
+            .iter()
+            .filter(|call| contains(&call.ranges))
+            .collect();
+        calls.sort_by_key(size);
+        let mut frames = Vec::new();
+        let mut line = self.get_line_in_unit(curr_addr);
+        for call in calls {
+            frames.push(Frame {
+                function: call.name.clone(),
+                line: line.clone(),
+                inlined: true,
+            });
+            line = line.map(|line| Line {
+                number: call.call_line,
+                ..line
+            });
+        }
+        match self.get_function_containing(curr_addr) {
+            Some(func) => frames.push(Frame {
+                function: func.name.clone(),
+                line,
+                inlined: false,
+            }),
+            None => frames.extend(self.get_symbol_frame(curr_addr)),
+        }
+        frames
+    }
+
+    fn get_symbol_frame(&self, curr_addr: usize) -> Option<Frame> {
+        Some(Frame {
+            function: self.get_symbol_containing(curr_addr)?.name.clone(),
+            line: None,
+            inlined: false,
+        })
+    }
+
+    /// Returns the address where the body of the function starting at func_addr begins, just
+    /// past its prologue: the second line table entry within it.
+    pub fn get_prologue_end(&self, func_addr: usize) -> Option<usize> {
+        let func = self.get_function_at(func_addr)?;
+        let end = func.address + func.text_length;
+        self.unit_containing(func_addr)?
+            .file
+            .lines
+            .iter()
+            .map(|line| line.address)
+            .filter(|addr| *addr > func.address && *addr < end)
+            .min()
+    }
+
+    /// Returns the function whose code contains curr_addr.
+    pub fn get_function_containing(&self, curr_addr: usize) -> Option<&Function> {
+        self.unit_containing(curr_addr)?
+            .file
+            .functions
+            .iter()
+            .find(|func| curr_addr >= func.address && curr_addr < func.address + func.text_length)
+    }
+
+    /// Returns the function that starts at addr.
+    pub fn get_function_at(&self, addr: usize) -> Option<&Function> {
+        self.unit_containing(addr)?
+            .file
+            .functions
+            .iter()
+            .find(|func| func.address == addr && func.text_length > 0)
+    }
+
+    /// Looks up a variable by name as seen from curr_addr: locals and parameters of the enclosing
+    /// function take precedence over globals.
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<&Variable> {
+        if let Some(func) = self.get_function_containing(curr_addr) {
+            if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                return Some(var);
             }
+        }
+        self.units_defining(name).into_iter().find_map(|i| {
+            let globals = &self.unit(i).file.global_variables;
+            globals.iter().find(|var| var.name == name)
+        })
+    }
+
+    /// Returns the type whose DIE is at offset, as referenced by a struct member.
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        self.unit_with_die(offset)?.types.get(&offset)
+    }
+
+    /// Looks up a type by the name a C programmer would write, e.g. `struct node` or a typedef.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        // The name index has struct node as just node
+        let indexed_name = name.rsplit(' ').next().unwrap();
+        self.units_defining(indexed_name)
+            .into_iter()
+            .find_map(|i| self.unit(i).types.values().find(|t| t.name == name))
+    }
+
+    /// Looks up an enumeration constant, returning the enum it belongs to and its value.
+    pub fn get_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.units_defining(name).into_iter().find_map(|i| {
+            self.unit(i).types.values().find_map(|t| match t.kind {
+                TypeKind::Enum(ref enumerators) => enumerators
+                    .iter()
+                    .find(|(enumerator, _)| enumerator == name)
+                    .map(|(_, value)| (t, *value)),
+                _ => None,
+            })
+        })
+    }
+
+    /// Returns the addresses where the line containing addr starts and where the next one does.
+    pub fn get_line_extent(&self, addr: usize) -> Option<(usize, usize)> {
+        let addrs = self
+            .unit_containing(addr)?
+            .file
+            .lines
+            .iter()
+            .map(|line| line.address);
+        let start = addrs.clone().filter(|line_addr| *line_addr <= addr).max()?;
+        let end = addrs.filter(|line_addr| *line_addr > addr).min()?;
+        Some((start, end))
+    }
+
+    /// Returns the global variable whose storage contains addr.
+    pub fn get_global_containing(&self, addr: usize) -> Option<&Variable> {
+        self.files()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| match var.location {
+                Location::Address(start) => {
+                    addr == start || (addr > start && addr < start + var.entity_type.size)
+                }
+                _ => false,
+            })
+    }
+
+    /// Returns every source file we have debug info for.
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        self.all_units().map(|contents| &contents.file)
+    }
+
+    /// Returns every type in the debug info.
+    pub fn types(&self) -> impl Iterator<Item = &Type> {
+        self.all_units()
+            .flat_map(|contents| contents.types.values())
+    }
+
+    /// Returns every function we have debug info for, along with the file it's in.
+    pub fn functions(&self) -> impl Iterator<Item = (&str, &Function)> {
+        self.files().flat_map(|file| {
+            let name = file.name.as_str();
+            file.functions.iter().map(move |func| (name, func))
+        })
+    }
+
+    /// Returns the function symbols from the symbol tables, sorted by address.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+/// Returns the index in units of the unit at offset in .debug_info.
+fn unit_index(units: &[Unit], offset: usize) -> Option<usize> {
+    units.binary_search_by_key(&offset, |unit| unit.offset).ok()
+}
+
+/// Converts a name index from the offsets of units to their indexes in units.
+fn unit_indexes(names: dwarf_index::NameIndex, units: &[Unit]) -> HashMap<String, Vec<usize>> {
+    names
+        .into_iter()
+        .map(|(name, offsets)| {
+            let indexes = offsets
+                .iter()
+                .filter_map(|offset| unit_index(units, *offset));
+            (name, indexes.collect())
+        })
+        .collect()
+}
+
+fn map_file(path: &str) -> Result<memmap::Mmap, Error> {
+    let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+    unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile)) }
+}
+
+/// Loads the split DWARF in the .dwo file or .dwp package at path, if there is one.
+fn load_split_dwarf(path: &str, dwarf: &gimli::Dwarf<DwarfReader>) -> Option<SplitDwarf> {
+    let mmap = sync::Arc::new(map_file(path).ok()?);
+    let object = object::File::parse(&**mmap).ok()?;
+    let endian = if object.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    gimli_wrapper::load_split_dwarf(&mmap, &object, endian, dwarf).ok()
+}
+
+/// Reads the function symbols from an object's .symtab and .dynsym.
+fn load_symbols(object: &object::File) -> Vec<Symbol> {
+    object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|(_, symbol)| {
+            symbol.kind() == object::SymbolKind::Text
+                && !symbol.is_undefined()
+                && symbol.address() != 0
+        })
+        .filter_map(|(_, symbol)| {
+            Some(Symbol {
+                name: demangle(symbol.name()?),
+                address: symbol.address().try_into().unwrap(),
+                size: symbol.size().try_into().unwrap(),
+            })
+        })
+        .collect()
+}
+
+/// Sorts symbols by address and drops the duplicates between .symtab and .dynsym.
+fn sort_symbols(mut symbols: Vec<Symbol>) -> Vec<Symbol> {
+    symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+    symbols.dedup_by(|a, b| a.address == b.address && a.name == b.name);
+    symbols
+}
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+/// Looks for the separate debug file of a stripped executable the way gdb does: by build ID
+/// under /usr/lib/debug/.build-id, then by the name in its .gnu_debuglink section, next to the
+/// executable, in a .debug directory there, or under /usr/lib/debug.
+fn find_debug_file(path: &str, object: &object::File) -> Option<String> {
+    if let Some(build_id) = get_build_id(object) {
+        if build_id.len() > 1 {
+            let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            let debug_path = format!(
+                "{}/.build-id/{}/{}.debug",
+                DEBUG_FILE_DIRECTORY,
+                &hex[..2],
+                &hex[2..]
+            );
+            if Path::new(&debug_path).exists() {
+                return Some(debug_path);
             }
         }
     }
+    let (name, crc) = get_debuglink(object)?;
+    let dir = fs::canonicalize(path).ok()?.parent()?.to_path_buf();
+    let candidates = [
+        dir.join(&name),
+        dir.join(".debug").join(&name),
+        Path::new(DEBUG_FILE_DIRECTORY)
+            .join(dir.strip_prefix("/").unwrap_or(&dir))
+            .join(&name),
+    ];
+    // The CRC makes sure we don't pick up a debug file for a different build
+    let debug_path = candidates
+        .iter()
+        .find(|candidate| matches!(fs::read(candidate), Ok(data) if crc32(&data) == crc))?;
+    Some(debug_path.to_str()?.to_string())
+}
+
+/// Reads the build ID from the .note.gnu.build-id section. The section holds an ELF note: the
+/// sizes of its name and descriptor and its type, then the name ("GNU") and the descriptor (the
+/// ID itself), each padded to 4 bytes.
+fn get_build_id(object: &object::File) -> Option<Vec<u8>> {
+    let note = object.section_data_by_name(".note.gnu.build-id")?;
+    let word = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(note.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    let (name_size, id_size) = (word(0)?, word(4)?);
+    let id_start = 12 + ((name_size + 3) & !3);
+    Some(note.get(id_start..id_start + id_size)?.to_vec())
+}
+
+/// Reads the .gnu_debuglink section: the name of the debug file, NUL-terminated and padded to 4
+/// bytes, followed by the CRC32 of its contents.
+fn get_debuglink(object: &object::File) -> Option<(String, u32)> {
+    let section = object.section_data_by_name(".gnu_debuglink")?;
+    let name_len = section.iter().position(|b| *b == 0)?;
+    let name = String::from_utf8(section[..name_len].to_vec()).ok()?;
+    let crc_start = (name_len + 4) & !3;
+    let crc = u32::from_le_bytes(section.get(crc_start..crc_start + 4)?.try_into().ok()?);
+    Some((name, crc))
+}
+
+/// The CRC32 used by .gnu_debuglink (the same as zlib's)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+    /// The types a generic type was instantiated with (DW_TAG_template_type_param), to be looked
+    /// up with DwarfData::get_type
+    pub template_params: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    #[default]
+    Void,
+    Int {
+        signed: bool,
+    },
+    Char {
+        signed: bool,
+    },
+    Bool,
+    Float,
+    /// Points to a value of the contained type
+    Pointer(Box<Type>),
+    /// Element type and number of elements, if known
+    Array(Box<Type>, Option<usize>),
+    /// A struct or union (whose members all have offset 0)
+    Struct(Vec<Member>),
+    /// Enumerator names and values
+    Enum(Vec<(String, i64)>),
+    /// A struct whose contents depend on a discriminant, as Rust describes its enums
+    /// (DW_TAG_variant_part)
+    Variants(Option<Member>, Vec<Variant>),
+    Function,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the member from the start of the struct
+    pub offset: usize,
+    /// The member's type, to be looked up with DwarfData::get_type
+    pub type_offset: usize,
+}
+
+/// One of the alternatives in a TypeKind::Variants
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value that selects this variant, or None for the default variant
+    pub discriminant: Option<u64>,
+    /// The variant's contents, which are named after it and have a struct type of their own
+    pub member: Member,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            kind,
+            template_params: Vec::new(),
         }
     }
+
+    pub fn void() -> Self {
+        // gcc (and gdb) treat sizeof(void) as 1, which makes void * arithmetic work bytewise
+        Type::new("void".to_string(), 1, TypeKind::Void)
+    }
+
+    pub fn pointer_to(target: Type) -> Self {
+        let name = match target.kind {
+            TypeKind::Function => target.name.replacen(" (", " (*)(", 1),
+            _ if target.name.ends_with('*') => format!("{}*", target.name),
+            _ => format!("{} *", target.name),
+        };
+        Type::new(name, 8, TypeKind::Pointer(Box::new(target)))
+    }
+
+    /// Returns true for types whose values are plain numbers: integers, characters, booleans,
+    /// floats, enums and pointers.
+    pub fn is_scalar(&self) -> bool {
+        !matches!(
+            self.kind,
+            TypeKind::Void
+                | TypeKind::Array(_, _)
+                | TypeKind::Struct(_)
+                | TypeKind::Variants(_, _)
+                | TypeKind::Function
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -169,6 +907,18 @@ pub enum Location {
     FramePointerOffset(isize),
 }
 
+impl Location {
+    /// Returns the address this location refers to, given the value of rbp in the frame it
+    /// belongs to. Frame offsets are relative to the canonical frame address, which sits 16 bytes
+    /// above rbp once the function prologue has run (return address + saved rbp).
+    pub fn address(&self, rbp: usize) -> usize {
+        match *self {
+            Location::Address(addr) => addr,
+            Location::FramePointerOffset(offset) => (rbp as isize + 16 + offset) as usize,
+        }
+    }
+}
+
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -196,18 +946,59 @@ pub struct Variable {
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
+    /// The demangled symbol name, for languages where it's more than the name, e.g. `rs::total`
+    pub qualified_name: String,
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub return_type: Type,
+    /// Types of the formal parameters, in order
+    pub parameters: Vec<Type>,
+}
+
+/// A place where one function's code was inlined into another (DW_TAG_inlined_subroutine)
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    /// The inlined function's name
+    pub name: String,
+    /// Where the inlined code starts
+    pub address: usize,
+    /// The [start, end) address ranges the inlined code occupies
+    pub ranges: Vec<(usize, usize)>,
+    /// The line of the call that was inlined
+    pub call_line: usize,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    pub compilation_directory: String,
+    /// The compiler that produced the file
+    pub producer: String,
+    pub language: String,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
+    pub inlined_calls: Vec<InlinedCall>,
+}
+
+/// A function from the ELF symbol table
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// The demangled name
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+}
+
+/// One level of the call chain at some address (see DwarfData::get_frames)
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    pub line: Option<Line>,
+    /// Whether this function was inlined into the one in the next frame out
+    pub inlined: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
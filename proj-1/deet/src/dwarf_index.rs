@@ -0,0 +1,366 @@
+//! Readers for the indexes that compilers and linkers can add to a binary so that a debugger can
+//! find things without reading all of its DWARF: .gdb_index (written by gdb-add-index, gold and
+//! lld with --gdb-index), .debug_names (DWARF 5, e.g. clang -gpubnames) and .debug_aranges (which
+//! gcc emits by default). Each of them maps names or addresses to the offsets in .debug_info of
+//! the compilation units they belong to. A .dwp package of split DWARF has an index too, of where
+//! each unit is in it.
+//!
+//! All of them are read as little-endian, since that's what deet runs on, and anything unexpected
+//! makes the reader give up and return None so that we fall back to reading the units.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Names of functions, variables and types, with the offsets of the units that define them
+pub type NameIndex = HashMap<String, Vec<usize>>;
+
+/// Address ranges as (start, end, offset of the unit containing them)
+pub type AddressIndex = Vec<(usize, usize, usize)>;
+
+/// A cursor over the bytes of a section.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+            shift += 7;
+        }
+    }
+}
+
+/// Reads a NUL-terminated string starting at offset.
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|b| *b == 0)?;
+    std::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Reads a .gdb_index section (versions 7 and 8), which has both a name index and an address
+/// index. It starts with a header of offsets to its parts: the list of units, the list of type
+/// units, the address area, the symbol table and the constant pool. The symbol table is a hash
+/// table of (name, unit vector) offsets into the constant pool, and each unit vector is a count
+/// followed by that many unit indexes (in the low 24 bits; the rest says what kind of symbol it
+/// is).
+pub fn read_gdb_index(data: &[u8]) -> Option<(NameIndex, AddressIndex)> {
+    let mut header = Reader::new(data, 0);
+    let version = header.u32()?;
+    if version != 7 && version != 8 {
+        return None;
+    }
+    let cu_list = header.u32()? as usize;
+    let tu_list = header.u32()? as usize;
+    let address_area = header.u32()? as usize;
+    let symbol_table = header.u32()? as usize;
+    let constant_pool = header.u32()? as usize;
+
+    // Each unit is its offset in .debug_info and its length
+    let mut units = Vec::new();
+    let mut reader = Reader::new(data, cu_list);
+    while reader.pos + 16 <= tu_list {
+        units.push(reader.u64()? as usize);
+        reader.u64()?;
+    }
+
+    let mut addresses = Vec::new();
+    let mut reader = Reader::new(data, address_area);
+    while reader.pos + 20 <= symbol_table {
+        let start = reader.u64()? as usize;
+        let end = reader.u64()? as usize;
+        // Indexes past the units are type units, which have no code
+        if let Some(unit) = units.get(reader.u32()? as usize) {
+            addresses.push((start, end, *unit));
+        }
+    }
+
+    let mut names = NameIndex::new();
+    let mut reader = Reader::new(data, symbol_table);
+    while reader.pos + 8 <= constant_pool {
+        let name_offset = reader.u32()? as usize;
+        let vector_offset = reader.u32()? as usize;
+        if name_offset == 0 && vector_offset == 0 {
+            continue;
+        }
+        let name = read_str(data, constant_pool + name_offset)?;
+        let mut vector = Reader::new(data, constant_pool + vector_offset);
+        let count = vector.u32()?;
+        let entry = names.entry(name.to_string()).or_default();
+        for _ in 0..count {
+            if let Some(unit) = units.get((vector.u32()? & 0xff_ffff) as usize) {
+                if !entry.contains(unit) {
+                    entry.push(*unit);
+                }
+            }
+        }
+    }
+    Some((names, addresses))
+}
+
+const DW_IDX_COMPILE_UNIT: u64 = 1;
+
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+
+/// Reads an attribute of a .debug_names entry, for the forms producers use there.
+fn read_index_attribute(reader: &mut Reader, form: u64) -> Option<u64> {
+    match form {
+        DW_FORM_DATA1 | DW_FORM_REF1 => reader.u8().map(|value| value as u64),
+        DW_FORM_DATA2 | DW_FORM_REF2 => reader.u16().map(|value| value as u64),
+        DW_FORM_DATA4 | DW_FORM_REF4 => reader.u32().map(|value| value as u64),
+        DW_FORM_DATA8 | DW_FORM_REF8 => reader.u64(),
+        DW_FORM_UDATA | DW_FORM_REF_UDATA => reader.uleb128(),
+        DW_FORM_FLAG_PRESENT => Some(1),
+        _ => None,
+    }
+}
+
+/// Reads the name indexes in a .debug_names section, whose names are offsets into .debug_str.
+/// The section may hold several of them, one per linked object file. Each has a header, the list
+/// of units it covers, a hash table we don't need, the name offsets, offsets of each name's
+/// entries in the entry pool, and the abbreviations that say which attributes (e.g.
+/// DW_IDX_compile_unit) those entries have.
+pub fn read_debug_names(data: &[u8], debug_str: &[u8]) -> Option<NameIndex> {
+    let mut names = NameIndex::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut reader = Reader::new(data, pos);
+        let length = reader.u32()? as usize;
+        // 64-bit DWARF, which nothing we run on produces
+        if length >= 0xffff_fff0 {
+            return None;
+        }
+        let end = reader.pos + length;
+        if reader.u16()? != 5 {
+            return None;
+        }
+        reader.u16()?;
+        let cu_count = reader.u32()? as usize;
+        let local_tu_count = reader.u32()? as usize;
+        let foreign_tu_count = reader.u32()? as usize;
+        let bucket_count = reader.u32()? as usize;
+        let name_count = reader.u32()? as usize;
+        let abbrev_table_size = reader.u32()? as usize;
+        let augmentation_size = reader.u32()? as usize;
+        reader.bytes(augmentation_size)?;
+
+        let mut units = Vec::new();
+        for _ in 0..cu_count {
+            units.push(reader.u32()? as usize);
+        }
+        reader.bytes(local_tu_count * 4 + foreign_tu_count * 8)?;
+        reader.bytes(bucket_count * 4)?;
+        if bucket_count > 0 {
+            reader.bytes(name_count * 4)?;
+        }
+        let string_offsets = reader.pos;
+        let entry_offsets = string_offsets + name_count * 4;
+        reader.bytes(name_count * 8)?;
+
+        // Abbreviation code -> (index attribute, form) pairs
+        let mut abbrevs: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+        let abbrev_end = reader.pos + abbrev_table_size;
+        loop {
+            let code = reader.uleb128()?;
+            if code == 0 {
+                break;
+            }
+            reader.uleb128()?;
+            let mut attributes = Vec::new();
+            loop {
+                let (index, form) = (reader.uleb128()?, reader.uleb128()?);
+                if index == 0 && form == 0 {
+                    break;
+                }
+                attributes.push((index, form));
+            }
+            abbrevs.insert(code, attributes);
+        }
+        let entry_pool = abbrev_end;
+
+        for i in 0..name_count {
+            let string_offset = Reader::new(data, string_offsets + i * 4).u32()? as usize;
+            let entry_offset = Reader::new(data, entry_offsets + i * 4).u32()? as usize;
+            let name = match read_str(debug_str, string_offset) {
+                Some(name) => name,
+                None => continue,
+            };
+            let entry = names.entry(name.to_string()).or_default();
+            // Each name has a series of entries, ended by abbreviation code 0
+            let mut reader = Reader::new(data, entry_pool + entry_offset);
+            loop {
+                let code = reader.uleb128()?;
+                if code == 0 {
+                    break;
+                }
+                let attributes = abbrevs.get(&code)?;
+                // An index of a single unit can leave it out
+                let mut unit_index = if cu_count == 1 { Some(0) } else { None };
+                for (index, form) in attributes {
+                    let value = read_index_attribute(&mut reader, *form)?;
+                    if *index == DW_IDX_COMPILE_UNIT {
+                        unit_index = Some(value as usize);
+                    }
+                }
+                if let Some(unit) = unit_index.and_then(|index| units.get(index)) {
+                    if !entry.contains(unit) {
+                        entry.push(*unit);
+                    }
+                }
+            }
+        }
+        pos = end;
+    }
+    Some(names)
+}
+
+/// Reads the address ranges in .debug_aranges. The section has a set of ranges for each unit,
+/// each a header (with the unit's offset) followed by (address, length) pairs that start at a
+/// multiple of twice the address size and end with a pair of zeros.
+pub fn read_aranges(data: &[u8]) -> Option<AddressIndex> {
+    let mut addresses = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut reader = Reader::new(data, pos);
+        let length = reader.u32()? as usize;
+        if length >= 0xffff_fff0 {
+            return None;
+        }
+        let end = reader.pos + length;
+        reader.u16()?;
+        let unit = reader.u32()? as usize;
+        let address_size = reader.u8()? as usize;
+        if reader.u8()? != 0 || (address_size != 4 && address_size != 8) {
+            return None;
+        }
+        let tuple_size = address_size * 2;
+        let misalignment = (reader.pos - pos) % tuple_size;
+        if misalignment != 0 {
+            reader.bytes(tuple_size - misalignment)?;
+        }
+        while reader.pos + tuple_size <= end {
+            let (start, len) = if address_size == 8 {
+                (reader.u64()? as usize, reader.u64()? as usize)
+            } else {
+                (reader.u32()? as usize, reader.u32()? as usize)
+            };
+            if start == 0 && len == 0 {
+                break;
+            }
+            addresses.push((start, start + len, unit));
+        }
+        pos = end;
+    }
+    Some(addresses)
+}
+
+/// Where a unit's contributions to the sections of a .dwp package start
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DwpUnit {
+    pub info: usize,
+    pub abbrev: usize,
+    pub str_offsets: usize,
+    pub loclists: usize,
+    pub rnglists: usize,
+}
+
+const DW_SECT_INFO: u32 = 1;
+const DW_SECT_ABBREV: u32 = 3;
+const DW_SECT_LOC: u32 = 5;
+const DW_SECT_STR_OFFSETS: u32 = 6;
+/// DWARF 5's section ID for .debug_rnglists.dwo, which version 2 packages don't have
+const DW_SECT_RNGLISTS: u32 = 8;
+
+/// Reads the .debug_cu_index of a .dwp package, which says where each unit's contributions to the
+/// package's sections start, by the unit's DWO ID. Version 2 is the GNU extension for DWARF 4, and
+/// version 5 is the standard one. After the header comes a hash table of IDs, a parallel table of
+/// rows, the section ID of each column, and then a row of offsets for each unit (and a row of
+/// sizes, which we don't need).
+pub fn read_cu_index(data: &[u8]) -> Option<HashMap<u64, DwpUnit>> {
+    let mut reader = Reader::new(data, 0);
+    let version = reader.u32()?;
+    if version != 2 && version != 5 {
+        return None;
+    }
+    let section_count = reader.u32()? as usize;
+    let unit_count = reader.u32()? as usize;
+    let slot_count = reader.u32()? as usize;
+    let ids = reader.pos;
+    let rows = ids + slot_count * 8;
+    let mut columns = Reader::new(data, rows + slot_count * 4);
+    let mut sections = Vec::new();
+    for _ in 0..section_count {
+        sections.push(columns.u32()?);
+    }
+    let offsets = columns.pos;
+
+    let mut units = HashMap::new();
+    for slot in 0..slot_count {
+        let id = Reader::new(data, ids + slot * 8).u64()?;
+        let row = Reader::new(data, rows + slot * 4).u32()? as usize;
+        // Row 0 means the slot is empty
+        if row == 0 || row > unit_count {
+            continue;
+        }
+        let mut unit = DwpUnit::default();
+        let mut reader = Reader::new(data, offsets + (row - 1) * section_count * 4);
+        for section in &sections {
+            let offset = reader.u32()? as usize;
+            match *section {
+                DW_SECT_INFO => unit.info = offset,
+                DW_SECT_ABBREV => unit.abbrev = offset,
+                DW_SECT_LOC => unit.loclists = offset,
+                DW_SECT_STR_OFFSETS => unit.str_offsets = offset,
+                DW_SECT_RNGLISTS if version == 5 => unit.rnglists = offset,
+                _ => {}
+            }
+        }
+        units.insert(id, unit);
+    }
+    Some(units)
+}
@@ -0,0 +1,1271 @@
+//! A small evaluator for C-like expressions, used by print, conditional breakpoints and set var.
+//!
+//! Expressions are parsed into an Expr tree and then evaluated against a ProcessImage, using the
+//! types from DwarfData to interpret memory. Values remember where they came from (a memory
+//! address or a register), which is what lets `&x`, `p->next` and `x = 5` work. Assignments are
+//! not carried out by the evaluator itself: they are collected so that the caller can write them
+//! to the inferior, since a core file can't be modified.
+
+use crate::arch::{Arch, Native, Registers};
+use crate::dwarf_data::{DwarfData, Member, Type, TypeKind};
+use crate::pretty;
+use crate::process_image::ProcessImage;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt;
+
+/// How many array elements or string characters we print before giving up
+pub const PRINT_LIMIT: usize = 200;
+
+/// How deeply nested structs and arrays are printed
+pub const MAX_PRINT_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax(String),
+    NoSymbol(String),
+    NoType(String),
+    NoMember(String, String),
+    NoRegister(String),
+    /// A value of the given type can't be used like that, e.g. dereferencing an int
+    BadOperand(String, String),
+    NotAnLvalue,
+    /// Function calls have to be carried out by the debugger, which can only do so for a call
+    /// that makes up the whole expression
+    NestedCall,
+    DivisionByZero,
+    Memory(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax(msg) => write!(f, "A syntax error in expression, near `{}'.", msg),
+            Error::NoSymbol(name) => write!(f, "No symbol \"{}\" in current context.", name),
+            Error::NoType(name) => write!(f, "No type named {}.", name),
+            Error::NoMember(type_name, member) => {
+                write!(f, "There is no member named {} in {}.", member, type_name)
+            }
+            Error::NoRegister(name) => write!(f, "Invalid register `{}'", name),
+            Error::BadOperand(op, type_name) => {
+                write!(f, "Cannot apply {} to a value of type {}.", op, type_name)
+            }
+            Error::NotAnLvalue => write!(f, "Left operand of assignment is not an lvalue."),
+            Error::NestedCall => write!(f, "Function calls can't be part of a larger expression."),
+            Error::DivisionByZero => write!(f, "Division by zero"),
+            Error::Memory(addr) => write!(f, "Cannot access memory at address {:#x}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+    AddrOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Int(u64),
+    Float(f64),
+    Char(u8),
+    Identifier(String),
+    Register(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Member(Box<Expr>, String),
+    Cast(Type, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    /// A function and its arguments
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(u64),
+    Float(f64),
+    Char(u8),
+    Identifier(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+/// Operators, longest first so that e.g. `->` isn't read as `-` `>`
+const PUNCTUATION: [&str; 28] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "(", ")", "[", "]", ".", "=", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            tokens.push(parse_number(&text[start..i])?);
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            let start = i;
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let word = &text[start..i];
+            tokens.push(match word.strip_prefix('$') {
+                Some(register) => Token::Register(register.to_string()),
+                None => Token::Identifier(word.to_string()),
+            });
+        } else if c == b'\'' {
+            let (value, len) = match (bytes.get(i + 1), bytes.get(i + 2)) {
+                (Some(b'\\'), Some(escaped)) => (unescape(*escaped), 4),
+                (Some(c), _) => (*c, 3),
+                _ => return Err(Error::Syntax(text[i..].to_string())),
+            };
+            if bytes.get(i + len - 1) != Some(&b'\'') {
+                return Err(Error::Syntax(text[i..].to_string()));
+            }
+            tokens.push(Token::Char(value));
+            i += len;
+        } else {
+            match PUNCTUATION.iter().find(|p| text[i..].starts_with(*p)) {
+                Some(punct) => {
+                    tokens.push(Token::Punct(punct));
+                    i += punct.len();
+                }
+                None => return Err(Error::Syntax(text[i..].to_string())),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Suffixes like the ones in 10UL, which don't change how we read the number
+const INTEGER_SUFFIXES: [char; 2] = ['u', 'l'];
+
+fn parse_number(text: &str) -> Result<Token, Error> {
+    let lower = text.to_lowercase();
+    let result = if let Some(hex) = lower.strip_prefix("0x") {
+        u64::from_str_radix(hex.trim_end_matches(&INTEGER_SUFFIXES[..]), 16).map(Token::Int)
+    } else if lower.contains('.') || lower.contains('e') {
+        return lower
+            .trim_end_matches('f')
+            .parse()
+            .map(Token::Float)
+            .map_err(|_| Error::Syntax(text.to_string()));
+    } else if lower.len() > 1 && lower.starts_with('0') {
+        u64::from_str_radix(lower.trim_end_matches(&INTEGER_SUFFIXES[..]), 8).map(Token::Int)
+    } else {
+        lower
+            .trim_end_matches(&INTEGER_SUFFIXES[..])
+            .parse()
+            .map(Token::Int)
+    };
+    result.map_err(|_| Error::Syntax(text.to_string()))
+}
+
+fn unescape(c: u8) -> u8 {
+    match c {
+        b'n' => b'\n',
+        b't' => b'\t',
+        b'r' => b'\r',
+        b'0' => 0,
+        other => other,
+    }
+}
+
+/// Returns the type a C keyword sequence like `unsigned long` names. Sizes are those of x86-64
+/// Linux.
+fn builtin_type(words: &[&str]) -> Option<Type> {
+    let mut signed = None;
+    let mut longs = 0;
+    let mut short = false;
+    let mut base = None;
+    for word in words {
+        match *word {
+            "signed" => signed = Some(true),
+            "unsigned" => signed = Some(false),
+            "long" => longs += 1,
+            "short" => short = true,
+            "int" | "char" | "float" | "double" | "void" | "_Bool" => base = Some(*word),
+            _ => return None,
+        }
+    }
+    let int = |size, name: &str| {
+        let signed = signed.unwrap_or(true);
+        let name = match (signed, name) {
+            (false, "char") => "unsigned char".to_string(),
+            (true, "char") if words.contains(&"signed") => "signed char".to_string(),
+            (false, _) => format!("unsigned {}", name),
+            (true, _) => name.to_string(),
+        };
+        Type::new(name, size, TypeKind::Int { signed })
+    };
+    Some(match (base, longs, short) {
+        (Some("void"), 0, false) => Type::void(),
+        (Some("_Bool"), 0, false) => Type::new("_Bool".to_string(), 1, TypeKind::Bool),
+        (Some("float"), 0, false) => Type::new("float".to_string(), 4, TypeKind::Float),
+        (Some("double"), 0, false) => Type::new("double".to_string(), 8, TypeKind::Float),
+        (Some("char"), 0, false) => {
+            let mut char_type = int(1, "char");
+            char_type.kind = TypeKind::Char {
+                signed: signed.unwrap_or(true),
+            };
+            char_type
+        }
+        (Some("int"), 0, true) | (None, 0, true) => int(2, "short"),
+        (Some("int"), 0, false) | (None, 0, false) => int(4, "int"),
+        (Some("int"), 1, false) | (None, 1, false) => int(8, "long"),
+        (Some("int"), 2, false) | (None, 2, false) => int(8, "long long"),
+        _ => return None,
+    })
+}
+
+pub fn int_type() -> Type {
+    builtin_type(&["int"]).unwrap()
+}
+
+fn long_type() -> Type {
+    builtin_type(&["long"]).unwrap()
+}
+
+pub fn double_type() -> Type {
+    builtin_type(&["double"]).unwrap()
+}
+
+const TYPE_KEYWORDS: [&str; 15] = [
+    "const", "volatile", "signed", "unsigned", "long", "short", "int", "char", "float", "double",
+    "void", "_Bool", "struct", "union", "enum",
+];
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    debug_data: &'a DwarfData,
+}
+
+/// Parses a C expression. Type names (for casts) are looked up in debug_data.
+pub fn parse(text: &str, debug_data: &DwarfData) -> Result<Expr, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        debug_data,
+    };
+    let expr = parser.assignment()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.error()),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.peek_punct(punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), Error> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Describes where parsing went wrong, in terms of the remaining input.
+    fn error(&self) -> Error {
+        let rest: Vec<String> = self.tokens[self.pos..]
+            .iter()
+            .map(|token| match token {
+                Token::Int(value) => value.to_string(),
+                Token::Float(value) => value.to_string(),
+                Token::Char(c) => format!("'{}'", std::ascii::escape_default(*c)),
+                Token::Identifier(name) => name.clone(),
+                Token::Register(name) => format!("${}", name),
+                Token::Punct(punct) => punct.to_string(),
+            })
+            .collect();
+        Error::Syntax(rest.join(" "))
+    }
+
+    fn assignment(&mut self) -> Result<Expr, Error> {
+        let lhs = self.binary(0)?;
+        if self.eat("=") {
+            let rhs = self.assignment()?;
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    /// Parses binary operators by precedence climbing, starting at the given precedence level.
+    fn binary(&mut self, min_level: usize) -> Result<Expr, Error> {
+        const LEVELS: [&[(&str, BinaryOp)]; 10] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+            ],
+            &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+        ];
+        if min_level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(min_level + 1)?;
+        'outer: loop {
+            for (punct, op) in LEVELS[min_level].iter() {
+                if self.eat(punct) {
+                    let rhs = self.binary(min_level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => UnaryOp::Neg,
+            Some(Token::Punct("!")) => UnaryOp::Not,
+            Some(Token::Punct("~")) => UnaryOp::BitNot,
+            Some(Token::Punct("*")) => UnaryOp::Deref,
+            Some(Token::Punct("&")) => UnaryOp::AddrOf,
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.unary();
+            }
+            Some(Token::Punct("(")) => {
+                let start = self.pos;
+                self.pos += 1;
+                if let Some(cast_type) = self.type_name()? {
+                    self.expect(")")?;
+                    return Ok(Expr::Cast(cast_type, Box::new(self.unary()?)));
+                }
+                self.pos = start;
+                return self.postfix();
+            }
+            _ => return self.postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    /// Tries to parse a type name like `unsigned int`, `struct node *` or a typedef. Returns None
+    /// (without consuming anything) if the next tokens aren't a type.
+    fn type_name(&mut self) -> Result<Option<Type>, Error> {
+        let start = self.pos;
+        let mut words: Vec<String> = Vec::new();
+        while let Some(Token::Identifier(word)) = self.peek() {
+            let is_keyword = TYPE_KEYWORDS.contains(&word.as_str());
+            let after_tag = matches!(
+                words.last().map(String::as_str),
+                Some("struct") | Some("union") | Some("enum")
+            );
+            let is_typedef = words.iter().all(|w| w == "const" || w == "volatile")
+                && self.debug_data.get_type_by_name(word).is_some();
+            if !is_keyword && !after_tag && !is_typedef {
+                break;
+            }
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        // Qualifiers don't matter to us
+        let words: Vec<&str> = words
+            .iter()
+            .map(String::as_str)
+            .filter(|w| *w != "const" && *w != "volatile")
+            .collect();
+        let base = match words.as_slice() {
+            [] => None,
+            [tag, name] if ["struct", "union", "enum"].contains(tag) => {
+                let full_name = format!("{} {}", tag, name);
+                match self.debug_data.get_type_by_name(&full_name) {
+                    Some(found) => Some(found.clone()),
+                    None => return Err(Error::NoType(full_name)),
+                }
+            }
+            [name] if !TYPE_KEYWORDS.contains(name) => {
+                self.debug_data.get_type_by_name(name).cloned()
+            }
+            _ => builtin_type(&words),
+        };
+        let mut found = match base {
+            Some(found) => found,
+            None => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        while self.eat("*") {
+            found = Type::pointer_to(found);
+        }
+        Ok(Some(found))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat("[") {
+                let index = self.assignment()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat(".") {
+                expr = Expr::Member(Box::new(expr), self.member_name()?);
+            } else if self.eat("(") {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.assignment()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                expr = Expr::Call(Box::new(expr), args);
+            } else if self.eat("->") {
+                let deref = Expr::Unary(UnaryOp::Deref, Box::new(expr));
+                expr = Expr::Member(Box::new(deref), self.member_name()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn member_name(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let expr = match self.peek() {
+            Some(Token::Int(value)) => Expr::Int(*value),
+            Some(Token::Float(value)) => Expr::Float(*value),
+            Some(Token::Char(value)) => Expr::Char(*value),
+            Some(Token::Identifier(name)) => Expr::Identifier(name.clone()),
+            Some(Token::Register(name)) => Expr::Register(name.clone()),
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let expr = self.assignment()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(self.error()),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+/// An assignment made by an expression: the bytes to write and where to write them.
+pub type Assignment = (Place, Vec<u8>);
+
+/// Where a value lives, so that it can be assigned to or have its address taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+    Memory(usize),
+    Register(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub ty: Type,
+    /// The value's bytes, little-endian. Empty for arrays of unknown length and functions.
+    pub bytes: Vec<u8>,
+    pub place: Option<Place>,
+}
+
+impl Value {
+    fn new(ty: Type, bytes: Vec<u8>) -> Value {
+        Value {
+            ty,
+            bytes,
+            place: None,
+        }
+    }
+
+    pub fn from_u64(ty: Type, value: u64) -> Value {
+        let bytes = value.to_le_bytes()[..ty.size.min(8)].to_vec();
+        Value::new(ty, bytes)
+    }
+
+    fn from_f64(ty: Type, value: f64) -> Value {
+        let bytes = if ty.size == 4 {
+            (value as f32).to_le_bytes().to_vec()
+        } else {
+            value.to_le_bytes().to_vec()
+        };
+        Value::new(ty, bytes)
+    }
+
+    pub fn address(&self) -> Option<usize> {
+        match self.place {
+            Some(Place::Memory(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Returns the value's bits zero-extended to 64 bits.
+    pub fn raw(&self) -> u64 {
+        let mut buf = [0u8; 8];
+        let len = self.bytes.len().min(8);
+        buf[..len].copy_from_slice(&self.bytes[..len]);
+        u64::from_le_bytes(buf)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self.ty.kind, TypeKind::Float)
+    }
+
+    fn is_signed(&self) -> bool {
+        match self.ty.kind {
+            TypeKind::Int { signed } | TypeKind::Char { signed } => signed,
+            TypeKind::Enum(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the value as an integer, sign-extending it if its type is signed.
+    pub fn as_i64(&self) -> i64 {
+        if self.is_float() {
+            return self.as_f64() as i64;
+        }
+        let raw = self.raw();
+        let width = 8 * self.bytes.len().min(8) as u32;
+        if self.is_signed() && width > 0 && width < 64 {
+            ((raw << (64 - width)) as i64) >> (64 - width)
+        } else {
+            raw as i64
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self.bytes.len() {
+            4 if self.is_float() => f32::from_le_bytes(self.bytes[..].try_into().unwrap()) as f64,
+            8 if self.is_float() => f64::from_le_bytes(self.bytes[..].try_into().unwrap()),
+            _ if self.is_signed() => self.as_i64() as f64,
+            _ => self.raw() as f64,
+        }
+    }
+
+    /// Returns true if the value is nonzero, which is what C uses as truth.
+    pub fn is_true(&self) -> bool {
+        if self.is_float() {
+            self.as_f64() != 0.0
+        } else {
+            self.raw() != 0
+        }
+    }
+}
+
+/// Evaluates expressions against the current frame of a process.
+pub struct Evaluator<'a> {
+    debug_data: &'a DwarfData,
+    image: &'a dyn ProcessImage,
+    regs: Registers,
+    /// Assignments made while evaluating, for the caller to carry out
+    writes: Vec<Assignment>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        debug_data: &'a DwarfData,
+        image: &'a dyn ProcessImage,
+    ) -> Result<Evaluator<'a>, nix::Error> {
+        Ok(Evaluator {
+            debug_data,
+            image,
+            regs: image.get_registers()?,
+            writes: Vec::new(),
+        })
+    }
+
+    /// Returns the assignments the evaluated expressions made, in order.
+    pub fn into_writes(self) -> Vec<Assignment> {
+        self.writes
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, Error> {
+        match expr {
+            Expr::Int(value) => {
+                // Like C, literals that don't fit in an int are longs
+                let ty = if *value > i32::MAX as u64 {
+                    long_type()
+                } else {
+                    int_type()
+                };
+                Ok(Value::from_u64(ty, *value))
+            }
+            Expr::Float(value) => Ok(Value::from_f64(double_type(), *value)),
+            Expr::Char(value) => Ok(Value::from_u64(
+                builtin_type(&["char"]).unwrap(),
+                *value as u64,
+            )),
+            Expr::Identifier(name) => self.variable(name),
+            Expr::Register(name) => self.register(name),
+            Expr::Unary(op, operand) => {
+                let operand = self.evaluate(operand)?;
+                self.unary(*op, operand)
+            }
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                let result = self.evaluate(lhs)?.is_true() && self.evaluate(rhs)?.is_true();
+                Ok(Value::from_u64(int_type(), result as u64))
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                let result = self.evaluate(lhs)?.is_true() || self.evaluate(rhs)?.is_true();
+                Ok(Value::from_u64(int_type(), result as u64))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                self.binary(*op, lhs, rhs)
+            }
+            Expr::Index(base, index) => {
+                let base = self.evaluate(base)?;
+                let index = self.evaluate(index)?;
+                let element = self.binary(BinaryOp::Add, base, index)?;
+                self.unary(UnaryOp::Deref, element)
+            }
+            Expr::Member(base, name) => {
+                let base = self.evaluate(base)?;
+                self.member(base, name)
+            }
+            Expr::Cast(ty, operand) => {
+                let operand = self.evaluate(operand)?;
+                self.convert(operand, ty)
+            }
+            Expr::Call(_, _) => Err(Error::NestedCall),
+            Expr::Assign(lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                let place = match lhs.place {
+                    Some(place) if lhs.ty.is_scalar() || lhs.ty.size == rhs.bytes.len() => place,
+                    _ => return Err(Error::NotAnLvalue),
+                };
+                let mut value = if lhs.ty.is_scalar() {
+                    self.convert(rhs, &lhs.ty)?
+                } else {
+                    rhs
+                };
+                self.writes.push((place, value.bytes.clone()));
+                value.ty = lhs.ty;
+                value.place = Some(place);
+                Ok(value)
+            }
+        }
+    }
+
+    fn read(&self, ty: Type, addr: usize) -> Result<Value, Error> {
+        let bytes = match ty.kind {
+            TypeKind::Function => Vec::new(),
+            _ => self
+                .image
+                .read_bytes(addr, ty.size)
+                .map_err(|_| Error::Memory(addr))?,
+        };
+        Ok(Value {
+            ty,
+            bytes,
+            place: Some(Place::Memory(addr)),
+        })
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, Error> {
+        if let Some(var) = self.debug_data.get_variable(Native::pc(&self.regs), name) {
+            let addr = var.location.address(Native::frame_pointer(&self.regs));
+            return self.read(var.entity_type.clone(), addr);
+        }
+        // A function name evaluates to the function, which can be called or have its address
+        // taken
+        if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            let ty = Type::new(format!("{} ()", name), 1, TypeKind::Function);
+            return self.read(ty, addr);
+        }
+        if let Some((ty, value)) = self.debug_data.get_enumerator(name) {
+            return Ok(Value::from_u64(ty.clone(), value as u64));
+        }
+        Err(Error::NoSymbol(name.to_string()))
+    }
+
+    fn register(&self, name: &str) -> Result<Value, Error> {
+        let name = match name {
+            "pc" => Native::PC_REGISTER,
+            "sp" => Native::SP_REGISTER,
+            "fp" => Native::FP_REGISTER,
+            other => other,
+        };
+        let (name, value) = Native::named_registers(&self.regs)
+            .into_iter()
+            .find(|(reg, _)| *reg == name)
+            .ok_or_else(|| Error::NoRegister(name.to_string()))?;
+        let pointers = [
+            Native::PC_REGISTER,
+            Native::SP_REGISTER,
+            Native::FP_REGISTER,
+        ];
+        let ty = if pointers.contains(&name) {
+            Type::pointer_to(Type::void())
+        } else {
+            long_type()
+        };
+        let mut value = Value::from_u64(ty, value);
+        value.place = Some(Place::Register(name));
+        Ok(value)
+    }
+
+    /// Turns arrays into pointers to their first element, as C does when they're used in an
+    /// expression.
+    pub fn decay(&self, value: Value) -> Result<Value, Error> {
+        match value.ty.kind {
+            TypeKind::Array(ref element, _) => {
+                let addr = value.address().ok_or(Error::NotAnLvalue)?;
+                Ok(Value::from_u64(
+                    Type::pointer_to((**element).clone()),
+                    addr as u64,
+                ))
+            }
+            TypeKind::Function => {
+                let addr = value.address().ok_or(Error::NotAnLvalue)?;
+                Ok(Value::from_u64(Type::pointer_to(value.ty), addr as u64))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn unary(&mut self, op: UnaryOp, operand: Value) -> Result<Value, Error> {
+        if op == UnaryOp::AddrOf {
+            return match operand.address() {
+                Some(addr) => Ok(Value::from_u64(Type::pointer_to(operand.ty), addr as u64)),
+                None => Err(Error::BadOperand("&".to_string(), operand.ty.name)),
+            };
+        }
+        let operand = self.decay(operand)?;
+        match op {
+            UnaryOp::Deref => match operand.ty.kind {
+                TypeKind::Pointer(ref target) if !matches!(target.kind, TypeKind::Void) => {
+                    self.read((**target).clone(), operand.raw() as usize)
+                }
+                _ => Err(Error::BadOperand("*".to_string(), operand.ty.name)),
+            },
+            UnaryOp::Not => Ok(Value::from_u64(int_type(), !operand.is_true() as u64)),
+            UnaryOp::Neg if operand.is_float() => {
+                let ty = operand.ty.clone();
+                Ok(Value::from_f64(ty, -operand.as_f64()))
+            }
+            UnaryOp::Neg | UnaryOp::BitNot => {
+                let ty = self.promote(&operand, &operand)?;
+                if operand.is_float() {
+                    return Err(Error::BadOperand("~".to_string(), operand.ty.name));
+                }
+                let operand = self.convert(operand, &ty)?;
+                let result = if op == UnaryOp::Neg {
+                    operand.as_i64().wrapping_neg()
+                } else {
+                    !operand.as_i64()
+                };
+                Ok(Value::from_u64(ty, result as u64))
+            }
+            UnaryOp::AddrOf => unreachable!(),
+        }
+    }
+
+    /// Returns the type C's usual arithmetic conversions give to an operation on lhs and rhs.
+    fn promote(&self, lhs: &Value, rhs: &Value) -> Result<Type, Error> {
+        for operand in [lhs, rhs].iter() {
+            if !operand.ty.is_scalar() {
+                return Err(Error::BadOperand(
+                    "an arithmetic operator".to_string(),
+                    operand.ty.name.clone(),
+                ));
+            }
+        }
+        if lhs.is_float() || rhs.is_float() {
+            return Ok(double_type());
+        }
+        let size = lhs.ty.size.max(rhs.ty.size).max(4);
+        let unsigned = [lhs, rhs]
+            .iter()
+            .any(|operand| operand.ty.size == size && !operand.is_signed());
+        Ok(match (size, unsigned) {
+            (4, false) => int_type(),
+            (4, true) => builtin_type(&["unsigned", "int"]).unwrap(),
+            (_, false) => long_type(),
+            (_, true) => builtin_type(&["unsigned", "long"]).unwrap(),
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, Error> {
+        let lhs = self.decay(lhs)?;
+        let rhs = self.decay(rhs)?;
+        let pointee_size = |value: &Value| match value.ty.kind {
+            TypeKind::Pointer(ref target) => Some(target.size.max(1) as i64),
+            _ => None,
+        };
+        // Pointer arithmetic scales by the size of what's pointed to
+        match (op, pointee_size(&lhs), pointee_size(&rhs)) {
+            (BinaryOp::Add, Some(size), None) | (BinaryOp::Sub, Some(size), None) => {
+                let offset = rhs.as_i64().wrapping_mul(size);
+                let offset = if op == BinaryOp::Sub {
+                    offset.wrapping_neg()
+                } else {
+                    offset
+                };
+                let addr = (lhs.raw() as i64).wrapping_add(offset);
+                return Ok(Value::from_u64(lhs.ty, addr as u64));
+            }
+            (BinaryOp::Add, None, Some(size)) => {
+                let addr = (rhs.raw() as i64).wrapping_add(lhs.as_i64().wrapping_mul(size));
+                return Ok(Value::from_u64(rhs.ty, addr as u64));
+            }
+            (BinaryOp::Sub, Some(size), Some(_)) => {
+                let difference = (lhs.raw() as i64).wrapping_sub(rhs.raw() as i64) / size;
+                return Ok(Value::from_u64(long_type(), difference as u64));
+            }
+            _ => {}
+        }
+
+        let ty = self.promote(&lhs, &rhs)?;
+        let is_comparison = matches!(
+            op,
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne
+        );
+        if matches!(ty.kind, TypeKind::Float) {
+            let (a, b) = (lhs.as_f64(), rhs.as_f64());
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Lt => (a < b) as u64 as f64,
+                BinaryOp::Gt => (a > b) as u64 as f64,
+                BinaryOp::Le => (a <= b) as u64 as f64,
+                BinaryOp::Ge => (a >= b) as u64 as f64,
+                BinaryOp::Eq => (a == b) as u64 as f64,
+                BinaryOp::Ne => (a != b) as u64 as f64,
+                _ => return Err(Error::BadOperand(format!("{:?}", op), ty.name)),
+            };
+            if is_comparison {
+                return Ok(Value::from_u64(int_type(), result as u64));
+            }
+            return Ok(Value::from_f64(ty, result));
+        }
+
+        let signed = matches!(ty.kind, TypeKind::Int { signed: true });
+        let a = self.convert(lhs, &ty)?;
+        let b = self.convert(rhs, &ty)?;
+        let (sa, sb) = (a.as_i64(), b.as_i64());
+        let (ua, ub) = (a.raw(), b.raw());
+        let ordering = if signed { sa.cmp(&sb) } else { ua.cmp(&ub) };
+        let result: u64 = match op {
+            BinaryOp::Add => ua.wrapping_add(ub),
+            BinaryOp::Sub => ua.wrapping_sub(ub),
+            BinaryOp::Mul => ua.wrapping_mul(ub),
+            BinaryOp::Div | BinaryOp::Rem if ub == 0 => return Err(Error::DivisionByZero),
+            BinaryOp::Div if signed => sa.wrapping_div(sb) as u64,
+            BinaryOp::Div => ua / ub,
+            BinaryOp::Rem if signed => sa.wrapping_rem(sb) as u64,
+            BinaryOp::Rem => ua % ub,
+            BinaryOp::Shl => ua.wrapping_shl(ub as u32),
+            BinaryOp::Shr if signed => sa.wrapping_shr(ub as u32) as u64,
+            BinaryOp::Shr => ua.wrapping_shr(ub as u32),
+            BinaryOp::BitAnd => ua & ub,
+            BinaryOp::BitXor => ua ^ ub,
+            BinaryOp::BitOr => ua | ub,
+            BinaryOp::Lt => (ordering == Ordering::Less) as u64,
+            BinaryOp::Gt => (ordering == Ordering::Greater) as u64,
+            BinaryOp::Le => (ordering != Ordering::Greater) as u64,
+            BinaryOp::Ge => (ordering != Ordering::Less) as u64,
+            BinaryOp::Eq => (ua == ub) as u64,
+            BinaryOp::Ne => (ua != ub) as u64,
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
+        };
+        if is_comparison {
+            return Ok(Value::from_u64(int_type(), result));
+        }
+        Ok(Value::from_u64(ty, result))
+    }
+
+    fn member(&self, base: Value, name: &str) -> Result<Value, Error> {
+        let members = match base.ty.kind {
+            TypeKind::Struct(ref members) => members,
+            _ => return Err(Error::BadOperand(format!(".{}", name), base.ty.name)),
+        };
+        let member = members
+            .iter()
+            .find(|member| member.name == name)
+            .ok_or_else(|| Error::NoMember(base.ty.name.clone(), name.to_string()))?;
+        let ty = self
+            .debug_data
+            .get_type(member.type_offset)
+            .ok_or_else(|| Error::NoMember(base.ty.name.clone(), name.to_string()))?
+            .clone();
+        let end = member.offset + ty.size;
+        let bytes = match base.bytes.get(member.offset..end) {
+            Some(bytes) => bytes.to_vec(),
+            None => return Err(Error::NoMember(base.ty.name.clone(), name.to_string())),
+        };
+        Ok(Value {
+            ty,
+            bytes,
+            place: base
+                .address()
+                .map(|addr| Place::Memory(addr + member.offset)),
+        })
+    }
+
+    /// Converts a scalar value to another scalar type, as a C cast would.
+    pub fn convert(&self, value: Value, ty: &Type) -> Result<Value, Error> {
+        let value = self.decay(value)?;
+        if !value.ty.is_scalar() || !ty.is_scalar() {
+            return Err(Error::BadOperand(
+                format!("a cast to {}", ty.name),
+                value.ty.name,
+            ));
+        }
+        Ok(match ty.kind {
+            TypeKind::Float => Value::from_f64(ty.clone(), value.as_f64()),
+            TypeKind::Bool => Value::from_u64(ty.clone(), value.is_true() as u64),
+            _ => Value::from_u64(ty.clone(), value.as_i64() as u64),
+        })
+    }
+}
+
+/// Parses and evaluates text, returning its value and any assignments it made.
+pub fn evaluate(
+    text: &str,
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+) -> Result<(Value, Vec<Assignment>), Error> {
+    let expr = parse(text, debug_data)?;
+    let mut evaluator = Evaluator::new(debug_data, image).map_err(|_| Error::Memory(0))?;
+    let value = evaluator.evaluate(&expr)?;
+    Ok((value, evaluator.into_writes()))
+}
+
+/// Formats a value the way gdb's print does. image is used to follow char pointers, and may be
+/// None if the process is gone.
+pub fn format_value(
+    value: &Value,
+    debug_data: &DwarfData,
+    image: Option<&dyn ProcessImage>,
+) -> String {
+    let mut out = String::new();
+    if let TypeKind::Pointer(ref target) = value.ty.kind {
+        // gdb shows the type of top-level pointers, unless it's obvious from the string or
+        // function printed after them
+        let obvious = matches!(target.kind, TypeKind::Char { .. } | TypeKind::Function);
+        if !obvious && !pretty::has_printer(&value.ty) {
+            out.push_str(&format!("({}) ", value.ty.name));
+        }
+    }
+    if let TypeKind::Function = value.ty.kind {
+        out.push_str(&format!("{{{}}} ", value.ty.name));
+    }
+    format_bytes(
+        &mut out,
+        &value.ty,
+        &value.bytes,
+        value.address(),
+        debug_data,
+        image,
+        0,
+    );
+    out
+}
+
+pub fn format_bytes(
+    out: &mut String,
+    ty: &Type,
+    bytes: &[u8],
+    addr: Option<usize>,
+    debug_data: &DwarfData,
+    image: Option<&dyn ProcessImage>,
+    depth: usize,
+) {
+    if pretty::format(out, ty, bytes, debug_data, image, depth) {
+        return;
+    }
+    let value = Value::new(ty.clone(), bytes.to_vec());
+    match ty.kind {
+        TypeKind::Void => out.push_str("void"),
+        TypeKind::Int { .. } => out.push_str(&value.as_i64().to_string()),
+        TypeKind::Char { signed } => {
+            let number = if signed {
+                value.as_i64().to_string()
+            } else {
+                value.raw().to_string()
+            };
+            // A member past the end of what we could read has no bytes
+            let byte = bytes.get(..1).unwrap_or(&[]);
+            out.push_str(&format!("{} '{}'", number, escape(byte)));
+        }
+        TypeKind::Bool => out.push_str(if value.is_true() { "true" } else { "false" }),
+        TypeKind::Float => out.push_str(&value.as_f64().to_string()),
+        TypeKind::Enum(ref enumerators) => {
+            let number = value.as_i64();
+            match enumerators.iter().find(|(_, value)| *value == number) {
+                Some((name, _)) => out.push_str(name),
+                None => out.push_str(&number.to_string()),
+            }
+        }
+        TypeKind::Pointer(ref target) => {
+            let pointer = value.raw() as usize;
+            out.push_str(&format!("{:#x}", pointer));
+            match target.kind {
+                TypeKind::Char { .. } if pointer != 0 => {
+                    if let Some(image) = image {
+                        out.push_str(&format!(" {}", read_c_string(image, pointer)));
+                    }
+                }
+                TypeKind::Function => {
+                    if let Some(func) = debug_data.get_function_from_addr(pointer) {
+                        out.push_str(&format!(" <{}>", func));
+                    }
+                }
+                _ => {}
+            }
+        }
+        TypeKind::Function => {
+            if let Some(addr) = addr {
+                out.push_str(&format!("{:#x}", addr));
+                if let Some(func) = debug_data.get_function_from_addr(addr) {
+                    out.push_str(&format!(" <{}>", func));
+                }
+            }
+        }
+        TypeKind::Array(ref element, count) => {
+            let count = count.unwrap_or(0);
+            if let TypeKind::Char { .. } = element.kind {
+                let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                out.push_str(&format!("\"{}\"", escape(&bytes[..len])));
+                return;
+            }
+            if depth > MAX_PRINT_DEPTH {
+                out.push_str("{...}");
+                return;
+            }
+            out.push('{');
+            for i in 0..count.min(PRINT_LIMIT) {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                let start = i * element.size;
+                let element_bytes = bytes.get(start..start + element.size).unwrap_or(&[]);
+                let element_addr = addr.map(|addr| addr + start);
+                format_bytes(
+                    out,
+                    element,
+                    element_bytes,
+                    element_addr,
+                    debug_data,
+                    image,
+                    depth + 1,
+                );
+            }
+            if count > PRINT_LIMIT {
+                out.push_str("...");
+            }
+            out.push('}');
+        }
+        TypeKind::Struct(ref members) => {
+            if depth > MAX_PRINT_DEPTH {
+                out.push_str("{...}");
+                return;
+            }
+            out.push('{');
+            for (i, member) in members.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("{} = ", member.name));
+                format_member(out, member, bytes, addr, debug_data, image, depth + 1);
+            }
+            out.push('}');
+        }
+        TypeKind::Variants(ref discriminant, ref variants) => {
+            if depth > MAX_PRINT_DEPTH {
+                out.push_str("{...}");
+                return;
+            }
+            let discriminant = discriminant.as_ref().and_then(|member| {
+                let member_type = debug_data.get_type(member.type_offset)?;
+                let end = member.offset + member_type.size;
+                let member_bytes = bytes.get(member.offset..end)?;
+                Some(Value::new(member_type.clone(), member_bytes.to_vec()).raw())
+            });
+            // The variant the discriminant selects, or else the default one
+            let variant = variants
+                .iter()
+                .find(|variant| discriminant.is_some() && variant.discriminant == discriminant)
+                .or_else(|| {
+                    variants
+                        .iter()
+                        .find(|variant| variant.discriminant.is_none())
+                });
+            let variant = match variant {
+                Some(variant) => &variant.member,
+                None => {
+                    out.push_str("<invalid discriminant>");
+                    return;
+                }
+            };
+            // Print a variant the way it's written in Rust: None, Some(5) or Move {x = 1, y = 2}
+            out.push_str(&variant.name);
+            let variant_type = match debug_data.get_type(variant.type_offset) {
+                Some(variant_type) => variant_type,
+                None => return,
+            };
+            let fields = match variant_type.kind {
+                TypeKind::Struct(ref fields) if !fields.is_empty() => fields,
+                _ => return,
+            };
+            let end = variant.offset + variant_type.size;
+            let variant_bytes = bytes.get(variant.offset..end).unwrap_or(&[]);
+            let variant_addr = addr.map(|addr| addr + variant.offset);
+            if fields.iter().all(|field| field.name.starts_with("__")) {
+                out.push('(');
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    format_member(
+                        out,
+                        field,
+                        variant_bytes,
+                        variant_addr,
+                        debug_data,
+                        image,
+                        depth + 1,
+                    );
+                }
+                out.push(')');
+            } else {
+                out.push(' ');
+                format_bytes(
+                    out,
+                    variant_type,
+                    variant_bytes,
+                    variant_addr,
+                    debug_data,
+                    image,
+                    depth + 1,
+                );
+            }
+        }
+    }
+}
+
+/// Formats the value of one member of a struct, given the struct's bytes and address.
+fn format_member(
+    out: &mut String,
+    member: &Member,
+    bytes: &[u8],
+    addr: Option<usize>,
+    debug_data: &DwarfData,
+    image: Option<&dyn ProcessImage>,
+    depth: usize,
+) {
+    match debug_data.get_type(member.type_offset) {
+        Some(member_type) => {
+            let end = member.offset + member_type.size;
+            let member_bytes = bytes.get(member.offset..end).unwrap_or(&[]);
+            let member_addr = addr.map(|addr| addr + member.offset);
+            format_bytes(
+                out,
+                member_type,
+                member_bytes,
+                member_addr,
+                debug_data,
+                image,
+                depth,
+            );
+        }
+        None => out.push_str("<unknown type>"),
+    }
+}
+
+/// Reads a NUL-terminated string from the process and formats it as a quoted C string.
+fn read_c_string(image: &dyn ProcessImage, addr: usize) -> String {
+    let mut bytes = Vec::new();
+    while bytes.len() < PRINT_LIMIT {
+        match image.read_bytes(addr + bytes.len(), 1) {
+            Ok(byte) if byte[0] != 0 => bytes.push(byte[0]),
+            Ok(_) => return format!("\"{}\"", escape(&bytes)),
+            Err(_) if bytes.is_empty() => {
+                return format!("<error: Cannot access memory at address {:#x}>", addr)
+            }
+            Err(_) => break,
+        }
+    }
+    format!("\"{}\"...", escape(&bytes))
+}
+
+pub fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(|b| b as char)
+        .collect()
+}
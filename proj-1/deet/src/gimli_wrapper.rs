@@ -6,241 +6,1242 @@
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
 use gimli;
+use gimli::Reader as _;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::demangle::demangle;
+use crate::dwarf_data::{
+    File, Function, InlinedCall, Line, Location, Member, Type, TypeKind, Variable, Variant,
+};
+use crate::dwarf_index;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
-use std::{io, path};
-
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        Ok(object
-            .section_data_by_name(id.name())
-            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+use std::{fmt, io, ops, path, sync};
+
+/// A section of an object file. It shares the file's mapping rather than copying the section out
+/// of it, so that we can keep the sections around and parse compilation units as we need them
+/// without reading the whole file in up front.
+#[derive(Clone)]
+pub struct Section {
+    /// The mapped file, or the section's own bytes if object had to decompress them
+    data: sync::Arc<dyn ops::Deref<Target = [u8]> + Send + Sync>,
+    range: ops::Range<usize>,
+}
+
+impl Section {
+    /// Returns the section whose contents are data, which object read from file.
+    fn new(file: &sync::Arc<memmap::Mmap>, data: borrow::Cow<[u8]>) -> Section {
+        let start = (data.as_ptr() as usize).wrapping_sub(file.as_ptr() as usize);
+        let in_file = start <= file.len() && data.len() <= file.len() - start;
+        match data {
+            borrow::Cow::Borrowed(data) if in_file => Section {
+                data: file.clone(),
+                range: start..start + data.len(),
+            },
+            data => Section {
+                range: 0..data.len(),
+                data: sync::Arc::new(data.into_owned()),
+            },
+        }
+    }
+
+    fn empty() -> Section {
+        Section {
+            data: sync::Arc::new(Vec::new()),
+            range: 0..0,
+        }
+    }
+}
+
+impl ops::Deref for Section {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.range.clone()]
+    }
+}
+
+impl fmt::Debug for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Section {{range: {:?}}}", self.range)
+    }
+}
+
+// The bytes live in the mapping (or Vec) behind the Arc, so they don't move when a Section is
+// moved or cloned.
+unsafe impl gimli::StableDeref for Section {}
+unsafe impl gimli::CloneStableDeref for Section {}
+
+/// The reader we parse sections with.
+pub type DwarfReader = gimli::EndianReader<gimli::RunTimeEndian, Section>;
+
+/// Loads the DWARF sections of an object file, which was parsed from file.
+pub fn load_dwarf(
+    file: &sync::Arc<memmap::Mmap>,
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<gimli::Dwarf<DwarfReader>, Error> {
+    let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
+        let section = match object.section_data_by_name(id.name()) {
+            Some(data) => Section::new(file, data),
+            None => Section::empty(),
+        };
+        Ok(gimli::EndianReader::new(section, endian))
     };
     // Load a supplementary section. We don't have a supplementary object file,
     // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    let load_section_sup = |_| Ok(gimli::EndianReader::new(Section::empty(), endian));
 
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+    Ok(gimli::Dwarf::load(&load_section, &load_section_sup)?)
+}
 
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+/// The DWARF of units built with -gsplit-dwarf, from a .dwo file or a .dwp package of them. The
+/// addresses their DIEs refer to stay in the executable's .debug_addr, and, before DWARF 5, so
+/// do their range lists.
+pub struct SplitDwarf {
+    dwarf: gimli::Dwarf<DwarfReader>,
+    /// Where each unit's contributions to the sections start in a package, by DWO ID
+    package: Option<HashMap<u64, dwarf_index::DwpUnit>>,
+}
 
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+impl SplitDwarf {
+    /// Returns true if this is a package with the unit whose DWO ID is id in it.
+    pub fn contains(&self, id: u64) -> bool {
+        match self.package {
+            Some(ref package) => package.contains_key(&id),
+            None => false,
+        }
+    }
+}
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+/// Loads the split DWARF sections (.debug_info.dwo and so on) of a .dwo file or .dwp package,
+/// along with a package's index. object was parsed from file, and dwarf is the executable's DWARF.
+pub fn load_split_dwarf(
+    file: &sync::Arc<memmap::Mmap>,
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+    dwarf: &gimli::Dwarf<DwarfReader>,
+) -> Result<SplitDwarf, Error> {
+    let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
+        let section = match object.section_data_by_name(&format!("{}.dwo", id.name())) {
+            Some(data) => Section::new(file, data),
+            None => Section::empty(),
+        };
+        Ok(gimli::EndianReader::new(section, endian))
+    };
+    let load_section_sup = |_| Ok(gimli::EndianReader::new(Section::empty(), endian));
+    let mut split_dwarf = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+    split_dwarf.debug_addr = dwarf.debug_addr.clone();
+    let package = match object.section_data_by_name(".debug_cu_index") {
+        Some(data) => Some(
+            dwarf_index::read_cu_index(&data)
+                .ok_or_else(|| Error::ObjectError("Bad .debug_cu_index".to_string()))?,
+        ),
+        None => None,
+    };
+    Ok(SplitDwarf {
+        dwarf: split_dwarf,
+        package,
+    })
+}
 
-    let mut compilation_units: Vec<File> = Vec::new();
+/// The .dwo file of a skeleton unit, and the ID of the split unit in it
+#[derive(Debug, Clone)]
+pub struct DwoRef {
+    pub path: String,
+    pub id: u64,
+}
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+/// What we know about a compilation unit before reading its DIEs.
+#[derive(Debug, Clone)]
+pub struct UnitSummary {
+    /// The offset of the unit in .debug_info
+    pub offset: usize,
+    /// The path of the unit's source file
+    pub name: String,
+    /// The address ranges of the unit's code, if we were asked to read them
+    pub ranges: Vec<(usize, usize)>,
+    /// Where the unit's DIEs are, if it was built with -gsplit-dwarf
+    pub dwo: Option<DwoRef>,
+}
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
+/// Reads the name (and, if read_ranges is set, the address ranges) of every compilation unit from
+/// its first DIE, without reading the rest of them.
+pub fn load_unit_summaries(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    read_ranges: bool,
+) -> Result<Vec<UnitSummary>, Error> {
+    let mut summaries = Vec::new();
+    for offset in unit_offsets(gimli::Section::reader(&dwarf.debug_info))? {
+        let info = match read_unit(dwarf, offset, Bases::default()) {
+            Ok(info) => info,
+            // e.g. type units, which we don't use
+            Err(Error::GimliError(gimli::Error::UnsupportedUnitType)) => continue,
+            Err(err) => return Err(err),
+        };
+        let mut ranges = Vec::new();
+        if read_ranges {
+            let mut entries = info.unit.entries();
+            if let Some((_, root)) = entries.next_dfs()? {
+                ranges = get_ranges(dwarf, dwarf, &info.unit, root, None)?;
+            }
+        }
+        summaries.push(UnitSummary {
+            offset,
+            name: get_unit_path(&info.unit),
+            ranges,
+            dwo: info.dwo,
+        });
+    }
+    Ok(summaries)
+}
+
+/// Reads the names of the functions, variables and types in the unit at offset in .debug_info,
+/// for building an index of them when the file doesn't have one. We only look at the names of
+/// the DIEs, which is much quicker than loading the unit.
+pub fn load_unit_names(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    offset: usize,
+    split: Option<&SplitDwarf>,
+) -> Result<Vec<String>, Error> {
+    let (skeleton, split_unit) = read_units(dwarf, offset, split)?;
+    let (dwarf, info) = split_unit.unwrap_or((dwarf, skeleton));
+    let unit = &info.unit;
+    let mut names = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs()? {
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            if attr.name() != gimli::DW_AT_name
+                && attr.name() != gimli::DW_AT_linkage_name
+                && attr.name() != gimli::DW_AT_MIPS_linkage_name
+            {
+                continue;
+            }
+            match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+                (gimli::DW_AT_name, Ok(DebugValue::Str(name))) => names.push(name),
+                // C++ and Rust functions can be looked up by their qualified names
+                (gimli::DW_AT_linkage_name, Ok(DebugValue::Str(name)))
+                | (gimli::DW_AT_MIPS_linkage_name, Ok(DebugValue::Str(name))) => {
+                    names.push(demangle(&name).split('(').next().unwrap().to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Set in the offsets we give the DIEs of split units, which aren't in .debug_info. The offset of
+/// the unit's skeleton goes in the bits below it, and the DIE's offset within the unit in the low
+/// 32 bits, so that every DIE still has an offset of its own.
+const SPLIT_UNIT_FLAG: usize = 1 << 63;
+
+/// Returns the offset in .debug_info of the skeleton unit of a DIE in a split unit, given the
+/// offset we gave the DIE, or None if the DIE is in .debug_info itself.
+pub fn get_skeleton_offset(die_offset: usize) -> Option<usize> {
+    if die_offset & SPLIT_UNIT_FLAG != 0 {
+        Some((die_offset & !SPLIT_UNIT_FLAG) >> 32)
+    } else {
+        None
+    }
+}
+
+/// Returns the offsets of the units in a .debug_info section.
+fn unit_offsets(debug_info: &DwarfReader) -> Result<Vec<usize>, Error> {
+    let mut offsets = Vec::new();
+    let mut input = debug_info.clone();
+    while !input.is_empty() {
+        offsets.push(input.offset_from(debug_info));
+        let (length, _) = input.read_initial_length()?;
+        input.skip(length)?;
+    }
+    Ok(offsets)
+}
+
+/// Reads the header of the unit at offset in a .debug_info section, along with the DWO ID in it if
+/// it's a skeleton or split unit. gimli only understands DWARF 5's full compilation units, so we
+/// read the headers ourselves: the other kinds have the ID after the usual fields.
+fn parse_header(
+    debug_info: &DwarfReader,
+    offset: usize,
+) -> Result<(gimli::UnitHeader<DwarfReader>, Option<u64>), Error> {
+    let mut input = debug_info.clone();
+    input.skip(offset)?;
+    let (unit_length, format) = input.read_initial_length()?;
+    let mut rest = input.split(unit_length)?;
+    let version = rest.read_u16()?;
+    let mut dwo_id = None;
+    let (address_size, abbrev_offset) = match version {
+        2..=4 => {
+            let abbrev_offset = rest.read_offset(format)?;
+            (rest.read_u8()?, abbrev_offset)
+        }
+        5 => {
+            let unit_type = gimli::DwUt(rest.read_u8()?);
+            let address_size = rest.read_u8()?;
+            let abbrev_offset = rest.read_offset(format)?;
+            match unit_type {
+                gimli::DW_UT_compile | gimli::DW_UT_partial => {}
+                gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => {
+                    dwo_id = Some(rest.read_u64()?)
+                }
+                _ => return Err(Error::GimliError(gimli::Error::UnsupportedUnitType)),
+            }
+            (address_size, abbrev_offset)
+        }
+        _ => {
+            return Err(Error::GimliError(gimli::Error::UnknownVersion(
+                version.into(),
+            )))
+        }
+    };
+    let encoding = gimli::Encoding {
+        format,
+        version,
+        address_size,
+    };
+    let header = gimli::UnitHeader::new(
+        encoding,
+        unit_length,
+        gimli::DebugAbbrevOffset(abbrev_offset),
+        rest,
+    );
+    Ok((header, dwo_id))
+}
+
+/// Where a unit's contributions to the sections start, for the ones a split unit doesn't have
+/// attributes to tell us
+#[derive(Debug, Default, Clone, Copy)]
+struct Bases {
+    abbrev: usize,
+    str_offsets: usize,
+    addr: usize,
+    loclists: usize,
+    rnglists: usize,
+}
+
+/// A compilation unit, along with where to find its split DWARF if it's a skeleton
+struct UnitInfo {
+    unit: gimli::Unit<DwarfReader>,
+    dwo: Option<DwoRef>,
+    /// The DWO ID, in the header in DWARF 5 and in an attribute before that
+    dwo_id: Option<u64>,
+    /// DW_AT_GNU_ranges_base, which DWARF 4 split units' range lists are relative to
+    ranges_base: usize,
+}
+
+/// Reads the unit at offset in dwarf's .debug_info. Like gimli's Unit::new, this reads the
+/// attributes of the unit's first DIE that we need to read the rest of them, but it also handles
+/// skeleton and split units, and DW_AT_low_pc given as an index into .debug_addr.
+fn read_unit(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    offset: usize,
+    bases: Bases,
+) -> Result<UnitInfo, Error> {
+    let (header, mut dwo_id) = parse_header(gimli::Section::reader(&dwarf.debug_info), offset)?;
+    let abbrev_offset = gimli::DebugAbbrevOffset(bases.abbrev + header.debug_abbrev_offset().0);
+    let abbreviations = dwarf.debug_abbrev.abbreviations(abbrev_offset)?;
+    let mut unit = gimli::Unit {
+        offset: UnitSectionOffset::DebugInfoOffset(gimli::DebugInfoOffset(offset)),
+        header,
+        abbreviations,
+        name: None,
+        comp_dir: None,
+        low_pc: 0,
+        str_offsets_base: gimli::DebugStrOffsetsBase(bases.str_offsets),
+        addr_base: gimli::DebugAddrBase(bases.addr),
+        loclists_base: gimli::DebugLocListsBase(bases.loclists),
+        rnglists_base: gimli::DebugRngListsBase(bases.rnglists),
+        line_program: None,
+    };
+    let mut name = None;
+    let mut comp_dir = None;
+    let mut low_pc = None;
+    let mut line_program_offset = None;
+    let mut dwo_name = None;
+    let mut ranges_base = 0;
+    let mut str_offsets_base = None;
+    let mut addr_base = None;
+    let mut loclists_base = None;
+    let mut rnglists_base = None;
+    {
         let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+        let root = match entries.next_dfs()? {
+            Some((_, root)) => root,
+            None => return Err(Error::GimliError(gimli::Error::MissingUnitDie)),
+        };
+        let mut attrs = root.attrs();
+        while let Some(attr) = attrs.next()? {
+            // The bases are section offsets, which gimli converts to different types
+            let base = attr.raw_value().offset_value();
+            match attr.name() {
+                gimli::DW_AT_name => name = Some(attr.value()),
+                gimli::DW_AT_comp_dir => comp_dir = Some(attr.value()),
+                gimli::DW_AT_low_pc => low_pc = Some(attr.value()),
+                gimli::DW_AT_stmt_list => {
+                    if let gimli::AttributeValue::DebugLineRef(offset) = attr.value() {
+                        line_program_offset = Some(offset);
+                    }
+                }
+                gimli::DW_AT_str_offsets_base => str_offsets_base = base,
+                gimli::DW_AT_addr_base | gimli::DW_AT_GNU_addr_base => addr_base = base,
+                gimli::DW_AT_loclists_base => loclists_base = base,
+                gimli::DW_AT_rnglists_base => rnglists_base = base,
+                gimli::DW_AT_GNU_ranges_base => ranges_base = base.unwrap_or(0),
+                gimli::DW_AT_dwo_name | gimli::DW_AT_GNU_dwo_name => dwo_name = Some(attr.value()),
+                gimli::DW_AT_GNU_dwo_id => dwo_id = attr.udata_value(),
+                _ => {}
+            }
+        }
+    }
+    if let Some(base) = str_offsets_base {
+        unit.str_offsets_base = gimli::DebugStrOffsetsBase(base);
+    }
+    if let Some(base) = addr_base {
+        unit.addr_base = gimli::DebugAddrBase(base);
+    }
+    if let Some(base) = loclists_base {
+        unit.loclists_base = gimli::DebugLocListsBase(base);
+    }
+    if let Some(base) = rnglists_base {
+        unit.rnglists_base = gimli::DebugRngListsBase(base);
+    }
+    unit.low_pc = match low_pc {
+        Some(gimli::AttributeValue::Addr(addr)) => addr,
+        Some(gimli::AttributeValue::DebugAddrIndex(index)) => dwarf.address(&unit, index)?,
+        _ => 0,
+    };
+    if let Some(name) = name {
+        unit.name = Some(dwarf.attr_string(&unit, name)?);
+    }
+    if let Some(comp_dir) = comp_dir {
+        unit.comp_dir = Some(dwarf.attr_string(&unit, comp_dir)?);
+    }
+    if let Some(offset) = line_program_offset {
+        unit.line_program = Some(dwarf.debug_line.program(
+            offset,
+            unit.header.address_size(),
+            unit.comp_dir.clone(),
+            unit.name.clone(),
+        )?);
+    }
+    // Skeleton units don't have a name, but their source file comes first in their line table
+    if let (None, Some(program)) = (unit.name.as_ref(), unit.line_program.as_ref()) {
+        let header = program.header();
+        let index = if header.version() >= 5 { 0 } else { 1 };
+        if let Some(file) = header.file(index) {
+            unit.name = Some(dwarf.attr_string(&unit, file.path_name())?);
+        }
+    }
+    let dwo = match (dwo_name, dwo_id) {
+        (Some(dwo_name), Some(id)) => {
+            let mut path = path::PathBuf::new();
+            if let Some(ref comp_dir) = unit.comp_dir {
+                path.push(comp_dir.to_string_lossy()?.as_ref());
+            }
+            path.push(
+                dwarf
+                    .attr_string(&unit, dwo_name)?
+                    .to_string_lossy()?
+                    .as_ref(),
+            );
+            Some(DwoRef {
+                path: path.to_string_lossy().to_string(),
+                id,
+            })
+        }
+        _ => None,
+    };
+    Ok(UnitInfo {
+        unit,
+        dwo,
+        dwo_id,
+        ranges_base,
+    })
+}
+
+/// A split unit, and the DWARF it's in
+type SplitUnit<'a> = (&'a gimli::Dwarf<DwarfReader>, UnitInfo);
+
+/// Reads the unit at offset in .debug_info and, if it's the skeleton of a split unit and we have
+/// the split DWARF, the split unit, which is where its DIEs are.
+fn read_units<'a>(
+    dwarf: &'a gimli::Dwarf<DwarfReader>,
+    offset: usize,
+    split: Option<&'a SplitDwarf>,
+) -> Result<(UnitInfo, Option<SplitUnit<'a>>), Error> {
+    let skeleton = read_unit(dwarf, offset, Bases::default())?;
+    let (split, dwo) = match (split, skeleton.dwo.clone()) {
+        (Some(split), Some(dwo)) => (split, dwo),
+        _ => return Ok((skeleton, None)),
+    };
+    // A package says where the unit is; a .dwo file normally has just the one
+    let (offsets, contributions) = match split.package {
+        Some(ref package) => match package.get(&dwo.id) {
+            Some(contributions) => (vec![contributions.info], *contributions),
+            None => return Ok((skeleton, None)),
+        },
+        None => (
+            unit_offsets(gimli::Section::reader(&split.dwarf.debug_info))?,
+            dwarf_index::DwpUnit::default(),
+        ),
+    };
+    for split_offset in offsets {
+        // DWARF 5 split units have no attributes giving their bases, which start after the
+        // headers of their contributions to .debug_str_offsets.dwo, .debug_loclists.dwo and
+        // .debug_rnglists.dwo
+        let header_size = if skeleton.unit.header.version() >= 5 {
+            (8, 12)
+        } else {
+            (0, 0)
+        };
+        let bases = Bases {
+            abbrev: contributions.abbrev,
+            str_offsets: contributions.str_offsets + header_size.0,
+            addr: skeleton.unit.addr_base.0,
+            loclists: contributions.loclists + header_size.1,
+            rnglists: contributions.rnglists + header_size.1,
+        };
+        let mut info = read_unit(&split.dwarf, split_offset, bases)?;
+        if info.dwo_id != Some(dwo.id) {
+            continue;
+        }
+        // Give the DIEs offsets that can't be confused with those in .debug_info
+        let base = SPLIT_UNIT_FLAG | offset << 32 | split_offset;
+        info.unit.offset = UnitSectionOffset::DebugInfoOffset(gimli::DebugInfoOffset(base));
+        info.unit.low_pc = skeleton.unit.low_pc;
+        // DWARF 5 skeletons have the compilation directory, and sometimes the name
+        if info.unit.name.is_none() {
+            info.unit.name = skeleton.unit.name.clone();
+        }
+        if info.unit.comp_dir.is_none() {
+            info.unit.comp_dir = skeleton.unit.comp_dir.clone();
+        }
+        info.unit.line_program = skeleton.unit.line_program.clone();
+        info.ranges_base = skeleton.ranges_base;
+        return Ok((skeleton, Some((&split.dwarf, info))));
+    }
+    Ok((skeleton, None))
+}
+
+/// Returns the path of a unit's source file, which its name may be relative to the compilation
+/// directory for. rustc names units after the crate's root file followed by /@/ and the codegen
+/// unit.
+fn get_unit_path<R: Reader>(unit: &gimli::Unit<R>) -> String {
+    let name = match unit.name.as_ref().map(|name| name.to_string_lossy()) {
+        Some(Ok(name)) => name.split("/@/").next().unwrap().to_string(),
+        _ => return "<unknown>".to_string(),
+    };
+    let mut path = path::PathBuf::new();
+    if let Some(Ok(comp_dir)) = unit.comp_dir.as_ref().map(|dir| dir.to_string_lossy()) {
+        path.push(comp_dir.as_ref());
+    }
+    path.push(name);
+    path.to_string_lossy().to_string()
+}
+
+/// Returns the address ranges of a DIE. Unlike gimli's die_ranges, this handles addresses given
+/// as indexes into .debug_addr, and the range lists of DWARF 4 split units, which are in the
+/// executable's .debug_ranges at an offset from its DW_AT_GNU_ranges_base.
+fn get_ranges(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    die_dwarf: &gimli::Dwarf<DwarfReader>,
+    unit: &gimli::Unit<DwarfReader>,
+    entry: &gimli::DebuggingInformationEntry<DwarfReader>,
+    split_ranges_base: Option<usize>,
+) -> Result<Vec<(usize, usize)>, Error> {
+    let mut low_pc = None;
+    let mut high_pc = None;
+    let mut size = None;
+    let mut ranges = Vec::new();
+    let mut attrs = entry.attrs();
+    while let Some(attr) = attrs.next()? {
+        match (attr.name(), attr.value()) {
+            (gimli::DW_AT_low_pc, gimli::AttributeValue::Addr(addr)) => low_pc = Some(addr),
+            (gimli::DW_AT_low_pc, gimli::AttributeValue::DebugAddrIndex(index)) => {
+                low_pc = Some(die_dwarf.address(unit, index)?)
+            }
+            (gimli::DW_AT_high_pc, gimli::AttributeValue::Addr(addr)) => high_pc = Some(addr),
+            (gimli::DW_AT_high_pc, gimli::AttributeValue::DebugAddrIndex(index)) => {
+                high_pc = Some(die_dwarf.address(unit, index)?)
+            }
+            (gimli::DW_AT_high_pc, gimli::AttributeValue::Udata(len)) => size = Some(len),
+            (gimli::DW_AT_ranges, value) => {
+                let list = match (value, split_ranges_base) {
+                    (gimli::AttributeValue::RangeListsRef(offset), Some(base))
+                        if unit.header.version() < 5 =>
+                    {
+                        let offset = gimli::RangeListsOffset(base + offset.0);
+                        Some(dwarf.ranges(unit, offset)?)
+                    }
+                    (value, _) => die_dwarf.attr_ranges(unit, value)?,
+                };
+                if let Some(mut list) = list {
+                    while let Some(range) = list.next()? {
+                        let (begin, end) = (range.begin.try_into(), range.end.try_into());
+                        ranges.push((begin.unwrap(), end.unwrap()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(low_pc) = low_pc {
+        if let Some(end) = size.map(|size| low_pc + size).or(high_pc) {
+            ranges.push((low_pc.try_into().unwrap(), end.try_into().unwrap()));
+        }
+    }
+    Ok(ranges)
+}
+
+/// Reads the compilation unit at offset in .debug_info: its functions, variables and lines, and
+/// its types keyed by the section offsets of their DIEs. If it was built with -gsplit-dwarf, its
+/// DIEs are read from split, but its lines are still in the executable.
+pub fn load_unit(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    offset: usize,
+    split: Option<&SplitDwarf>,
+) -> Result<(File, HashMap<usize, Type>), Error> {
+    let (skeleton, split_unit) = read_units(dwarf, offset, split)?;
+    let split_ranges_base = split_unit.as_ref().map(|_| skeleton.ranges_base);
+    let (die_dwarf, info) = match split_unit {
+        Some((split_dwarf, ref info)) => (split_dwarf, info),
+        None => (dwarf, &skeleton),
+    };
+    let unit = &info.unit;
+
+    // Define a mapping from type offsets to type structs. Types are loaded up front so that
+    // variables can refer to types that are declared after them.
+    let offset_to_type = load_types(die_dwarf, unit)?;
+
+    let mut file = File::default();
+    // Inlined calls and out-of-line copies of inline functions refer to the function's abstract
+    // instance (DW_AT_abstract_origin) for its name, as C++ function definitions refer to their
+    // declarations (DW_AT_specification). We fill the names in once we've seen them all.
+    let mut subprogram_names: HashMap<usize, (String, String)> = HashMap::new();
+    let mut unnamed_functions: Vec<(usize, usize)> = Vec::new();
+    let mut unnamed_calls: Vec<(usize, usize)> = Vec::new();
+
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // The depth of the function whose DIEs we're in, which is deeper than 1 when it's in a
+    // namespace, and what its frame base is relative to the CFA
+    let mut function_depth = None;
+    let mut frame_base_offset = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        if matches!(function_depth, Some(function_depth) if depth <= function_depth) {
+            function_depth = None;
+        }
+        // Update the offset_to_type mapping for types
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                file.name = get_unit_path(unit);
+                if let Some(ref dir) = unit.comp_dir {
+                    file.compilation_directory = dir.to_string_lossy()?.to_string();
+                }
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    if let (gimli::DW_AT_producer, Ok(DebugValue::Str(producer))) =
+                        (attr.name(), get_attr_value(&attr, unit, die_dwarf))
+                    {
+                        file.producer = producer
+                    }
+                    if let gimli::AttributeValue::Language(lang) = attr.value() {
+                        // e.g. DW_LANG_C99 becomes c99
+                        if let Some(lang) = lang.static_string() {
+                            file.language = lang.trim_start_matches("DW_LANG_").to_lowercase();
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+                    }
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func = Function {
+                    return_type: Type::void(),
+                    ..Default::default()
+                };
+                let mut origin = None;
+                frame_base_offset = 0;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, die_dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                func.text_length = high_pc.try_into().unwrap();
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if let Some(dtype) = offset_to_type.get(&offset) {
+                                    func.return_type = dtype.clone();
                                 }
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_abstract_origin | gimli::DW_AT_specification => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                origin = Some(offset);
+                            }
+                        }
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.qualified_name = demangle(&name);
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_frame_base => {
+                            frame_base_offset = get_frame_base_offset(&attr, unit);
+                        }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
-                }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                }
+                function_depth = Some(depth);
+                if !func.name.is_empty() {
+                    let offset = section_offset(entry.offset(), unit);
+                    let names = (func.name.clone(), func.qualified_name.clone());
+                    subprogram_names.insert(offset, names);
+                } else if let Some(origin) = origin {
+                    unnamed_functions.push((file.functions.len(), origin));
+                }
+                file.functions.push(func);
+            }
+            gimli::DW_TAG_inlined_subroutine => {
+                let mut call = InlinedCall::default();
+                let mut origin = None;
+                let mut entry_pc = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    match (attr.name(), get_attr_value(&attr, unit, die_dwarf)) {
+                        (gimli::DW_AT_abstract_origin, Ok(DebugValue::Size(offset))) => {
+                            origin = Some(offset)
+                        }
+                        (gimli::DW_AT_call_line, Ok(DebugValue::Uint(line_number))) => {
+                            call.call_line = line_number.try_into().unwrap()
+                        }
+                        (gimli::DW_AT_entry_pc, Ok(DebugValue::Uint(addr))) => {
+                            entry_pc = Some(addr.try_into().unwrap())
+                        }
+                        _ => {}
+                    }
+                }
+                call.ranges = get_ranges(dwarf, die_dwarf, unit, entry, split_ranges_base)?;
+                // Without an entry_pc, the inlined code starts at its lowest address
+                call.address = match entry_pc {
+                    Some(addr) => addr,
+                    None => match call.ranges.iter().map(|range| range.0).min() {
+                        Some(addr) => addr,
+                        None => continue,
+                    },
+                };
+                if let Some(origin) = origin {
+                    unnamed_calls.push((file.inlined_calls.len(), origin));
+                    file.inlined_calls.push(call);
+                }
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, die_dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if let Some(dtype) = offset_to_type.get(&offset).clone() {
+                                    entity_type = Some(dtype.clone());
                                 }
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, unit, die_dwarf) {
+                                location = Some(loc);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        _ => {}
+                    }
+                }
+                // Parameters are direct children of their function. Record their types
+                // even if they have no location (as in a prototype), so calls can be set up.
+                if entry.tag() == gimli::DW_TAG_formal_parameter
+                    && function_depth == Some(depth - 1)
+                {
+                    if let (Some(dtype), Some(func)) =
+                        (entity_type.as_ref(), file.functions.last_mut())
+                    {
+                        func.parameters.push(dtype.clone());
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                }
+                if entity_type.is_some() && location.is_some() {
+                    let location = match location.unwrap() {
+                        Location::FramePointerOffset(offset) if function_depth.is_some() => {
+                            Location::FramePointerOffset(offset + frame_base_offset)
                         }
+                        location => location,
+                    };
+                    let var = Variable {
+                        name,
+                        entity_type: entity_type.unwrap(),
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                    };
+                    if function_depth.is_none() {
+                        file.global_variables.push(var);
+                    } else if let Some(func) = file.functions.last_mut() {
+                        func.variables.push(var);
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
             }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
         }
+    }
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
-                        }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
+    // Get line numbers. A split unit's line table is still in the executable, and is read with the
+    // skeleton unit.
+    if let Some(program) = skeleton.unit.line_program.clone() {
+        let comp_dir = match skeleton.unit.comp_dir {
+            Some(ref dir) => dir.to_string_lossy()?.to_string(),
+            None => String::new(),
+        };
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Directories may be relative to the compilation directory,
+                // which is what the unit's name is relative to too.
+                let mut path = path::PathBuf::from(&comp_dir);
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        let dir = dwarf.attr_string(&skeleton.unit, dir)?;
+                        path.push(dir.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        dwarf
+                            .attr_string(&skeleton.unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = row.line().unwrap_or(0);
 
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
+                if file.name == path.as_os_str().to_str().unwrap() {
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: row.address().try_into().unwrap(),
+                    });
+                }
+            }
+        }
+    }
+    for (index, origin) in unnamed_functions {
+        if let Some((name, qualified_name)) = subprogram_names.get(&origin) {
+            let func = &mut file.functions[index];
+            func.name = name.clone();
+            func.qualified_name = qualified_name.clone();
+        }
+    }
+    for (index, origin) in unnamed_calls {
+        if let Some((name, _)) = subprogram_names.get(&origin) {
+            file.inlined_calls[index].name = name.clone();
+        }
+    }
+    Ok((file, offset_to_type))
+}
+
+/// A type DIE as it appears in the file, with its references to other types not yet resolved.
+struct RawType {
+    tag: gimli::DwTag,
+    name: Option<String>,
+    size: Option<usize>,
+    encoding: Option<gimli::DwAte>,
+    /// The type this one points to, is an array of, qualifies, etc. (DW_AT_type)
+    target: Option<usize>,
+    members: Vec<Member>,
+    /// Element counts of each array dimension, outermost first
+    dimensions: Vec<Option<usize>>,
+    enumerators: Vec<(String, i64)>,
+    template_params: Vec<usize>,
+    /// The member holding the discriminant of a variant part, and the variants it selects from
+    discriminant: Option<Member>,
+    variants: Vec<Variant>,
+    /// Whether the type comes from a Rust compilation unit, whose type names are used as-is
+    rust: bool,
+}
+
+fn is_type_tag(tag: gimli::DwTag) -> bool {
+    matches!(
+        tag,
+        gimli::DW_TAG_base_type
+            | gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_enumeration_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_subroutine_type
+            | gimli::DW_TAG_unspecified_type
+    )
+}
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
+/// Returns the offset of a DIE from the start of its section, which is what DW_AT_type refers to.
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+/// Loads every type in a unit, keyed by the section offset of its DIE.
+fn load_types<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<HashMap<usize, Type>, Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // Type DIEs whose children (members, subranges, enumerators) we may be reading, with
+    // their depths. Struct definitions can be nested, hence the stack.
+    let mut parents: Vec<(usize, isize)> = Vec::new();
+    // The struct whose variant part we're reading, the variant part's depth, and the
+    // discriminant value of the variant we're in
+    let mut variant_part: Option<(usize, isize)> = None;
+    let mut discriminant = None;
+    let mut rust = false;
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while let Some(&(_, parent_depth)) = parents.last() {
+            if parent_depth < depth {
+                break;
+            }
+            parents.pop();
+        }
+        if let Some((_, part_depth)) = variant_part {
+            if depth <= part_depth {
+                variant_part = None;
+            }
+        }
+        let mut name = None;
+        let mut size = None;
+        let mut encoding = None;
+        let mut target = None;
+        let mut member_offset = 0;
+        let mut count = None;
+        let mut value = 0;
+        let mut discr_value = None;
+        let mut language = None;
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                gimli::DW_AT_name => {
+                    if let Ok(DebugValue::Str(attr_name)) = get_attr_value(&attr, unit, dwarf) {
+                        name = Some(attr_name);
+                    }
+                }
+                gimli::DW_AT_language => {
+                    if let gimli::AttributeValue::Language(lang) = attr.value() {
+                        language = Some(lang);
+                    }
+                }
+                gimli::DW_AT_discr_value => {
+                    discr_value = attr
+                        .udata_value()
+                        .or_else(|| attr.sdata_value().map(|value| value as u64))
+                }
+                gimli::DW_AT_byte_size => size = attr.udata_value().map(|size| size as usize),
+                gimli::DW_AT_encoding => {
+                    if let gimli::AttributeValue::Encoding(ate) = attr.value() {
+                        encoding = Some(ate);
+                    }
+                }
+                gimli::DW_AT_type => {
+                    if let Ok(DebugValue::Size(offset)) = get_attr_value(&attr, unit, dwarf) {
+                        target = Some(offset);
+                    }
+                }
+                gimli::DW_AT_data_member_location => {
+                    member_offset = match attr.value() {
+                        // DWARF 2 style: an expression that adds the offset to the address
+                        gimli::AttributeValue::Exprloc(ref data) => {
+                            let mut pc = data.0.clone();
+                            match gimli::Operation::parse(&mut pc, unit.encoding()) {
+                                Ok(gimli::Operation::PlusConstant { value }) => value as usize,
+                                _ => 0,
+                            }
+                        }
+                        _ => attr.udata_value().unwrap_or(0) as usize,
+                    }
+                }
+                gimli::DW_AT_upper_bound => {
+                    // An upper bound of -1 is how gcc describes a flexible array member
+                    count = match attr.sdata_value() {
+                        Some(bound) if bound >= 0 => Some(bound as usize + 1),
+                        _ => Some(0),
+                    }
+                }
+                gimli::DW_AT_count => count = attr.udata_value().map(|count| count as usize),
+                gimli::DW_AT_const_value => value = attr.sdata_value().unwrap_or(0),
+                _ => {}
+            }
+        }
+
+        let offset = section_offset(entry.offset(), unit);
+        let parent = match parents.last() {
+            Some(&(parent, parent_depth)) if parent_depth == depth - 1 => {
+                raw_types.get_mut(&parent)
+            }
+            _ => None,
+        };
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => rust = language == Some(gimli::DW_LANG_Rust),
+            gimli::DW_TAG_variant_part => {
+                if let Some(&(parent, parent_depth)) = parents.last() {
+                    if parent_depth == depth - 1 {
+                        variant_part = Some((parent, depth));
+                    }
+                }
+            }
+            gimli::DW_TAG_variant => discriminant = discr_value,
+            gimli::DW_TAG_member if variant_part.is_some() => {
+                // Directly inside the variant part is the discriminant; inside each of its
+                // variants is the member holding that variant's fields
+                let (owner, part_depth) = variant_part.unwrap();
+                if let (Some(owner), Some(type_offset)) = (raw_types.get_mut(&owner), target) {
+                    let member = Member {
+                        name: name.unwrap_or_default(),
+                        offset: member_offset,
+                        type_offset,
+                    };
+                    if depth == part_depth + 1 {
+                        owner.discriminant = Some(member);
+                    } else if depth == part_depth + 2 {
+                        owner.variants.push(Variant {
+                            discriminant,
+                            member,
                         });
                     }
                 }
             }
+            gimli::DW_TAG_member => {
+                if let (Some(parent), Some(type_offset)) = (parent, target) {
+                    parent.members.push(Member {
+                        name: name.unwrap_or_default(),
+                        offset: member_offset,
+                        type_offset,
+                    });
+                }
+            }
+            gimli::DW_TAG_template_type_parameter => {
+                if let (Some(parent), Some(type_offset)) = (parent, target) {
+                    parent.template_params.push(type_offset);
+                }
+            }
+            gimli::DW_TAG_subrange_type => {
+                if let Some(parent) = parent {
+                    parent.dimensions.push(count);
+                }
+            }
+            gimli::DW_TAG_enumerator => {
+                if let Some(parent) = parent {
+                    parent.enumerators.push((name.unwrap_or_default(), value));
+                }
+            }
+            tag if is_type_tag(tag) => {
+                raw_types.insert(
+                    offset,
+                    RawType {
+                        tag,
+                        name,
+                        size,
+                        encoding,
+                        target,
+                        members: Vec::new(),
+                        dimensions: Vec::new(),
+                        enumerators: Vec::new(),
+                        template_params: Vec::new(),
+                        discriminant: None,
+                        variants: Vec::new(),
+                        rust,
+                    },
+                );
+                if entry.has_children() {
+                    parents.push((offset, depth));
+                }
+            }
+            _ => {}
         }
     }
-    Ok(compilation_units)
+
+    let mut types = HashMap::new();
+    for offset in raw_types.keys() {
+        if let Some(resolved) = resolve_type(*offset, &raw_types, 0) {
+            types.insert(*offset, resolved);
+        }
+    }
+    Ok(types)
+}
+
+/// How deep a chain of pointers/typedefs/arrays we follow before deciding the file is broken.
+const MAX_TYPE_DEPTH: usize = 64;
+
+/// Builds the Type for the DIE at offset. Pointers, arrays and qualifiers contain the type they
+/// refer to, but struct members are kept as offsets: that's what stops a struct that points to
+/// itself from turning into an infinitely large Type.
+fn resolve_type(offset: usize, raw_types: &HashMap<usize, RawType>, depth: usize) -> Option<Type> {
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    let raw = raw_types.get(&offset)?;
+    // The referenced type, or void if there isn't one
+    let target = || match raw.target {
+        Some(target) => resolve_type(target, raw_types, depth + 1),
+        None => Some(Type::void()),
+    };
+    let tag_name = |keyword: &str| match raw.name {
+        Some(ref name) if raw.rust => name.clone(),
+        Some(ref name) => format!("{} {}", keyword, name),
+        None => format!("{} {{...}}", keyword),
+    };
+    Some(match raw.tag {
+        gimli::DW_TAG_base_type => {
+            let size = raw.size.unwrap_or(0);
+            let kind = match raw.encoding {
+                Some(gimli::DW_ATE_float) => TypeKind::Float,
+                Some(gimli::DW_ATE_boolean) => TypeKind::Bool,
+                Some(gimli::DW_ATE_signed_char) => TypeKind::Char { signed: true },
+                Some(gimli::DW_ATE_unsigned_char) => TypeKind::Char { signed: false },
+                Some(gimli::DW_ATE_signed) => TypeKind::Int { signed: true },
+                _ => TypeKind::Int { signed: false },
+            };
+            Type::new(raw.name.clone().unwrap_or_default(), size, kind)
+        }
+        gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type => {
+            let mut pointer = Type::pointer_to(target()?);
+            // Only Rust names its pointer types, e.g. &str or *const u8
+            if let Some(ref name) = raw.name {
+                pointer.name = name.clone();
+            }
+            pointer
+        }
+        gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type
+        | gimli::DW_TAG_atomic_type => {
+            // Qualifiers don't change how we read or print a value, so just fold them in to the
+            // name
+            let qualifier = match raw.tag {
+                gimli::DW_TAG_const_type => "const",
+                gimli::DW_TAG_volatile_type => "volatile",
+                gimli::DW_TAG_restrict_type => "restrict",
+                _ => "_Atomic",
+            };
+            let mut qualified = target()?;
+            qualified.name = match qualified.kind {
+                TypeKind::Pointer(_) => format!("{} {}", qualified.name, qualifier),
+                _ => format!("{} {}", qualifier, qualified.name),
+            };
+            qualified
+        }
+        gimli::DW_TAG_typedef => {
+            let mut aliased = target()?;
+            if let Some(ref name) = raw.name {
+                aliased.name = name.clone();
+            }
+            aliased
+        }
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+            let keyword = match raw.tag {
+                gimli::DW_TAG_union_type => "union",
+                gimli::DW_TAG_class_type => "class",
+                _ => "struct",
+            };
+            let kind = if raw.discriminant.is_some() || !raw.variants.is_empty() {
+                TypeKind::Variants(raw.discriminant.clone(), raw.variants.clone())
+            } else {
+                TypeKind::Struct(raw.members.clone())
+            };
+            let mut compound = Type::new(tag_name(keyword), raw.size.unwrap_or(0), kind);
+            compound.template_params = raw.template_params.clone();
+            compound
+        }
+        gimli::DW_TAG_enumeration_type => Type::new(
+            tag_name("enum"),
+            raw.size.unwrap_or(4),
+            TypeKind::Enum(raw.enumerators.clone()),
+        ),
+        gimli::DW_TAG_array_type => {
+            let mut array = target()?;
+            let element_name = array.name.clone();
+            // Build int[2][3] as an array of 2 arrays of 3 ints, starting from the inside
+            for (i, count) in raw.dimensions.iter().enumerate().rev() {
+                let suffix: String = raw.dimensions[i..]
+                    .iter()
+                    .map(|count| match count {
+                        Some(count) => format!("[{}]", count),
+                        None => "[]".to_string(),
+                    })
+                    .collect();
+                array = Type::new(
+                    format!("{} {}", element_name, suffix),
+                    count.unwrap_or(0) * array.size,
+                    TypeKind::Array(Box::new(array), *count),
+                );
+            }
+            array
+        }
+        gimli::DW_TAG_subroutine_type => {
+            Type::new(format!("{} ()", target()?.name), 1, TypeKind::Function)
+        }
+        _ => Type::void(),
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -284,34 +1285,89 @@ impl From<std::fmt::Error> for Error {
     }
 }
 
-impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
-    Endian: gimli::Endianity + Send + Sync
-{
-}
+impl Reader for DwarfReader {}
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+/// Returns where a variable lives, from an expression or, when its location changes as the function
+/// runs, the first entry of its location list (DW_FORM_loclistx or a .debug_loclists offset) that
+/// we can represent.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+        return get_expression_location(data, unit, dwarf);
+    }
+    // DWARF 4 split units' location lists are in a format gimli doesn't read
+    let split = get_skeleton_offset(section_offset(UnitOffset(0), unit)).is_some();
+    if split && unit.header.version() < 5 {
+        return None;
+    }
+    let mut locations = dwarf.attr_locations(unit, attr.value()).ok()??;
+    while let Ok(Some(entry)) = locations.next() {
+        if let Some(location) = get_expression_location(&entry.data, unit, dwarf) {
+            return Some(location);
+        }
+    }
+    None
+}
+
+const DW_OP_GNU_ADDR_INDEX: u8 = 0xfb;
+
+fn get_expression_location<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let encoding = unit.encoding();
+    let mut pc = data.0.clone();
+    // gimli doesn't know DW_OP_GNU_addr_index, the DWARF 4 split units' DW_OP_addrx
+    if pc.len() > 0 && pc.read_u8().ok() == Some(DW_OP_GNU_ADDR_INDEX) {
+        let index = gimli::DebugAddrIndex(pc.read_uleb128().ok()? as usize);
+        let address = dwarf.address(unit, index).ok()?;
+        return Some(Location::Address(address.try_into().unwrap()));
+    }
+    let mut pc = data.0.clone();
+    if pc.len() > 0 {
+        if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
+            match op {
+                gimli::Operation::FrameOffset { offset } => {
+                    return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
+                }
+                gimli::Operation::Address { address } => {
+                    return Some(Location::Address(address.try_into().unwrap()));
                 }
+                gimli::Operation::AddressIndex { index } => {
+                    let address = dwarf.address(unit, index).ok()?;
+                    return Some(Location::Address(address.try_into().unwrap()));
+                }
+                _ => {}
             }
         }
     }
     None
 }
 
+/// Returns the offset from the CFA to a function's frame base (DW_AT_frame_base), which we add to
+/// its variables' frame offsets since Location::FramePointerOffset is relative to the CFA. gcc
+/// uses the CFA itself as the frame base, but rustc and clang use rbp, which sits 16 bytes below
+/// it.
+fn get_frame_base_offset<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> isize {
+    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+        let mut pc = data.0.clone();
+        if let Ok(gimli::Operation::Register { register }) =
+            gimli::Operation::parse(&mut pc, unit.encoding())
+        {
+            if register == gimli::X86_64::RBP {
+                return -16;
+            }
+        }
+    }
+    0
+}
+
 // based on dwarf_dump.rs
 fn get_attr_value<R: Reader>(
     attr: &gimli::Attribute<R>,
@@ -344,8 +1400,16 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugStrOffsetsIndex(_)
+        | gimli::AttributeValue::DebugLineStrRef(_) => {
+            let s = dwarf.attr_string(unit, value)?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
 
         gimli::AttributeValue::String(s) => {
@@ -0,0 +1,386 @@
+//! A walker for the heap glibc's malloc manages, for `info heap` and `info bins`. It reads
+//! malloc's data structures out of the program's memory as glibc 2.26 and later lay them out on
+//! x86-64: the chunks in the [heap] mapping one after another, each headed by its size, and the
+//! lists of free chunks kept in the tcache (at the start of the heap) and in the main arena (in
+//! libc's data). A chunk size that doesn't add up, or a free list that wanders out of the heap or
+//! loops, is the mark an overflow or a double free leaves before the crash it leads to.
+
+use crate::proc_maps::Mapping;
+use crate::process_image::ProcessImage;
+
+/// The flag in the low bits of a chunk's size that says the chunk before it is in use
+const PREV_INUSE: usize = 1;
+/// All the flags in the low bits of a chunk's size
+const SIZE_FLAGS: usize = 7;
+const MIN_CHUNK_SIZE: usize = 0x20;
+const MALLOC_ALIGNMENT: usize = 0x10;
+/// How far the memory malloc hands out (and the free list links) are into a chunk
+const CHUNK_HEADER_SIZE: usize = 0x10;
+/// Where the fastbins, the top chunk and the other bins are in struct malloc_state
+const ARENA_FASTBINS: usize = 0x10;
+const ARENA_TOP: usize = 0x60;
+const ARENA_BINS: usize = 0x70;
+const NFASTBINS: usize = 10;
+const NBINS: usize = 128;
+/// Bins from this one on hold chunks of a range of sizes, rather than one size
+const FIRST_LARGE_BIN: usize = 64;
+const TCACHE_BINS: usize = 64;
+/// The size of the chunk holding the tcache, which is the first in the heap: with 16-bit counts
+/// (glibc 2.30 and later), and with 8-bit ones
+const TCACHE_CHUNK_SIZE: usize = 0x290;
+const OLD_TCACHE_CHUNK_SIZE: usize = 0x250;
+/// A free list longer than this is taken to have been corrupted into a loop
+const MAX_LIST_LEN: usize = 10000;
+
+pub struct Chunk {
+    pub addr: usize,
+    /// The size from its header, without the flags
+    pub size: usize,
+    /// Whether the header says the chunk before this one is in use
+    pub prev_inuse: bool,
+}
+
+/// One of malloc's lists of free chunks
+pub struct Bin {
+    /// e.g. `tcache 0x20`, or `unsorted`
+    pub name: String,
+    /// The addresses of the chunks on it, in order
+    pub chunks: Vec<usize>,
+    /// What's wrong with the list, if following it went astray
+    pub error: Option<String>,
+}
+
+pub struct Heap {
+    pub start: usize,
+    pub end: usize,
+    pub chunks: Vec<Chunk>,
+    /// What's wrong with the chunks' headers. The walk stops at the first bad size, since the
+    /// next chunk can't be found without it.
+    pub errors: Vec<String>,
+    /// The chunk holding the tcache, if there is one
+    pub tcache: Option<usize>,
+    /// The main arena, if we could find it
+    pub arena: Option<usize>,
+    /// The free lists that aren't empty
+    pub bins: Vec<Bin>,
+}
+
+impl Bin {
+    fn new(name: String) -> Bin {
+        Bin {
+            name,
+            chunks: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl Heap {
+    /// Reads the heap of the process the mappings are from.
+    pub fn read(image: &dyn ProcessImage, maps: &[Mapping]) -> Result<Heap, String> {
+        let mapping = maps
+            .iter()
+            .find(|mapping| mapping.path.as_deref() == Some("[heap]"))
+            .ok_or_else(|| "The program hasn't allocated anything on the heap.".to_string())?;
+        let mut heap = Heap {
+            start: mapping.start,
+            end: mapping.end,
+            chunks: Vec::new(),
+            errors: Vec::new(),
+            tcache: None,
+            arena: None,
+            bins: Vec::new(),
+        };
+        heap.walk_chunks(image);
+        heap.read_tcache(image);
+        heap.arena = heap.find_main_arena(image, maps);
+        if let Some(arena) = heap.arena {
+            heap.read_arena_bins(image, arena);
+        }
+        Ok(heap)
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// Returns the top chunk, the free space at the end of the heap that malloc carves new chunks
+    /// from: the last chunk, if the walk got all the way to the end.
+    pub fn top(&self) -> Option<&Chunk> {
+        self.chunks
+            .last()
+            .filter(|chunk| chunk.addr + chunk.size + MIN_CHUNK_SIZE > self.end)
+    }
+
+    /// Returns the bin a chunk is on, if it's on one.
+    pub fn bin_holding(&self, chunk: usize) -> Option<&Bin> {
+        self.bins.iter().find(|bin| bin.chunks.contains(&chunk))
+    }
+
+    /// Goes through the chunks from the start of the heap, each one's size leading to the next.
+    fn walk_chunks(&mut self, image: &dyn ProcessImage) {
+        let mut addr = self.start;
+        let mut prev_size = 0;
+        while addr + MIN_CHUNK_SIZE <= self.end {
+            let field = match image.read_word(addr + 8) {
+                Ok(field) => field as usize,
+                Err(_) => {
+                    self.errors
+                        .push(format!("Cannot access memory at address {:#x}", addr + 8));
+                    return;
+                }
+            };
+            let size = field & !SIZE_FLAGS;
+            if size < MIN_CHUNK_SIZE || size & (MALLOC_ALIGNMENT - 1) != 0 || size > self.end - addr
+            {
+                self.errors.push(format!(
+                    "The chunk at {:#x} has a bad size field ({:#x}), so the heap can't be \
+                     followed past it.",
+                    addr, field
+                ));
+                return;
+            }
+            let prev_inuse = field & PREV_INUSE != 0;
+            if !prev_inuse && !self.chunks.is_empty() {
+                // A free chunk's size is repeated at the start of the chunk after it
+                let recorded = image.read_word(addr).unwrap_or(0) as usize;
+                if recorded != prev_size {
+                    self.errors.push(format!(
+                        "The chunk at {:#x} says the free chunk before it has size {:#x}, but \
+                         that chunk's own size is {:#x}.",
+                        addr, recorded, prev_size
+                    ));
+                }
+            }
+            self.chunks.push(Chunk {
+                addr,
+                size,
+                prev_inuse,
+            });
+            prev_size = size;
+            addr += size;
+        }
+    }
+
+    /// Reads the tcache's bins, if the first chunk looks like the tcache.
+    fn read_tcache(&mut self, image: &dyn ProcessImage) {
+        let (tcache, size) = match self.chunks.first() {
+            Some(chunk)
+                if chunk.size == TCACHE_CHUNK_SIZE || chunk.size == OLD_TCACHE_CHUNK_SIZE =>
+            {
+                (chunk.addr, chunk.size)
+            }
+            _ => return,
+        };
+        self.tcache = Some(tcache);
+        let count_size = if size == TCACHE_CHUNK_SIZE { 2 } else { 1 };
+        let counts_addr = tcache + CHUNK_HEADER_SIZE;
+        let entries_addr = counts_addr + TCACHE_BINS * count_size;
+        let (counts, entries) = match (
+            image.read_bytes(counts_addr, TCACHE_BINS * count_size),
+            image.read_bytes(entries_addr, TCACHE_BINS * 8),
+        ) {
+            (Ok(counts), Ok(entries)) => (counts, entries),
+            _ => return,
+        };
+        for i in 0..TCACHE_BINS {
+            let count = if count_size == 2 {
+                u16::from_le_bytes([counts[2 * i], counts[2 * i + 1]]) as usize
+            } else {
+                counts[i] as usize
+            };
+            let mut entry = [0u8; 8];
+            entry.copy_from_slice(&entries[8 * i..8 * i + 8]);
+            let entry = u64::from_le_bytes(entry) as usize;
+            if count == 0 && entry == 0 {
+                continue;
+            }
+            let name = format!("tcache {:#x}", MIN_CHUNK_SIZE + i * MALLOC_ALIGNMENT);
+            // The tcache points at the memory malloc hands out, rather than at the chunk
+            let mut bin = self.follow_list(image, name, entry, CHUNK_HEADER_SIZE);
+            if bin.error.is_none() && bin.chunks.len() != count {
+                bin.error = Some(format!(
+                    "it holds {} chunks, but its count says {}",
+                    bin.chunks.len(),
+                    count
+                ));
+            }
+            self.bins.push(bin);
+        }
+    }
+
+    /// Finds the main arena, malloc's state for the main thread, in the writable data of libc (or
+    /// of whatever else malloc is linked into): it's where the address of a chunk in the heap (the
+    /// top chunk) is kept, with the bins after it either empty or pointing into the heap. This
+    /// doesn't rely on walking the chunks having got as far as the top one.
+    fn find_main_arena(&self, image: &dyn ProcessImage, maps: &[Mapping]) -> Option<usize> {
+        let data = maps.iter().filter(|mapping| {
+            mapping.is_writable()
+                && matches!(mapping.path, Some(ref path) if !path.starts_with('['))
+        });
+        for mapping in data {
+            let words = match image.read_bytes(mapping.start, mapping.len()) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+            for (i, word) in words.chunks_exact(8).enumerate() {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(word);
+                let addr = mapping.start + i * 8;
+                let top = u64::from_le_bytes(buf) as usize;
+                if self.contains(top)
+                    && top & (MALLOC_ALIGNMENT - 1) == 0
+                    && addr >= mapping.start + ARENA_TOP
+                    && self.looks_like_arena(image, addr - ARENA_TOP)
+                {
+                    return Some(addr - ARENA_TOP);
+                }
+            }
+        }
+        None
+    }
+
+    /// Checks that each of the bins of what might be an arena is either empty, pointing back at
+    /// itself both ways, or pointing into the heap.
+    fn looks_like_arena(&self, image: &dyn ProcessImage, arena: usize) -> bool {
+        let links = match image.read_bytes(arena + ARENA_BINS, (NBINS - 1) * 16) {
+            Ok(links) => links,
+            Err(_) => return false,
+        };
+        links.chunks_exact(16).enumerate().all(|(i, pair)| {
+            let mut fd = [0u8; 8];
+            let mut bk = [0u8; 8];
+            fd.copy_from_slice(&pair[..8]);
+            bk.copy_from_slice(&pair[8..]);
+            let (fd, bk) = (
+                u64::from_le_bytes(fd) as usize,
+                u64::from_le_bytes(bk) as usize,
+            );
+            let head = bin_head(arena, i + 1);
+            (fd == head && bk == head) || (self.contains(fd) && self.contains(bk))
+        })
+    }
+
+    fn read_arena_bins(&mut self, image: &dyn ProcessImage, arena: usize) {
+        for i in 0..NFASTBINS {
+            let first = match image.read_word(arena + ARENA_FASTBINS + 8 * i) {
+                Ok(first) if first != 0 => first as usize,
+                _ => continue,
+            };
+            let name = format!("fastbin {:#x}", MIN_CHUNK_SIZE + i * MALLOC_ALIGNMENT);
+            let bin = self.follow_list(image, name, first, 0);
+            self.bins.push(bin);
+        }
+        for i in 1..NBINS {
+            let name = if i == 1 {
+                "unsorted".to_string()
+            } else if i < FIRST_LARGE_BIN {
+                format!("small {:#x}", i * MALLOC_ALIGNMENT)
+            } else {
+                format!("large bin {}", i)
+            };
+            let bin = self.follow_circular_list(image, name, bin_head(arena, i));
+            if !bin.chunks.is_empty() || bin.error.is_some() {
+                self.bins.push(bin);
+            }
+        }
+    }
+
+    /// Follows a singly linked free list (a tcache bin or a fastbin), in which each chunk's link
+    /// points offset bytes into the next chunk, and the last one's is null.
+    fn follow_list(
+        &self,
+        image: &dyn ProcessImage,
+        name: String,
+        first: usize,
+        offset: usize,
+    ) -> Bin {
+        let mut bin = Bin::new(name);
+        let mut link = first;
+        while link != 0 {
+            let chunk = link.wrapping_sub(offset);
+            if !self.contains(chunk) || chunk & (MALLOC_ALIGNMENT - 1) != 0 {
+                bin.error = Some(format!("it points to {:#x}, which isn't a chunk", link));
+                break;
+            }
+            if bin.chunks.contains(&chunk) || bin.chunks.len() == MAX_LIST_LEN {
+                bin.error = Some(format!("it loops back to {:#x}", chunk));
+                break;
+            }
+            bin.chunks.push(chunk);
+            let stored_at = chunk + CHUNK_HEADER_SIZE;
+            link = match image.read_word(stored_at) {
+                Ok(next) => self.demangle(next as usize, stored_at, offset),
+                Err(_) => {
+                    bin.error = Some(format!("Cannot access memory at address {:#x}", stored_at));
+                    break;
+                }
+            };
+        }
+        bin
+    }
+
+    /// glibc 2.32 and later store the links in tcache bins and fastbins XORed with the address
+    /// they're stored at shifted right by 12 ("safe-linking"). Whether this one is can be told
+    /// by which reading of it is null or a chunk in the heap.
+    fn demangle(&self, link: usize, stored_at: usize, offset: usize) -> usize {
+        let demangled = link ^ (stored_at >> 12);
+        let is_chunk = |link: usize| {
+            let chunk = link.wrapping_sub(offset);
+            self.contains(chunk) && chunk & (MALLOC_ALIGNMENT - 1) == 0
+        };
+        if demangled == 0 || (is_chunk(demangled) && !is_chunk(link)) {
+            demangled
+        } else {
+            link
+        }
+    }
+
+    /// Follows a doubly linked bin, in which each chunk points forward to the next and back to
+    /// the one before, starting and ending at the bin's head in the arena.
+    fn follow_circular_list(&self, image: &dyn ProcessImage, name: String, head: usize) -> Bin {
+        let mut bin = Bin::new(name);
+        let mut prev = head;
+        let mut chunk = match image.read_word(head + CHUNK_HEADER_SIZE) {
+            Ok(fd) => fd as usize,
+            Err(_) => return bin,
+        };
+        while chunk != head {
+            if !self.contains(chunk) {
+                bin.error = Some(format!("it points to {:#x}, outside the heap", chunk));
+                break;
+            }
+            if bin.chunks.contains(&chunk) || bin.chunks.len() == MAX_LIST_LEN {
+                bin.error = Some(format!("it loops back to {:#x}", chunk));
+                break;
+            }
+            let (fd, bk) = match (
+                image.read_word(chunk + CHUNK_HEADER_SIZE),
+                image.read_word(chunk + CHUNK_HEADER_SIZE + 8),
+            ) {
+                (Ok(fd), Ok(bk)) => (fd as usize, bk as usize),
+                _ => {
+                    bin.error = Some(format!("Cannot access memory at address {:#x}", chunk));
+                    break;
+                }
+            };
+            bin.chunks.push(chunk);
+            if bk != prev {
+                bin.error = Some(format!(
+                    "the chunk at {:#x} points back to {:#x} rather than {:#x}",
+                    chunk, bk, prev
+                ));
+                break;
+            }
+            prev = chunk;
+            chunk = fd;
+        }
+        bin
+    }
+}
+
+/// Returns where bin i's head would start if it were a chunk: its two links are where a chunk's
+/// would be, so that the head can be treated as one in the list.
+fn bin_head(arena: usize, i: usize) -> usize {
+    arena + ARENA_BINS + (i - 1) * 16 - CHUNK_HEADER_SIZE
+}
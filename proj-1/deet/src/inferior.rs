@@ -1,14 +1,24 @@
+use crate::arch::{Arch, Native, Registers};
 use crate::debugger::Breakpoint;
-use crate::dwarf_data::DwarfData;
+use crate::debugger_command::Redirections;
+use crate::process_image::ProcessImage;
+use crate::pty::Pty;
+use nix::errno::Errno;
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+#[cfg(target_arch = "x86_64")]
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
 use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::process::CommandExt;
-use std::process::{Child, Command};
+use std::process::Command;
+use std::rc::Rc;
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -21,6 +31,66 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped on entry to or exit from a system call, after being resumed
+    /// with cont_syscall. Contains the current instruction pointer.
+    Syscall(usize),
+
+    /// Indicates the inferior forked (or vforked), when asked for with set_event_options.
+    /// Contains the new child, which has been detached and left to run.
+    Forked(Pid),
+
+    /// Indicates the inferior successfully called execve, when asked for with set_event_options.
+    Execed,
+
+    /// Indicates the inferior is about to exit, when asked for with set_event_options. It is still
+    /// around to be inspected, and will exit when continued. Contains the wait status it will
+    /// exit with (see describe_exit).
+    Exiting(i32),
+}
+
+/// Describes a wait status as returned by waitpid, like the one in Status::Exiting, the way
+/// "Child exited (...)" messages do: "status 0" or "signal SIGSEGV".
+pub fn describe_exit(status: i32) -> String {
+    // The low 7 bits hold the signal that killed the process, if any
+    match status & 0x7f {
+        0 => format!("status {}", (status >> 8) & 0xff),
+        signal => match Signal::try_from(signal) {
+            Ok(signal) => format!("signal {}", signal),
+            Err(_) => format!("signal {}", signal),
+        },
+    }
+}
+
+/// The terminal the program runs in
+#[derive(Clone)]
+pub enum Terminal {
+    /// deet's own
+    Shared,
+    /// A terminal device, e.g. /dev/pts/3 (set with `tty`)
+    Path(String),
+    /// A pseudo-terminal of its own, whose input and output deet passes on (`--pty`)
+    Pty,
+}
+
+/// How the program is started each time it's run, as set with `set environment`, `set cwd` and
+/// `tty`
+pub struct LaunchSettings {
+    /// The whole environment, which starts out as deet's
+    pub environment: BTreeMap<String, String>,
+    /// The directory to run in, if not deet's
+    pub cwd: Option<String>,
+    pub terminal: Terminal,
+}
+
+impl LaunchSettings {
+    pub fn new() -> LaunchSettings {
+        LaunchSettings {
+            environment: std::env::vars().collect(),
+            cwd: None,
+            terminal: Terminal::Shared,
+        }
+    }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -32,41 +102,181 @@ pub fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// Makes the terminal open as fd the controlling terminal of the child process, in a session of
+/// its own, so that typing ctrl+c there interrupts it. You should use pre_exec with Command to call
+/// this in the child process.
+fn child_set_terminal(fd: RawFd) -> Result<(), std::io::Error> {
+    nix::unistd::setsid().or(Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "setsid failed",
+    )))?;
+    // This fails if the terminal is already some other session's, e.g. a shell's, but the
+    // program can still use it
+    unsafe { libc::ioctl(fd, libc::TIOCSCTTY, 0) };
+    Ok(())
+}
+
+/// An argument to a function called with Inferior::call_function, classified the way the System V
+/// x86-64 ABI passes it.
+#[cfg(target_arch = "x86_64")]
+pub enum Argument {
+    /// Integers and pointers, passed in rdi, rsi, rdx, rcx, r8 and r9
+    Integer(u64),
+    /// The bits of a float or double, passed in xmm0-xmm7
+    Sse(u64),
+}
+
+/// How a call made with Inferior::call_function ended.
+#[cfg(target_arch = "x86_64")]
+pub enum CallOutcome {
+    /// The function returned. Contains rax and the low 64 bits of xmm0, which between them hold
+    /// any scalar return value.
+    Returned(u64, u64),
+    /// Something else happened first: a breakpoint, a signal, or the process exiting.
+    Interrupted(Status),
+}
+
+/// The ABI lets functions use the 128 bytes below rsp without moving rsp, so we must not put
+/// anything there when setting up a call.
+#[cfg(target_arch = "x86_64")]
+const RED_ZONE: usize = 128;
+
+/// The bytes of the x86-64 `syscall` instruction
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_INSTRUCTION: [u8; 2] = [0x0f, 0x05];
+
+/// The ptrace options every inferior runs with, on top of any events asked for with
+/// set_event_options. TRACESYSGOOD makes syscall stops distinguishable from the inferior getting
+/// a real SIGTRAP.
+const TRACE_OPTIONS: ptrace::Options = ptrace::Options::PTRACE_O_TRACESYSGOOD;
+
+/// Opens a file the program's input is to come from, or its output go to, truncating it in the
+/// latter case. Prints why if it can't.
+fn open_redirection(path: &str, write: bool) -> Option<File> {
+    let file = if write {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    } else {
+        File::open(path)
+    };
+    match file {
+        Ok(file) => Some(file),
+        Err(err) => {
+            println!("{}: {}.", path, err);
+            None
+        }
+    }
+}
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The ptrace options currently set on the process
+    options: ptrace::Options,
+    /// The pseudo-terminal the process runs in, if deet made it one. Checkpoints forked from the
+    /// process share it.
+    pty: Option<Rc<Pty>>,
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
+    /// an error is encountered. The program runs with the environment, in the directory, and on
+    /// the terminal given by settings, except that its standard streams go to the files in
+    /// redirections, if there are any.
     pub fn new(
         target: &str,
         args: &Vec<String>,
+        settings: &LaunchSettings,
+        redirections: &Redirections,
         breakpoints: &mut HashMap<usize, Breakpoint>,
     ) -> Option<Inferior> {
-        let mut cmd = Command::new(&target);
-        cmd.args(args);
+        let mut cmd = match settings.cwd {
+            Some(ref cwd) => {
+                // A relative path to the program would be taken relative to cwd instead
+                let mut cmd = Command::new(fs::canonicalize(target).ok()?);
+                cmd.arg0(target).current_dir(cwd);
+                cmd
+            }
+            None => Command::new(&target),
+        };
+        cmd.args(args).env_clear().envs(&settings.environment);
+        let pty = match settings.terminal {
+            Terminal::Pty => match Pty::open() {
+                Ok(pty) => Some(Rc::new(pty)),
+                Err(err) => {
+                    println!("Could not open a pseudo-terminal: {}", err);
+                    return None;
+                }
+            },
+            _ => None,
+        };
+        let terminal = match (&settings.terminal, &pty) {
+            (Terminal::Path(path), _) => match OpenOptions::new().read(true).write(true).open(path)
+            {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    println!("{}: {}.", path, err);
+                    return None;
+                }
+            },
+            (_, Some(pty)) => Some(pty.slave().try_clone().ok()?),
+            _ => None,
+        };
+        if let Some(terminal) = &terminal {
+            cmd.stdin(terminal.try_clone().ok()?);
+            cmd.stdout(terminal.try_clone().ok()?);
+            cmd.stderr(terminal.try_clone().ok()?);
+        }
+        // Not for a pty of our own: it would be the session leader's, and the checkpoints forked
+        // from the process would be sent SIGHUP when it died. Nothing can type ctrl+c there anyway.
+        if let (Some(terminal), None) = (&terminal, &pty) {
+            let fd = terminal.as_raw_fd();
+            unsafe {
+                cmd.pre_exec(move || child_set_terminal(fd));
+            }
+        }
+        if let Some(path) = &redirections.stdin {
+            cmd.stdin(open_redirection(path, false)?);
+        }
+        if let Some(path) = &redirections.stdout {
+            cmd.stdout(open_redirection(path, true)?);
+        }
+        if let Some(path) = &redirections.stderr {
+            cmd.stderr(open_redirection(path, true)?);
+        }
         unsafe {
             cmd.pre_exec(child_traceme);
         }
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                println!("Could not run {}: {}.", target, err);
+                return None;
+            }
+        };
         let mut inferior = Inferior {
-            child: cmd.spawn().ok()?,
+            pid: Pid::from_raw(child.id() as i32),
+            options: TRACE_OPTIONS,
+            pty,
         };
         let status = inferior.wait(Some(WaitPidFlag::WUNTRACED)).ok()?;
         if let Status::Stopped(signal, _) = status {
             if signal != Signal::SIGTRAP {
                 return None;
             }
+            ptrace::setoptions(inferior.pid(), TRACE_OPTIONS).ok()?;
             for bp in breakpoints {
-                let res = inferior.write_byte(*bp.0, 0xcc);
+                let res = inferior.plant_breakpoint(*bp.0);
                 match res {
-                    Ok(orig_byte) => {
-                        // update original byte when actually setting breakpoints
-                        bp.1.orig_byte = orig_byte;
+                    Ok(orig_bytes) => {
+                        // update original bytes when actually setting breakpoints
+                        bp.1.orig_bytes = orig_bytes;
                     }
                     Err(_) => {
                         println!("Unable to set breakpoint at {:#x}", *bp.0);
@@ -80,7 +290,14 @@ impl Inferior {
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Asks to be stopped for the given ptrace events (PTRACE_O_TRACEFORK, PTRACE_O_TRACEEXEC and
+    /// so on), which cont and step_instruction will then return as Status variants.
+    pub fn set_event_options(&mut self, events: ptrace::Options) -> Result<(), nix::Error> {
+        self.options = TRACE_OPTIONS | events;
+        ptrace::setoptions(self.pid(), self.options)
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
@@ -89,77 +306,183 @@ impl Inferior {
         Ok(match waitpid(self.pid(), options)? {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
-            }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::Stopped(_pid, signal) => Status::Stopped(signal, self.pc()?),
+            WaitStatus::PtraceSyscall(_pid) => Status::Syscall(self.pc()?),
+            WaitStatus::PtraceEvent(_pid, _signal, event) => match event {
+                libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK => {
+                    Status::Forked(Pid::from_raw(ptrace::getevent(self.pid())? as i32))
+                }
+                libc::PTRACE_EVENT_EXEC => Status::Execed,
+                libc::PTRACE_EVENT_EXIT => Status::Exiting(ptrace::getevent(self.pid())? as i32),
+                _ => return Err(nix::Error::UnsupportedOperation),
+            },
+            // We never ask for anything else (like WCONTINUED), so this shouldn't happen
+            _ => return Err(nix::Error::UnsupportedOperation),
         })
     }
+
+    /// Waits for the inferior to stop after being resumed. If it forked, the child is traced too,
+    /// but we only debug the parent: take our breakpoints out of the child and let it go.
+    fn wait_resumed(&self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Status, nix::Error> {
+        if let Some(ref pty) = self.pty {
+            // Pass the program's input and output on while it runs. Most steps are over within a
+            // millisecond, so start by checking that often.
+            let mut timeout = 1;
+            while !self.has_stopped() {
+                pty.relay(timeout);
+                timeout = (timeout * 2).min(10);
+            }
+            pty.drain();
+        }
+        let status = self.wait(Some(WaitPidFlag::WUNTRACED))?;
+        if let Status::Forked(child_pid) = status {
+            // The child starts out stopped by SIGSTOP
+            waitpid(child_pid, Some(WaitPidFlag::__WALL))?;
+            let mut child = Inferior {
+                pid: child_pid,
+                options: self.options,
+                pty: None,
+            };
+            for bp in breakpoints.values() {
+                child.write_bytes(bp.addr, &bp.orig_bytes)?;
+            }
+            ptrace::detach(child_pid, None)?;
+        }
+        Ok(status)
+    }
+
+    /// Returns whether the process has stopped or exited, without collecting the status the way
+    /// wait does.
+    fn has_stopped(&self) -> bool {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                self.pid().as_raw() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WSTOPPED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        // If waitid fails, so will the wait that follows, which is where to report it
+        result != 0 || unsafe { info.si_pid() } != 0
+    }
+
+    /// Returns where the program counter is.
+    fn pc(&self) -> Result<usize, nix::Error> {
+        Ok(Native::pc(&Native::get_registers(self.pid())?))
+    }
+
+    /// Executes a single instruction. If rip is at a breakpoint, the original instruction is
+    /// swapped back in for the duration of the step.
+    pub fn step_instruction(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        let rip = self.pc()?;
+        match breakpoints.get(&rip) {
+            Some(bp) => {
+                self.write_bytes(rip, &bp.orig_bytes)?;
+                ptrace::step(self.pid(), None)?;
+                let status = self.wait_resumed(breakpoints);
+                // (after an exec, the old program and its breakpoints are gone)
+                if let Ok(Status::Stopped(_, _)) | Ok(Status::Forked(_)) | Ok(Status::Exiting(_)) =
+                    status
+                {
+                    // put the breakpoint instruction back
+                    if self.write_bytes(rip, Native::BREAKPOINT).is_err() {
+                        println!("Unable to set breakpoint at {:#x}", rip);
+                    }
+                }
+                status
+            }
+            None => {
+                ptrace::step(self.pid(), None)?;
+                self.wait_resumed(breakpoints)
+            }
+        }
+    }
+
     // make process to continue executing
     pub fn cont(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Status, nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        // if inferior is stopped at a breakpoint
+        self.resume(breakpoints, false, None)
+    }
+
+    /// Continues the process like cont, but also stops it at the entry to and exit from every
+    /// system call (returning Status::Syscall). signal, if given, is delivered to the process as
+    /// it resumes.
+    pub fn cont_syscall(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+        signal: Option<Signal>,
+    ) -> Result<Status, nix::Error> {
+        self.resume(breakpoints, true, signal)
+    }
+
+    fn resume(
+        &mut self,
+        breakpoints: &HashMap<usize, Breakpoint>,
+        trace_syscalls: bool,
+        signal: Option<Signal>,
+    ) -> Result<Status, nix::Error> {
+        let rip = self.pc()?;
+        // if inferior is stopped at a breakpoint, get past it before putting the breakpoint back
         if breakpoints.contains_key(&rip) {
-            // go to next instruction
-            ptrace::step(self.pid(), None)?;
-            let result = self.wait(Some(WaitPidFlag::WUNTRACED));
-            match result {
-                Ok(status) => match status {
-                    Status::Exited(_) | Status::Signaled(_) => {
-                        return Ok(status);
-                    }
-                    Status::Stopped(signal, _) => {
-                        if signal == Signal::SIGTRAP {
-                            // restore 0xcc in the breakpoint location
-                            let res = self.write_byte(rip, 0xcc);
-                            if res.is_err() {
-                                println!("Unable to set breakpoint at {:#x}", rip);
-                            }
-                        }
-                    }
-                },
-                Err(e) => {
-                    println!("{}", e);
+            match self.step_instruction(breakpoints)? {
+                Status::Stopped(Signal::SIGTRAP, _) => {}
+                status => return Ok(status),
+            }
+        }
+        if trace_syscalls {
+            ptrace::syscall(self.pid(), signal)?;
+        } else {
+            ptrace::cont(self.pid(), signal)?;
+        }
+        let status = self.wait_resumed(breakpoints)?;
+        if let Status::Stopped(Signal::SIGTRAP, rip) = status {
+            // A trap raised by one of our breakpoints can leave rip past it (on x86-64, just past
+            // the int3). Rewind so that rip points at the breakpoint's instruction, which will run
+            // when we continue.
+            let si_code = ptrace::getsiginfo(self.pid())?.si_code;
+            let addr = rip.wrapping_sub(Native::TRAP_PC_OFFSET);
+            if Native::is_breakpoint_trap(si_code) && breakpoints.contains_key(&addr) {
+                if addr != rip {
+                    let mut regs = Native::get_registers(self.pid())?;
+                    Native::set_pc(&mut regs, addr);
+                    Native::set_registers(self.pid(), &regs)?;
                 }
+                return Ok(Status::Stopped(Signal::SIGTRAP, addr));
             }
         }
-        ptrace::cont(self.pid(), None)?;
-        self.wait(Some(WaitPidFlag::WUNTRACED))
+        Ok(status)
     }
+
     // kill inferior process
     pub fn kill(&mut self) -> Result<Status, nix::Error> {
         println!("Killing running inferior (pid {})", self.pid());
-        self.child.kill().unwrap();
+        self.kill_silently()
+    }
+
+    /// Kills the process without announcing it, e.g. for checkpoints that are being discarded.
+    pub fn kill_silently(&mut self) -> Result<Status, nix::Error> {
+        signal::kill(self.pid(), Signal::SIGKILL)?;
         self.wait(None)
     }
 
-    //
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip;
-        let mut rbp = regs.rbp;
-        loop {
-            let line = match debug_data.get_line_from_addr(rip as usize) {
-                None => {
-                    println!("There's no code at {:#x}", rip);
-                    return Ok(());
-                }
-                Some(val) => val,
-            };
-            let func = match debug_data.get_function_from_addr(rip as usize) {
-                None => return Ok(()),
-                Some(val) => val,
-            };
-            println!("{} ({})", func, line);
-            if func == "main" {
-                break;
+    /// Plants a breakpoint instruction at addr, returning the bytes it replaced.
+    pub fn plant_breakpoint(&mut self, addr: usize) -> Result<Vec<u8>, nix::Error> {
+        let orig_bytes = self.read_bytes(addr, Native::BREAKPOINT.len())?;
+        self.write_bytes(addr, Native::BREAKPOINT)?;
+        Ok(orig_bytes)
+    }
+
+    /// Writes the breakpoint instruction to every breakpoint location, without touching the saved
+    /// original bytes.
+    pub fn insert_breakpoints(&mut self, breakpoints: &HashMap<usize, Breakpoint>) {
+        for addr in breakpoints.keys() {
+            if self.write_bytes(*addr, Native::BREAKPOINT).is_err() {
+                println!("Unable to set breakpoint at {:#x}", addr);
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as u64;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as u64;
         }
-        Ok(())
     }
 
     // write byte val to given address and return original byte
@@ -177,4 +500,260 @@ impl Inferior {
         )?;
         Ok(orig_byte as u8)
     }
+
+    /// Sets the named register (see Arch::named_registers).
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<(), nix::Error> {
+        let mut regs = Native::get_registers(self.pid())?;
+        match Native::register_mut(&mut regs, name) {
+            Some(register) => *register = value,
+            None => return Err(nix::Error::Sys(Errno::EINVAL)),
+        }
+        Native::set_registers(self.pid(), &regs)
+    }
+
+    /// Writes bytes to the inferior's memory starting at addr.
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_byte(addr + i, *byte)?;
+        }
+        Ok(())
+    }
+
+    /// Programs watchpoint `slot` to trap after any write to the len bytes at addr (see
+    /// Arch::set_watchpoint).
+    pub fn set_hw_watchpoint(
+        &mut self,
+        slot: usize,
+        addr: usize,
+        len: usize,
+    ) -> Result<(), nix::Error> {
+        Native::set_watchpoint(self.pid(), slot, addr, len)
+    }
+
+    /// Turns watchpoint `slot` off again.
+    pub fn clear_hw_watchpoint(&mut self, slot: usize) -> Result<(), nix::Error> {
+        Native::clear_watchpoint(self.pid(), slot)
+    }
+
+    /// Returns which slots' watchpoints were hit since the last call, one bit per slot.
+    pub fn take_watchpoint_hits(&mut self) -> Result<usize, nix::Error> {
+        Native::take_watchpoint_hits(self.pid())
+    }
+}
+
+/// Injecting system calls and calling functions need to know the instruction set and the calling
+/// convention, so they're only done on x86-64.
+#[cfg(target_arch = "x86_64")]
+impl Inferior {
+    /// Makes a copy of this process by injecting a fork() syscall at the current instruction. The
+    /// copy is traced by us and left stopped in exactly the state this process is in, breakpoints
+    /// included; this process carries on as if nothing happened.
+    pub fn fork(&mut self) -> Result<Inferior, nix::Error> {
+        let regs = Native::get_registers(self.pid())?;
+        let rip = Native::pc(&regs);
+        let orig_bytes = self.read_bytes(rip, SYSCALL_INSTRUCTION.len())?;
+        // Ask to be told about the fork so that the child is traced from its first instruction
+        ptrace::setoptions(
+            self.pid(),
+            self.options | ptrace::Options::PTRACE_O_TRACEFORK,
+        )?;
+        self.write_bytes(rip, &SYSCALL_INSTRUCTION)?;
+        let mut fork_regs = regs;
+        fork_regs.rax = libc::SYS_fork as u64;
+        Native::set_registers(self.pid(), &fork_regs)?;
+
+        let child = self.run_fork();
+
+        // Put things back the way they were, whether or not the fork worked
+        self.write_bytes(rip, &orig_bytes)?;
+        Native::set_registers(self.pid(), &regs)?;
+        ptrace::setoptions(self.pid(), self.options)?;
+
+        // The child starts out with a copy of our memory as it was during the syscall, and with
+        // the ptrace options we forked it with rather than our usual ones
+        let mut child = child?;
+        ptrace::setoptions(child.pid(), self.options)?;
+        child.write_bytes(rip, &orig_bytes)?;
+        Native::set_registers(child.pid(), &regs)?;
+        Ok(child)
+    }
+
+    /// Steps through the fork() syscall set up by fork, and returns the new (stopped) child.
+    fn run_fork(&mut self) -> Result<Inferior, nix::Error> {
+        let child_pid = loop {
+            ptrace::step(self.pid(), None)?;
+            match waitpid(self.pid(), None)? {
+                WaitStatus::PtraceEvent(_, Signal::SIGTRAP, libc::PTRACE_EVENT_FORK) => {
+                    break Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+                }
+                // The syscall failed (e.g. we ran out of processes), so there was no fork event
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    let errno = -(Native::get_registers(self.pid())?.rax as i64);
+                    return Err(nix::Error::Sys(Errno::from_i32(errno as i32)));
+                }
+                // A signal that was pending, e.g. the SIGCHLD a checkpoint gets when a process
+                // forked from it dies. Discard it (by not passing it on) and try again.
+                WaitStatus::Stopped(_, _) => continue,
+                _ => return Err(nix::Error::Sys(Errno::ESRCH)),
+            }
+        };
+        // Finish the syscall in the parent...
+        ptrace::step(self.pid(), None)?;
+        waitpid(self.pid(), None)?;
+        // ...and collect the child, which starts out stopped by SIGSTOP
+        waitpid(child_pid, Some(WaitPidFlag::__WALL))?;
+        Ok(Inferior {
+            pid: child_pid,
+            options: self.options,
+            pty: self.pty.clone(),
+        })
+    }
+
+    /// Calls the function at addr with the given arguments, following the System V x86-64 calling
+    /// convention, and runs until it returns. The return address is a temporary breakpoint at the
+    /// program's entry point, which won't be running again. Registers and memory other than
+    /// what the function itself changes are restored afterwards, even if the call is interrupted.
+    pub fn call_function(
+        &mut self,
+        addr: usize,
+        args: &[Argument],
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<CallOutcome, nix::Error> {
+        let saved_regs = Native::get_registers(self.pid())?;
+        let saved_fpregs = self.get_fpregs()?;
+        let mut regs = saved_regs;
+        let mut fpregs = saved_fpregs;
+
+        let mut integer_count = 0;
+        let mut sse_count = 0;
+        let mut stack_args = Vec::new();
+        for arg in args {
+            match *arg {
+                Argument::Integer(value) if integer_count < 6 => {
+                    let register = match integer_count {
+                        0 => &mut regs.rdi,
+                        1 => &mut regs.rsi,
+                        2 => &mut regs.rdx,
+                        3 => &mut regs.rcx,
+                        4 => &mut regs.r8,
+                        _ => &mut regs.r9,
+                    };
+                    *register = value;
+                    integer_count += 1;
+                }
+                Argument::Sse(bits) if sse_count < 8 => {
+                    // Each xmm register is four u32s in xmm_space
+                    let xmm = &mut fpregs.xmm_space[sse_count * 4..sse_count * 4 + 4];
+                    xmm.copy_from_slice(&[bits as u32, (bits >> 32) as u32, 0, 0]);
+                    sse_count += 1;
+                }
+                Argument::Integer(value) | Argument::Sse(value) => stack_args.push(value),
+            }
+        }
+        // Arguments that didn't fit in registers go on the stack, which must be 16-byte aligned
+        // at the call instruction, i.e. just before the return address is pushed
+        let mut rsp = (saved_regs.rsp as usize - RED_ZONE - 8 * stack_args.len()) & !0xf;
+        for (i, value) in stack_args.iter().enumerate() {
+            self.write_bytes(rsp + 8 * i, &value.to_le_bytes())?;
+        }
+        let trap_addr = self.entry_point()?;
+        rsp -= 8;
+        self.write_bytes(rsp, &(trap_addr as u64).to_le_bytes())?;
+        regs.rsp = rsp as u64;
+        regs.rip = addr as u64;
+        // Variadic functions expect the number of vector registers used in al
+        regs.rax = sse_count as u64;
+        // If we stopped during a system call, stop the kernel from restarting it at our new rip
+        regs.orig_rax = u64::MAX;
+        Native::set_registers(self.pid(), &regs)?;
+        self.set_fpregs(&fpregs)?;
+
+        let trap_orig_bytes = self.plant_breakpoint(trap_addr)?;
+        let mut breakpoints = breakpoints.clone();
+        breakpoints.insert(
+            trap_addr,
+            Breakpoint {
+                addr: trap_addr,
+                orig_bytes: trap_orig_bytes.clone(),
+                location: format!("*{:#x}", trap_addr),
+                condition: None,
+                temporary: true,
+            },
+        );
+        let outcome = match self.cont(&breakpoints)? {
+            Status::Stopped(Signal::SIGTRAP, rip) if rip == trap_addr => {
+                let (rax, xmm0) = self.return_value()?;
+                CallOutcome::Returned(rax, xmm0)
+            }
+            // The process (or, after an exec, the program we were running) is gone, so there's
+            // nothing to restore
+            status @ Status::Exited(_) | status @ Status::Signaled(_) | status @ Status::Execed => {
+                return Ok(CallOutcome::Interrupted(status))
+            }
+            status => CallOutcome::Interrupted(status),
+        };
+        self.write_bytes(trap_addr, &trap_orig_bytes)?;
+        Native::set_registers(self.pid(), &saved_regs)?;
+        self.set_fpregs(&saved_fpregs)?;
+        Ok(outcome)
+    }
+
+    /// Returns the address of the program's entry point (_start), from the auxiliary vector.
+    fn entry_point(&self) -> Result<usize, nix::Error> {
+        let auxv = std::fs::read(format!("/proc/{}/auxv", self.pid()))
+            .map_err(|_| nix::Error::Sys(Errno::EIO))?;
+        auxv.chunks_exact(16)
+            .map(|entry| {
+                let key = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                let value = u64::from_le_bytes(entry[8..].try_into().unwrap());
+                (key, value)
+            })
+            .find(|(key, _)| *key == libc::AT_ENTRY)
+            .map(|(_, value)| value as usize)
+            .ok_or(nix::Error::Sys(Errno::ENOENT))
+    }
+
+    /// Returns rax and the low 64 bits of xmm0, where a function that just returned left its
+    /// return value (depending on its type).
+    pub fn return_value(&self) -> Result<(u64, u64), nix::Error> {
+        let rax = Native::get_registers(self.pid())?.rax;
+        let xmm = self.get_fpregs()?.xmm_space;
+        Ok((rax, xmm[0] as u64 | (xmm[1] as u64) << 32))
+    }
+
+    fn get_fpregs(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs = std::mem::MaybeUninit::<libc::user_fpregs_struct>::uninit();
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs.as_mut_ptr(),
+            )
+        };
+        Errno::result(res)?;
+        Ok(unsafe { fpregs.assume_init() })
+    }
+
+    fn set_fpregs(&mut self, fpregs: &libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs as *const libc::user_fpregs_struct,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+}
+
+impl ProcessImage for Inferior {
+    fn get_registers(&self) -> Result<Registers, nix::Error> {
+        Native::get_registers(self.pid())
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        Ok(ptrace::read(self.pid(), addr as ptrace::AddressType)? as u64)
+    }
 }
@@ -1,24 +1,98 @@
+mod arch;
+mod completion;
+mod core_file;
 mod debugger;
 mod debugger_command;
-mod inferior;
+mod demangle;
+#[cfg(target_arch = "x86_64")]
+mod disasm;
 mod dwarf_data;
+mod dwarf_index;
+mod expr;
 mod gimli_wrapper;
+mod heap;
+mod inferior;
+mod pretty;
+mod proc_maps;
+mod process_image;
+mod pty;
+#[cfg(target_arch = "x86_64")]
+mod record;
+mod syscalls;
+mod tui;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} <target program> [--core <core file>] [--tui] [--pty] [--nx]",
+        program
+    );
+    println!(
+        "       {} <target program> --batch <script> [--core <core file>]",
+        program
+    );
+    println!("       {} --strace <target program> [args...]", program);
+    std::process::exit(1);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    let mut target: Option<&String> = None;
+    let mut core: Option<&String> = None;
+    let mut strace = false;
+    let mut tui = false;
+    let mut pty = false;
+    // Don't run the .deetinit beside the target
+    let mut nx = false;
+    // Run the commands in a script instead of reading them from the terminal
+    let mut batch: Option<&String> = None;
+    let mut program_args = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            // With --strace, everything after the target is passed on to it
+            _ if strace && target.is_some() => program_args.push(arg.to_string()),
+            "--core" => core = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--strace" if target.is_none() => strace = true,
+            "--tui" => tui = true,
+            "--pty" => pty = true,
+            "--nx" => nx = true,
+            "--batch" => batch = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            _ if target.is_none() => target = Some(arg),
+            _ => usage(&args[0]),
+        }
+    }
+    let target = target.unwrap_or_else(|| usage(&args[0]));
+    if strace && (core.is_some() || tui || pty || nx || batch.is_some()) {
+        usage(&args[0]);
     }
-    let target = &args[1];
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(target);
+    if strace {
+        std::process::exit(debugger.strace(&program_args));
+    }
+    if tui {
+        debugger.enable_tui();
+    }
+    if pty {
+        debugger.use_pty();
+    }
+    if let Some(core) = core {
+        debugger.load_core(core);
+    }
+    if !nx && !debugger.source_init_file() {
+        return;
+    }
+    if let Some(script) = batch {
+        debugger.run_batch(script);
+        return;
+    }
+    debugger.run();
 }
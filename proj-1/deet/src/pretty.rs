@@ -0,0 +1,314 @@
+//! Pretty printers for Rust standard library types, which print what a value holds rather than
+//! how the library lays it out: a Vec's elements rather than its pointer, capacity and length.
+//!
+//! The printers find their way around a value by member names, and fall back to printing the raw
+//! struct if the layout isn't what they expect, since it changes between Rust versions.
+
+use crate::dwarf_data::{DwarfData, Type, TypeKind};
+use crate::expr::{format_bytes, MAX_PRINT_DEPTH, PRINT_LIMIT};
+use crate::process_image::ProcessImage;
+use std::convert::TryInto;
+
+/// The most buckets we believe a HashMap has. Anything bigger is more likely a map that hasn't
+/// been initialized yet, and reading its control bytes would take forever.
+const MAX_HASH_MAP_BUCKETS: usize = 1 << 20;
+
+#[derive(Clone, Copy)]
+enum Printer {
+    String,
+    Str,
+    Vec,
+    Box,
+    HashMap,
+}
+
+fn printer_for(ty: &Type) -> Option<Printer> {
+    // Generic types are named with their parameters, e.g. Vec<i32, alloc::alloc::Global>
+    let name = ty.name.split('<').next().unwrap();
+    match (name, &ty.kind) {
+        ("String", TypeKind::Struct(_)) => Some(Printer::String),
+        ("&str", TypeKind::Struct(_)) => Some(Printer::Str),
+        ("Vec", TypeKind::Struct(_)) => Some(Printer::Vec),
+        ("alloc::boxed::Box", TypeKind::Pointer(_)) => Some(Printer::Box),
+        ("HashMap", TypeKind::Struct(_)) => Some(Printer::HashMap),
+        _ => None,
+    }
+}
+
+/// Returns true if values of type ty are printed by one of the pretty printers.
+pub fn has_printer(ty: &Type) -> bool {
+    printer_for(ty).is_some()
+}
+
+/// Formats a value with the pretty printer for its type, returning false if there isn't one (or
+/// the value can't be read) so that the caller can print it as usual.
+pub fn format(
+    out: &mut String,
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: Option<&dyn ProcessImage>,
+    depth: usize,
+) -> bool {
+    let (printer, image) = match (printer_for(ty), image) {
+        (Some(printer), Some(image)) => (printer, image),
+        _ => return false,
+    };
+    let printed = match printer {
+        Printer::String => format_string(ty, bytes, debug_data, image),
+        Printer::Str => format_str(ty, bytes, debug_data, image),
+        Printer::Vec => format_vec(ty, bytes, debug_data, image, depth),
+        Printer::Box => format_box(ty, bytes, debug_data, image, depth),
+        Printer::HashMap => format_hash_map(ty, bytes, debug_data, image, depth),
+    };
+    match printed {
+        Some(text) => {
+            out.push_str(&text);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the type and bytes of the member of a struct called name.
+fn member<'a, 'd>(
+    ty: &Type,
+    bytes: &'a [u8],
+    name: &str,
+    debug_data: &'d DwarfData,
+) -> Option<(&'d Type, &'a [u8])> {
+    let members = match ty.kind {
+        TypeKind::Struct(ref members) => members,
+        _ => return None,
+    };
+    let member = members.iter().find(|member| member.name == name)?;
+    let member_type = debug_data.get_type(member.type_offset)?;
+    Some((
+        member_type,
+        bytes.get(member.offset..member.offset + member_type.size)?,
+    ))
+}
+
+fn read_usize(bytes: &[u8]) -> Option<usize> {
+    Some(u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?) as usize)
+}
+
+/// Reads a pointer that may be wrapped in structs like Unique and NonNull, by following the first
+/// member of each until it gets to a pointer.
+fn read_pointer(ty: &Type, bytes: &[u8], debug_data: &DwarfData) -> Option<usize> {
+    match ty.kind {
+        TypeKind::Pointer(_) => read_usize(bytes),
+        TypeKind::Struct(ref members) => members.iter().find_map(|member| {
+            let member_type = debug_data.get_type(member.type_offset)?;
+            let end = member.offset + member_type.size;
+            read_pointer(member_type, bytes.get(member.offset..end)?, debug_data)
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the address of a Vec's buffer and the number of elements in it.
+fn vec_contents(ty: &Type, bytes: &[u8], debug_data: &DwarfData) -> Option<(usize, usize)> {
+    let (buf_type, buf) = member(ty, bytes, "buf", debug_data)?;
+    let (_, len) = member(ty, bytes, "len", debug_data)?;
+    Some((read_pointer(buf_type, buf, debug_data)?, read_usize(len)?))
+}
+
+/// Reads up to PRINT_LIMIT bytes of UTF-8 from the inferior and quotes them.
+fn read_str(image: &dyn ProcessImage, addr: usize, len: usize) -> Option<String> {
+    let bytes = image.read_bytes(addr, len.min(PRINT_LIMIT)).ok()?;
+    let mut text = format!("{:?}", String::from_utf8_lossy(&bytes));
+    if len > PRINT_LIMIT {
+        text.push_str("...");
+    }
+    Some(text)
+}
+
+fn format_string(
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+) -> Option<String> {
+    let (vec_type, vec) = member(ty, bytes, "vec", debug_data)?;
+    let (addr, len) = vec_contents(vec_type, vec, debug_data)?;
+    read_str(image, addr, len)
+}
+
+fn format_str(
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+) -> Option<String> {
+    let (pointer_type, pointer) = member(ty, bytes, "data_ptr", debug_data)?;
+    let (_, len) = member(ty, bytes, "length", debug_data)?;
+    read_str(
+        image,
+        read_pointer(pointer_type, pointer, debug_data)?,
+        read_usize(len)?,
+    )
+}
+
+/// Formats count values of type element_type stored one after another starting at addr,
+/// separated by commas.
+fn format_elements(
+    out: &mut String,
+    element_type: &Type,
+    addr: usize,
+    count: usize,
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+    depth: usize,
+) -> Option<()> {
+    let size = element_type.size;
+    let bytes = image.read_bytes(addr, size * count.min(PRINT_LIMIT)).ok()?;
+    for i in 0..count.min(PRINT_LIMIT) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        format_bytes(
+            out,
+            element_type,
+            &bytes[i * size..(i + 1) * size],
+            Some(addr + i * size),
+            debug_data,
+            Some(image),
+            depth + 1,
+        );
+    }
+    if count > PRINT_LIMIT {
+        out.push_str("...");
+    }
+    Some(())
+}
+
+fn format_vec(
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+    depth: usize,
+) -> Option<String> {
+    let element_type = debug_data.get_type(*ty.template_params.get(0)?)?;
+    let (addr, len) = vec_contents(ty, bytes, debug_data)?;
+    let mut out = format!("Vec(size={})", len);
+    if len == 0 {
+        return Some(out);
+    }
+    if depth > MAX_PRINT_DEPTH {
+        out.push_str(" = {...}");
+        return Some(out);
+    }
+    out.push_str(" = {");
+    format_elements(&mut out, element_type, addr, len, debug_data, image, depth)?;
+    out.push('}');
+    Some(out)
+}
+
+fn format_box(
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+    depth: usize,
+) -> Option<String> {
+    let target = match ty.kind {
+        TypeKind::Pointer(ref target) => target,
+        _ => return None,
+    };
+    let addr = read_usize(bytes)?;
+    let mut out = "Box(".to_string();
+    format_elements(&mut out, target, addr, 1, debug_data, image, depth)?;
+    out.push(')');
+    Some(out)
+}
+
+/// Prints the entries of a HashMap, which is a hashbrown table: a buffer of (key, value) tuples,
+/// followed by a control byte for each of them whose top bit is clear if the slot is in use. The
+/// table points at the control bytes, and the tuples are stored backwards in front of them.
+fn format_hash_map(
+    ty: &Type,
+    bytes: &[u8],
+    debug_data: &DwarfData,
+    image: &dyn ProcessImage,
+    depth: usize,
+) -> Option<String> {
+    let (base_type, base) = member(ty, bytes, "base", debug_data)?;
+    let (table_type, table) = member(base_type, base, "table", debug_data)?;
+    let entry_type = debug_data.get_type(*table_type.template_params.get(0)?)?;
+    // Newer versions of hashbrown keep the untyped parts of the table in a RawTableInner
+    let (inner_type, inner) = match member(table_type, table, "table", debug_data) {
+        Some(inner) => inner,
+        None => (table_type, table),
+    };
+    let (_, bucket_mask) = member(inner_type, inner, "bucket_mask", debug_data)?;
+    let (ctrl_type, ctrl) = member(inner_type, inner, "ctrl", debug_data)?;
+    let (_, items) = member(inner_type, inner, "items", debug_data)?;
+    let buckets = read_usize(bucket_mask)?.checked_add(1)?;
+    let ctrl = read_pointer(ctrl_type, ctrl, debug_data)?;
+    let items = read_usize(items)?;
+    let entry_size = entry_type.size;
+    let entries_size = buckets.checked_mul(entry_size)?;
+    // A map that hasn't been initialized yet holds garbage. Rather than trust it, leave the caller
+    // to print the raw struct
+    if !buckets.is_power_of_two()
+        || buckets > MAX_HASH_MAP_BUCKETS
+        || items > buckets
+        || entries_size > ctrl
+    {
+        return None;
+    }
+
+    let mut out = format!("HashMap(size={})", items);
+    if items == 0 {
+        return Some(out);
+    }
+    if depth > MAX_PRINT_DEPTH {
+        out.push_str(" = {...}");
+        return Some(out);
+    }
+    out.push_str(" = {");
+    let control_bytes = image.read_bytes(ctrl, buckets).ok()?;
+    let mut printed = 0;
+    for (i, control) in control_bytes.iter().enumerate() {
+        if control & 0x80 != 0 {
+            continue;
+        }
+        if printed == PRINT_LIMIT {
+            out.push_str("...");
+            break;
+        }
+        if printed > 0 {
+            out.push_str(", ");
+        }
+        // Can't underflow, since we checked that every bucket's entry fits below ctrl
+        let addr = ctrl - (i + 1) * entry_size;
+        let entry = image.read_bytes(addr, entry_size).ok()?;
+        let (key_type, key) = member(entry_type, &entry, "__0", debug_data)?;
+        let (value_type, value) = member(entry_type, &entry, "__1", debug_data)?;
+        out.push('[');
+        format_bytes(
+            &mut out,
+            key_type,
+            key,
+            None,
+            debug_data,
+            Some(image),
+            depth + 1,
+        );
+        out.push_str("] = ");
+        format_bytes(
+            &mut out,
+            value_type,
+            value,
+            None,
+            debug_data,
+            Some(image),
+            depth + 1,
+        );
+        printed += 1;
+    }
+    out.push('}');
+    Some(out)
+}
@@ -0,0 +1,61 @@
+use nix::unistd::Pid;
+use std::fs;
+
+/// One line of /proc/<pid>/maps: a range of the process's address space and what backs it.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub perms: String,
+    /// Where in the file the mapping starts
+    pub offset: usize,
+    /// The file it maps, or a name like [heap] or [stack]; None for anonymous memory
+    pub path: Option<String>,
+}
+
+impl Mapping {
+    pub fn is_readable(&self) -> bool {
+        self.perms.starts_with('r')
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.perms.chars().nth(1) == Some('w')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.perms.chars().nth(2) == Some('x')
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// Reads and parses /proc/<pid>/maps.
+pub fn read_maps(pid: Pid) -> Result<Vec<Mapping>, std::io::Error> {
+    let contents = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(contents.lines().filter_map(parse_line).collect())
+}
+
+/// Parses a line like `7ffff7dd3000-7ffff7dfc000 r-xp 00000000 08:01 1049 /usr/lib/ld-2.31.so`:
+/// range, permissions, offset, device, inode, and then the path, which can contain spaces.
+fn parse_line(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, ' ');
+    let mut range = fields.next()?.split('-');
+    let start = usize::from_str_radix(range.next()?, 16).ok()?;
+    let end = usize::from_str_radix(range.next()?, 16).ok()?;
+    let perms = fields.next()?.to_string();
+    let offset = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let path = fields
+        .nth(2)
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_string);
+    Some(Mapping {
+        start,
+        end,
+        perms,
+        offset,
+        path,
+    })
+}
@@ -0,0 +1,76 @@
+use crate::arch::{Arch, Native, Registers};
+use crate::dwarf_data::DwarfData;
+use std::cmp::min;
+use std::mem::size_of;
+
+/// A ProcessImage is anything whose registers and memory deet can inspect: either a live inferior
+/// that is stopped under ptrace, or a core dump it left behind. Commands that only look at state
+/// (backtrace, print, x, info registers) are written against this trait so that they work on both.
+pub trait ProcessImage {
+    /// Returns the general-purpose registers of the process.
+    fn get_registers(&self) -> Result<Registers, nix::Error>;
+
+    /// Reads the 8-byte word starting at addr. addr does not need to be aligned.
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error>;
+
+    /// Reads len bytes starting at addr.
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut curr = addr;
+        while bytes.len() < len {
+            let word = self.read_word(curr)?;
+            let needed = min(len - bytes.len(), size_of::<u64>());
+            bytes.extend_from_slice(&word.to_le_bytes()[..needed]);
+            curr += size_of::<u64>();
+        }
+        Ok(bytes)
+    }
+
+    /// Walks the frame pointer chain and prints one line per stack frame, stopping at main.
+    /// Functions inlined into a frame get a line each, innermost first.
+    fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        self.walk_stack(debug_data, &mut |line| println!("{}", line))
+    }
+
+    /// Passes each line print_backtrace prints to f, as soon as we've worked it out, so that
+    /// the frames we found before running into an error are still shown.
+    fn walk_stack(
+        &self,
+        debug_data: &DwarfData,
+        f: &mut dyn FnMut(String),
+    ) -> Result<(), nix::Error> {
+        let regs = self.get_registers()?;
+        let mut rip = Native::pc(&regs) as u64;
+        let mut rbp = Native::frame_pointer(&regs) as u64;
+        // Where to look up the code a frame is running. For callers that's the call instruction,
+        // just before the return address, which may belong to the next line (or inlined call).
+        let mut lookup_addr = rip;
+        loop {
+            let frames = debug_data.get_frames(lookup_addr as usize);
+            if frames.is_empty() {
+                f(format!("There's no code at {:#x}", rip));
+                return Ok(());
+            }
+            for frame in &frames {
+                // Functions we only have a symbol for are shown with the address instead
+                let line = match frame.line {
+                    Some(ref line) => line.to_string(),
+                    None => format!("{:#x}", rip),
+                };
+                if frame.inlined {
+                    f(format!("{} ({}) [inlined]", frame.function, line));
+                } else {
+                    f(format!("{} ({})", frame.function, line));
+                }
+            }
+            match frames.last() {
+                Some(frame) if frame.function != "main" => {}
+                _ => break,
+            }
+            rip = self.read_word((rbp + 8) as usize)?;
+            rbp = self.read_word(rbp as usize)?;
+            lookup_addr = rip - 1;
+        }
+        Ok(())
+    }
+}
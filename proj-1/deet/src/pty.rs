@@ -0,0 +1,100 @@
+//! A pseudo-terminal for the program to run in, with `deet --pty` or `tty pty`. The program gets
+//! a terminal of its own, so that it isn't fighting deet over the one deet reads commands from,
+//! and it still sees a terminal rather than a pipe. While it runs, deet copies what it writes
+//! there to deet's stdout, and what's typed at deet's terminal to it.
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::openpty;
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, OutputFlags, SetArg};
+use nix::unistd;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+/// The character that, typed at a terminal, makes a read from it return end of file (ctrl+d)
+const EOF_CHARACTER: u8 = 4;
+
+pub struct Pty {
+    master: File,
+    /// The program's end, handed to it as its stdin, stdout and stderr
+    slave: File,
+    /// Set once deet's own stdin has hit end of file, after which there's nothing to pass on
+    stdin_closed: Cell<bool>,
+}
+
+impl Pty {
+    pub fn open() -> nix::Result<Pty> {
+        let pty = openpty(None, None)?;
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        // deet's terminal already echoes what's typed, and translates the newlines in what's
+        // printed, so the program's terminal mustn't do either again
+        let mut termios = tcgetattr(slave.as_raw_fd())?;
+        termios
+            .local_flags
+            .remove(LocalFlags::ECHO | LocalFlags::ECHONL);
+        termios.output_flags.remove(OutputFlags::OPOST);
+        tcsetattr(slave.as_raw_fd(), SetArg::TCSANOW, &termios)?;
+        Ok(Pty {
+            master,
+            slave,
+            stdin_closed: Cell::new(false),
+        })
+    }
+
+    pub fn slave(&self) -> &File {
+        &self.slave
+    }
+
+    /// Waits up to timeout milliseconds for the program to write something or for input for it
+    /// to be typed, and passes on whatever turns up.
+    pub fn relay(&self, timeout: i32) {
+        let master = self.master.as_raw_fd();
+        let mut fds = vec![PollFd::new(master, PollFlags::POLLIN)];
+        if !self.stdin_closed.get() {
+            fds.push(PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN));
+        }
+        if poll(&mut fds, timeout).unwrap_or(0) <= 0 {
+            return;
+        }
+        let ready = |fd: &PollFd| !fd.revents().unwrap_or_else(PollFlags::empty).is_empty();
+        if ready(&fds[0]) {
+            self.copy_output();
+        }
+        if fds.len() > 1 && ready(&fds[1]) {
+            let mut buf = [0; 4096];
+            match unistd::read(libc::STDIN_FILENO, &mut buf) {
+                Ok(0) | Err(_) => {
+                    // Pass the end of file on, once
+                    self.stdin_closed.set(true);
+                    let _ = unistd::write(master, &[EOF_CHARACTER]);
+                }
+                Ok(len) => {
+                    let _ = unistd::write(master, &buf[..len]);
+                }
+            }
+        }
+    }
+
+    /// Copies whatever the program has written and deet hasn't yet shown, e.g. after it stops.
+    pub fn drain(&self) {
+        let mut fds = [PollFd::new(self.master.as_raw_fd(), PollFlags::POLLIN)];
+        while poll(&mut fds, 0).unwrap_or(0) > 0 && self.copy_output() {}
+    }
+
+    /// Copies one chunk of the program's output to deet's stdout. Returns false if there wasn't
+    /// any.
+    fn copy_output(&self) -> bool {
+        let mut buf = [0; 4096];
+        match unistd::read(self.master.as_raw_fd(), &mut buf) {
+            Ok(len) if len > 0 => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(&buf[..len]);
+                let _ = stdout.flush();
+                true
+            }
+            _ => false,
+        }
+    }
+}
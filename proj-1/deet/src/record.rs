@@ -0,0 +1,176 @@
+//! Process record and replay, which lets us execute the inferior backwards.
+//!
+//! While recording, the inferior is single-stepped and each instruction's effects are logged: its
+//! registers before and after, and the old and new contents of any memory it wrote (found by
+//! decoding the instruction). Stepping backwards restores the "before" state in the live process;
+//! stepping forwards again re-applies the "after" state from the log instead of re-executing, until
+//! we reach the end of the log and go back to live execution. The log is a ring buffer, so the
+//! oldest instructions are forgotten once it fills up.
+//!
+//! Memory written by the kernel on the inferior's behalf (e.g. the buffer passed to read(2)) is not
+//! tracked, so replay is only faithful for code that stays in user space.
+
+use crate::arch::{Arch, Native, Registers};
+use crate::debugger::Breakpoint;
+use crate::disasm;
+use crate::inferior::{Inferior, Status};
+use crate::process_image::ProcessImage;
+use iced_x86::{InstructionInfoFactory, OpAccess};
+use nix::sys::signal::Signal;
+use std::collections::{HashMap, VecDeque};
+
+/// How many instructions we remember if the user doesn't say otherwise.
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 200_000;
+
+struct MemoryDelta {
+    addr: usize,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+pub struct InstructionRecord {
+    pub regs_before: Registers,
+    pub regs_after: Registers,
+    memory: Vec<MemoryDelta>,
+}
+
+pub struct Recording {
+    log: VecDeque<InstructionRecord>,
+    limit: usize,
+    /// How many instructions we have stepped back from the end of the log. 0 means the inferior
+    /// is at the live end of the log.
+    replay_offset: usize,
+    info_factory: InstructionInfoFactory,
+}
+
+impl Recording {
+    pub fn new(limit: usize) -> Recording {
+        Recording {
+            log: VecDeque::new(),
+            limit,
+            replay_offset: 0,
+            info_factory: InstructionInfoFactory::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of instructions in the log.
+    pub fn len(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Returns how many instructions behind the live end of the log we are.
+    pub fn replay_offset(&self) -> usize {
+        self.replay_offset
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay_offset > 0
+    }
+
+    /// Returns the instruction that step_back would undo next.
+    pub fn previous(&self) -> Option<&InstructionRecord> {
+        let index = self.log.len().checked_sub(self.replay_offset + 1)?;
+        self.log.get(index)
+    }
+
+    /// Executes one instruction in the live inferior and logs its effects. Must not be called
+    /// while replaying.
+    pub fn record_step(
+        &mut self,
+        inferior: &mut Inferior,
+        breakpoints: &HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        assert!(!self.is_replaying());
+        let regs_before = inferior.get_registers()?;
+        let mut memory = Vec::new();
+        if let Some(instruction) =
+            disasm::decode_instruction(inferior, Native::pc(&regs_before), breakpoints)
+        {
+            let info = self.info_factory.info(&instruction);
+            for used in info.used_memory() {
+                match used.access() {
+                    OpAccess::Write
+                    | OpAccess::CondWrite
+                    | OpAccess::ReadWrite
+                    | OpAccess::ReadCondWrite => {}
+                    _ => continue,
+                }
+                let addr = match used
+                    .virtual_address(0, |reg, _, _| disasm::register_value(&regs_before, reg))
+                {
+                    Some(addr) => addr as usize,
+                    None => continue,
+                };
+                let len = used.memory_size().size();
+                // An unreadable address means the instruction is about to fault, in which case
+                // it won't complete and there is nothing to log
+                if let Ok(before) = inferior.read_bytes(addr, len) {
+                    memory.push(MemoryDelta {
+                        addr,
+                        before,
+                        after: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let status = inferior.step_instruction(breakpoints)?;
+        if let Status::Stopped(Signal::SIGTRAP, _) = status {
+            for delta in memory.iter_mut() {
+                delta.after = inferior.read_bytes(delta.addr, delta.before.len())?;
+            }
+            if self.log.len() == self.limit {
+                self.log.pop_front();
+            }
+            self.log.push_back(InstructionRecord {
+                regs_before,
+                regs_after: inferior.get_registers()?,
+                memory,
+            });
+        }
+        Ok(status)
+    }
+
+    /// Undoes the most recent instruction that hasn't been undone yet, restoring the registers and
+    /// memory it changed. Returns the undone instruction's record, or None if there is no more
+    /// history.
+    pub fn step_back(
+        &mut self,
+        inferior: &mut Inferior,
+    ) -> Result<Option<&InstructionRecord>, nix::Error> {
+        let index = match self.log.len().checked_sub(self.replay_offset + 1) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let record = &self.log[index];
+        // Undo memory writes in reverse, in case one instruction wrote the same bytes twice
+        for delta in record.memory.iter().rev() {
+            inferior.write_bytes(delta.addr, &delta.before)?;
+        }
+        Native::set_registers(inferior.pid(), &record.regs_before)?;
+        self.replay_offset += 1;
+        Ok(Some(record))
+    }
+
+    /// Re-applies the next instruction in the log. Returns its record, or None if we are already
+    /// at the live end of the log.
+    pub fn replay_forward(
+        &mut self,
+        inferior: &mut Inferior,
+    ) -> Result<Option<&InstructionRecord>, nix::Error> {
+        if !self.is_replaying() {
+            return Ok(None);
+        }
+        let record = &self.log[self.log.len() - self.replay_offset];
+        for delta in record.memory.iter() {
+            inferior.write_bytes(delta.addr, &delta.after)?;
+        }
+        Native::set_registers(inferior.pid(), &record.regs_after)?;
+        self.replay_offset -= 1;
+        Ok(Some(record))
+    }
+}
@@ -0,0 +1,756 @@
+use crate::arch::{Arch, Native, Registers};
+use crate::expr;
+use crate::process_image::ProcessImage;
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+
+/// Names of the x86-64 system calls, indexed by number. Other architectures number the older calls
+/// differently, so there we only know the names of the newer ones.
+const NAMES: [&str; 335] = [
+    "read",
+    "write",
+    "open",
+    "close",
+    "stat",
+    "fstat",
+    "lstat",
+    "poll",
+    "lseek",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "brk",
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "ioctl",
+    "pread64",
+    "pwrite64",
+    "readv",
+    "writev",
+    "access",
+    "pipe",
+    "select",
+    "sched_yield",
+    "mremap",
+    "msync",
+    "mincore",
+    "madvise",
+    "shmget",
+    "shmat",
+    "shmctl",
+    "dup",
+    "dup2",
+    "pause",
+    "nanosleep",
+    "getitimer",
+    "alarm",
+    "setitimer",
+    "getpid",
+    "sendfile",
+    "socket",
+    "connect",
+    "accept",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "shutdown",
+    "bind",
+    "listen",
+    "getsockname",
+    "getpeername",
+    "socketpair",
+    "setsockopt",
+    "getsockopt",
+    "clone",
+    "fork",
+    "vfork",
+    "execve",
+    "exit",
+    "wait4",
+    "kill",
+    "uname",
+    "semget",
+    "semop",
+    "semctl",
+    "shmdt",
+    "msgget",
+    "msgsnd",
+    "msgrcv",
+    "msgctl",
+    "fcntl",
+    "flock",
+    "fsync",
+    "fdatasync",
+    "truncate",
+    "ftruncate",
+    "getdents",
+    "getcwd",
+    "chdir",
+    "fchdir",
+    "rename",
+    "mkdir",
+    "rmdir",
+    "creat",
+    "link",
+    "unlink",
+    "symlink",
+    "readlink",
+    "chmod",
+    "fchmod",
+    "chown",
+    "fchown",
+    "lchown",
+    "umask",
+    "gettimeofday",
+    "getrlimit",
+    "getrusage",
+    "sysinfo",
+    "times",
+    "ptrace",
+    "getuid",
+    "syslog",
+    "getgid",
+    "setuid",
+    "setgid",
+    "geteuid",
+    "getegid",
+    "setpgid",
+    "getppid",
+    "getpgrp",
+    "setsid",
+    "setreuid",
+    "setregid",
+    "getgroups",
+    "setgroups",
+    "setresuid",
+    "getresuid",
+    "setresgid",
+    "getresgid",
+    "getpgid",
+    "setfsuid",
+    "setfsgid",
+    "getsid",
+    "capget",
+    "capset",
+    "rt_sigpending",
+    "rt_sigtimedwait",
+    "rt_sigqueueinfo",
+    "rt_sigsuspend",
+    "sigaltstack",
+    "utime",
+    "mknod",
+    "uselib",
+    "personality",
+    "ustat",
+    "statfs",
+    "fstatfs",
+    "sysfs",
+    "getpriority",
+    "setpriority",
+    "sched_setparam",
+    "sched_getparam",
+    "sched_setscheduler",
+    "sched_getscheduler",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_rr_get_interval",
+    "mlock",
+    "munlock",
+    "mlockall",
+    "munlockall",
+    "vhangup",
+    "modify_ldt",
+    "pivot_root",
+    "_sysctl",
+    "prctl",
+    "arch_prctl",
+    "adjtimex",
+    "setrlimit",
+    "chroot",
+    "sync",
+    "acct",
+    "settimeofday",
+    "mount",
+    "umount2",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "sethostname",
+    "setdomainname",
+    "iopl",
+    "ioperm",
+    "create_module",
+    "init_module",
+    "delete_module",
+    "get_kernel_syms",
+    "query_module",
+    "quotactl",
+    "nfsservctl",
+    "getpmsg",
+    "putpmsg",
+    "afs_syscall",
+    "tuxcall",
+    "security",
+    "gettid",
+    "readahead",
+    "setxattr",
+    "lsetxattr",
+    "fsetxattr",
+    "getxattr",
+    "lgetxattr",
+    "fgetxattr",
+    "listxattr",
+    "llistxattr",
+    "flistxattr",
+    "removexattr",
+    "lremovexattr",
+    "fremovexattr",
+    "tkill",
+    "time",
+    "futex",
+    "sched_setaffinity",
+    "sched_getaffinity",
+    "set_thread_area",
+    "io_setup",
+    "io_destroy",
+    "io_getevents",
+    "io_submit",
+    "io_cancel",
+    "get_thread_area",
+    "lookup_dcookie",
+    "epoll_create",
+    "epoll_ctl_old",
+    "epoll_wait_old",
+    "remap_file_pages",
+    "getdents64",
+    "set_tid_address",
+    "restart_syscall",
+    "semtimedop",
+    "fadvise64",
+    "timer_create",
+    "timer_settime",
+    "timer_gettime",
+    "timer_getoverrun",
+    "timer_delete",
+    "clock_settime",
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "exit_group",
+    "epoll_wait",
+    "epoll_ctl",
+    "tgkill",
+    "utimes",
+    "vserver",
+    "mbind",
+    "set_mempolicy",
+    "get_mempolicy",
+    "mq_open",
+    "mq_unlink",
+    "mq_timedsend",
+    "mq_timedreceive",
+    "mq_notify",
+    "mq_getsetattr",
+    "kexec_load",
+    "waitid",
+    "add_key",
+    "request_key",
+    "keyctl",
+    "ioprio_set",
+    "ioprio_get",
+    "inotify_init",
+    "inotify_add_watch",
+    "inotify_rm_watch",
+    "migrate_pages",
+    "openat",
+    "mkdirat",
+    "mknodat",
+    "fchownat",
+    "futimesat",
+    "newfstatat",
+    "unlinkat",
+    "renameat",
+    "linkat",
+    "symlinkat",
+    "readlinkat",
+    "fchmodat",
+    "faccessat",
+    "pselect6",
+    "ppoll",
+    "unshare",
+    "set_robust_list",
+    "get_robust_list",
+    "splice",
+    "tee",
+    "sync_file_range",
+    "vmsplice",
+    "move_pages",
+    "utimensat",
+    "epoll_pwait",
+    "signalfd",
+    "timerfd_create",
+    "eventfd",
+    "fallocate",
+    "timerfd_settime",
+    "timerfd_gettime",
+    "accept4",
+    "signalfd4",
+    "eventfd2",
+    "epoll_create1",
+    "dup3",
+    "pipe2",
+    "inotify_init1",
+    "preadv",
+    "pwritev",
+    "rt_tgsigqueueinfo",
+    "perf_event_open",
+    "recvmmsg",
+    "fanotify_init",
+    "fanotify_mark",
+    "prlimit64",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "clock_adjtime",
+    "syncfs",
+    "sendmmsg",
+    "setns",
+    "getcpu",
+    "process_vm_readv",
+    "process_vm_writev",
+    "kcmp",
+    "finit_module",
+    "sched_setattr",
+    "sched_getattr",
+    "renameat2",
+    "seccomp",
+    "getrandom",
+    "memfd_create",
+    "kexec_file_load",
+    "bpf",
+    "execveat",
+    "userfaultfd",
+    "membarrier",
+    "mlock2",
+    "copy_file_range",
+    "preadv2",
+    "pwritev2",
+    "pkey_mprotect",
+    "pkey_alloc",
+    "pkey_free",
+    "statx",
+    "io_pgetevents",
+    "rseq",
+];
+
+/// Newer system calls are numbered from 424 on, the same on every architecture.
+const NEWER_NAMES_START: u64 = 424;
+const NEWER_NAMES: [&str; 39] = [
+    "pidfd_send_signal",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+    "open_tree",
+    "move_mount",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+    "pidfd_open",
+    "clone3",
+    "close_range",
+    "openat2",
+    "pidfd_getfd",
+    "faccessat2",
+    "process_madvise",
+    "epoll_pwait2",
+    "mount_setattr",
+    "quotactl_fd",
+    "landlock_create_ruleset",
+    "landlock_add_rule",
+    "landlock_restrict_self",
+    "memfd_secret",
+    "process_mrelease",
+    "futex_waitv",
+    "set_mempolicy_home_node",
+    "cachestat",
+    "fchmodat2",
+    "map_shadow_stack",
+    "futex_wake",
+    "futex_wait",
+    "futex_requeue",
+    "statmount",
+    "listmount",
+    "lsm_get_self_attr",
+    "lsm_set_self_attr",
+    "lsm_list_modules",
+    "mseal",
+];
+
+/// How long a string argument can get before we cut it short with "..."
+const STRING_LIMIT: usize = 64;
+
+/// Paths can be much longer than what we print, but we don't want to read forever if the pointer
+/// is garbage.
+const PATH_LIMIT: usize = 4096;
+
+/// How to print a system call argument
+#[derive(Clone, Copy)]
+enum Arg {
+    /// A signed int, in decimal
+    Int,
+    /// A size or count, in decimal
+    Unsigned,
+    /// A number best read in hex, like an ioctl request
+    Hex,
+    /// An address, or NULL
+    Pointer,
+    /// A file descriptor
+    Fd,
+    /// A directory file descriptor for the *at() calls, which can be AT_FDCWD
+    DirFd,
+    /// A NUL-terminated path
+    Path,
+    /// A buffer whose length is given by the argument with this index
+    Buffer(usize),
+    /// Flags for open()
+    OpenFlags,
+    /// File permissions, only passed when creating a file with open()
+    CreateMode,
+    /// File permissions
+    Mode,
+    /// Flags for the *at() calls, like AT_SYMLINK_NOFOLLOW
+    AtFlags,
+    /// Mode for access(), like R_OK
+    AccessMode,
+    /// Memory protection for mmap() and mprotect()
+    Prot,
+    /// Flags for mmap()
+    MapFlags,
+    /// Whence for lseek()
+    Whence,
+    Signal,
+}
+
+/// Returns the name of the system call with the given number.
+pub fn name(number: u64) -> Option<&'static str> {
+    if number < NAMES.len() as u64 && cfg!(target_arch = "x86_64") {
+        Some(NAMES[number as usize])
+    } else if number >= NEWER_NAMES_START {
+        NEWER_NAMES
+            .get((number - NEWER_NAMES_START) as usize)
+            .copied()
+    } else {
+        None
+    }
+}
+
+/// Returns the name of the system call with the given number, or something like `syscall_400`
+/// if we don't know it.
+pub fn display_name(number: u64) -> String {
+    match name(number) {
+        Some(name) => name.to_string(),
+        None => format!("syscall_{}", number),
+    }
+}
+
+/// Looks up a system call number by name.
+pub fn number(name: &str) -> Option<u64> {
+    if cfg!(target_arch = "x86_64") {
+        if let Some(index) = NAMES.iter().position(|n| *n == name) {
+            return Some(index as u64);
+        }
+    }
+    NEWER_NAMES
+        .iter()
+        .position(|n| *n == name)
+        .map(|index| NEWER_NAMES_START + index as u64)
+}
+
+/// Returns true for the system calls that don't come back when they work, so there is no exit
+/// stop to wait for.
+pub fn never_returns(number: u64) -> bool {
+    number == libc::SYS_exit as u64 || number == libc::SYS_exit_group as u64
+}
+
+fn signature(name: &str) -> Option<&'static [Arg]> {
+    use Arg::*;
+    Some(match name {
+        "getpid" | "getppid" | "gettid" | "getuid" | "geteuid" | "getgid" | "getegid" | "fork"
+        | "vfork" | "sched_yield" | "pause" | "setsid" | "getpgrp" => &[],
+        "read" | "getdents" | "getdents64" => &[Fd, Pointer, Unsigned],
+        "write" => &[Fd, Buffer(2), Unsigned],
+        "pread64" => &[Fd, Pointer, Unsigned, Int],
+        "pwrite64" => &[Fd, Buffer(2), Unsigned, Int],
+        "readv" | "writev" => &[Fd, Pointer, Int],
+        "open" => &[Path, OpenFlags, CreateMode],
+        "openat" => &[DirFd, Path, OpenFlags, CreateMode],
+        "creat" => &[Path, Mode],
+        "close" | "fsync" | "fdatasync" | "fchdir" | "dup" => &[Fd],
+        "dup2" => &[Fd, Fd],
+        "dup3" => &[Fd, Fd, OpenFlags],
+        "pipe" => &[Pointer],
+        "pipe2" => &[Pointer, OpenFlags],
+        "stat" | "lstat" => &[Path, Pointer],
+        "fstat" => &[Fd, Pointer],
+        "newfstatat" => &[DirFd, Path, Pointer, AtFlags],
+        "statx" => &[DirFd, Path, AtFlags, Hex, Pointer],
+        "lseek" => &[Fd, Int, Whence],
+        "truncate" => &[Path, Int],
+        "ftruncate" => &[Fd, Int],
+        "access" => &[Path, AccessMode],
+        "faccessat" => &[DirFd, Path, AccessMode],
+        "faccessat2" => &[DirFd, Path, AccessMode, AtFlags],
+        "chdir" | "rmdir" | "unlink" | "chroot" => &[Path],
+        "unlinkat" => &[DirFd, Path, AtFlags],
+        "mkdir" | "chmod" => &[Path, Mode],
+        "mkdirat" | "fchmodat" => &[DirFd, Path, Mode],
+        "fchmod" => &[Fd, Mode],
+        "rename" | "link" | "symlink" => &[Path, Path],
+        "renameat" | "linkat" => &[DirFd, Path, DirFd, Path],
+        "readlink" => &[Path, Pointer, Unsigned],
+        "readlinkat" => &[DirFd, Path, Pointer, Unsigned],
+        "getcwd" => &[Pointer, Unsigned],
+        "umask" => &[Mode],
+        "fcntl" => &[Fd, Int, Hex],
+        "ioctl" => &[Fd, Hex, Hex],
+        "flock" => &[Fd, Int],
+        "poll" => &[Pointer, Unsigned, Int],
+        "mmap" => &[Pointer, Unsigned, Prot, MapFlags, Fd, Hex],
+        "mprotect" => &[Pointer, Unsigned, Prot],
+        "munmap" => &[Pointer, Unsigned],
+        "mremap" => &[Pointer, Unsigned, Unsigned, Hex, Pointer],
+        "madvise" => &[Pointer, Unsigned, Int],
+        "brk" | "set_tid_address" | "uname" | "sysinfo" | "times" => &[Pointer],
+        "rt_sigaction" => &[Signal, Pointer, Pointer, Unsigned],
+        "rt_sigprocmask" => &[Int, Pointer, Pointer, Unsigned],
+        "rt_sigreturn" => &[],
+        "sigaltstack" => &[Pointer, Pointer],
+        "kill" => &[Int, Signal],
+        "tkill" => &[Int, Signal],
+        "tgkill" => &[Int, Int, Signal],
+        "alarm" => &[Unsigned],
+        "nanosleep" => &[Pointer, Pointer],
+        "clock_nanosleep" => &[Int, Int, Pointer, Pointer],
+        "clock_gettime" | "clock_getres" => &[Int, Pointer],
+        "gettimeofday" => &[Pointer, Pointer],
+        "socket" | "socketpair" => &[Int, Int, Int],
+        "connect" | "bind" => &[Fd, Pointer, Unsigned],
+        "accept" => &[Fd, Pointer, Pointer],
+        "accept4" => &[Fd, Pointer, Pointer, Hex],
+        "listen" | "shutdown" => &[Fd, Int],
+        "sendto" => &[Fd, Buffer(2), Unsigned, Hex, Pointer, Unsigned],
+        "recvfrom" => &[Fd, Pointer, Unsigned, Hex, Pointer, Pointer],
+        "clone" => &[Hex, Pointer, Pointer, Pointer, Hex],
+        "execve" => &[Path, Pointer, Pointer],
+        "exit" | "exit_group" => &[Int],
+        "wait4" => &[Int, Pointer, Hex, Pointer],
+        "arch_prctl" => &[Hex, Pointer],
+        "prctl" => &[Int, Hex, Hex, Hex, Hex],
+        "set_robust_list" => &[Pointer, Unsigned],
+        "prlimit64" => &[Int, Int, Pointer, Pointer],
+        "getrlimit" | "setrlimit" => &[Int, Pointer],
+        "getrandom" => &[Pointer, Unsigned, Hex],
+        "rseq" => &[Pointer, Unsigned, Int, Hex],
+        "futex" => &[Pointer, Int, Int, Pointer, Pointer, Int],
+        _ => return None,
+    })
+}
+
+/// Describes the system call that the inferior, stopped at its entry or exit, is making, the way
+/// strace would: `openat(AT_FDCWD, "/etc/passwd", O_RDONLY)`.
+pub fn format_call(regs: &Registers, image: &dyn ProcessImage) -> String {
+    let number = Native::syscall_number(regs);
+    let args = Native::syscall_args(regs);
+    // Without a signature, show all six argument registers; we can't tell how many are used
+    let signature = name(number).and_then(signature).unwrap_or(&[Arg::Hex; 6]);
+    let mut formatted = Vec::new();
+    for (i, arg) in signature.iter().enumerate() {
+        let value = args[i];
+        formatted.push(match *arg {
+            Arg::Int => (value as i32).to_string(),
+            Arg::Unsigned => value.to_string(),
+            Arg::Hex => format!("{:#x}", value),
+            Arg::Pointer if value == 0 => "NULL".to_string(),
+            Arg::Pointer => format!("{:#x}", value),
+            Arg::DirFd if value as i32 == libc::AT_FDCWD => "AT_FDCWD".to_string(),
+            Arg::Fd | Arg::DirFd => (value as i32).to_string(),
+            Arg::Path => read_string(image, value as usize, PATH_LIMIT),
+            Arg::Buffer(len_index) => read_buffer(image, value as usize, args[len_index] as usize),
+            Arg::OpenFlags => open_flags(value),
+            // open() only looks at the mode when it creates a file
+            Arg::CreateMode if args[i - 1] & CREATE_FLAGS == 0 => continue,
+            Arg::CreateMode | Arg::Mode => format!("0{:o}", value),
+            Arg::AtFlags => flags(value, AT_FLAGS, "0"),
+            Arg::AccessMode => flags(value, ACCESS_MODES, "F_OK"),
+            Arg::Prot => flags(value, PROT_FLAGS, "PROT_NONE"),
+            Arg::MapFlags => flags(value, MAP_FLAGS, "0"),
+            Arg::Whence => match value as i32 {
+                libc::SEEK_SET => "SEEK_SET".to_string(),
+                libc::SEEK_CUR => "SEEK_CUR".to_string(),
+                libc::SEEK_END => "SEEK_END".to_string(),
+                whence => whence.to_string(),
+            },
+            Arg::Signal => match Signal::try_from(value as i32) {
+                Ok(signal) => format!("{:?}", signal),
+                Err(_) => (value as i32).to_string(),
+            },
+        });
+    }
+    format!("{}({})", display_name(number), formatted.join(", "))
+}
+
+/// Describes a system call's return value: a number, an address for calls that return one, or -1
+/// and the error for calls that failed, like `-1 ENOENT (No such file or directory)`.
+pub fn format_return(number: u64, ret: u64) -> String {
+    let ret = ret as i64;
+    // The kernel returns -errno, and no successful call returns a value in this range
+    if (-4095..0).contains(&ret) {
+        let errno = Errno::from_i32(-ret as i32);
+        return match errno {
+            Errno::UnknownErrno => format!("-1 (errno {})", -ret),
+            _ => format!("-1 {:?} ({})", errno, errno.desc()),
+        };
+    }
+    match name(number) {
+        Some("mmap") | Some("mremap") | Some("brk") | Some("shmat") => format!("{:#x}", ret),
+        _ => ret.to_string(),
+    }
+}
+
+/// O_CREAT and O_TMPFILE are the open() flags that make it take a mode argument.
+const CREATE_FLAGS: u64 = (libc::O_CREAT | libc::O_TMPFILE) as u64;
+
+const OPEN_FLAGS: &[(u64, &str)] = &[
+    (libc::O_CREAT as u64, "O_CREAT"),
+    (libc::O_EXCL as u64, "O_EXCL"),
+    (libc::O_NOCTTY as u64, "O_NOCTTY"),
+    (libc::O_TRUNC as u64, "O_TRUNC"),
+    (libc::O_APPEND as u64, "O_APPEND"),
+    (libc::O_NONBLOCK as u64, "O_NONBLOCK"),
+    (libc::O_DSYNC as u64, "O_DSYNC"),
+    (libc::O_DIRECT as u64, "O_DIRECT"),
+    (libc::O_LARGEFILE as u64, "O_LARGEFILE"),
+    (libc::O_NOFOLLOW as u64, "O_NOFOLLOW"),
+    (libc::O_NOATIME as u64, "O_NOATIME"),
+    (libc::O_CLOEXEC as u64, "O_CLOEXEC"),
+    (libc::O_PATH as u64, "O_PATH"),
+    // O_TMPFILE includes O_DIRECTORY, so it has to be matched first
+    (libc::O_TMPFILE as u64, "O_TMPFILE"),
+    (libc::O_DIRECTORY as u64, "O_DIRECTORY"),
+    // O_SYNC includes O_DSYNC, which will have been taken out already
+    ((libc::O_SYNC & !libc::O_DSYNC) as u64, "O_SYNC"),
+];
+
+const AT_FLAGS: &[(u64, &str)] = &[
+    (libc::AT_SYMLINK_NOFOLLOW as u64, "AT_SYMLINK_NOFOLLOW"),
+    (libc::AT_REMOVEDIR as u64, "AT_REMOVEDIR"),
+    (libc::AT_SYMLINK_FOLLOW as u64, "AT_SYMLINK_FOLLOW"),
+    (libc::AT_NO_AUTOMOUNT as u64, "AT_NO_AUTOMOUNT"),
+    (libc::AT_EMPTY_PATH as u64, "AT_EMPTY_PATH"),
+];
+
+const ACCESS_MODES: &[(u64, &str)] = &[
+    (libc::R_OK as u64, "R_OK"),
+    (libc::W_OK as u64, "W_OK"),
+    (libc::X_OK as u64, "X_OK"),
+];
+
+const PROT_FLAGS: &[(u64, &str)] = &[
+    (libc::PROT_READ as u64, "PROT_READ"),
+    (libc::PROT_WRITE as u64, "PROT_WRITE"),
+    (libc::PROT_EXEC as u64, "PROT_EXEC"),
+];
+
+const MAP_FLAGS: &[(u64, &str)] = &[
+    (libc::MAP_SHARED as u64, "MAP_SHARED"),
+    (libc::MAP_PRIVATE as u64, "MAP_PRIVATE"),
+    (libc::MAP_FIXED as u64, "MAP_FIXED"),
+    (libc::MAP_ANONYMOUS as u64, "MAP_ANONYMOUS"),
+    (libc::MAP_GROWSDOWN as u64, "MAP_GROWSDOWN"),
+    (libc::MAP_DENYWRITE as u64, "MAP_DENYWRITE"),
+    (libc::MAP_EXECUTABLE as u64, "MAP_EXECUTABLE"),
+    (libc::MAP_LOCKED as u64, "MAP_LOCKED"),
+    (libc::MAP_NORESERVE as u64, "MAP_NORESERVE"),
+    (libc::MAP_POPULATE as u64, "MAP_POPULATE"),
+    (libc::MAP_NONBLOCK as u64, "MAP_NONBLOCK"),
+    (libc::MAP_STACK as u64, "MAP_STACK"),
+    (libc::MAP_HUGETLB as u64, "MAP_HUGETLB"),
+    (0x100000, "MAP_FIXED_NOREPLACE"),
+];
+
+/// Formats open() flags: the access mode, which is a number rather than a bit, then the rest.
+fn open_flags(value: u64) -> String {
+    let access = match value & libc::O_ACCMODE as u64 {
+        0 => "O_RDONLY".to_string(),
+        1 => "O_WRONLY".to_string(),
+        2 => "O_RDWR".to_string(),
+        mode => format!("0{:o}", mode),
+    };
+    match value & !(libc::O_ACCMODE as u64) {
+        0 => access,
+        rest => format!("{}|{}", access, flags(rest, OPEN_FLAGS, "0")),
+    }
+}
+
+/// Formats a set of flags as `A|B|0x40`, with any bits we don't have names for in hex at the end.
+fn flags(value: u64, names: &[(u64, &str)], zero: &str) -> String {
+    if value == 0 {
+        return zero.to_string();
+    }
+    let mut parts = Vec::new();
+    let mut rest = value;
+    for (bits, name) in names {
+        if *bits != 0 && rest & bits == *bits {
+            parts.push(name.to_string());
+            rest &= !bits;
+        }
+    }
+    if rest != 0 {
+        parts.push(format!("{:#x}", rest));
+    }
+    parts.join("|")
+}
+
+/// Reads a NUL-terminated string, printing at most STRING_LIMIT characters of it.
+fn read_string(image: &dyn ProcessImage, addr: usize, max_len: usize) -> String {
+    if addr == 0 {
+        return "NULL".to_string();
+    }
+    let mut bytes = Vec::new();
+    while bytes.len() < max_len {
+        match image.read_word(addr + bytes.len()) {
+            Ok(word) => {
+                let word = word.to_le_bytes();
+                match word.iter().position(|b| *b == 0) {
+                    Some(end) => {
+                        bytes.extend_from_slice(&word[..end]);
+                        break;
+                    }
+                    None => bytes.extend_from_slice(&word),
+                }
+            }
+            Err(_) if bytes.is_empty() => return format!("{:#x}", addr),
+            Err(_) => break,
+        }
+    }
+    quote(&bytes, STRING_LIMIT)
+}
+
+/// Reads a buffer of len bytes, printing at most STRING_LIMIT of them.
+fn read_buffer(image: &dyn ProcessImage, addr: usize, len: usize) -> String {
+    match image.read_bytes(addr, len.min(STRING_LIMIT + 1)) {
+        Ok(bytes) => quote(&bytes, STRING_LIMIT),
+        Err(_) => format!("{:#x}", addr),
+    }
+}
+
+fn quote(bytes: &[u8], limit: usize) -> String {
+    if bytes.len() > limit {
+        format!("\"{}\"...", expr::escape(&bytes[..limit]))
+    } else {
+        format!("\"{}\"", expr::escape(bytes))
+    }
+}
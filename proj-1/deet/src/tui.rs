@@ -0,0 +1,330 @@
+//! The full-screen interface of `deet --tui`: panes showing the source, disassembly, registers and
+//! backtrace fill the top of the terminal and are redrawn after every command, and the command
+//! prompt sits below them. The panes are drawn with plain ANSI escape sequences. The command area
+//! is set up as the terminal's scrolling region, so commands (and the program) print there just
+//! as they would without the TUI, and their output scrolls without disturbing the panes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+
+/// The command area never gets smaller than this many rows
+const MIN_COMMAND_ROWS: usize = 8;
+
+/// What the panes show, gathered from the debugger's state after each command
+#[derive(Default)]
+pub struct View {
+    /// The source file and line the program is stopped at
+    pub source: Option<(String, usize)>,
+    /// The lines of that file with breakpoints on them
+    pub breakpoint_lines: Vec<usize>,
+    /// The instructions around rip: their addresses, text, and whether there's a breakpoint on
+    /// them
+    pub disassembly: Vec<(usize, String, bool)>,
+    pub rip: Option<usize>,
+    /// The registers, as `info registers` lists them
+    pub registers: Vec<(&'static str, u64)>,
+    /// The lines of the backtrace
+    pub backtrace: Vec<String>,
+    /// Why there's nothing to show, e.g. the program isn't running
+    pub message: Option<String>,
+}
+
+pub struct Tui {
+    rows: usize,
+    cols: usize,
+    /// The contents of the source files we've shown, split into lines
+    sources: HashMap<String, Option<Vec<String>>>,
+    /// The registers as we last drew them, so that we can highlight the ones that changed
+    old_registers: Vec<(&'static str, u64)>,
+}
+
+/// One of the rectangles the top of the screen is divided into. Rows and columns count from 1, as
+/// they do in escape sequences.
+struct Pane {
+    top: usize,
+    left: usize,
+    height: usize,
+    width: usize,
+}
+
+impl Tui {
+    /// Switches the terminal to the TUI, or returns None if stdout isn't a terminal.
+    pub fn new() -> Option<Tui> {
+        let (rows, cols) = terminal_size()?;
+        let mut tui = Tui {
+            rows,
+            cols,
+            sources: HashMap::new(),
+            old_registers: Vec::new(),
+        };
+        // Use the alternate screen, so that whatever was on the terminal comes back when we exit
+        print!("\x1b[?1049h");
+        tui.reset();
+        Some(tui)
+    }
+
+    /// The number of rows the panes take up, leaving the rest for commands
+    fn pane_rows(&self) -> usize {
+        let command_rows = MIN_COMMAND_ROWS.max(self.rows / 4);
+        self.rows.saturating_sub(command_rows)
+    }
+
+    /// Clears the screen, makes everything below the panes the scrolling region, and puts the
+    /// cursor at the bottom of it.
+    fn reset(&mut self) {
+        print!(
+            "\x1b[2J\x1b[{};{}r\x1b[{};1H",
+            self.pane_rows() + 1,
+            self.rows,
+            self.rows
+        );
+        let _ = io::stdout().flush();
+    }
+
+    /// Redraws the panes. The cursor goes back to where it was, at the prompt.
+    pub fn draw(&mut self, view: &View) {
+        if let Some((rows, cols)) = terminal_size() {
+            if (rows, cols) != (self.rows, self.cols) {
+                self.rows = rows;
+                self.cols = cols;
+                self.reset();
+            }
+        }
+        let pane_rows = self.pane_rows();
+        if pane_rows < 4 {
+            return;
+        }
+        let top_height = pane_rows / 2;
+        let left_width = self.cols * 2 / 3;
+        let source = Pane::new(1, 1, top_height, left_width);
+        let registers = Pane::new(1, left_width + 1, top_height, self.cols - left_width);
+        let disassembly = Pane::new(top_height + 1, 1, pane_rows - top_height, left_width);
+        let backtrace = Pane::new(
+            top_height + 1,
+            left_width + 1,
+            pane_rows - top_height,
+            self.cols - left_width,
+        );
+
+        // Save the cursor, draw, and restore it
+        let mut out = String::from("\x1b7");
+        self.draw_source(&mut out, &source, view);
+        self.draw_registers(&mut out, &registers, view);
+        draw_disassembly(&mut out, &disassembly, view);
+        backtrace.draw(&mut out, "Backtrace", &view.backtrace);
+        out.push_str("\x1b8");
+        print!("{}", out);
+        let _ = io::stdout().flush();
+        self.old_registers = view.registers.clone();
+    }
+
+    fn draw_source(&mut self, out: &mut String, pane: &Pane, view: &View) {
+        let (path, current) = match view.source {
+            Some((ref path, line)) => (path, line),
+            None => {
+                let message = view.message.clone().unwrap_or_default();
+                pane.draw(out, "Source", &[message]);
+                return;
+            }
+        };
+        let source = self.sources.entry(path.clone()).or_insert_with(|| {
+            Some(
+                fs::read_to_string(path)
+                    .ok()?
+                    .lines()
+                    .map(expand_tabs)
+                    .collect(),
+            )
+        });
+        let source = match source {
+            Some(source) => source,
+            None => {
+                pane.draw(out, path, &[format!("Could not read {}", path)]);
+                return;
+            }
+        };
+        // Keep the current line in the middle of the pane
+        let rows = pane.height - 1;
+        let first = current.saturating_sub(rows / 2).max(1);
+        let number_width = (first + rows).to_string().len();
+        let lines: Vec<String> = (first..first + rows)
+            .filter_map(|number| {
+                let text = source.get(number.checked_sub(1)?)?;
+                let marker = if view.breakpoint_lines.contains(&number) {
+                    "b"
+                } else {
+                    " "
+                };
+                let arrow = if number == current { ">" } else { " " };
+                let line = format!(
+                    "{}{}{:>w$}  {}",
+                    marker,
+                    arrow,
+                    number,
+                    text,
+                    w = number_width
+                );
+                Some(if number == current {
+                    highlight(&line, pane.width)
+                } else {
+                    line
+                })
+            })
+            .collect();
+        pane.draw(out, path, &lines);
+    }
+
+    fn draw_registers(&self, out: &mut String, pane: &Pane, view: &View) {
+        // Registers go in two columns if there's room
+        let columns = if pane.width >= 60 { 2 } else { 1 };
+        let rows = view.registers.chunks(columns).count();
+        let lines: Vec<String> = (0..rows)
+            .map(|row| {
+                let mut line = String::new();
+                for column in 0..columns {
+                    if let Some(&(name, value)) = view.registers.get(column * rows + row) {
+                        let entry = format!("{:<8}{:<#20x}", name, value);
+                        // Highlight the registers that changed since the last command
+                        if !self.old_registers.is_empty()
+                            && !self.old_registers.contains(&(name, value))
+                        {
+                            line.push_str(&format!("\x1b[1m{}\x1b[0m  ", entry));
+                        } else {
+                            line.push_str(&format!("{}  ", entry));
+                        }
+                    }
+                }
+                line
+            })
+            .collect();
+        pane.draw(out, "Registers", &lines);
+    }
+}
+
+impl Drop for Tui {
+    /// Puts the terminal back the way it was.
+    fn drop(&mut self) {
+        print!("\x1b[r\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Pane {
+    fn new(top: usize, left: usize, height: usize, width: usize) -> Pane {
+        Pane {
+            top,
+            left,
+            height,
+            width,
+        }
+    }
+
+    /// Draws the pane: its title in a bar across the top, then as many of lines as fit, cut off
+    /// a column short of the pane's width to leave a gap before the next pane.
+    fn draw(&self, out: &mut String, title: &str, lines: &[String]) {
+        let title = format!(" {}", title);
+        out.push_str(&format!(
+            "\x1b[{};{}H\x1b[7m{}\x1b[0m",
+            self.top,
+            self.left,
+            fit(&title, self.width)
+        ));
+        for row in 1..self.height {
+            let line = lines.get(row - 1).map(String::as_str).unwrap_or("");
+            out.push_str(&format!(
+                "\x1b[{};{}H{} ",
+                self.top + row,
+                self.left,
+                fit(line, self.width - 1)
+            ));
+        }
+    }
+}
+
+fn draw_disassembly(out: &mut String, pane: &Pane, view: &View) {
+    // Show a few instructions before rip, and as many after it as fit
+    let rows = pane.height - 1;
+    let current = view
+        .disassembly
+        .iter()
+        .position(|(addr, _, _)| Some(*addr) == view.rip)
+        .unwrap_or(0);
+    let first = current.saturating_sub(rows / 4);
+    let lines: Vec<String> = view.disassembly[first..]
+        .iter()
+        .map(|(addr, text, breakpoint)| {
+            let marker = if *breakpoint { "b" } else { " " };
+            if Some(*addr) == view.rip {
+                highlight(&format!("{}>{:#x}  {}", marker, addr, text), pane.width)
+            } else {
+                format!("{} {:#x}  {}", marker, addr, text)
+            }
+        })
+        .collect();
+    pane.draw(out, "Disassembly", &lines);
+}
+
+/// Pads or cuts off text to exactly width columns. Escape sequences in it don't take up any
+/// columns.
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut columns = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Copy the escape sequence up to and including its final letter
+            fitted.push(c);
+            for c in &mut chars {
+                fitted.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else if columns < width {
+            fitted.push(if c.is_control() { ' ' } else { c });
+            columns += 1;
+        }
+    }
+    fitted.push_str(&" ".repeat(width - columns));
+    fitted.push_str("\x1b[0m");
+    fitted
+}
+
+/// Shows a line in reverse video, all the way across the pane.
+fn highlight(line: &str, width: usize) -> String {
+    format!("\x1b[7m{}", fit(line, width))
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = 8 - column % 8;
+            expanded.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+/// Returns the number of rows and columns of the terminal stdout is, or None if it isn't one.
+fn terminal_size() -> Option<(usize, usize)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0
+        || size.ws_row == 0
+        || size.ws_col == 0
+    {
+        return None;
+    }
+    Some((size.ws_row as usize, size.ws_col as usize))
+}
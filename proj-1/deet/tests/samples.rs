@@ -0,0 +1,1210 @@
+//! Runs deet against the programs in samples/ and checks what it prints. Each test compiles the
+//! samples it needs into a directory of its own, the way the Makefile does unless it's checking
+//! how deet copes with another kind of build, and drives deet with a script of commands through
+//! `--batch`, or, for the TUI, by typing them on a pseudo-terminal.
+
+use nix::pty;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// A directory for one test, which the samples it debugs are compiled into and deet runs in. It's
+/// removed when the test passes and left behind to look at when it fails.
+struct Workspace {
+    dir: PathBuf,
+}
+
+impl Workspace {
+    fn new(test: &str) -> Workspace {
+        let dir = std::env::temp_dir().join(format!("deet-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("creating the test's directory");
+        Workspace { dir }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Compiles samples/<name>.c and returns the path of the program.
+    fn compile(&self, name: &str) -> PathBuf {
+        self.compile_with(name, name, &[])
+    }
+
+    /// Compiles samples/<name>.c into program, with some flags on top of the Makefile's. gcc runs
+    /// in this directory, so that's where any .dwo files go.
+    fn compile_with(&self, name: &str, program: &str, flags: &[&str]) -> PathBuf {
+        let source = sample(&format!("{}.c", name));
+        let program = self.path(program);
+        let status = Command::new("gcc")
+            .args(&["-O0", "-g", "-no-pie", "-fno-omit-frame-pointer"])
+            .args(flags)
+            .arg("-o")
+            .arg(&program)
+            .arg(&source)
+            .current_dir(&self.dir)
+            .status()
+            .expect("running gcc");
+        assert!(status.success(), "compiling {}", source.display());
+        program
+    }
+
+    /// Compiles samples/<name>.rs, without optimizations and, since deet can't debug position
+    /// independent executables, at a fixed address.
+    fn compile_rust(&self, name: &str) -> PathBuf {
+        let source = sample(&format!("{}.rs", name));
+        let program = self.path(name);
+        let status = Command::new("rustc")
+            .args(&["-g", "-C", "opt-level=0", "-C", "force-frame-pointers=yes"])
+            .args(&["-C", "relocation-model=static", "-o"])
+            .arg(&program)
+            .arg(&source)
+            .status()
+            .expect("running rustc");
+        assert!(status.success(), "compiling {}", source.display());
+        program
+    }
+
+    /// Runs a tool like objcopy in this directory.
+    fn run_tool(&self, tool: &str, args: &[&str]) {
+        let status = Command::new(tool)
+            .args(args)
+            .current_dir(&self.dir)
+            .status()
+            .unwrap_or_else(|err| panic!("running {}: {}", tool, err));
+        assert!(status.success(), "{} {:?} failed", tool, args);
+    }
+
+    /// Runs deet with some arguments, in this directory and with nothing on its input.
+    fn run_deet(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_deet"))
+            .args(args)
+            .current_dir(&self.dir)
+            .stdin(Stdio::null())
+            .output()
+            .expect("running deet")
+    }
+
+    /// Runs deet with some arguments on a terminal of its own, 30 rows by 100 columns, typing each
+    /// of the commands at its prompt. Returns everything deet wrote to the terminal.
+    fn run_deet_on_terminal(&self, args: &[&str], commands: &[&str]) -> String {
+        let size = pty::Winsize {
+            ws_row: 30,
+            ws_col: 100,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = pty::openpty(&size, None).expect("opening a pseudo-terminal");
+        let (mut terminal, tty) = unsafe {
+            (
+                fs::File::from_raw_fd(pty.master),
+                fs::File::from_raw_fd(pty.slave),
+            )
+        };
+        let mut deet = Command::new(env!("CARGO_BIN_EXE_deet"))
+            .args(args)
+            .current_dir(&self.dir)
+            // Keep the commands out of the user's history
+            .env("HOME", &self.dir)
+            .stdin(tty.try_clone().unwrap())
+            .stdout(tty.try_clone().unwrap())
+            .stderr(tty)
+            .spawn()
+            .expect("running deet");
+
+        let (sender, receiver) = mpsc::channel();
+        let mut reader = terminal.try_clone().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // Reading fails once deet and the program have exited and closed the terminal
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        let timeout = Duration::from_secs(10);
+        let mut output = Vec::new();
+        for (i, command) in commands.iter().enumerate() {
+            // Wait for the prompt, since deet may throw away anything typed before it
+            while String::from_utf8_lossy(&output).matches("(deet) ").count() <= i {
+                match receiver.recv_timeout(timeout) {
+                    Ok(bytes) => output.extend(bytes),
+                    Err(_) => panic!(
+                        "deet didn't prompt for {:?}:\n{}",
+                        command,
+                        String::from_utf8_lossy(&output)
+                    ),
+                }
+            }
+            writeln!(terminal, "{}", command).unwrap();
+        }
+        while let Ok(bytes) = receiver.recv_timeout(timeout) {
+            output.extend(bytes);
+        }
+        let output = String::from_utf8_lossy(&output).to_string();
+        assert!(deet.wait().unwrap().success(), "deet failed:\n{}", output);
+        output
+    }
+
+    /// Debugs a sample with the commands in script, without reading a .deetinit, and returns
+    /// everything printed: deet's output, and the program's, since it shares deet's stdout.
+    fn debug(&self, sample: &str, script: &str) -> String {
+        self.debug_with(sample, &["--nx"], script)
+    }
+
+    fn debug_with(&self, sample: &str, args: &[&str], script: &str) -> String {
+        let program = self.compile(sample);
+        self.debug_program(&program, args, script)
+    }
+
+    /// Debugs a program that has already been built, like debug_with.
+    fn debug_program(&self, program: &Path, args: &[&str], script: &str) -> String {
+        let script_path = self.path("script.deet");
+        fs::write(&script_path, script).expect("writing the script");
+        let mut all_args = vec![program.to_str().unwrap()];
+        all_args.extend_from_slice(args);
+        all_args.extend_from_slice(&["--batch", script_path.to_str().unwrap()]);
+        let output = self.run_deet(&all_args);
+        assert!(output.status.success(), "deet failed: {:?}", output);
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// The path of a file in samples/
+fn sample(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("samples")
+        .join(name)
+}
+
+/// Checks that each of the expected pieces of text comes up in the output, in order.
+fn assert_in_order(output: &str, expected: &[&str]) {
+    let mut rest = output;
+    for text in expected {
+        match rest.find(text) {
+            Some(pos) => rest = &rest[pos + text.len()..],
+            None => panic!(
+                "expected {:?} (in order after {:?}) in deet's output:\n{}",
+                text, expected, output
+            ),
+        }
+    }
+}
+
+fn assert_lacks(output: &str, unexpected: &str) {
+    assert!(
+        !output.contains(unexpected),
+        "didn't expect {:?} in deet's output:\n{}",
+        unexpected,
+        output
+    );
+}
+
+/// The address deet said it set breakpoint number n at
+fn breakpoint_address(output: &str, n: usize) -> String {
+    let prefix = format!("Set breakpoint {} at ", n);
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .unwrap_or_else(|| panic!("no breakpoint {} in deet's output:\n{}", n, output))
+        .to_string()
+}
+
+#[test]
+fn runs_programs_to_their_exit_status() {
+    let ws = Workspace::new("runs_programs_to_their_exit_status");
+    let output = ws.debug("hello", "run\n");
+    assert_in_order(&output, &["Hello world!", "Child exited (status 0)"]);
+
+    // loop.c exits with 1 when it isn't given a number of seconds
+    let output = ws.debug("loop", "run\nrun 1\n");
+    assert_in_order(
+        &output,
+        &["Child exited (status 1)", "0\n", "Child exited (status 0)"],
+    );
+
+    // exit.c makes the exit system call itself, without going through libc
+    let output = ws.debug("exit", "run\n");
+    assert_in_order(&output, &["Child exited (status 0)"]);
+    assert_lacks(&output, "Killing running inferior");
+}
+
+#[test]
+fn breakpoints_stop_at_lines_with_a_backtrace() {
+    let ws = Workspace::new("breakpoints_stop_at_lines_with_a_backtrace");
+    let output = ws.debug(
+        "function_calls",
+        "break function_calls.c:12\nrun\nbacktrace\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Set breakpoint 0 at 0x",
+            "Child stopped (signal SIGTRAP)",
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            "func2 (",
+            "function_calls.c:12)\n",
+            "func1 (",
+            "function_calls.c:18)\n",
+            "main (",
+            "function_calls.c:24)\n",
+            "Child exited (status 0)",
+        ],
+    );
+    // Everything the program printed was flushed when it exited
+    assert_in_order(
+        &output,
+        &[
+            "func1(42) was called",
+            "func2(42, 5) was called",
+            "sum = 47",
+        ],
+    );
+}
+
+#[test]
+fn breakpoints_on_functions_lines_and_addresses() {
+    let ws = Workspace::new("breakpoints_on_functions_lines_and_addresses");
+    let output = ws.debug(
+        "function_calls",
+        "break func2\nb 19\nrun\ncontinue\ncontinue\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Set breakpoint 0 at 0x",
+            "Set breakpoint 1 at 0x",
+            "Stopped at func2 (",
+            "function_calls.c:9)",
+            "Stopped at func1 (",
+            "function_calls.c:19)",
+            "Child exited (status 0)",
+        ],
+    );
+
+    let func3 = breakpoint_address(&ws.debug("function_calls", "break func3\n"), 0);
+    let output = ws.debug(
+        "function_calls",
+        &format!("break *{}\nrun\ncontinue\ncontinue\n", func3),
+    );
+    assert_in_order(
+        &output,
+        &[
+            &format!("Set breakpoint 0 at {}", func3),
+            "Stopped at func3 (",
+            "Stopped at func3 (",
+            "Child exited (status 0)",
+        ],
+    );
+}
+
+#[test]
+fn conditional_and_temporary_breakpoints() {
+    let ws = Workspace::new("conditional_and_temporary_breakpoints");
+    // func3 is only ever called with 100
+    let output = ws.debug(
+        "function_calls",
+        "break 6 if a != 100\nbreak 10 if b == 5\ntbreak func1\nrun\ncontinue\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Set breakpoint 0 at 0x",
+            "Set breakpoint 1 at 0x",
+            "Set temporary breakpoint 2 at 0x",
+            "Deleted temporary breakpoint at 0x",
+            "Stopped at func1 (",
+            "Stopped at func2 (",
+            "function_calls.c:10)",
+            "Child exited (status 0)",
+        ],
+    );
+    assert_lacks(&output, "Stopped at func3");
+}
+
+#[test]
+fn prints_and_sets_variables() {
+    let ws = Workspace::new("prints_and_sets_variables");
+    let output = ws.debug(
+        "function_calls",
+        "break 12\nrun\nprint sum\nprint a\nprint b\nprint global\nprint a + b * 2\n\
+         print &global\nprint func2\nprint nosuch\nset var global = 7\nprint global\n\
+         set var sum = global * 3\nprint sum\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "sum = 47\n",
+            "a = 42\n",
+            "b = 5\n",
+            "global = 5\n",
+            "a + b * 2 = 52\n",
+            "&global = (int *) 0x",
+            "func2 = {func2 ()} 0x",
+            "No symbol \"nosuch\" in current context.",
+            "global = 7\n",
+            "sum = 21\n",
+            "Child exited (status 0)",
+        ],
+    );
+    // The new value of sum is what func2 goes on to print
+    assert_in_order(&output, &["sum = 21\n", "sum = 21\n"]);
+}
+
+#[test]
+fn steps_over_and_into_lines() {
+    let ws = Workspace::new("steps_over_and_into_lines");
+    let output = ws.debug(
+        "count",
+        "break main\nrun\nnext\nnext\nuntil\nadvance 8\nc\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at main (",
+            "count.c:3)",
+            "count.c:4)",
+            "count.c:5)",
+            "count.c:6)",
+            "count.c:8)",
+            "Child exited (status 0)",
+        ],
+    );
+
+    let output = ws.debug(
+        "function_calls",
+        "break 18\nrun\nstep\nbt\nnext\nnext\nnext\nnext\nstep\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func1 (",
+            "function_calls.c:18)",
+            "Stopped at func2 (",
+            "function_calls.c:10)",
+            "func1 (",
+            "function_calls.c:18)",
+            "function_calls.c:11)",
+            "function_calls.c:12)",
+            "function_calls.c:13)",
+            "function_calls.c:14)",
+            "Child exited (status 0)",
+        ],
+    );
+}
+
+#[test]
+fn finishes_function_calls() {
+    let ws = Workspace::new("finishes_function_calls");
+    let output = ws.debug(
+        "function_calls",
+        "break 6\nrun\nfinish\nfin\ncontinue\nfinish\nbt\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func3 (",
+            "Run till exit from func3",
+            "Stopped at func2 (",
+            "function_calls.c:14)",
+            "Run till exit from func2",
+            "Stopped at func1 (",
+            "function_calls.c:19)",
+            // The second call to func3 is from func1
+            "Stopped at func3 (",
+            "Run till exit from func3",
+            "Stopped at func1 (",
+            "function_calls.c:20)",
+            "main (",
+        ],
+    );
+
+    let output = ws.debug("count", "break main\nrun\nfinish\nfinish\n");
+    assert_in_order(
+        &output,
+        &[
+            "Run till exit from main",
+            "Value returned is 0",
+            "\"finish\" not meaningful in the outermost frame.",
+        ],
+    );
+}
+
+#[test]
+fn steps_instructions_and_shows_registers() {
+    let ws = Workspace::new("steps_instructions_and_shows_registers");
+    let output = ws.debug("count", "break 8\nrun\nstepi\ninfo registers\ncontinue\n");
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at main (",
+            "count.c:8)",
+            "Stopped at main (",
+            "count.c:8)",
+            "rax        0x",
+            "rip        0x",
+            "eflags     0x",
+            "Child exited (status 0)",
+        ],
+    );
+}
+
+#[test]
+fn examines_and_searches_memory() {
+    let ws = Workspace::new("examines_and_searches_memory");
+    // Without -pie, the program's ELF header is mapped at 0x400000
+    let output = ws.debug(
+        "function_calls",
+        "x/2 0x400000\nbreak func1\nrun\nx/2 0x400000\nx/q 0x400000\n\
+         find &global, +4, 5\nfind/b 0x400000, +0x10, 0x7f, 'E', 'L', 'F'\n\
+         find 0x400000, +0x10, 1234567\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "The program is not being run.",
+            "Stopped at func1 (",
+            "0x400000:\t0x00010102464c457f\t0x0000000000000000",
+            "Invalid count \"q\".",
+            "1 pattern found.",
+            "0x400000\n1 pattern found.",
+            "Pattern not found.",
+        ],
+    );
+
+    let output = ws.debug(
+        "hello",
+        "break main\nrun\ninfo proc mappings\nfind 0x402000, +0x1000, \"Hello world!\"\n",
+    );
+    assert_in_order(&output, &["r--p", "1 pattern found."]);
+}
+
+#[test]
+fn calls_functions_in_the_program() {
+    let ws = Workspace::new("calls_functions_in_the_program");
+    let output = ws.debug(
+        "function_calls",
+        "break 17\nrun\ncall func3(7)\ncall func3(global + 1)\ncontinue\n",
+    );
+    assert_in_order(&output, &["Stopped at func1 (", "Child exited (status 0)"]);
+    // The calls' output came before the program's own
+    assert_in_order(
+        &output,
+        &[
+            "Hello from func3! 7",
+            "Hello from func3! 6",
+            "func1(42) was called",
+        ],
+    );
+}
+
+#[test]
+fn watchpoints_stop_when_values_change() {
+    let ws = Workspace::new("watchpoints_stop_when_values_change");
+    let output = ws.debug(
+        "function_calls",
+        "break 10\nrun\nwatch sum\ncontinue\nprint sum\nbt\ncontinue\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "Watchpoint 0: sum (0x",
+            "Watchpoint 0: sum\n",
+            "Old value = ",
+            "New value = 47",
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            "sum = 47",
+            "func1 (",
+            // sum is gone once func2 returns
+            "Watchpoint 0 deleted because the program has left the block in\n\
+             which its expression is valid.",
+            "Stopped at func1 (",
+            "Child exited (status 0)",
+        ],
+    );
+
+    // Nor is it in the program started again
+    let output = ws.debug(
+        "function_calls",
+        "break 10\nrun\nwatch sum\nwatch global\nrun\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Watchpoint 1: global (0x",
+            "Watchpoint 0 deleted because",
+            "Stopped at func2 (",
+            "Child exited (status 0)",
+        ],
+    );
+
+    let output = ws.debug(
+        "function_calls",
+        "break 17\nrun\nwatch global\nset var global = 9\ncontinue\n",
+    );
+    // global never changes on its own
+    assert_in_order(
+        &output,
+        &["Watchpoint 0: global (0x", "Child exited (status 0)"],
+    );
+    assert_in_order(&output, &["func2(42, 9) was called"]);
+}
+
+#[test]
+fn displays_expressions_when_stopping() {
+    let ws = Workspace::new("displays_expressions_when_stopping");
+    let output = ws.debug(
+        "function_calls",
+        "break 10\nrun\ndisplay global\ndisplay a + 1\ninfo display\nnext\n\
+         undisplay 1\ninfo display\nnext\ndisplay\nundisplay\ninfo display\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "1: global = 5",
+            "2: a + 1 = 43",
+            "Auto-display expressions now in effect:\n1    global\n2    a + 1",
+            "function_calls.c:11)",
+            "1: global = 5",
+            "2: a + 1 = 43",
+            "Auto-display expressions now in effect:\n2    a + 1",
+            "function_calls.c:12)",
+            "2: a + 1 = 43",
+            "2: a + 1 = 43",
+            "There are no auto-display expressions now.",
+        ],
+    );
+
+    // Every stop shows them, however the program got there, and they say when they can't be
+    // evaluated where it is
+    let output = ws.debug(
+        "function_calls",
+        "display global\ndisplay sum\nbreak 12\ncatch syscall exit_group\nrun\nfinish\n\
+         continue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "1: global = 5\n2: sum = 47",
+            "Run till exit from func2",
+            "Stopped at func1 (",
+            "1: global = 5\n2: sum = <No symbol \"sum\" in current context.>",
+            "Catchpoint 0 (call to syscall exit_group)",
+            "1: global = 5",
+        ],
+    );
+
+    // A core file doesn't go anywhere, so there's nothing new to show
+    ws.debug("segfault", "run\ngcore crash.core\n");
+    let output = ws.debug_with(
+        "segfault",
+        &["--nx", "--core", "crash.core"],
+        "display a\ncontinue\n",
+    );
+    assert_in_order(&output, &["1: a = 2", "The program is not being run."]);
+    assert_eq!(output.matches("1: a = 2").count(), 1, "{}", output);
+}
+
+#[test]
+fn catches_system_calls_signals_and_exits() {
+    let ws = Workspace::new("catches_system_calls_signals_and_exits");
+    let output = ws.debug("exit", "catch syscall exit\nrun\ncontinue\n");
+    assert_in_order(
+        &output,
+        &[
+            "Catchpoint 0 (syscall 'exit' [60])",
+            "Catchpoint 0 (call to syscall exit), exit(0)",
+            "Stopped at main (",
+            "exit.c:4)",
+            "Child exited (status 0)",
+        ],
+    );
+
+    let output = ws.debug("loop", "catch exit\nrun\ncontinue\n");
+    assert_in_order(
+        &output,
+        &[
+            "Catchpoint 0 (exit)",
+            "about to exit (status 1)",
+            "Child exited (status 1)",
+        ],
+    );
+
+    let output = ws.debug("segfault", "catch signal SIGSEGV\nrun\nprint a\n");
+    assert_in_order(
+        &output,
+        &[
+            "Catchpoint 0 (signal SIGSEGV)",
+            "Catchpoint 0 (signal SIGSEGV)",
+            "Stopped at func2 (",
+            "segfault.c:5)",
+            "a = 2",
+        ],
+    );
+}
+
+#[test]
+fn summarizes_crashes() {
+    let ws = Workspace::new("summarizes_crashes");
+    let output = ws.debug("segfault", "run\nbt\n");
+    assert_in_order(
+        &output,
+        &[
+            "Child stopped (signal SIGSEGV)",
+            "Stopped at func2 (",
+            "segfault.c:5)",
+            "Crash summary:",
+            "Cause:       writing 4 bytes at 0x0: address not mapped to object (SEGV_MAPERR)",
+            "a null pointer was probably dereferenced",
+            "Instruction: 0x",
+            "<func2+",
+            "Source:      ",
+            "segfault.c:5: *(int*)0 = a;",
+            "Variables:   a = 2",
+            "Backtrace:",
+            "func2 (",
+            "func1 (",
+            "segfault.c:11)",
+            "main (",
+            "segfault.c:15)",
+            "Killing running inferior",
+        ],
+    );
+}
+
+#[test]
+fn writes_and_loads_core_files() {
+    let ws = Workspace::new("writes_and_loads_core_files");
+    let output = ws.debug("segfault", "run\ngcore crash.core\n");
+    assert_in_order(&output, &["Crash summary:", "Saved corefile crash.core"]);
+
+    let output = ws.debug_with(
+        "segfault",
+        &["--nx", "--core", "crash.core"],
+        "bt\nprint a\ninfo registers\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Program terminated with signal SIGSEGV.",
+            "Stopped at func2 (",
+            "func2 (",
+            "func1 (",
+            "main (",
+            "a = 2",
+            "rip        0x",
+        ],
+    );
+}
+
+#[test]
+fn records_and_runs_backwards() {
+    let ws = Workspace::new("records_and_runs_backwards");
+    let output = ws.debug(
+        "count",
+        "break main\nrun\nrecord\nnext\nnext\ninfo record\nreverse-next\nreverse-continue\n\
+         record stop\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Recording execution (up to 200000 instructions).",
+            "count.c:4)",
+            "count.c:5)",
+            "Recorded ",
+            "count.c:4)",
+            "Hit breakpoint at 0x",
+            "count.c:3)",
+            "Process record is stopped and all execution logs are deleted.",
+            "Child exited (status 0)",
+        ],
+    );
+    // Nothing was printed twice by going back over it
+    assert_eq!(output.matches("1\n").count(), 1, "{}", output);
+}
+
+#[test]
+fn restarts_from_checkpoints() {
+    let ws = Workspace::new("restarts_from_checkpoints");
+    let output = ws.debug(
+        "count",
+        "break 5\nrun\ncheckpoint\nnext\nnext\ninfo checkpoints\nrestart 1\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "count.c:5)",
+            "Checkpoint 1: fork returned pid ",
+            "count.c:6)",
+            "count.c:7)",
+            "1    process ",
+            "count.c:5)",
+            "Switching to checkpoint 1",
+            "count.c:5)",
+            "Child exited (status 0)",
+        ],
+    );
+}
+
+#[test]
+fn shows_information_about_symbols() {
+    let ws = Workspace::new("shows_information_about_symbols");
+    let output = ws.debug(
+        "function_calls",
+        "info functions ^func\ninfo variables glob\ninfo types int\ninfo line func2\n\
+         break 17\nrun\ninfo source\ninfo symbol 0x401000\ninfo bogus\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "All defined functions:",
+            "16:\tvoid func1(int);\n9:\tvoid func2(int, int);\n5:\tvoid func3(int);",
+            "All defined variables:",
+            "3:\tint global;",
+            "All defined types:",
+            "int\n",
+            "Line 9 of \"",
+            "function_calls.c\" starts at address 0x",
+            "Current source file is ",
+            "function_calls.c",
+            "Source language is c",
+            "Undefined info command: \"bogus\".",
+        ],
+    );
+}
+
+#[test]
+fn shows_the_memory_map_and_heap() {
+    let ws = Workspace::new("shows_the_memory_map_and_heap");
+    let output = ws.debug(
+        "function_calls",
+        "break 17\nrun\ninfo heap\ninfo proc mappings\ninfo sharedlibrary\n\
+         break 18\ncontinue\ninfo heap\ninfo bins\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "The program hasn't allocated anything on the heap.",
+            "Start Addr",
+            "r-xp",
+            "function_calls",
+            "libc.so",
+            "[stack]",
+            "Shared Object Library",
+            "libc.so",
+            "function_calls.c:18)",
+            // The buffer stdout was given when func1 first printed
+            "Heap 0x",
+            "0x1010  in use",
+            "top",
+            "Main arena at 0x",
+        ],
+    );
+}
+
+#[test]
+fn keeps_launch_settings() {
+    let ws = Workspace::new("keeps_launch_settings");
+    let output = ws.debug(
+        "function_calls",
+        "set environment DEET_TEST=hello\nshow environment DEET_TEST\n\
+         unset environment DEET_TEST\nshow environment DEET_TEST\nset cwd /\nshow cwd\n\
+         show tty\ntty pty\nshow tty\ntty shared\nshow tty\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "DEET_TEST = hello",
+            "Environment variable \"DEET_TEST\" not defined.",
+            "The program runs in /.",
+            "The program runs on deet's terminal.",
+            "The program runs on a pseudo-terminal of its own.",
+            "The program runs on deet's terminal.",
+        ],
+    );
+}
+
+#[test]
+fn passes_arguments_and_redirects_output() {
+    let ws = Workspace::new("passes_arguments_and_redirects_output");
+    let output = ws.debug("loop", "run 2 > loop.out\n");
+    assert_in_order(&output, &["Child exited (status 0)"]);
+    assert_eq!(fs::read_to_string(ws.path("loop.out")).unwrap(), "0\n1\n");
+
+    fs::write(ws.path("input"), "").unwrap();
+    let output = ws.debug("hello", "run < input > hello.out\n");
+    assert_in_order(&output, &["Child exited (status 0)"]);
+    assert_lacks(&output, "Hello world!");
+    assert_eq!(
+        fs::read_to_string(ws.path("hello.out")).unwrap(),
+        "Hello world!\n"
+    );
+}
+
+#[test]
+fn runs_programs_on_a_pseudo_terminal() {
+    let ws = Workspace::new("runs_programs_on_a_pseudo_terminal");
+    // On a terminal, stdout is line buffered, so each line is written as it's printed
+    let output = ws.debug(
+        "function_calls",
+        "tty pty\ncatch syscall write\nrun\ncontinue\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Catchpoint 0 (call to syscall write), write(1, \"func1(42) was called\\n\", 21)",
+            "func1(42) was called",
+            "Catchpoint 0 (returned from syscall write), write(1, \"func1(42) was called\\n\", 21) = 21",
+            "Catchpoint 0 (call to syscall write), write(1, \"func2(42, 5) was called\\n\", 24)",
+        ],
+    );
+}
+
+#[test]
+fn saves_and_sources_sessions() {
+    let ws = Workspace::new("saves_and_sources_sessions");
+    let output = ws.debug(
+        "function_calls",
+        "break 12\ntbreak func3\ncatch syscall exit_group\nset environment DEET_TEST=1\n\
+         display global\nsave breakpoints breakpoints.deet\nsave session session.deet\nsave session\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Saved to file 'breakpoints.deet'.",
+            "Saved to file 'session.deet'.",
+            "Saved to file '",
+            ".deetinit'.",
+        ],
+    );
+    let breakpoints = fs::read_to_string(ws.path("breakpoints.deet")).unwrap();
+    assert_lacks(&breakpoints, "environment");
+
+    let output = ws.debug(
+        "function_calls",
+        "source breakpoints.deet\nrun\ncontinue\ncontinue\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            "Stopped at func3 (",
+            "Catchpoint 0 (call to syscall exit_group), exit_group(0)",
+        ],
+    );
+
+    let output = ws.debug(
+        "function_calls",
+        "source session.deet\nshow environment DEET_TEST\n",
+    );
+    assert_in_order(&output, &["DEET_TEST = 1"]);
+
+    // Without --nx, deet runs the .deetinit beside the program when it starts
+    let output = ws.debug_with("function_calls", &[], "run\n");
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            "1: global = 5",
+        ],
+    );
+
+    let output = ws.debug("function_calls", "source nosuch.deet\n");
+    assert_in_order(&output, &["nosuch.deet: No such file or directory"]);
+}
+
+#[test]
+fn explains_commands_and_mistakes() {
+    let ws = Workspace::new("explains_commands_and_mistakes");
+    let output = ws.debug(
+        "hello",
+        "help\nhelp break\nhelp info\nhelp frobnicate\nfrobnicate\ncontinue\nbacktrace\n\
+         print global\nbreak nosuch\nrun\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "List of commands:",
+            "quit ",
+            "break <location> [if <condition>]",
+            "Abbreviations: b",
+            "info registers",
+            "Undefined command: \"frobnicate\". Try \"help\".",
+            "Undefined command: \"frobnicate\". Try \"help\".",
+            "The program is not being run.",
+            "Child exited (status 0)",
+        ],
+    );
+}
+
+#[test]
+fn quitting_kills_the_program() {
+    let ws = Workspace::new("quitting_kills_the_program");
+    let output = ws.debug("count", "break 5\nrun\nquit\nprint 1\n");
+    assert_in_order(&output, &["count.c:5)", "Killing running inferior (pid "]);
+    // Nothing after quit is run
+    assert_lacks(&output, "1 = 1");
+    assert_lacks(&output, "3\n");
+}
+
+#[test]
+fn traces_system_calls() {
+    let ws = Workspace::new("traces_system_calls");
+    let hello = ws.compile("hello");
+    let output = ws.run_deet(&["--strace", hello.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    // The trace goes to stderr, leaving stdout to the program
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello world!\n");
+    let trace = String::from_utf8_lossy(&output.stderr);
+    assert_in_order(
+        &trace,
+        &[
+            "brk(NULL) = 0x",
+            "write(1, \"Hello world!\\n\", 13) = 13",
+            "exit_group(0) = ?",
+            "+++ exited with 0 +++",
+        ],
+    );
+
+    // deet exits with the program's status, and passes its arguments on
+    let looping = ws.compile("loop");
+    let output = ws.run_deet(&["--strace", looping.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = ws.run_deet(&["--strace", looping.to_str().unwrap(), "1"]);
+    assert_eq!(output.status.code(), Some(0));
+    let trace = String::from_utf8_lossy(&output.stderr);
+    assert_in_order(&trace, &["clock_nanosleep", "+++ exited with 0 +++"]);
+}
+
+#[test]
+fn prints_rust_values_with_demangled_names() {
+    let ws = Workspace::new("prints_rust_values_with_demangled_names");
+    let program = ws.compile_rust("collections");
+    let output = ws.debug_program(
+        &program,
+        &["--nx"],
+        "break total\nrun\nfinish\nbacktrace\nprint v\nprint s\nprint some\nprint none\n\
+         print b\nprint m\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at collections::total (",
+            "collections.rs:8)",
+            "Run till exit from collections::total",
+            "Value returned is 6",
+            "collections::main (",
+            "collections.rs:21)\n",
+            "v = Vec(size=3) = {1, 2, 3}\n",
+            "s = \"hello\"\n",
+            "some = Some(5)\n",
+            "none = None\n",
+            "b = Box({x = 1, y = 2})\n",
+            "m = HashMap(size=2) = {",
+        ],
+    );
+    // The map's entries come out in whatever order they hash to
+    assert_in_order(&output, &["m = HashMap", "[1] = 10"]);
+    assert_in_order(&output, &["m = HashMap", "[2] = 20"]);
+    assert_lacks(&output, "_ZN");
+}
+
+#[test]
+fn reads_split_dwarf_from_dwo_files_and_packages() {
+    let ws = Workspace::new("reads_split_dwarf_from_dwo_files_and_packages");
+    let program = ws.compile_with(
+        "function_calls",
+        "function_calls",
+        &["-gdwarf-5", "-gsplit-dwarf"],
+    );
+    let script = "break function_calls.c:12\nrun\nprint sum\nprint a\nbacktrace\n";
+    let expected = [
+        "Stopped at func2 (",
+        "function_calls.c:12)",
+        "sum = 47\n",
+        "a = 42\n",
+        "func2 (",
+        "function_calls.c:12)\n",
+        "func1 (",
+        "function_calls.c:18)\n",
+        "main (",
+        "function_calls.c:24)\n",
+    ];
+    let output = ws.debug_program(&program, &["--nx"], script);
+    assert_in_order(&output, &expected);
+
+    // The same, from a package of the .dwo files
+    ws.run_tool(
+        "llvm-dwp",
+        &["-e", "function_calls", "-o", "function_calls.dwp"],
+    );
+    // gcc names the .dwo files differently from version to version
+    for entry in fs::read_dir(&ws.dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() == Some("dwo".as_ref()) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    let output = ws.debug_program(&program, &["--nx"], script);
+    assert_in_order(&output, &expected);
+}
+
+#[test]
+fn finds_separate_debug_info_or_falls_back_to_symbols() {
+    let ws = Workspace::new("finds_separate_debug_info_or_falls_back_to_symbols");
+    let program = ws.compile("function_calls");
+    ws.run_tool(
+        "objcopy",
+        &[
+            "--only-keep-debug",
+            "function_calls",
+            "function_calls.debug",
+        ],
+    );
+    ws.run_tool(
+        "objcopy",
+        &[
+            "--strip-debug",
+            "--add-gnu-debuglink=function_calls.debug",
+            "function_calls",
+        ],
+    );
+    let output = ws.debug_program(
+        &program,
+        &["--nx"],
+        "break function_calls.c:12\nrun\nprint sum\nbacktrace\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            "sum = 47\n",
+            "func1 (",
+            "function_calls.c:18)\n",
+        ],
+    );
+    assert_lacks(&output, "No debugging symbols found");
+
+    // Without the debug file, there are only the symbol table's functions
+    fs::remove_file(ws.path("function_calls.debug")).unwrap();
+    let output = ws.debug_program(
+        &program,
+        &["--nx"],
+        "info functions ^func\nbreak func2\nrun\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "No debugging symbols found in ",
+            "Non-debugging symbols:",
+            "  func3\n",
+            "  func2\n",
+            "  func1\n",
+            "Set breakpoint 0 at 0x",
+            "Stopped at func2 (0x",
+        ],
+    );
+}
+
+#[test]
+fn looks_names_and_addresses_up_in_the_gdb_index() {
+    let ws = Workspace::new("looks_names_and_addresses_up_in_the_gdb_index");
+    let program = ws.compile_with(
+        "function_calls",
+        "function_calls",
+        &["-fuse-ld=gold", "-Wl,--gdb-index"],
+    );
+    let sections = Command::new("readelf")
+        .args(&["-S", program.to_str().unwrap()])
+        .output()
+        .expect("running readelf");
+    assert_in_order(&String::from_utf8_lossy(&sections.stdout), &[".gdb_index"]);
+
+    let output = ws.debug_program(
+        &program,
+        &["--nx"],
+        "info functions ^func\nbreak func3\nbreak function_calls.c:12\nrun\nbacktrace\n\
+         print global\ninfo symbol 0x401000\n",
+    );
+    assert_in_order(
+        &output,
+        &[
+            "All defined functions:",
+            "16:\tvoid func1(int);\n9:\tvoid func2(int, int);\n5:\tvoid func3(int);",
+            "Set breakpoint 0 at 0x",
+            "Set breakpoint 1 at 0x",
+            "Stopped at func2 (",
+            "func2 (",
+            "function_calls.c:12)\n",
+            "func1 (",
+            "function_calls.c:18)\n",
+            "main (",
+            "function_calls.c:24)\n",
+            "global = 5\n",
+        ],
+    );
+}
+
+#[test]
+fn draws_the_tui_on_a_terminal() {
+    let ws = Workspace::new("draws_the_tui_on_a_terminal");
+    let program = ws.compile("function_calls");
+    let output = ws.run_deet_on_terminal(
+        &[program.to_str().unwrap(), "--nx", "--tui"],
+        &["break 12", "run", "quit"],
+    );
+    assert_in_order(
+        &output,
+        &[
+            // The alternate screen, with the command area below the panes
+            "\x1b[?1049h",
+            "\x1b[23;30r",
+            " Source ",
+            "The program is not being run.",
+            " Registers ",
+            " Disassembly ",
+            " Backtrace ",
+            "Set breakpoint 0 at 0x",
+            "Stopped at func2 (",
+            "function_calls.c:12)",
+            // The panes again, showing where func2 stopped, with a mark at the breakpoint
+            "function_calls.c ",
+            "  11      int sum = a + b;",
+            "b>12      printf(",
+            " Registers ",
+            "rax     0x",
+            " Disassembly ",
+            "b>0x",
+            " Backtrace ",
+            "func2 (",
+            "func1 (",
+            "main (",
+            "Killing running inferior",
+            // Back to the normal screen
+            "\x1b[?1049l",
+        ],
+    );
+}